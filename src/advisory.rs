@@ -0,0 +1,117 @@
+// Detection of the security advisories fixed by the patches applied by nixpkgs.
+// The nixpkgs maintainers usually name the patches after the advisory they fix,
+// e.g. CVE-2023-4911.patch, or fetch them from a URL that contains the identifier
+// of the advisory, which makes it possible to generate a partial VEX document.
+use std::collections::{BTreeMap, BTreeSet};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// The advisory identifiers must not be preceded by a letter or a digit, so that
+// for example ECDSA-1234 is not detected as a Debian advisory.
+lazy_static! {
+    static ref ADVISORY_REGEXES: Vec<Regex> = vec![
+        Regex::new(r"(?i)(?:^|[^a-z0-9])(cve-\d{4}-\d{4,})").unwrap(),
+        Regex::new(r"(?i)(?:^|[^a-z0-9])(ghsa(?:-[23456789cfghjmpqrvwx]{4}){3})").unwrap(),
+        Regex::new(r"(?i)(?:^|[^a-z0-9])(dsa-\d{3,}(?:-\d+)?)").unwrap(),
+    ];
+}
+
+// Returns the normalized identifiers of the advisories referenced in a patch
+// filename or URL.
+pub fn get_advisory_ids(text: &str) -> BTreeSet<String> {
+    let mut response: BTreeSet<String> = BTreeSet::default();
+    for regex in ADVISORY_REGEXES.iter() {
+        for captures in regex.captures_iter(text) {
+            let advisory_id = captures.get(1).unwrap().as_str();
+            // The GHSA identifiers are written with a lowercase suffix.
+            response.insert(match advisory_id.split_once('-') {
+                Some((prefix, suffix)) if prefix.eq_ignore_ascii_case("ghsa") => {
+                    format!("GHSA-{}", suffix.to_lowercase())
+                }
+                _ => advisory_id.to_uppercase(),
+            });
+        }
+    }
+    response
+}
+
+// Returns the name and the URL of the database that published the advisory.
+pub fn get_advisory_source(advisory_id: &str) -> (String, String) {
+    if advisory_id.starts_with("GHSA-") {
+        return (
+            "GitHub".to_string(),
+            format!("https://github.com/advisories/{}", advisory_id),
+        );
+    }
+    if advisory_id.starts_with("DSA-") {
+        return (
+            "Debian".to_string(),
+            format!("https://security-tracker.debian.org/tracker/{}", advisory_id),
+        );
+    }
+    (
+        "NVD".to_string(),
+        format!("https://nvd.nist.gov/vuln/detail/{}", advisory_id),
+    )
+}
+
+// Returns the advisories fixed by the patches of a package, with the patches
+// referencing each advisory. The local patches are only known by their store path,
+// and the fetched patches by their URL.
+pub fn get_patched_advisories(
+    package_node: &crate::nix::PackageNode,
+    package_graph: &crate::nix::PackageGraph,
+) -> BTreeMap<String, BTreeSet<String>> {
+    let mut patches: BTreeSet<String> = package_node.main_derivation.get_patches().into_iter().collect();
+    for patch_derivation_path in &package_node.patches {
+        let patch = match package_graph.nodes.get(patch_derivation_path) {
+            Some(p) => &p.main_derivation,
+            None => continue,
+        };
+        if let Some(url) = patch.get_url() {
+            // The out path of the patch is replaced by the URL it was fetched from.
+            for output_path in patch.get_output_paths() {
+                patches.remove(&output_path);
+            }
+            patches.insert(url);
+        }
+    }
+
+    let mut response: BTreeMap<String, BTreeSet<String>> = BTreeMap::default();
+    for patch in patches {
+        for advisory_id in get_advisory_ids(&patch) {
+            response.entry(advisory_id).or_default().insert(patch.clone());
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_advisory_ids() {
+        assert_eq!(
+            get_advisory_ids("/nix/store/1n2bl0bs3y5aba5wk3ax3yd6f0q0lmfl-CVE-2023-4911.patch"),
+            BTreeSet::from(["CVE-2023-4911".to_string()])
+        );
+        assert_eq!(
+            get_advisory_ids("https://example.org/patches/fix_cve-2024-12345_and_CVE-2024-1234.diff"),
+            BTreeSet::from(["CVE-2024-12345".to_string(), "CVE-2024-1234".to_string()])
+        );
+        assert_eq!(
+            get_advisory_ids("https://github.com/advisories/GHSA-4Q6P-R6V2-JVC5"),
+            BTreeSet::from(["GHSA-4q6p-r6v2-jvc5".to_string()])
+        );
+        assert_eq!(
+            get_advisory_ids("https://salsa.debian.org/debian/foo/-/raw/main/debian/patches/dsa-5514-1.patch"),
+            BTreeSet::from(["DSA-5514-1".to_string()])
+        );
+        assert!(
+            get_advisory_ids("/nix/store/1n2bl0bs3y5aba5wk3ax3yd6f0q0lmfl-fix-ECDSA-1234.patch").is_empty()
+        );
+        assert!(get_advisory_ids("/nix/store/1n2bl0bs3y5aba5wk3ax3yd6f0q0lmfl-CVE-23-1.patch").is_empty());
+    }
+}
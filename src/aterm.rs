@@ -0,0 +1,280 @@
+// Parser for the ATerm serialization of the .drv files of the Nix store, to read the
+// derivations without the nix CLI or the Nix daemon, for example in a sandbox:
+//
+//   Derive([("out","/nix/store/...-hello-2.12.1","","")],
+//          [("/nix/store/...-bash-5.2p26.drv",["out"])],
+//          ["/nix/store/...-default-builder.sh"],
+//          "x86_64-linux","/nix/store/...-bash-5.2p26/bin/bash",["-e","..."],
+//          [("name","hello-2.12.1"),("system","x86_64-linux")])
+//
+// See https://nix.dev/manual/nix/latest/protocols/derivation-aterm
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::nix::{Derivation, DerivationBuilder, Derivations, InputDerivation, Output};
+
+const STORE_DIR: &str = "/nix/store";
+
+struct Parser<'a> {
+    content: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(content: &'a str) -> Parser<'a> {
+        Parser {
+            content: content.as_bytes(),
+            position: 0,
+        }
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow::format_err!("{} at offset {} of the derivation", message, self.position)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.content.get(self.position).copied()
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), anyhow::Error> {
+        if !self.content[self.position..].starts_with(expected.as_bytes()) {
+            return Err(self.error(&format!("Expected {}", expected)));
+        }
+        self.position += expected.len();
+        Ok(())
+    }
+
+    fn parse_string(&mut self) -> Result<String, anyhow::Error> {
+        self.expect("\"")?;
+        let mut response: Vec<u8> = vec![];
+        loop {
+            let c = self.peek().ok_or(self.error("Unterminated string"))?;
+            self.position += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = self.peek().ok_or(self.error("Unterminated string"))?;
+                    self.position += 1;
+                    response.push(match escaped {
+                        b'n' => b'\n',
+                        b'r' => b'\r',
+                        b't' => b'\t',
+                        c => c,
+                    });
+                }
+                c => response.push(c),
+            }
+        }
+        String::from_utf8(response).map_err(|e| self.error(&e.to_string()))
+    }
+
+    // Parses a comma-separated list between brackets, using the parser of its items.
+    fn parse_list<T>(
+        &mut self,
+        parse_item: impl Fn(&mut Parser<'a>) -> Result<T, anyhow::Error>,
+    ) -> Result<Vec<T>, anyhow::Error> {
+        let mut response: Vec<T> = vec![];
+        self.expect("[")?;
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(response);
+        }
+        loop {
+            response.push(parse_item(self)?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(response);
+                }
+                _ => return Err(self.error("Expected , or ]")),
+            }
+        }
+    }
+
+    fn parse_output(&mut self) -> Result<(String, Output), anyhow::Error> {
+        self.expect("(")?;
+        let name = self.parse_string()?;
+        self.expect(",")?;
+        let path = self.parse_string()?;
+        self.expect(",")?;
+        let hash_algo = self.parse_string()?;
+        self.expect(",")?;
+        let hash = self.parse_string()?;
+        self.expect(")")?;
+        // The hash is only set for the fixed-output derivations.
+        let output = Output {
+            path,
+            hash: Some(hash).filter(|h| !h.is_empty()),
+            hash_algo: Some(hash_algo).filter(|a| !a.is_empty()),
+        };
+        Ok((name, output))
+    }
+
+    fn parse_input_derivation(&mut self) -> Result<(String, InputDerivation), anyhow::Error> {
+        self.expect("(")?;
+        let derivation_path = self.parse_string()?;
+        self.expect(",")?;
+        let output_names = self.parse_list(|p| p.parse_string())?;
+        self.expect(")")?;
+        Ok((derivation_path, InputDerivation::List(output_names)))
+    }
+
+    fn parse_env_variable(&mut self) -> Result<(String, String), anyhow::Error> {
+        self.expect("(")?;
+        let name = self.parse_string()?;
+        self.expect(",")?;
+        let value = self.parse_string()?;
+        self.expect(")")?;
+        Ok((name, value))
+    }
+}
+
+// Parses the content of a .drv file.
+pub fn parse_derivation(content: &str) -> Result<Derivation, anyhow::Error> {
+    let mut parser = Parser::new(content.trim_end());
+    parser.expect("Derive(")?;
+    let outputs = parser.parse_list(|p| p.parse_output())?;
+    parser.expect(",")?;
+    let input_derivations = parser.parse_list(|p| p.parse_input_derivation())?;
+    parser.expect(",")?;
+    let inputs_sources = parser.parse_list(|p| p.parse_string())?;
+    parser.expect(",")?;
+    let system = parser.parse_string()?;
+    parser.expect(",")?;
+    let builder = parser.parse_string()?;
+    parser.expect(",")?;
+    let args = parser.parse_list(|p| p.parse_string())?;
+    parser.expect(",")?;
+    let env = parser.parse_list(|p| p.parse_env_variable())?;
+    parser.expect(")")?;
+    if parser.peek().is_some() {
+        return Err(parser.error("Unexpected content after the derivation"));
+    }
+
+    Ok(Derivation {
+        outputs: outputs.into_iter().collect(),
+        inputs_sources,
+        input_derivations: input_derivations.into_iter().collect(),
+        system,
+        builder: DerivationBuilder::from_string(&builder).map_err(|e| anyhow::format_err!(e))?,
+        args,
+        env: env.into_iter().collect(),
+        extra: HashMap::default(),
+        nar_hash: None,
+    })
+}
+
+// Reads the .drv files and the .drv files of all their input derivations, as with
+// `nix derivation show -r`. The input derivations are looked up in the directory of
+// the .drv files given, so a store mounted at another path can be read. The derivations
+// are always indexed by their path in the Nix store. The input derivations missing from
+// the store are skipped, and become placeholders in the graph.
+pub fn read_derivations(derivation_paths: &[String]) -> Result<Derivations, anyhow::Error> {
+    let mut response = Derivations::default();
+    // The path of the derivation in the store, the path of its .drv file, and whether
+    // it is one of the derivations given.
+    let mut path_queue: VecDeque<(String, String, bool)> = VecDeque::default();
+    for derivation_path in derivation_paths {
+        path_queue.push_back((get_store_path(derivation_path), derivation_path.to_string(), true));
+    }
+
+    while let Some((store_path, file_path, is_root)) = path_queue.pop_front() {
+        if response.contains_key(&store_path) {
+            continue;
+        }
+        let content = match fs::read_to_string(&file_path) {
+            Ok(c) => c,
+            Err(e) if is_root => {
+                return Err(anyhow::format_err!("Could not read {}: {}", &file_path, e));
+            }
+            Err(e) => {
+                log::debug!("Could not read {}: {}", &file_path, e);
+                continue;
+            }
+        };
+        let derivation = parse_derivation(&content)
+            .map_err(|e| anyhow::format_err!("Could not parse {}: {}", &file_path, e))?;
+        let store_directory = Path::new(&file_path).parent().unwrap_or(Path::new(""));
+        for input_derivation_path in derivation.input_derivations.keys() {
+            let file_name = Path::new(input_derivation_path).file_name().unwrap_or_default();
+            path_queue.push_back((
+                input_derivation_path.to_string(),
+                store_directory.join(file_name).display().to_string(),
+                false,
+            ));
+        }
+        response.insert(store_path, derivation);
+    }
+    Ok(response)
+}
+
+// Returns the path in the Nix store of a .drv file, which can be read from another path.
+fn get_store_path(derivation_path: &str) -> String {
+    match Path::new(derivation_path).file_name() {
+        Some(file_name) => format!("{}/{}", STORE_DIR, file_name.to_string_lossy()),
+        None => derivation_path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_derivation() {
+        let derivation = parse_derivation(
+            r###"Derive([("out","/nix/store/a-hello-2.12.1","","")],[("/nix/store/b-bash-5.2p26.drv",["out"]),("/nix/store/c-hello-2.12.1.tar.gz.drv",["out"])],["/nix/store/d-default-builder.sh"],"x86_64-linux","/nix/store/e-bash-5.2p26/bin/bash",["-e","/nix/store/d-default-builder.sh"],[("name","hello-2.12.1"),("pname","hello"),("script","echo \"hello\"\nexit 0\\"),("version","2.12.1")])"###,
+        )
+        .unwrap();
+        assert_eq!(derivation.outputs["out"].path, "/nix/store/a-hello-2.12.1");
+        assert_eq!(derivation.outputs["out"].hash, None);
+        assert_eq!(derivation.input_derivations.len(), 2);
+        assert_eq!(
+            derivation.input_derivations["/nix/store/b-bash-5.2p26.drv"],
+            InputDerivation::List(vec!["out".to_string()])
+        );
+        assert_eq!(derivation.inputs_sources, vec!["/nix/store/d-default-builder.sh"]);
+        assert_eq!(derivation.system, "x86_64-linux");
+        assert_eq!(derivation.builder, DerivationBuilder::Bash);
+        assert_eq!(derivation.args.len(), 2);
+        assert_eq!(derivation.env["script"], "echo \"hello\"\nexit 0\\");
+        assert_eq!(derivation.get_name(), Some("hello".to_string()));
+
+        let source = parse_derivation(
+            r###"Derive([("out","/nix/store/f-hello-2.12.1.tar.gz","sha256","8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20")],[],[],"builtin","builtin:fetchurl",[],[("url","mirror://gnu/hello/hello-2.12.1.tar.gz")])"###,
+        )
+        .unwrap();
+        assert_eq!(source.outputs["out"].hash_algo, Some("sha256".to_string()));
+        assert_eq!(source.builder, DerivationBuilder::FetchURL);
+
+        assert!(parse_derivation("Derive([],[],[],\"x86_64-linux\"").is_err());
+        assert!(parse_derivation("{}").is_err());
+    }
+
+    #[test]
+    pub fn test_read_derivations() {
+        let directory = std::env::temp_dir().join(format!("nix2sbom-test-aterm-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let app_path = directory.join("a-app.drv").display().to_string();
+        std::fs::write(
+            &app_path,
+            r###"Derive([("out","/nix/store/a-app","","")],[("/nix/store/b-zlib-1.3.1.drv",["out"]),("/nix/store/c-missing.drv",["out"])],[],"x86_64-linux","/bin/sh",[],[("name","app")])"###,
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("b-zlib-1.3.1.drv"),
+            r###"Derive([("out","/nix/store/b-zlib-1.3.1","","")],[],[],"x86_64-linux","/bin/sh",[],[("name","zlib-1.3.1")])"###,
+        )
+        .unwrap();
+
+        let derivations = read_derivations(std::slice::from_ref(&app_path)).unwrap();
+        assert_eq!(derivations.len(), 2);
+        assert!(derivations.contains_key("/nix/store/a-app.drv"));
+        assert!(!derivations.contains_key(&app_path));
+        assert!(derivations.contains_key("/nix/store/b-zlib-1.3.1.drv"));
+        assert!(read_derivations(&[directory.join("d-unknown.drv").display().to_string()]).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
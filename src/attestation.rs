@@ -0,0 +1,295 @@
+// Attestations wrapping the SBOM in an in-toto statement, one per output path of the
+// root derivations, for example to attach the SBOM to a container image with cosign.
+// The statements are not signed. See https://github.com/in-toto/attestation/tree/main/spec/v1
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+pub const CYCLONE_DX_PREDICATE_TYPE: &str = "https://cyclonedx.org/bom";
+pub const SPDX_PREDICATE_TYPE: &str = "https://spdx.dev/Document";
+
+// The digest of a store path is the SHA-256 hash of its NAR serialization.
+pub const NAR_DIGEST_ALGORITHM: &str = "narSha256";
+pub const SHA256_DIGEST_ALGORITHM: &str = "sha256";
+
+const STATEMENT_FILE_EXTENSION: &str = "intoto.json";
+// Set on the derivations of the images built with dockerTools.buildImage and
+// dockerTools.buildLayeredImage.
+const IMAGE_NAME_ENV_VAR: &str = "imageName";
+const IMAGE_TAG_ENV_VAR: &str = "imageTag";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Subject {
+    pub name: String,
+    pub digest: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Statement {
+    #[serde(rename = "_type")]
+    pub type_: String,
+
+    pub subject: Vec<Subject>,
+
+    #[serde(rename = "predicateType")]
+    pub predicate_type: String,
+
+    pub predicate: serde_json::Value,
+}
+
+/// An entry of the manifest.json file of a docker archive.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+pub struct ImageManifest {
+    #[serde(rename = "Config")]
+    pub config: String,
+
+    #[serde(rename = "RepoTags")]
+    #[serde(default)]
+    pub repo_tags: Option<Vec<String>>,
+}
+
+// Returns the digest of the image manifest, given as sha256:<digest> or <digest>, in base16.
+// This is the digest of the manifest pushed to the registry, which cosign uses to find the
+// attestations of an image. It cannot be computed from the docker archive built by
+// dockerTools, since the manifest is only created when the image is pushed.
+pub fn parse_image_digest(image_digest: &str) -> Result<String, anyhow::Error> {
+    let digest = image_digest.strip_prefix("sha256:").unwrap_or(image_digest);
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow::format_err!(
+            "Invalid image digest {}, expected sha256:<digest>",
+            image_digest
+        ));
+    }
+    Ok(digest.to_lowercase())
+}
+
+// Returns the type of the in-toto predicate for the format, which must be
+// serialized as JSON.
+pub fn get_predicate_type(
+    format: &crate::format::Format,
+    serialization_format: &crate::format::SerializationFormat,
+) -> Option<&'static str> {
+    if serialization_format != &crate::format::SerializationFormat::JSON {
+        return None;
+    }
+    match format {
+        crate::format::Format::CycloneDX => Some(CYCLONE_DX_PREDICATE_TYPE),
+        crate::format::Format::SPDX => Some(SPDX_PREDICATE_TYPE),
+        _ => None,
+    }
+}
+
+// Writes one in-toto statement per output path of the root derivations in the
+// directory, and returns the paths of the statements. The image digest is the
+// digest of the manifest of the image built with dockerTools, once pushed.
+pub fn write_attestation_bundle(
+    bundle_dir: &str,
+    package_graph: &crate::nix::PackageGraph,
+    backend: &dyn crate::backend::NixBackend,
+    predicate_type: &str,
+    sbom_dump: &str,
+    image_digest: Option<&str>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let predicate: serde_json::Value = serde_json::from_str(sbom_dump)?;
+    let mut out_paths: BTreeMap<String, &crate::nix::PackageNode> = BTreeMap::default();
+    for root_node_path in &package_graph.root_nodes {
+        if let Some(package_node) = package_graph.nodes.get(root_node_path) {
+            for output in package_node.main_derivation.outputs.values() {
+                out_paths.insert(output.path.to_string(), package_node);
+            }
+        }
+    }
+    let local_out_paths: Vec<String> = out_paths
+        .keys()
+        .filter(|p| backend.is_in_store(p))
+        .cloned()
+        .collect();
+    let nar_hashes = get_nar_hashes(&local_out_paths, backend)?;
+    let image_count = out_paths
+        .values()
+        .filter(|n| n.main_derivation.env.contains_key(IMAGE_NAME_ENV_VAR))
+        .count();
+    if image_digest.is_some() && image_count > 1 {
+        return Err(anyhow::format_err!(
+            "The image digest can only be used when a single image is built, found {} images",
+            image_count
+        ));
+    }
+
+    std::fs::create_dir_all(bundle_dir)?;
+    let mut response: Vec<String> = vec![];
+    for (out_path, package_node) in out_paths {
+        let mut digest: BTreeMap<String, String> = BTreeMap::default();
+        if let Some(nar_hash) = nar_hashes.get(&out_path) {
+            digest.insert(NAR_DIGEST_ALGORITHM.to_string(), nar_hash.to_string());
+        }
+        let mut subject = vec![Subject {
+            name: out_path.to_string(),
+            digest,
+        }];
+        if let Some(image_name) = package_node.main_derivation.env.get(IMAGE_NAME_ENV_VAR) {
+            match image_digest {
+                Some(digest) => subject.push(get_image_subject(
+                    image_name,
+                    &package_node.main_derivation,
+                    &out_path,
+                    digest,
+                )),
+                None => log::warn!(
+                    "Not adding image {} as a subject of the attestation, since the digest of its manifest is not known",
+                    image_name
+                ),
+            }
+        }
+        let statement = Statement {
+            type_: STATEMENT_TYPE.to_string(),
+            subject,
+            predicate_type: predicate_type.to_string(),
+            predicate: predicate.clone(),
+        };
+        let statement_path = std::path::Path::new(bundle_dir)
+            .join(format!(
+                "{}.{}",
+                crate::nix::get_store_path_name(&out_path),
+                STATEMENT_FILE_EXTENSION
+            ))
+            .display()
+            .to_string();
+        crate::utils::write_file_atomically(&statement_path, &serde_json::to_string_pretty(&statement)?)?;
+        response.push(statement_path);
+    }
+    Ok(response)
+}
+
+// Returns the NAR hashes of the paths in base16, indexed by path.
+fn get_nar_hashes(
+    paths: &[String],
+    backend: &dyn crate::backend::NixBackend,
+) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let mut response: BTreeMap<String, String> = BTreeMap::default();
+    if paths.is_empty() {
+        return Ok(response);
+    }
+    for (path, path_info) in backend.get_path_infos(paths, false)? {
+        let nar_hash = match path_info.nar_hash {
+            Some(h) => crate::fetcher::OutputHash {
+                algorithm: "sha256".to_string(),
+                mode: "recursive".to_string(),
+                value: h,
+            },
+            None => continue,
+        };
+        if let Some(digest) = nar_hash.get_sha256_hex() {
+            response.insert(path, digest);
+        }
+    }
+    Ok(response)
+}
+
+// When the output is an image built with dockerTools, the image is also a subject
+// of the statement, with the digest of its manifest. The name of the image is read
+// from the docker archive when it is in the store.
+fn get_image_subject(
+    image_name: &str,
+    derivation: &crate::nix::Derivation,
+    out_path: &str,
+    image_digest: &str,
+) -> Subject {
+    let repo_tags = match std::path::Path::new(out_path).is_file() {
+        true => match read_image_manifest(out_path) {
+            Ok(m) => m.repo_tags.unwrap_or_default(),
+            Err(e) => {
+                log::warn!("Could not read the manifest of image {}: {}", out_path, e);
+                vec![]
+            }
+        },
+        false => vec![],
+    };
+    Subject {
+        name: get_image_name(image_name, derivation.env.get(IMAGE_TAG_ENV_VAR), &repo_tags),
+        digest: BTreeMap::from([(SHA256_DIGEST_ALGORITHM.to_string(), image_digest.to_string())]),
+    }
+}
+
+fn get_image_name(image_name: &str, image_tag: Option<&String>, repo_tags: &[String]) -> String {
+    match (repo_tags.first(), image_tag) {
+        (Some(repo_tag), _) => repo_tag.to_string(),
+        (None, Some(image_tag)) => format!("{}:{}", image_name, image_tag),
+        (None, None) => image_name.to_string(),
+    }
+}
+
+// Reads the manifest.json file of a docker archive, which can be compressed.
+fn read_image_manifest(image_path: &str) -> Result<ImageManifest, anyhow::Error> {
+    let output = std::process::Command::new("tar")
+        .arg("-xOf")
+        .arg(image_path)
+        .arg("manifest.json")
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not extract manifest.json: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let image_manifests: Vec<ImageManifest> = serde_json::from_slice(&output.stdout)?;
+    image_manifests
+        .into_iter()
+        .next()
+        .ok_or(anyhow::format_err!("The manifest.json file has no image"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_image_digest() {
+        assert_eq!(
+            parse_image_digest("sha256:2BD3A9C1C36A2C1B5BC42C8EE8CA18CB8BB6A5BA8BD2F5A3A4CEC4BD2C64FF0B")
+                .unwrap(),
+            "2bd3a9c1c36a2c1b5bc42c8ee8ca18cb8bb6a5ba8bd2f5a3a4cec4bd2c64ff0b"
+        );
+        assert_eq!(
+            parse_image_digest("2bd3a9c1c36a2c1b5bc42c8ee8ca18cb8bb6a5ba8bd2f5a3a4cec4bd2c64ff0b").unwrap(),
+            "2bd3a9c1c36a2c1b5bc42c8ee8ca18cb8bb6a5ba8bd2f5a3a4cec4bd2c64ff0b"
+        );
+        assert!(parse_image_digest("sha512:2bd3a9c1").is_err());
+        assert!(parse_image_digest("").is_err());
+    }
+
+    #[test]
+    pub fn test_get_image_name() {
+        let image_manifests: Vec<ImageManifest> = serde_json::from_str(
+            r###"
+            [
+              {
+                "Config": "2bd3a9c1c36a2c1b5bc42c8ee8ca18cb8bb6a5ba8bd2f5a3a4cec4bd2c64ff0b.json",
+                "RepoTags": ["hello:latest"],
+                "Layers": ["a/layer.tar"]
+              }
+            ]
+            "###,
+        )
+        .unwrap();
+        let repo_tags = image_manifests[0].repo_tags.clone().unwrap_or_default();
+        let image_tag = "2.12.1".to_string();
+        assert_eq!(
+            get_image_name("hello", Some(&image_tag), &repo_tags),
+            "hello:latest"
+        );
+        assert_eq!(get_image_name("hello", Some(&image_tag), &[]), "hello:2.12.1");
+        assert_eq!(get_image_name("hello", None, &[]), "hello");
+    }
+}
@@ -0,0 +1,516 @@
+// The interactions with Nix needed to build the package graph: evaluating the
+// derivations, querying the store and evaluating the package metadata. The
+// pipeline only uses the NixBackend trait, so that it can run against the nix
+// CLI, the Nix daemon, or a mock when Nix is not installed, for example in tests.
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::flake::FlakeMetadata;
+use crate::nix::{Derivations, Package, PackageMeta, PathInfo};
+
+pub trait NixBackend: Send + Sync {
+    /// Evaluates an installable, e.g. nixpkgs#hello or a store path, and returns
+    /// its derivation and the derivations of all its dependencies.
+    fn get_derivations(&self, installable: &str) -> Result<Derivations, anyhow::Error>;
+
+    /// Same as get_derivations, for .drv paths present in the store.
+    fn get_derivations_from_paths(&self, derivation_paths: &[String]) -> Result<Derivations, anyhow::Error>;
+
+    /// Returns the path info of the store paths, and of their closure if recursive is set,
+    /// indexed by store path. The paths not in the store are skipped.
+    fn get_path_infos(
+        &self,
+        paths: &[String],
+        recursive: bool,
+    ) -> Result<BTreeMap<String, PathInfo>, anyhow::Error>;
+
+    /// Same as get_path_infos, with the size of the closure of the paths.
+    fn get_output_path_infos(&self, paths: &[String]) -> Result<BTreeMap<String, PathInfo>, anyhow::Error>;
+
+    /// Returns the metadata of the whole package set, in the format of nix-env -qa --meta --json.
+    fn get_nix_env_metadata(&self, nixpkgs_path: Option<&str>) -> Result<Vec<u8>, anyhow::Error>;
+
+    /// Evaluates the meta attribute of a single installable, e.g. nixpkgs#hello.
+    fn get_package_meta(&self, installable: &str) -> Result<PackageMeta, anyhow::Error>;
+
+    /// Returns the output of nix --version, if Nix is installed.
+    fn get_nix_version(&self) -> Option<String>;
+
+    /// Returns the version of the nixpkgs of the channels, e.g. 24.05.20240601.abcdef0
+    fn get_nixpkgs_version(&self) -> Result<String, anyhow::Error>;
+
+    /// Returns the packages of nixpkgs, in the format of nix search --json.
+    fn get_nix_search_metadata(&self) -> Result<Vec<u8>, anyhow::Error>;
+
+    /// Evaluates the metadata of the packages of a nixpkgs flake, e.g. nixpkgs, in the format
+    /// of nix-env -qa --meta --json. The packages that do not exist are skipped.
+    fn get_nix_eval_metadata(
+        &self,
+        nixpkgs_ref: &str,
+        attribute_names: &[String],
+        meta_fields: Option<&[String]>,
+    ) -> Result<Vec<u8>, anyhow::Error>;
+
+    /// Returns the metadata of a flake, with its lock file, e.g. for github:NixOS/nixpkgs.
+    fn get_flake_metadata(&self, flake_ref: &str) -> Result<FlakeMetadata, anyhow::Error>;
+
+    /// Returns a reference to every package exposed by the flake for the current system.
+    fn get_flake_package_refs(&self, flake_ref: &str) -> Result<Vec<String>, anyhow::Error>;
+
+    /// Evaluates the metadata of a package of a flake, e.g. .#hello, and returns it with
+    /// the .drv path of the package.
+    fn get_flake_package_metadata(
+        &self,
+        nix_ref: &str,
+        meta_fields: Option<&[String]>,
+    ) -> Result<(String, Package), anyhow::Error>;
+
+    /// Whether the store path is present in the local store.
+    fn is_in_store(&self, path: &str) -> bool {
+        std::path::Path::new(path).exists()
+    }
+}
+
+/// Spawns the nix commands.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct CliBackend {}
+
+impl NixBackend for CliBackend {
+    fn get_derivations(&self, installable: &str) -> Result<Derivations, anyhow::Error> {
+        crate::nix::Derivation::get_derivations(installable)
+    }
+
+    fn get_derivations_from_paths(&self, derivation_paths: &[String]) -> Result<Derivations, anyhow::Error> {
+        crate::nix::Derivation::get_derivations_from_paths(derivation_paths)
+    }
+
+    fn get_path_infos(
+        &self,
+        paths: &[String],
+        recursive: bool,
+    ) -> Result<BTreeMap<String, PathInfo>, anyhow::Error> {
+        let mut response: BTreeMap<String, PathInfo> = BTreeMap::default();
+        for batch in paths.chunks(crate::nix::DERIVATION_PATHS_BATCH_SIZE) {
+            response.extend(crate::nix::get_path_infos(batch, recursive)?);
+        }
+        Ok(response)
+    }
+
+    fn get_output_path_infos(&self, paths: &[String]) -> Result<BTreeMap<String, PathInfo>, anyhow::Error> {
+        crate::nix::get_output_path_infos(paths)
+    }
+
+    fn get_nix_env_metadata(&self, nixpkgs_path: Option<&str>) -> Result<Vec<u8>, anyhow::Error> {
+        crate::nix::get_nix_env_metadata(nixpkgs_path)
+    }
+
+    fn get_package_meta(&self, installable: &str) -> Result<PackageMeta, anyhow::Error> {
+        crate::nix::get_package_meta(installable)
+    }
+
+    fn get_nix_version(&self) -> Option<String> {
+        crate::nix::get_nix_version()
+    }
+
+    fn get_nixpkgs_version(&self) -> Result<String, anyhow::Error> {
+        crate::nix::get_nixpkgs_version()
+    }
+
+    fn get_nix_search_metadata(&self) -> Result<Vec<u8>, anyhow::Error> {
+        crate::nix::get_nix_search_metadata()
+    }
+
+    fn get_nix_eval_metadata(
+        &self,
+        nixpkgs_ref: &str,
+        attribute_names: &[String],
+        meta_fields: Option<&[String]>,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        crate::nix::get_nix_eval_metadata(nixpkgs_ref, attribute_names, meta_fields)
+    }
+
+    fn get_flake_metadata(&self, flake_ref: &str) -> Result<FlakeMetadata, anyhow::Error> {
+        crate::flake::get_flake_metadata(flake_ref)
+    }
+
+    fn get_flake_package_refs(&self, flake_ref: &str) -> Result<Vec<String>, anyhow::Error> {
+        crate::flake::get_package_refs(flake_ref)
+    }
+
+    fn get_flake_package_metadata(
+        &self,
+        nix_ref: &str,
+        meta_fields: Option<&[String]>,
+    ) -> Result<(String, Package), anyhow::Error> {
+        crate::flake::get_package_metadata(nix_ref, meta_fields)
+    }
+}
+
+/// Queries the store through the socket of the Nix daemon. The daemon cannot evaluate
+/// Nix expressions, so the derivations and the package metadata still come from the CLI.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct DaemonBackend {
+    pub socket_path: String,
+}
+
+impl NixBackend for DaemonBackend {
+    fn get_derivations(&self, installable: &str) -> Result<Derivations, anyhow::Error> {
+        CliBackend::default().get_derivations(installable)
+    }
+
+    fn get_derivations_from_paths(&self, derivation_paths: &[String]) -> Result<Derivations, anyhow::Error> {
+        CliBackend::default().get_derivations_from_paths(derivation_paths)
+    }
+
+    fn get_path_infos(
+        &self,
+        paths: &[String],
+        recursive: bool,
+    ) -> Result<BTreeMap<String, PathInfo>, anyhow::Error> {
+        let mut daemon_client = crate::daemon::DaemonClient::connect(&self.socket_path)?;
+        let mut response: BTreeMap<String, PathInfo> = BTreeMap::default();
+        let mut path_queue: VecDeque<String> = paths.iter().cloned().collect();
+        while let Some(path) = path_queue.pop_front() {
+            if response.contains_key(&path) {
+                continue;
+            }
+            if let Some(path_info) = daemon_client.query_path_info(&path)? {
+                if recursive {
+                    path_queue.extend(path_info.references.iter().cloned());
+                }
+                response.insert(path.to_string(), path_info.into_path_info(&path, None));
+            }
+        }
+        Ok(response)
+    }
+
+    fn get_output_path_infos(&self, paths: &[String]) -> Result<BTreeMap<String, PathInfo>, anyhow::Error> {
+        crate::daemon::DaemonClient::connect(&self.socket_path)?.get_output_path_infos(paths)
+    }
+
+    fn get_nix_env_metadata(&self, nixpkgs_path: Option<&str>) -> Result<Vec<u8>, anyhow::Error> {
+        CliBackend::default().get_nix_env_metadata(nixpkgs_path)
+    }
+
+    fn get_package_meta(&self, installable: &str) -> Result<PackageMeta, anyhow::Error> {
+        CliBackend::default().get_package_meta(installable)
+    }
+
+    fn get_nix_version(&self) -> Option<String> {
+        CliBackend::default().get_nix_version()
+    }
+
+    fn get_nixpkgs_version(&self) -> Result<String, anyhow::Error> {
+        CliBackend::default().get_nixpkgs_version()
+    }
+
+    fn get_nix_search_metadata(&self) -> Result<Vec<u8>, anyhow::Error> {
+        CliBackend::default().get_nix_search_metadata()
+    }
+
+    fn get_nix_eval_metadata(
+        &self,
+        nixpkgs_ref: &str,
+        attribute_names: &[String],
+        meta_fields: Option<&[String]>,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        CliBackend::default().get_nix_eval_metadata(nixpkgs_ref, attribute_names, meta_fields)
+    }
+
+    fn get_flake_metadata(&self, flake_ref: &str) -> Result<FlakeMetadata, anyhow::Error> {
+        CliBackend::default().get_flake_metadata(flake_ref)
+    }
+
+    fn get_flake_package_refs(&self, flake_ref: &str) -> Result<Vec<String>, anyhow::Error> {
+        CliBackend::default().get_flake_package_refs(flake_ref)
+    }
+
+    fn get_flake_package_metadata(
+        &self,
+        nix_ref: &str,
+        meta_fields: Option<&[String]>,
+    ) -> Result<(String, Package), anyhow::Error> {
+        CliBackend::default().get_flake_package_metadata(nix_ref, meta_fields)
+    }
+}
+
+/// Serves the derivations, the path infos, the package metadata and the flake metadata
+/// given to it, to run the pipeline without a Nix installation.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct MockBackend {
+    /// All the derivations known to the backend, indexed by .drv path.
+    pub derivations: Derivations,
+
+    /// The .drv paths that the installables evaluate to.
+    pub installables: BTreeMap<String, Vec<String>>,
+
+    /// The paths of the store, with their path info.
+    pub path_infos: BTreeMap<String, PathInfo>,
+
+    pub nix_env_metadata: Option<Vec<u8>>,
+
+    /// The meta attribute of the installables, e.g. nixpkgs#hello.
+    pub package_metas: BTreeMap<String, PackageMeta>,
+
+    pub nix_version: Option<String>,
+
+    pub nixpkgs_version: Option<String>,
+
+    pub nix_search_metadata: Option<Vec<u8>>,
+
+    /// The metadata of the packages of the nixpkgs flakes, in the format of nix-env,
+    /// indexed by flake reference.
+    pub nix_eval_metadata: BTreeMap<String, Vec<u8>>,
+
+    /// The metadata of the flakes, indexed by flake reference.
+    pub flake_metadatas: BTreeMap<String, FlakeMetadata>,
+
+    /// The references of the packages of the flakes, indexed by flake reference.
+    pub flake_package_refs: BTreeMap<String, Vec<String>>,
+
+    /// The .drv path and the metadata of the packages of the flakes, e.g. .#hello
+    pub flake_package_metadatas: BTreeMap<String, (String, Package)>,
+}
+
+impl NixBackend for MockBackend {
+    fn get_derivations(&self, installable: &str) -> Result<Derivations, anyhow::Error> {
+        match self.installables.get(installable) {
+            Some(derivation_paths) => self.get_derivations_from_paths(derivation_paths),
+            None => self.get_derivations_from_paths(&[installable.to_string()]),
+        }
+    }
+
+    fn get_derivations_from_paths(&self, derivation_paths: &[String]) -> Result<Derivations, anyhow::Error> {
+        let mut response = Derivations::default();
+        let mut path_queue: VecDeque<String> = derivation_paths.iter().cloned().collect();
+        while let Some(derivation_path) = path_queue.pop_front() {
+            if response.contains_key(&derivation_path) {
+                continue;
+            }
+            let derivation = self.derivations.get(&derivation_path).ok_or(anyhow::format_err!(
+                "Could not get derivations from {}",
+                derivation_path
+            ))?;
+            path_queue.extend(derivation.input_derivations.keys().cloned());
+            response.insert(derivation_path, derivation.clone());
+        }
+        Ok(response)
+    }
+
+    fn get_path_infos(
+        &self,
+        paths: &[String],
+        recursive: bool,
+    ) -> Result<BTreeMap<String, PathInfo>, anyhow::Error> {
+        let mut response: BTreeMap<String, PathInfo> = BTreeMap::default();
+        let mut path_queue: VecDeque<String> = paths.iter().cloned().collect();
+        while let Some(path) = path_queue.pop_front() {
+            if response.contains_key(&path) {
+                continue;
+            }
+            if let Some(path_info) = self.path_infos.get(&path) {
+                if recursive {
+                    path_queue.extend(path_info.references.iter().cloned());
+                }
+                response.insert(path, path_info.clone());
+            }
+        }
+        Ok(response)
+    }
+
+    fn get_output_path_infos(&self, paths: &[String]) -> Result<BTreeMap<String, PathInfo>, anyhow::Error> {
+        self.get_path_infos(paths, false)
+    }
+
+    fn get_nix_env_metadata(&self, _nixpkgs_path: Option<&str>) -> Result<Vec<u8>, anyhow::Error> {
+        self.nix_env_metadata
+            .clone()
+            .ok_or(anyhow::format_err!("No package metadata in the mock backend"))
+    }
+
+    fn get_package_meta(&self, installable: &str) -> Result<PackageMeta, anyhow::Error> {
+        self.package_metas
+            .get(installable)
+            .cloned()
+            .ok_or(anyhow::format_err!(
+                "No meta for {} in the mock backend",
+                installable
+            ))
+    }
+
+    fn get_nix_version(&self) -> Option<String> {
+        self.nix_version.clone()
+    }
+
+    fn get_nixpkgs_version(&self) -> Result<String, anyhow::Error> {
+        self.nixpkgs_version
+            .clone()
+            .ok_or(anyhow::format_err!("No nixpkgs version in the mock backend"))
+    }
+
+    fn get_nix_search_metadata(&self) -> Result<Vec<u8>, anyhow::Error> {
+        self.nix_search_metadata
+            .clone()
+            .ok_or(anyhow::format_err!("No nix search results in the mock backend"))
+    }
+
+    fn get_nix_eval_metadata(
+        &self,
+        nixpkgs_ref: &str,
+        attribute_names: &[String],
+        _meta_fields: Option<&[String]>,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let content = self
+            .nix_eval_metadata
+            .get(nixpkgs_ref)
+            .ok_or(anyhow::format_err!(
+                "No package metadata for {} in the mock backend",
+                nixpkgs_ref
+            ))?;
+        let mut packages: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(content)?;
+        packages.retain(|attribute_name, _| attribute_names.contains(attribute_name));
+        Ok(serde_json::to_vec(&packages)?)
+    }
+
+    fn get_flake_metadata(&self, flake_ref: &str) -> Result<FlakeMetadata, anyhow::Error> {
+        self.flake_metadatas
+            .get(flake_ref)
+            .cloned()
+            .ok_or(anyhow::format_err!(
+                "No flake metadata for {} in the mock backend",
+                flake_ref
+            ))
+    }
+
+    fn get_flake_package_refs(&self, flake_ref: &str) -> Result<Vec<String>, anyhow::Error> {
+        self.flake_package_refs
+            .get(flake_ref)
+            .cloned()
+            .ok_or(anyhow::format_err!(
+                "No packages for flake {} in the mock backend",
+                flake_ref
+            ))
+    }
+
+    fn get_flake_package_metadata(
+        &self,
+        nix_ref: &str,
+        _meta_fields: Option<&[String]>,
+    ) -> Result<(String, Package), anyhow::Error> {
+        self.flake_package_metadatas
+            .get(nix_ref)
+            .cloned()
+            .ok_or(anyhow::format_err!(
+                "No package metadata for {} in the mock backend",
+                nix_ref
+            ))
+    }
+
+    fn is_in_store(&self, path: &str) -> bool {
+        self.path_infos.contains_key(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_runtime_closure_with_mock_backend() {
+        let derivations: Derivations = serde_json::from_str(
+            r###"
+            {
+              "/nix/store/a-hello-2.12.1.drv": {
+                "args": [],
+                "builder": "/bin/sh",
+                "env": { "name": "hello-2.12.1" },
+                "inputDrvs": {
+                  "/nix/store/b-glibc-2.39.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                  "/nix/store/c-gcc-13.2.0.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+                },
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/a-hello-2.12.1" } },
+                "system": "x86_64-linux"
+              },
+              "/nix/store/b-glibc-2.39.drv": {
+                "args": [],
+                "builder": "/bin/sh",
+                "env": { "name": "glibc-2.39" },
+                "inputDrvs": {},
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/b-glibc-2.39" } },
+                "system": "x86_64-linux"
+              },
+              "/nix/store/c-gcc-13.2.0.drv": {
+                "args": [],
+                "builder": "/bin/sh",
+                "env": { "name": "gcc-13.2.0" },
+                "inputDrvs": {},
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/c-gcc-13.2.0" } },
+                "system": "x86_64-linux"
+              }
+            }
+            "###,
+        )
+        .unwrap();
+        let path_infos: crate::nix::PathInfos = serde_json::from_str(
+            r###"
+            {
+              "/nix/store/a-hello-2.12.1": {
+                "narHash": "sha256-mdFmFJl5cw3nuDZsfWZO4Svv0mX5bGcaA8gdDsbZZDw=",
+                "narSize": 120,
+                "closureSize": 200,
+                "references": ["/nix/store/b-glibc-2.39"],
+                "ultimate": true
+              },
+              "/nix/store/b-glibc-2.39": {
+                "narHash": "sha256-mdFmFJl5cw3nuDZsfWZO4Svv0mX5bGcaA8gdDsbZZDw=",
+                "narSize": 80,
+                "closureSize": 80,
+                "references": [],
+                "signatures": ["cache.nixos.org-1:abc"]
+              }
+            }
+            "###,
+        )
+        .unwrap();
+        let backend = MockBackend {
+            derivations,
+            installables: BTreeMap::from([(
+                "nixpkgs#hello".to_string(),
+                vec!["/nix/store/a-hello-2.12.1.drv".to_string()],
+            )]),
+            path_infos: path_infos.into_map(),
+            ..Default::default()
+        };
+
+        let derivations =
+            crate::nix::Derivation::get_derivations_from_refs(&["nixpkgs#hello".to_string()], 1, &backend)
+                .unwrap();
+        assert_eq!(derivations.len(), 3);
+        let mut package_graph = crate::nix::get_package_graph(&derivations);
+        package_graph.nodes_next = package_graph.nodes.clone();
+
+        package_graph.limit_to_runtime_closure(&backend).unwrap();
+        assert_eq!(
+            package_graph.nodes_next.keys().collect::<Vec<_>>(),
+            vec!["/nix/store/a-hello-2.12.1.drv", "/nix/store/b-glibc-2.39.drv"]
+        );
+        package_graph.populate_build_provenance(&backend).unwrap();
+        assert_eq!(
+            package_graph
+                .build_provenance
+                .get("/nix/store/a-hello-2.12.1.drv"),
+            Some(&crate::nix::BUILT_PROVENANCE.to_string())
+        );
+        assert_eq!(
+            package_graph.build_provenance.get("/nix/store/b-glibc-2.39.drv"),
+            Some(&crate::nix::SUBSTITUTED_PROVENANCE.to_string())
+        );
+    }
+}
@@ -0,0 +1,264 @@
+// Job files for `nix2sbom batch`, to generate several SBOMs in one run. Each job is
+// converted to the arguments of a nix2sbom invocation, and the jobs share the package
+// metadata loaded by the previous jobs. Both YAML and JSON are accepted:
+//
+//   jobs:
+//     - name: host-a
+//       refs: [".#nixosConfigurations.hostA.config.system.build.toplevel"]
+//       formats: [cdx, spdx]
+//       output_dir: sboms/host-a
+//       exclude_licenses: [AGPL-3.0-only]
+//       args: ["--meta", "--runtime-only"]
+use std::fs;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct BatchFile {
+    pub jobs: Vec<BatchJob>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct BatchJob {
+    /// Name of the job in the summary. Defaults to the references of the job.
+    pub name: Option<String>,
+
+    /// The nix references, as given on the command line.
+    #[serde(default)]
+    pub refs: Vec<String>,
+
+    /// The output formats, e.g. cdx or spdx.
+    #[serde(default)]
+    pub formats: Vec<String>,
+
+    pub output: Option<String>,
+
+    pub output_dir: Option<String>,
+
+    /// Path of the nix2sbom configuration file of the job.
+    pub config: Option<String>,
+
+    #[serde(default)]
+    pub only_licenses: Vec<String>,
+
+    #[serde(default)]
+    pub exclude_licenses: Vec<String>,
+
+    /// Regular expressions of the components to exclude, as with --exclude.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    pub ignore_file: Option<String>,
+
+    /// Fail the job when the metadata or some derivations are missing, as with --strict.
+    #[serde(default)]
+    pub strict: bool,
+
+    /// Any other arguments of the job, e.g. ["--meta", "--runtime-only"].
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl BatchFile {
+    pub fn from_file(path: &str) -> Result<BatchFile, anyhow::Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow::format_err!("Could not read the job file {}: {}", path, e))?;
+        BatchFile::parse(&content).map_err(|e| anyhow::format_err!("Invalid job file {}: {}", path, e))
+    }
+
+    pub fn parse(content: &str) -> Result<BatchFile, anyhow::Error> {
+        let batch_file: BatchFile = serde_yaml::from_str(content)?;
+        for (job_index, job) in batch_file.jobs.iter().enumerate() {
+            if job.refs.is_empty() && job.args.is_empty() {
+                return Err(anyhow::format_err!("Job {} has no nix reference", job_index + 1));
+            }
+            if job.formats.len() > 1 && job.output_dir.is_none() {
+                return Err(anyhow::format_err!(
+                    "Job {} requests several formats without an output_dir",
+                    job_index + 1
+                ));
+            }
+        }
+        Ok(batch_file)
+    }
+}
+
+impl BatchJob {
+    pub fn get_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.to_string(),
+            None => self.refs.join(" "),
+        }
+    }
+
+    // The arguments of the job, without the name of the program.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut response: Vec<String> = vec![];
+        if !self.formats.is_empty() {
+            response.push(format!("--format={}", self.formats.join(",")));
+        }
+        for (flag, value) in [
+            ("--output", &self.output),
+            ("--output-dir", &self.output_dir),
+            ("--config", &self.config),
+            ("--ignore-file", &self.ignore_file),
+        ] {
+            if let Some(value) = value {
+                response.push(format!("{}={}", flag, value));
+            }
+        }
+        for (flag, values) in [
+            ("--only-licenses", &self.only_licenses),
+            ("--exclude-licenses", &self.exclude_licenses),
+            ("--exclude", &self.exclude),
+        ] {
+            for value in values {
+                response.push(format!("{}={}", flag, value));
+            }
+        }
+        if self.strict {
+            response.push("--strict".to_string());
+        }
+        response.extend(self.args.iter().cloned());
+        // The references are positional, and could otherwise be taken as the value of a flag.
+        if !self.refs.is_empty() {
+            response.push("--".to_string());
+            response.extend(self.refs.iter().cloned());
+        }
+        response
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct JobResult {
+    pub name: String,
+    pub exit_code: u8,
+    pub duration: Duration,
+
+    /// The error of the job, when it could not complete.
+    pub error: Option<String>,
+}
+
+// Formats the results of the jobs as a table, one line per job.
+pub fn format_summary(results: &[JobResult]) -> String {
+    let name_width = results
+        .iter()
+        .map(|r| r.name.len())
+        .chain(std::iter::once("JOB".len()))
+        .max()
+        .unwrap_or_default();
+    let mut lines: Vec<String> = vec![format!(
+        "{:<name_width$}  {:<6}  {:>9}",
+        "JOB", "STATUS", "DURATION"
+    )];
+    for result in results {
+        let status = if result.exit_code == crate::consts::EXIT_SUCCESS {
+            "ok".to_string()
+        } else {
+            format!("exit {}", result.exit_code)
+        };
+        let mut line = format!(
+            "{:<name_width$}  {:<6}  {:>8.1}s",
+            result.name,
+            status,
+            result.duration.as_secs_f64()
+        );
+        if let Some(error) = &result.error {
+            line.push_str(&format!("  {}", error));
+        }
+        lines.push(line);
+    }
+    let failed_jobs = results
+        .iter()
+        .filter(|r| r.exit_code != crate::consts::EXIT_SUCCESS)
+        .count();
+    lines.push(format!(
+        "{} jobs, {} succeeded, {} failed",
+        results.len(),
+        results.len() - failed_jobs,
+        failed_jobs
+    ));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_batch_file() {
+        let batch_file = BatchFile::parse(
+            r###"
+jobs:
+  - name: host-a
+    refs: [".#hostA"]
+    formats: [cdx, spdx]
+    output_dir: sboms/host-a
+    exclude_licenses: [AGPL-3.0-only]
+    args: ["--meta"]
+  - refs: [".#container"]
+    strict: true
+            "###,
+        )
+        .unwrap();
+        assert_eq!(batch_file.jobs.len(), 2);
+        assert_eq!(
+            batch_file.jobs[0].to_args(),
+            vec![
+                "--format=cdx,spdx",
+                "--output-dir=sboms/host-a",
+                "--exclude-licenses=AGPL-3.0-only",
+                "--meta",
+                "--",
+                ".#hostA",
+            ]
+        );
+        assert_eq!(batch_file.jobs[1].get_name(), ".#container");
+        assert_eq!(
+            batch_file.jobs[1].to_args(),
+            vec!["--strict", "--", ".#container"]
+        );
+
+        assert!(BatchFile::parse("jobs:\n  - formats: [cdx, spdx]\n    refs: ['.#hostA']\n").is_err());
+        assert!(BatchFile::parse("jobs:\n  - name: empty\n").is_err());
+    }
+
+    #[test]
+    pub fn test_format_summary() {
+        let summary = format_summary(&[
+            JobResult {
+                name: "host-a".to_string(),
+                exit_code: crate::consts::EXIT_SUCCESS,
+                duration: Duration::from_millis(1500),
+                error: None,
+            },
+            JobResult {
+                name: "container".to_string(),
+                exit_code: 1,
+                duration: Duration::from_secs(2),
+                error: Some("Could not get derivations".to_string()),
+            },
+        ]);
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], "JOB        STATUS   DURATION");
+        assert_eq!(lines[1], "host-a     ok           1.5s");
+        assert_eq!(
+            lines[2],
+            "container  exit 1       2.0s  Could not get derivations"
+        );
+        assert_eq!(lines[3], "2 jobs, 1 succeeded, 1 failed");
+    }
+}
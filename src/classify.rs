@@ -0,0 +1,343 @@
+// Classification of the packages of a GNU/Linux userland into broad categories,
+// so that the SBOM can be grouped for human reviewers. The classification only
+// uses heuristics over the names and the outputs of the packages, and will get
+// some packages wrong. It must not be used for anything else than presentation.
+
+// Packages used to build other packages.
+const TOOLCHAIN_NAMES: &[&str] = &[
+    "autoconf",
+    "automake",
+    "binutils",
+    "bison",
+    "cargo",
+    "clang",
+    "cmake",
+    "flex",
+    "gcc",
+    "gnumake",
+    "go",
+    "libtool",
+    "linux-headers",
+    "lld",
+    "llvm",
+    "m4",
+    "meson",
+    "nasm",
+    "ninja",
+    "patchelf",
+    "pkg-config",
+    "rustc",
+    "stdenv",
+    "yasm",
+];
+
+const TOOLCHAIN_PREFIXES: &[&str] = &["gcc-", "clang-", "llvm-", "binutils-", "rustc-", "stdenv-"];
+
+// Compilers reported as the build toolchain of the graph, for the build provenance.
+const COMPILER_NAMES: &[&str] = &["gcc", "clang", "rustc", "go"];
+
+// Suffixes of the wrapped and unwrapped variants of the compilers, e.g. gcc-wrapper.
+const COMPILER_SUFFIXES: &[&str] = &["-wrapper", "-unwrapped"];
+
+const KERNEL_FIRMWARE_PREFIXES: &[&str] = &["linux-", "kernel-", "firmware-"];
+
+const KERNEL_FIRMWARE_KEYWORDS: &[&str] = &["firmware", "microcode"];
+
+const FONTS_DATA_NAMES: &[&str] = &[
+    "cacert",
+    "hwdata",
+    "iso-codes",
+    "mailcap",
+    "mime-types",
+    "shared-mime-info",
+    "tzdata",
+    "xkeyboard-config",
+];
+
+const FONTS_DATA_KEYWORDS: &[&str] = &["font", "-data", "-icons", "-theme", "locales", "dejavu", "noto-"];
+
+// Names of the kernel packages of nixpkgs, e.g. linuxPackages_hardened.kernel.
+const KERNEL_NAMES: &[&str] = &[
+    "linux",
+    "linux-hardened",
+    "linux-libre",
+    "linux-lqx",
+    "linux-rpi",
+    "linux-rt",
+    "linux-xanmod",
+    "linux-zen",
+];
+
+// Make variables pointing to the kernel build directory, which the out-of-tree
+// modules of nixpkgs pass to the kernel build system.
+const KERNEL_BUILD_VARIABLES: &[&str] = &["KERNELDIR=", "KERNEL_DIR=", "KDIR=", "KSRC=", "KERNELRELEASE="];
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum KernelComponent {
+    Kernel,
+    /// Out-of-tree kernel module, e.g. zfs-kernel or nvidia-x11.
+    Module,
+    Firmware,
+}
+
+impl KernelComponent {
+    pub fn get_name(&self) -> &str {
+        match self {
+            KernelComponent::Kernel => "kernel",
+            KernelComponent::Module => "kernel-module",
+            KernelComponent::Firmware => "firmware",
+        }
+    }
+
+    // See https://cyclonedx.org/docs/1.5/json/#components_items_type
+    pub fn get_cyclonedx_type(&self) -> &str {
+        match self {
+            KernelComponent::Kernel => "operating-system",
+            KernelComponent::Module => "device-driver",
+            KernelComponent::Firmware => "firmware",
+        }
+    }
+
+    // SPDX 2.3 has no purpose for the drivers.
+    pub fn get_spdx_purpose(&self) -> &str {
+        match self {
+            KernelComponent::Kernel => "OPERATING-SYSTEM",
+            KernelComponent::Module => "OTHER",
+            KernelComponent::Firmware => "FIRMWARE",
+        }
+    }
+}
+
+// Detects the kernel, the out-of-tree kernel modules and the firmware of a NixOS
+// system closure.
+pub fn get_kernel_component(package_node: &crate::nix::PackageNode) -> Option<KernelComponent> {
+    let name = package_node.name.clone().unwrap_or_default().to_lowercase();
+    if KERNEL_FIRMWARE_KEYWORDS.iter().any(|k| name.contains(k)) {
+        return Some(KernelComponent::Firmware);
+    }
+    if KERNEL_NAMES.contains(&name.as_str()) {
+        return Some(KernelComponent::Kernel);
+    }
+    let env = &package_node.main_derivation.env;
+    let make_flags = env.get("makeFlags").cloned().unwrap_or_default();
+    if name.ends_with("-kernel")
+        || env.contains_key("kernel")
+        || KERNEL_BUILD_VARIABLES.iter().any(|v| make_flags.contains(v))
+    {
+        return Some(KernelComponent::Module);
+    }
+    None
+}
+
+// Detects the compilers of the build graph, and returns the name of the compiler.
+pub fn get_compiler(package_node: &crate::nix::PackageNode) -> Option<&'static str> {
+    let name = package_node.name.clone().unwrap_or_default().to_lowercase();
+    let name = COMPILER_SUFFIXES
+        .iter()
+        .find_map(|s| name.strip_suffix(s))
+        .unwrap_or(&name);
+    COMPILER_NAMES.iter().find(|c| **c == name).copied()
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Eq)]
+#[derive(PartialOrd)]
+#[derive(Ord)]
+pub enum Category {
+    Toolchain,
+    Library,
+    Application,
+    KernelFirmware,
+    FontsData,
+}
+
+impl Category {
+    pub fn get_name(&self) -> &str {
+        match self {
+            Category::Toolchain => "toolchain",
+            Category::Library => "libraries",
+            Category::Application => "applications",
+            Category::KernelFirmware => "kernel-firmware",
+            Category::FontsData => "fonts-data",
+        }
+    }
+
+    pub fn get_description(&self) -> &str {
+        match self {
+            Category::Toolchain => "Compilers and tools used to build the other packages",
+            Category::Library => "Shared libraries",
+            Category::Application => "Programs and the packages that could not be classified",
+            Category::KernelFirmware => "Kernel, kernel modules and firmware",
+            Category::FontsData => "Fonts and data files",
+        }
+    }
+}
+
+pub fn classify(package_node: &crate::nix::PackageNode) -> Category {
+    let name = package_node.name.clone().unwrap_or_default().to_lowercase();
+
+    if TOOLCHAIN_NAMES.contains(&name.as_str()) || TOOLCHAIN_PREFIXES.iter().any(|p| name.starts_with(p)) {
+        return Category::Toolchain;
+    }
+    // The userland packages named after the kernel are not kernel packages.
+    if get_kernel_component(package_node).is_some()
+        || name != "linux-pam"
+            && (name == "linux"
+                || KERNEL_FIRMWARE_PREFIXES.iter().any(|p| name.starts_with(p))
+                || KERNEL_FIRMWARE_KEYWORDS.iter().any(|k| name.contains(k)))
+    {
+        return Category::KernelFirmware;
+    }
+    if FONTS_DATA_NAMES.contains(&name.as_str()) || FONTS_DATA_KEYWORDS.iter().any(|k| name.contains(k)) {
+        return Category::FontsData;
+    }
+
+    let outputs = &package_node.main_derivation.outputs;
+    if outputs.contains_key("bin") {
+        return Category::Application;
+    }
+    if name.starts_with("lib") || outputs.contains_key("lib") || outputs.contains_key("dev") {
+        return Category::Library;
+    }
+    Category::Application
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_package_node(name: &str, outputs: &[&str]) -> crate::nix::PackageNode {
+        let outputs = outputs
+            .iter()
+            .map(|o| format!(r#""{}": {{ "path": "/nix/store/a-{}-{}" }}"#, o, name, o))
+            .collect::<Vec<String>>();
+        let derivation: crate::nix::Derivation = serde_json::from_str(&format!(
+            r#"{{
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {{ "name": "{}" }},
+              "inputDrvs": {{}},
+              "inputSrcs": [],
+              "outputs": {{ {} }},
+              "system": "x86_64-linux"
+            }}"#,
+            name,
+            outputs.join(", ")
+        ))
+        .unwrap();
+        crate::nix::PackageNode {
+            id: format!("/nix/store/a-{}.drv", name),
+            url: None,
+            version: None,
+            name: Some(name.to_string()),
+            git_urls: std::collections::BTreeSet::default(),
+            main_derivation: derivation,
+            source_derivation: None,
+            group_id: None,
+            package: None,
+            patches: std::collections::BTreeSet::default(),
+            build_inputs: std::collections::BTreeSet::default(),
+            children: std::collections::BTreeSet::default(),
+            concluded_license: None,
+            owners: vec![],
+            closure_size: None,
+            nar_size: None,
+            ambiguous_packages: vec![],
+            missing_meta_reason: None,
+            incomplete: false,
+            substituted: false,
+            purl_type: None,
+            purl_qualifiers: std::collections::BTreeMap::default(),
+        }
+    }
+
+    #[test]
+    pub fn test_classify() {
+        assert_eq!(classify(&get_package_node("gcc", &["out"])), Category::Toolchain);
+        assert_eq!(
+            classify(&get_package_node("zlib", &["out", "dev"])),
+            Category::Library
+        );
+        assert_eq!(
+            classify(&get_package_node("libxml2", &["out"])),
+            Category::Library
+        );
+        assert_eq!(
+            classify(&get_package_node("curl", &["bin", "dev", "out"])),
+            Category::Application
+        );
+        assert_eq!(
+            classify(&get_package_node("linux-firmware", &["out"])),
+            Category::KernelFirmware
+        );
+        assert_eq!(
+            classify(&get_package_node("dejavu-fonts", &["out"])),
+            Category::FontsData
+        );
+        assert_eq!(
+            classify(&get_package_node("hello", &["out"])),
+            Category::Application
+        );
+    }
+
+    #[test]
+    pub fn test_get_kernel_component() {
+        assert_eq!(
+            get_kernel_component(&get_package_node("linux", &["out", "dev", "modules"])),
+            Some(KernelComponent::Kernel)
+        );
+        assert_eq!(
+            get_kernel_component(&get_package_node("linux-firmware", &["out"])),
+            Some(KernelComponent::Firmware)
+        );
+        assert_eq!(
+            get_kernel_component(&get_package_node("intel-microcode", &["out"])),
+            Some(KernelComponent::Firmware)
+        );
+        assert_eq!(
+            get_kernel_component(&get_package_node("zfs-kernel", &["out"])),
+            Some(KernelComponent::Module)
+        );
+
+        let mut package_node = get_package_node("v4l2loopback", &["out"]);
+        package_node.main_derivation.env.insert(
+            "makeFlags".to_string(),
+            "KERNELRELEASE=6.6.30 KERNEL_DIR=/nix/store/a-linux-6.6.30-dev/lib/modules/6.6.30/build"
+                .to_string(),
+        );
+        assert_eq!(get_kernel_component(&package_node), Some(KernelComponent::Module));
+        assert_eq!(classify(&package_node), Category::KernelFirmware);
+
+        assert_eq!(
+            get_kernel_component(&get_package_node("linux-pam", &["out"])),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_get_compiler() {
+        assert_eq!(get_compiler(&get_package_node("gcc", &["out"])), Some("gcc"));
+        assert_eq!(
+            get_compiler(&get_package_node("clang-wrapper", &["out"])),
+            Some("clang")
+        );
+        assert_eq!(
+            get_compiler(&get_package_node("rustc-unwrapped", &["out"])),
+            Some("rustc")
+        );
+        assert_eq!(get_compiler(&get_package_node("go", &["out"])), Some("go"));
+        assert_eq!(
+            get_compiler(&get_package_node("gcc-wrapper-wrapper", &["out"])),
+            None
+        );
+        assert_eq!(
+            get_compiler(&get_package_node("gobject-introspection", &["out"])),
+            None
+        );
+        assert_eq!(get_compiler(&get_package_node("cmake", &["out"])), None);
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Configuration file for nix2sbom. Both YAML and JSON are accepted.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct Config {
+    /// Rules applied, in order, to every URL before it is written to the SBOM.
+    #[serde(default)]
+    pub url_rewrites: Vec<UrlRewriteRule>,
+
+    /// Names of the query parameters to remove from the URLs. `*` removes all of them.
+    #[serde(default)]
+    pub strip_query_params: Vec<String>,
+
+    /// Licenses concluded after review, indexed by package name or purl. The
+    /// values must be SPDX license expressions.
+    #[serde(default)]
+    pub concluded_licenses: BTreeMap<String, String>,
+
+    /// Identities of the packages that cannot be identified automatically, indexed
+    /// by derivation name, e.g. openssl-3.0.13.
+    #[serde(default)]
+    pub identity_overrides: BTreeMap<String, crate::identify::IdentityOverride>,
+
+    /// Overrides for the metadata of the generated SBOMs.
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+
+    /// URLs of additional mirrors, indexed by mirror name, used to translate the
+    /// mirror://<name>/ URLs. They take precedence over the mirrors of nixpkgs.
+    #[serde(default)]
+    pub mirrors: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct MetadataConfig {
+    /// Fixed timestamp of the SBOM, in RFC 3339 format. Defaults to the time
+    /// of the generation.
+    pub timestamp: Option<String>,
+
+    /// Vendor of the tool recorded in the SBOM metadata. Defaults to louib.
+    pub tool_vendor: Option<String>,
+
+    /// Custom properties added to the SBOM metadata, indexed by name.
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+
+    /// Organization supplying the software described by the SBOM.
+    pub supplier: Option<MetadataSupplier>,
+
+    /// Licenses of the SBOM document itself, as SPDX license identifiers or names.
+    #[serde(default)]
+    pub licenses: Vec<String>,
+
+    /// Description of the component described by the SBOM. Overrides the description
+    /// of the root package.
+    pub description: Option<String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct MetadataSupplier {
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub url: Vec<String>,
+
+    #[serde(default)]
+    pub contact: Vec<MetadataContact>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct MetadataContact {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+impl MetadataConfig {
+    // Reads a metadata template shared across projects, for example the supplier of
+    // all the SBOMs of an organization. Both YAML and JSON are accepted.
+    pub fn from_template_file(path: &str) -> Result<MetadataConfig, anyhow::Error> {
+        let content = fs::read_to_string(path)?;
+        let template: MetadataConfig = serde_yaml::from_str(&content)
+            .map_err(|e| anyhow::format_err!("Could not parse metadata template {}: {}", path, e))?;
+        template.validate()?;
+        Ok(template)
+    }
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(timestamp) = &self.timestamp {
+            chrono::DateTime::parse_from_rfc3339(timestamp)
+                .map_err(|e| anyhow::format_err!("Invalid metadata timestamp {}: {}", timestamp, e))?;
+        }
+        Ok(())
+    }
+
+    // The fields set in the template override the fields of the configuration, and
+    // the properties are added to the properties of the configuration.
+    pub fn merge(&mut self, template: MetadataConfig) {
+        if template.timestamp.is_some() {
+            self.timestamp = template.timestamp;
+        }
+        if template.tool_vendor.is_some() {
+            self.tool_vendor = template.tool_vendor;
+        }
+        self.properties.extend(template.properties);
+        if template.supplier.is_some() {
+            self.supplier = template.supplier;
+        }
+        if !template.licenses.is_empty() {
+            self.licenses = template.licenses;
+        }
+        if template.description.is_some() {
+            self.description = template.description;
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct UrlRewriteRule {
+    /// Regular expression matched against the URL.
+    pub pattern: String,
+
+    /// Replacement for the matched part of the URL. Capture groups can be
+    /// referenced using `$1`, `$2`, etc.
+    #[serde(default)]
+    pub replacement: String,
+}
+
+impl Config {
+    pub fn from_file(path: &str) -> Result<Config, anyhow::Error> {
+        let content = fs::read_to_string(path)?;
+        // JSON being a subset of YAML, we can use the YAML parser for both.
+        let config: Config = serde_yaml::from_str(&content)
+            .map_err(|e| anyhow::format_err!("Could not parse config file {}: {}", path, e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        self.metadata.validate()
+    }
+
+    pub fn get_url_rewriter(&self) -> Result<UrlRewriter, anyhow::Error> {
+        let mut rules: Vec<(Regex, String)> = vec![];
+        for rule in &self.url_rewrites {
+            let regex = Regex::new(&rule.pattern)
+                .map_err(|e| anyhow::format_err!("Invalid URL rewrite pattern {}: {}", &rule.pattern, e))?;
+            rules.push((regex, rule.replacement.to_string()));
+        }
+        Ok(UrlRewriter {
+            rules,
+            strip_query_params: self.strip_query_params.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct UrlRewriter {
+    rules: Vec<(Regex, String)>,
+    strip_query_params: Vec<String>,
+}
+
+impl UrlRewriter {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.strip_query_params.is_empty()
+    }
+
+    pub fn rewrite(&self, url: &str) -> String {
+        let mut response = url.to_string();
+        for (regex, replacement) in &self.rules {
+            response = regex.replace_all(&response, replacement.as_str()).to_string();
+        }
+        if !self.strip_query_params.is_empty() {
+            response = self.strip_query(&response);
+        }
+        response
+    }
+
+    fn strip_query(&self, url: &str) -> String {
+        let (url, fragment) = match url.split_once('#') {
+            Some((u, f)) => (u, Some(f)),
+            None => (url, None),
+        };
+        let (base_url, query) = match url.split_once('?') {
+            Some((b, q)) => (b, q),
+            None => return with_fragment(url.to_string(), fragment),
+        };
+
+        let strip_all = self.strip_query_params.iter().any(|p| p == "*");
+        let kept_params = query
+            .split('&')
+            .filter(|param| {
+                if strip_all || param.is_empty() {
+                    return false;
+                }
+                let param_name = param.split('=').next().unwrap_or("");
+                !self.strip_query_params.iter().any(|p| p == param_name)
+            })
+            .collect::<Vec<&str>>();
+
+        let mut response = base_url.to_string();
+        if !kept_params.is_empty() {
+            response += "?";
+            response += &kept_params.join("&");
+        }
+        with_fragment(response, fragment)
+    }
+}
+
+fn with_fragment(url: String, fragment: Option<&str>) -> String {
+    match fragment {
+        Some(f) => format!("{}#{}", url, f),
+        None => url,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_rewrite_url() {
+        let config: Config = serde_yaml::from_str(
+            r###"
+            url_rewrites:
+              - pattern: "^https://mirror.internal.example.com/github/"
+                replacement: "https://github.com/"
+            strip_query_params:
+              - token
+            "###,
+        )
+        .unwrap();
+        let rewriter = config.get_url_rewriter().unwrap();
+
+        assert_eq!(
+            rewriter.rewrite("https://mirror.internal.example.com/github/sass/libsass/archive/3.6.4.tar.gz"),
+            "https://github.com/sass/libsass/archive/3.6.4.tar.gz"
+        );
+        assert_eq!(
+            rewriter.rewrite("https://example.com/archive.tar.gz?token=secret&version=2"),
+            "https://example.com/archive.tar.gz?version=2"
+        );
+        assert_eq!(
+            rewriter.rewrite("https://example.com/archive.tar.gz?token=secret#readme"),
+            "https://example.com/archive.tar.gz#readme"
+        );
+    }
+
+    #[test]
+    pub fn test_metadata_config() {
+        let config: Config = serde_yaml::from_str(
+            r###"
+            metadata:
+              timestamp: "2024-01-01T00:00:00Z"
+              tool_vendor: "ACME Corp"
+              properties:
+                acme:product-line: "widgets"
+            "###,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.metadata.tool_vendor, Some("ACME Corp".to_string()));
+        assert_eq!(
+            config.metadata.properties.get("acme:product-line"),
+            Some(&"widgets".to_string())
+        );
+
+        let config = Config {
+            metadata: MetadataConfig {
+                timestamp: Some("yesterday".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    pub fn test_merge_metadata_template() {
+        let mut metadata: MetadataConfig = serde_yaml::from_str(
+            r###"
+            tool_vendor: "ACME Corp"
+            description: "Widgets"
+            properties:
+              acme:product-line: "widgets"
+            "###,
+        )
+        .unwrap();
+        let template: MetadataConfig = serde_json::from_str(
+            r###"
+            {
+              "supplier": {
+                "name": "ACME Corp",
+                "url": ["https://acme.example.com"],
+                "contact": [{ "email": "security@acme.example.com" }]
+              },
+              "licenses": ["CC0-1.0"],
+              "description": "Widgets for everyone",
+              "properties": { "acme:team": "platform" }
+            }
+            "###,
+        )
+        .unwrap();
+        metadata.merge(template);
+        assert_eq!(metadata.tool_vendor, Some("ACME Corp".to_string()));
+        assert_eq!(metadata.description, Some("Widgets for everyone".to_string()));
+        assert_eq!(metadata.licenses, vec!["CC0-1.0"]);
+        assert_eq!(metadata.properties.len(), 2);
+        let supplier = metadata.supplier.unwrap();
+        assert_eq!(supplier.name, Some("ACME Corp".to_string()));
+        assert_eq!(
+            supplier.contact[0].email,
+            Some("security@acme.example.com".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_strip_all_query_params() {
+        let config = Config {
+            strip_query_params: vec!["*".to_string()],
+            ..Default::default()
+        };
+        let rewriter = config.get_url_rewriter().unwrap();
+        assert_eq!(
+            rewriter.rewrite("https://example.com/archive.tar.gz?a=1&b=2"),
+            "https://example.com/archive.tar.gz"
+        );
+    }
+}
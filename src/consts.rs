@@ -1 +1,15 @@
 pub const PROJECT_NAME: &str = "nix2sbom";
+
+// Version of the output contract of the CLI. Within an API version, the exit codes,
+// the content printed on stdout and the schema of the native format stay the same.
+pub const API_VERSION: &str = "1";
+pub const SUPPORTED_API_VERSIONS: [&str; 1] = ["1"];
+
+// Exit codes of the CLI, which are part of the API contract.
+pub const EXIT_SUCCESS: u8 = 0;
+// The SBOM could not be generated.
+pub const EXIT_FAILURE: u8 = 1;
+// Same code as the argument parsing errors.
+pub const EXIT_INVALID_ARGUMENTS: u8 = 2;
+// Some derivations were missing from the store and --strict was used.
+pub const EXIT_INCOMPLETE: u8 = 3;
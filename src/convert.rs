@@ -0,0 +1,263 @@
+// Converts a SBOM previously generated by nix2sbom, in the CycloneDX (JSON or XML) or
+// the native format, to the CycloneDX or the SPDX format, without evaluating the
+// derivations again. Only the data present in the SBOM is converted: a native SBOM has
+// no licenses and no dependencies, and the Nix-specific properties of the CycloneDX
+// components are not carried over to SPDX.
+use std::collections::BTreeMap;
+
+use serde_cyclonedx::cyclonedx::v_1_5::{
+    Component, ComponentBuilder, CycloneDx, CycloneDxBuilder, ExternalReference, ExternalReferenceBuilder,
+    Metadata, MetadataTools, ToolBuilder,
+};
+use serde_spdx::spdx::v_2_3::{
+    Spdx, SpdxBuilder, SpdxCreationInfoBuilder, SpdxItemPackages, SpdxItemPackagesBuilder,
+    SpdxItemPackagesItemExternalRefs, SpdxItemRelationships,
+};
+
+pub fn dump(
+    sbom: &crate::merge::Sbom,
+    output_format: &crate::format::Format,
+    serialization_format: &crate::format::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, anyhow::Error> {
+    let cyclonedx = to_cyclonedx(sbom, options);
+    match output_format {
+        crate::format::Format::CycloneDX => {
+            let spec_version = options
+                .spec_version
+                .clone()
+                .unwrap_or(crate::format::cyclone_dx::DEFAULT_SPEC_VERSION.to_string());
+            let cyclonedx = crate::format::cyclone_dx::convert_to_spec_version(&cyclonedx, &spec_version)?;
+            crate::format::cyclone_dx::serialize(&cyclonedx, serialization_format, options)
+        }
+        crate::format::Format::SPDX => {
+            let spdx = to_spdx(&cyclonedx, options)?;
+            crate::format::spdx::serialize(&spdx, serialization_format, options)
+        }
+        _ => Err(anyhow::format_err!(
+            "SBOMs can only be converted to the cdx and spdx formats"
+        )),
+    }
+}
+
+pub fn to_cyclonedx(sbom: &crate::merge::Sbom, options: &crate::nix::DumpOptions) -> CycloneDx {
+    let native_packages = match sbom {
+        crate::merge::Sbom::CycloneDX(cyclonedx) => return *cyclonedx.clone(),
+        crate::merge::Sbom::Native(p) => p,
+    };
+    let metadata = Metadata {
+        timestamp: Some(options.get_timestamp().to_rfc3339()),
+        tools: Some(MetadataTools::Variant1(vec![ToolBuilder::default()
+            .name(crate::consts::PROJECT_NAME.to_string())
+            .version(env!("CARGO_PKG_VERSION"))
+            .build()
+            .unwrap()])),
+        ..Default::default()
+    };
+    CycloneDxBuilder::default()
+        .bom_format(crate::format::CYCLONE_DX_NAME)
+        .spec_version(crate::format::cyclone_dx::CURRENT_SPEC_VERSION)
+        .version(1)
+        .metadata(metadata)
+        .components(
+            native_packages
+                .iter()
+                .map(dump_native_package)
+                .collect::<Vec<Component>>(),
+        )
+        .build()
+        .unwrap()
+}
+
+// The bom-ref of the component is the derivation path of the package.
+pub fn dump_native_package(native_package: &crate::format::native::NativePackage) -> Component {
+    let mut component_builder = ComponentBuilder::default();
+    component_builder
+        .bom_ref(native_package.id.to_string())
+        .type_("library")
+        .name(native_package.name.to_string())
+        .purl(native_package.purl.to_string());
+    if let Some(version) = &native_package.version {
+        component_builder.version(version.to_string());
+    }
+    let mut external_references: Vec<ExternalReference> = vec![];
+    for (reference_type, urls) in [
+        (
+            "website",
+            native_package.homepages.iter().collect::<Vec<&String>>(),
+        ),
+        ("vcs", native_package.git_urls.iter().collect()),
+        ("distribution", native_package.download_urls.iter().collect()),
+    ] {
+        for url in urls {
+            external_references.push(
+                ExternalReferenceBuilder::default()
+                    .type_(reference_type)
+                    .url(url.as_str())
+                    .build()
+                    .unwrap(),
+            );
+        }
+    }
+    if !external_references.is_empty() {
+        component_builder.external_references(external_references);
+    }
+    component_builder.build().unwrap()
+}
+
+pub fn to_spdx(cyclonedx: &CycloneDx, options: &crate::nix::DumpOptions) -> Result<Spdx, anyhow::Error> {
+    let cyclonedx = serde_json::to_value(cyclonedx)?;
+    let root_component = cyclonedx.pointer("/metadata/component");
+
+    let mut packages: BTreeMap<String, SpdxItemPackages> = BTreeMap::default();
+    let mut queue: Vec<&serde_json::Value> = root_component
+        .into_iter()
+        .chain(cyclonedx["components"].as_array().into_iter().flatten())
+        .collect();
+    while let Some(component) = queue.pop() {
+        let nested_components = component["components"].as_array();
+        queue.extend(nested_components.into_iter().flatten());
+        // The components only used to group other components are not packages.
+        if nested_components.is_some() && component["purl"].is_null() {
+            continue;
+        }
+        let spdx_package = dump_component(component)?;
+        packages.insert(spdx_package.spdxid.to_string(), spdx_package);
+    }
+
+    let mut relationships: Vec<SpdxItemRelationships> = vec![];
+    let name = match root_component {
+        Some(c) => {
+            let root_ref = get_ref(c);
+            relationships.push(crate::format::spdx::build_relationship(
+                crate::format::spdx::DOCUMENT_SPDX_ID,
+                crate::format::spdx::DESCRIBES_RELATIONSHIP,
+                &crate::format::spdx::get_spdx_id(&root_ref),
+            )?);
+            root_ref
+        }
+        None => crate::consts::PROJECT_NAME.to_string(),
+    };
+    for dependency in cyclonedx["dependencies"].as_array().into_iter().flatten() {
+        let spdx_id = crate::format::spdx::get_spdx_id(dependency["ref"].as_str().unwrap_or_default());
+        for dependency_ref in dependency["dependsOn"].as_array().into_iter().flatten() {
+            let related_spdx_id = crate::format::spdx::get_spdx_id(dependency_ref.as_str().unwrap_or_default());
+            // The relationships can only point to the elements of the document.
+            if !packages.contains_key(&spdx_id) || !packages.contains_key(&related_spdx_id) {
+                continue;
+            }
+            relationships.push(crate::format::spdx::build_relationship(
+                &spdx_id,
+                crate::format::spdx::DEPENDS_ON_RELATIONSHIP,
+                &related_spdx_id,
+            )?);
+        }
+    }
+
+    let creation_info = SpdxCreationInfoBuilder::default()
+        .created(options.get_timestamp().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .creators(vec!["Tool: nix2sbom".to_string()])
+        .build()?;
+    Ok(SpdxBuilder::default()
+        .creation_info(creation_info)
+        .document_namespace(crate::format::spdx::get_document_namespace(&name, options))
+        .data_license(crate::format::spdx::CREATIVE_COMMONS_LICENSE)
+        .spdx_version("SPDX-2.3")
+        .spdxid(crate::format::spdx::DOCUMENT_SPDX_ID)
+        .name(name)
+        .packages(packages.into_values().collect::<Vec<SpdxItemPackages>>())
+        .relationships(relationships)
+        .build()?)
+}
+
+fn dump_component(component: &serde_json::Value) -> Result<SpdxItemPackages, anyhow::Error> {
+    let mut package_builder = SpdxItemPackagesBuilder::default();
+    package_builder
+        .name(component["name"].as_str().unwrap_or_default())
+        .spdxid(crate::format::spdx::get_spdx_id(&get_ref(component)))
+        .download_location(
+            get_external_reference_url(component, "distribution")
+                .unwrap_or(crate::format::spdx::NO_ASSERTION.to_string()),
+        )
+        .license_declared(
+            crate::diff::get_cyclonedx_license(&component["licenses"])
+                .unwrap_or(crate::format::spdx::NO_ASSERTION.to_string()),
+        )
+        .license_concluded(crate::format::spdx::NO_ASSERTION);
+    if let Some(version) = component["version"].as_str() {
+        package_builder.version_info(version);
+    }
+    if let Some(description) = component["description"].as_str() {
+        package_builder.description(description);
+    }
+    if let Some(homepage) = get_external_reference_url(component, "website") {
+        package_builder.homepage(homepage);
+    }
+    if let Some(purl) = component["purl"].as_str() {
+        package_builder.external_refs(vec![SpdxItemPackagesItemExternalRefs {
+            comment: None,
+            reference_category: crate::format::spdx::PACKAGE_MANAGER_CATEGORY.to_string(),
+            reference_locator: purl.to_string(),
+            reference_type: crate::format::spdx::PURL_REFERENCE_TYPE.to_string(),
+        }]);
+    }
+    Ok(package_builder.build()?)
+}
+
+fn get_ref(component: &serde_json::Value) -> String {
+    component["bom-ref"]
+        .as_str()
+        .or(component["purl"].as_str())
+        .or(component["name"].as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn get_external_reference_url(component: &serde_json::Value, reference_type: &str) -> Option<String> {
+    component["externalReferences"]
+        .as_array()?
+        .iter()
+        .find(|r| r["type"] == reference_type)
+        .and_then(|r| r["url"].as_str())
+        .map(|u| u.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_convert_native_to_spdx() {
+        let sbom = crate::merge::load(
+            r###"
+            [
+              {
+                "id": "/nix/store/a-zlib-1.3.1.drv",
+                "name": "zlib",
+                "version": "1.3.1",
+                "purl": "pkg:generic/zlib@1.3.1",
+                "git_urls": ["https://github.com/madler/zlib.git"],
+                "download_urls": ["https://github.com/madler/zlib/releases/download/v1.3.1/zlib-1.3.1.tar.gz"],
+                "homepages": [],
+                "source_derivation": "/nix/store/b-zlib-1.3.1.tar.gz.drv"
+              }
+            ]
+            "###,
+        )
+        .unwrap();
+        let cyclonedx = to_cyclonedx(&sbom, &crate::nix::DumpOptions::default());
+        let spdx = to_spdx(&cyclonedx, &crate::nix::DumpOptions::default()).unwrap();
+        let packages = spdx.packages.unwrap();
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].spdxid, "SPDXRef-a-zlib-1.3.1.drv");
+        assert_eq!(packages[0].version_info, Some("1.3.1".to_string()));
+        assert_eq!(
+            packages[0].download_location,
+            "https://github.com/madler/zlib/releases/download/v1.3.1/zlib-1.3.1.tar.gz"
+        );
+        assert_eq!(
+            packages[0].external_refs.as_ref().unwrap()[0].reference_locator,
+            "pkg:generic/zlib@1.3.1"
+        );
+    }
+}
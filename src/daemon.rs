@@ -0,0 +1,412 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+// Minimal client for the Nix worker protocol, used to query the store through
+// the Nix daemon instead of spawning a nix process for every query.
+// See https://github.com/NixOS/nix/blob/master/src/libstore/worker-protocol.hh
+
+pub const DEFAULT_DAEMON_SOCKET_PATH: &str = "/nix/var/nix/daemon-socket/socket";
+
+const WORKER_MAGIC_1: u64 = 0x6e697863;
+const WORKER_MAGIC_2: u64 = 0x6478696f;
+
+// Version 1.21 is supported by all the daemons since Nix 2.0, and does not
+// require handling the features added in later versions of the protocol.
+const PROTOCOL_VERSION: u64 = (1 << 8) | 21;
+
+const QUERY_PATH_INFO_OP: u64 = 26;
+
+const STDERR_NEXT: u64 = 0x6f6c6d67;
+const STDERR_READ: u64 = 0x64617461;
+const STDERR_WRITE: u64 = 0x64617416;
+const STDERR_LAST: u64 = 0x616c7473;
+const STDERR_ERROR: u64 = 0x63787470;
+const STDERR_START_ACTIVITY: u64 = 0x53545254;
+const STDERR_STOP_ACTIVITY: u64 = 0x53544f50;
+const STDERR_RESULT: u64 = 0x52534c54;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct ValidPathInfo {
+    pub deriver: Option<String>,
+    pub nar_hash: String,
+    pub references: Vec<String>,
+    pub registration_time: u64,
+    pub nar_size: u64,
+    /// Whether the path was built locally, rather than substituted.
+    pub ultimate: bool,
+    pub signatures: Vec<String>,
+}
+
+impl ValidPathInfo {
+    // Same as the path info returned by nix path-info --json.
+    pub fn into_path_info(self, path: &str, closure_size: Option<u64>) -> crate::nix::PathInfo {
+        crate::nix::PathInfo {
+            path: Some(path.to_string()),
+            closure_size,
+            deriver: self.deriver,
+            // The daemon sends the hash in base16, so it is converted to the SRI
+            // format used by the CLI, e.g. sha256-I3PGgh0X...=
+            nar_hash: Some(get_sri_hash(&self.nar_hash)),
+            nar_size: Some(self.nar_size),
+            references: self.references,
+            ultimate: self.ultimate,
+            signatures: self.signatures,
+        }
+    }
+}
+
+fn get_sri_hash(nar_hash: &str) -> String {
+    let digest = nar_hash.strip_prefix("sha256:").unwrap_or(nar_hash);
+    match crate::utils::decode_base16(digest) {
+        Some(d) => format!("sha256-{}", crate::utils::encode_base64(&d)),
+        None => format!("sha256:{}", digest),
+    }
+}
+
+pub struct DaemonClient<S: Read + Write> {
+    stream: S,
+    daemon_version: u64,
+}
+
+impl DaemonClient<UnixStream> {
+    pub fn connect(socket_path: &str) -> Result<DaemonClient<UnixStream>, anyhow::Error> {
+        let stream = UnixStream::connect(socket_path).map_err(|e| {
+            anyhow::format_err!("Could not connect to the Nix daemon at {}: {}", socket_path, e)
+        })?;
+        DaemonClient::new(stream)
+    }
+}
+
+impl<S: Read + Write> DaemonClient<S> {
+    // Performs the handshake with the daemon.
+    pub fn new(stream: S) -> Result<DaemonClient<S>, anyhow::Error> {
+        let mut client = DaemonClient {
+            stream,
+            daemon_version: 0,
+        };
+        client.write_u64(WORKER_MAGIC_1)?;
+        if client.read_u64()? != WORKER_MAGIC_2 {
+            return Err(anyhow::format_err!("Invalid handshake from the Nix daemon"));
+        }
+        client.daemon_version = client.read_u64()?;
+        if get_major_version(client.daemon_version) != get_major_version(PROTOCOL_VERSION) {
+            return Err(anyhow::format_err!(
+                "Unsupported Nix daemon protocol version {}",
+                client.daemon_version
+            ));
+        }
+        client.write_u64(PROTOCOL_VERSION)?;
+        // Obsolete CPU affinity and reserve space settings.
+        client.write_u64(0)?;
+        client.write_u64(0)?;
+        client.process_stderr()?;
+        Ok(client)
+    }
+
+    pub fn query_path_info(&mut self, path: &str) -> Result<Option<ValidPathInfo>, anyhow::Error> {
+        self.write_u64(QUERY_PATH_INFO_OP)?;
+        self.write_string(path)?;
+        self.process_stderr()?;
+        if self.read_u64()? == 0 {
+            return Ok(None);
+        }
+        let deriver = self.read_string()?;
+        let nar_hash = self.read_string()?;
+        let references = self.read_strings()?;
+        let registration_time = self.read_u64()?;
+        let nar_size = self.read_u64()?;
+        let ultimate = self.read_u64()? != 0;
+        let signatures = self.read_strings()?;
+        // Content address.
+        self.read_string()?;
+        Ok(Some(ValidPathInfo {
+            deriver: if deriver.is_empty() { None } else { Some(deriver) },
+            nar_hash,
+            references,
+            registration_time,
+            nar_size,
+            ultimate,
+            signatures,
+        }))
+    }
+
+    // Computes the closure size of the paths, which is the sum of the NAR sizes
+    // of all the paths in their closure. Paths not in the store are skipped.
+    pub fn get_closure_sizes(&mut self, paths: &[String]) -> Result<BTreeMap<String, u64>, anyhow::Error> {
+        let mut path_infos: BTreeMap<String, Option<ValidPathInfo>> = BTreeMap::default();
+        let mut response: BTreeMap<String, u64> = BTreeMap::default();
+        for path in paths {
+            let mut closure_size = 0;
+            let mut visited: BTreeSet<String> = BTreeSet::default();
+            let mut queue: VecDeque<String> = VecDeque::from([path.to_string()]);
+            while let Some(current_path) = queue.pop_front() {
+                if !visited.insert(current_path.to_string()) {
+                    continue;
+                }
+                if !path_infos.contains_key(&current_path) {
+                    let path_info = self.query_path_info(&current_path)?;
+                    path_infos.insert(current_path.to_string(), path_info);
+                }
+                if let Some(path_info) = &path_infos[&current_path] {
+                    closure_size += path_info.nar_size;
+                    queue.extend(path_info.references.iter().cloned());
+                }
+            }
+            if path_infos.get(path).is_some_and(|p| p.is_some()) {
+                response.insert(path.to_string(), closure_size);
+            }
+        }
+        Ok(response)
+    }
+
+    // Same as nix path-info --json --closure-size. The paths not in the store are skipped.
+    pub fn get_output_path_infos(
+        &mut self,
+        paths: &[String],
+    ) -> Result<BTreeMap<String, crate::nix::PathInfo>, anyhow::Error> {
+        let closure_sizes = self.get_closure_sizes(paths)?;
+        let mut response: BTreeMap<String, crate::nix::PathInfo> = BTreeMap::default();
+        for (path, closure_size) in closure_sizes {
+            let path_info = match self.query_path_info(&path)? {
+                Some(p) => p,
+                None => continue,
+            };
+            response.insert(
+                path.to_string(),
+                path_info.into_path_info(&path, Some(closure_size)),
+            );
+        }
+        Ok(response)
+    }
+
+    // Reads the log messages sent by the daemon until the end of the logs.
+    fn process_stderr(&mut self) -> Result<(), anyhow::Error> {
+        loop {
+            match self.read_u64()? {
+                STDERR_LAST => return Ok(()),
+                STDERR_NEXT => {
+                    let message = self.read_string()?;
+                    log::debug!("nix-daemon: {}", message.trim_end());
+                }
+                STDERR_ERROR => {
+                    let message = self.read_string()?;
+                    let _status = self.read_u64()?;
+                    return Err(anyhow::format_err!("Nix daemon error: {}", message));
+                }
+                STDERR_START_ACTIVITY => {
+                    let _activity_id = self.read_u64()?;
+                    let _level = self.read_u64()?;
+                    let _activity_type = self.read_u64()?;
+                    let text = self.read_string()?;
+                    self.read_fields()?;
+                    let _parent_id = self.read_u64()?;
+                    if !text.is_empty() {
+                        log::debug!("nix-daemon: {}", text);
+                    }
+                }
+                STDERR_STOP_ACTIVITY => {
+                    let _activity_id = self.read_u64()?;
+                }
+                STDERR_RESULT => {
+                    let _activity_id = self.read_u64()?;
+                    let _result_type = self.read_u64()?;
+                    self.read_fields()?;
+                }
+                STDERR_READ | STDERR_WRITE => {
+                    return Err(anyhow::format_err!(
+                        "Unexpected data transfer request from the Nix daemon"
+                    ));
+                }
+                message_type => {
+                    return Err(anyhow::format_err!(
+                        "Unknown message type {:#x} from the Nix daemon",
+                        message_type
+                    ));
+                }
+            }
+        }
+    }
+
+    fn read_fields(&mut self) -> Result<(), anyhow::Error> {
+        let fields_count = self.read_u64()?;
+        for _ in 0..fields_count {
+            match self.read_u64()? {
+                0 => {
+                    self.read_u64()?;
+                }
+                1 => {
+                    self.read_string()?;
+                }
+                field_type => return Err(anyhow::format_err!("Unknown field type {}", field_type)),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_u64(&mut self) -> Result<u64, anyhow::Error> {
+        let mut buffer = [0u8; 8];
+        self.stream.read_exact(&mut buffer)?;
+        Ok(u64::from_le_bytes(buffer))
+    }
+
+    fn read_string(&mut self) -> Result<String, anyhow::Error> {
+        let length = self.read_u64()? as usize;
+        let mut buffer = vec![0u8; get_padded_length(length)];
+        self.stream.read_exact(&mut buffer)?;
+        buffer.truncate(length);
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    fn read_strings(&mut self) -> Result<Vec<String>, anyhow::Error> {
+        let count = self.read_u64()?;
+        let mut response: Vec<String> = vec![];
+        for _ in 0..count {
+            response.push(self.read_string()?);
+        }
+        Ok(response)
+    }
+
+    fn write_u64(&mut self, value: u64) -> Result<(), anyhow::Error> {
+        self.stream.write_all(&value.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn write_string(&mut self, value: &str) -> Result<(), anyhow::Error> {
+        self.write_u64(value.len() as u64)?;
+        let mut buffer = value.as_bytes().to_vec();
+        buffer.resize(get_padded_length(value.len()), 0);
+        self.stream.write_all(&buffer)?;
+        Ok(())
+    }
+}
+
+fn get_major_version(version: u64) -> u64 {
+    version & 0xff00
+}
+
+// Strings are padded with zeros to a multiple of 8 bytes.
+fn get_padded_length(length: usize) -> usize {
+    length.div_ceil(8) * 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u64(stream: &mut UnixStream, value: u64) {
+        stream.write_all(&value.to_le_bytes()).unwrap();
+    }
+
+    fn write_string(stream: &mut UnixStream, value: &str) {
+        write_u64(stream, value.len() as u64);
+        let mut buffer = value.as_bytes().to_vec();
+        buffer.resize(get_padded_length(value.len()), 0);
+        stream.write_all(&buffer).unwrap();
+    }
+
+    fn read_u64(stream: &mut UnixStream) -> u64 {
+        let mut buffer = [0u8; 8];
+        stream.read_exact(&mut buffer).unwrap();
+        u64::from_le_bytes(buffer)
+    }
+
+    fn read_string(stream: &mut UnixStream) -> String {
+        let length = read_u64(stream) as usize;
+        let mut buffer = vec![0u8; get_padded_length(length)];
+        stream.read_exact(&mut buffer).unwrap();
+        buffer.truncate(length);
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    pub fn test_into_path_info() {
+        let path_info = ValidPathInfo {
+            deriver: None,
+            nar_hash: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(),
+            references: vec![],
+            registration_time: 0,
+            nar_size: 120,
+            ultimate: false,
+            signatures: vec![],
+        }
+        .into_path_info("/nix/store/aaa-hello", None);
+        assert_eq!(
+            path_info.nar_hash,
+            Some("sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_get_closure_sizes() {
+        let (client_stream, mut daemon_stream) = UnixStream::pair().unwrap();
+        let store: BTreeMap<&str, (u64, Vec<&str>)> = BTreeMap::from([
+            (
+                "/nix/store/aaa-hello",
+                (100, vec!["/nix/store/aaa-hello", "/nix/store/bbb-glibc"]),
+            ),
+            ("/nix/store/bbb-glibc", (1000, vec![])),
+        ]);
+
+        let daemon = std::thread::spawn(move || {
+            assert_eq!(read_u64(&mut daemon_stream), WORKER_MAGIC_1);
+            write_u64(&mut daemon_stream, WORKER_MAGIC_2);
+            write_u64(&mut daemon_stream, (1 << 8) | 35);
+            assert_eq!(read_u64(&mut daemon_stream), PROTOCOL_VERSION);
+            read_u64(&mut daemon_stream);
+            read_u64(&mut daemon_stream);
+            write_u64(&mut daemon_stream, STDERR_LAST);
+
+            let mut queries: Vec<String> = vec![];
+            while let Ok(op) = {
+                let mut buffer = [0u8; 8];
+                daemon_stream
+                    .read_exact(&mut buffer)
+                    .map(|_| u64::from_le_bytes(buffer))
+            } {
+                assert_eq!(op, QUERY_PATH_INFO_OP);
+                let path = read_string(&mut daemon_stream);
+                write_u64(&mut daemon_stream, STDERR_NEXT);
+                write_string(&mut daemon_stream, "querying path info\n");
+                write_u64(&mut daemon_stream, STDERR_LAST);
+                match store.get(path.as_str()) {
+                    Some((nar_size, references)) => {
+                        write_u64(&mut daemon_stream, 1);
+                        write_string(&mut daemon_stream, "");
+                        write_string(&mut daemon_stream, "0123456789abcdef");
+                        write_u64(&mut daemon_stream, references.len() as u64);
+                        for reference in references {
+                            write_string(&mut daemon_stream, reference);
+                        }
+                        write_u64(&mut daemon_stream, 1700000000);
+                        write_u64(&mut daemon_stream, *nar_size);
+                        write_u64(&mut daemon_stream, 0);
+                        write_u64(&mut daemon_stream, 0);
+                        write_string(&mut daemon_stream, "");
+                    }
+                    None => write_u64(&mut daemon_stream, 0),
+                }
+                queries.push(path);
+            }
+            queries
+        });
+
+        let mut client = DaemonClient::new(client_stream).unwrap();
+        let closure_sizes = client
+            .get_closure_sizes(&[
+                "/nix/store/aaa-hello".to_string(),
+                "/nix/store/bbb-glibc".to_string(),
+                "/nix/store/ccc-missing".to_string(),
+            ])
+            .unwrap();
+        drop(client);
+
+        assert_eq!(closure_sizes.get("/nix/store/aaa-hello"), Some(&1100));
+        assert_eq!(closure_sizes.get("/nix/store/bbb-glibc"), Some(&1000));
+        assert_eq!(closure_sizes.get("/nix/store/ccc-missing"), None);
+        // Each path is only queried once.
+        assert_eq!(daemon.join().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,388 @@
+// Differences between two SBOMs generated by nix2sbom, in the CycloneDX (JSON or XML)
+// or the native format. The components are matched by purl, without the version, and
+// only the components with a purl are compared.
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct SbomComponent {
+    pub name: String,
+
+    pub version: Option<String>,
+
+    pub purl: String,
+
+    /// Not available with the native format.
+    pub license: Option<String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Sbom {
+    pub components: Vec<SbomComponent>,
+
+    /// Whether the licenses of the components are known, which is not the case
+    /// with the native format.
+    pub with_licenses: bool,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct LicenseChange {
+    pub name: String,
+
+    pub from: Option<String>,
+
+    pub to: Option<String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct SbomDiff {
+    pub added: Vec<SbomComponent>,
+    pub removed: Vec<SbomComponent>,
+    pub upgraded: Vec<crate::generations::VersionChange>,
+    pub downgraded: Vec<crate::generations::VersionChange>,
+    pub license_changes: Vec<LicenseChange>,
+}
+
+impl SbomDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.upgraded.is_empty()
+            && self.downgraded.is_empty()
+            && self.license_changes.is_empty()
+    }
+
+    // One line per change, grouped by kind of change.
+    pub fn to_text(&self) -> String {
+        let mut lines: Vec<String> = vec![];
+        for (title, prefix, components) in [("Added:", "+", &self.added), ("Removed:", "-", &self.removed)] {
+            if components.is_empty() {
+                continue;
+            }
+            lines.push(title.to_string());
+            for component in components {
+                lines.push(format!("  {} {}", prefix, component.purl));
+            }
+        }
+        for (title, changes) in [("Upgraded:", &self.upgraded), ("Downgraded:", &self.downgraded)] {
+            if changes.is_empty() {
+                continue;
+            }
+            lines.push(title.to_string());
+            for change in changes {
+                lines.push(format!(
+                    "  ~ {} {} -> {}",
+                    change.name,
+                    change.from.join(", "),
+                    change.to.join(", ")
+                ));
+            }
+        }
+        if !self.license_changes.is_empty() {
+            lines.push("License changes:".to_string());
+            for change in &self.license_changes {
+                lines.push(format!(
+                    "  ~ {} {} -> {}",
+                    change.name,
+                    change.from.as_deref().unwrap_or("unknown"),
+                    change.to.as_deref().unwrap_or("unknown")
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn load_file(path: &str) -> Result<Sbom, anyhow::Error> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| anyhow::format_err!("Could not read {}: {}", path, e))?;
+    load(&content).map_err(|e| anyhow::format_err!("Could not load the SBOM {}: {}", path, e))
+}
+
+// Loads the components of a SBOM, detecting its format from its content.
+pub fn load(content: &str) -> Result<Sbom, anyhow::Error> {
+    if content.trim_start().starts_with('<') {
+        let cyclonedx = crate::format::cyclone_dx::load_xml(content)?;
+        return Ok(Sbom {
+            components: get_cyclonedx_components(&serde_json::to_value(&cyclonedx)?),
+            with_licenses: true,
+        });
+    }
+    let value: serde_json::Value = serde_json::from_str(content)?;
+    if value.is_array() {
+        let native_packages: Vec<crate::format::native::NativePackage> = serde_json::from_value(value)?;
+        let components = native_packages
+            .into_iter()
+            .map(|p| SbomComponent {
+                name: p.name,
+                version: p.version,
+                purl: p.purl,
+                license: None,
+            })
+            .collect();
+        return Ok(Sbom {
+            components,
+            with_licenses: false,
+        });
+    }
+    if value["bomFormat"] == crate::format::CYCLONE_DX_NAME {
+        return Ok(Sbom {
+            components: get_cyclonedx_components(&value),
+            with_licenses: true,
+        });
+    }
+    Err(anyhow::format_err!(
+        "Only the CycloneDX and the native formats are supported"
+    ))
+}
+
+fn get_cyclonedx_components(cyclonedx: &serde_json::Value) -> Vec<SbomComponent> {
+    let mut response: Vec<SbomComponent> = vec![];
+    let metadata_component = cyclonedx.pointer("/metadata/component").into_iter();
+    let components = cyclonedx["components"].as_array().into_iter().flatten();
+    let mut queue: Vec<&serde_json::Value> = metadata_component.chain(components).collect();
+    while let Some(component) = queue.pop() {
+        queue.extend(component["components"].as_array().into_iter().flatten());
+        let purl = match component["purl"].as_str() {
+            Some(p) => p,
+            None => continue,
+        };
+        response.push(SbomComponent {
+            name: component["name"].as_str().unwrap_or_default().to_string(),
+            version: component["version"].as_str().map(|v| v.to_string()),
+            purl: purl.to_string(),
+            license: get_cyclonedx_license(&component["licenses"]),
+        });
+    }
+    response
+}
+
+// Returns the licenses of a CycloneDX component as a single expression.
+pub fn get_cyclonedx_license(licenses: &serde_json::Value) -> Option<String> {
+    let mut response: Vec<String> = vec![];
+    for license_choice in licenses.as_array().into_iter().flatten() {
+        let license = license_choice["expression"]
+            .as_str()
+            .or(license_choice["license"]["id"].as_str())
+            .or(license_choice["license"]["name"].as_str());
+        if let Some(license) = license {
+            response.push(license.to_string());
+        }
+    }
+    if response.is_empty() {
+        return None;
+    }
+    Some(response.join(" AND "))
+}
+
+// The purl without its version, e.g. pkg:generic/openssl for pkg:generic/openssl@3.0.13.
+fn get_component_key(purl: &str) -> String {
+    let purl = purl.split(['?', '#']).next().unwrap_or(purl);
+    match purl.rsplit_once('@') {
+        Some((key, _version)) => key.to_string(),
+        None => purl.to_string(),
+    }
+}
+
+// The license changes are only reported when the licenses are known in both SBOMs.
+pub fn diff(old_sbom: &Sbom, new_sbom: &Sbom) -> SbomDiff {
+    let old_components = index_components(&old_sbom.components);
+    let new_components = index_components(&new_sbom.components);
+    let with_licenses = old_sbom.with_licenses && new_sbom.with_licenses;
+
+    let mut response = SbomDiff::default();
+    for (key, new_versions) in &new_components {
+        let old_versions = match old_components.get(key) {
+            Some(v) => v,
+            None => {
+                response.added.extend(new_versions.values().cloned());
+                continue;
+            }
+        };
+        let old_latest = get_latest_version(old_versions);
+        let new_latest = get_latest_version(new_versions);
+        if with_licenses && old_latest.license != new_latest.license {
+            response.license_changes.push(LicenseChange {
+                name: new_latest.name.to_string(),
+                from: old_latest.license.clone(),
+                to: new_latest.license.clone(),
+            });
+        }
+        if old_versions.keys().eq(new_versions.keys()) {
+            continue;
+        }
+        let change = crate::generations::VersionChange {
+            name: new_latest.name.to_string(),
+            from: old_versions
+                .keys()
+                .map(|v| v.clone().unwrap_or_default())
+                .collect(),
+            to: new_versions
+                .keys()
+                .map(|v| v.clone().unwrap_or_default())
+                .collect(),
+        };
+        let old_version = old_latest.version.as_deref().unwrap_or_default();
+        let new_version = new_latest.version.as_deref().unwrap_or_default();
+        if crate::generations::compare_versions(new_version, old_version) == Ordering::Less {
+            response.downgraded.push(change);
+        } else {
+            response.upgraded.push(change);
+        }
+    }
+    for (key, old_versions) in &old_components {
+        if !new_components.contains_key(key) {
+            response.removed.extend(old_versions.values().cloned());
+        }
+    }
+    response
+}
+
+fn index_components(components: &[SbomComponent]) -> BTreeMap<String, BTreeMap<Option<String>, SbomComponent>> {
+    let mut response: BTreeMap<String, BTreeMap<Option<String>, SbomComponent>> = BTreeMap::default();
+    for component in components {
+        response
+            .entry(get_component_key(&component.purl))
+            .or_default()
+            .entry(component.version.clone())
+            .or_insert_with(|| component.clone());
+    }
+    response
+}
+
+fn get_latest_version(versions: &BTreeMap<Option<String>, SbomComponent>) -> &SbomComponent {
+    versions
+        .values()
+        .max_by(|a, b| {
+            crate::generations::compare_versions(
+                a.version.as_deref().unwrap_or_default(),
+                b.version.as_deref().unwrap_or_default(),
+            )
+        })
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_diff() {
+        let old_sbom = load(
+            r###"
+            {
+              "bomFormat": "CycloneDX",
+              "specVersion": "1.5",
+              "version": 1,
+              "metadata": {
+                "component": { "type": "application", "name": "hello", "version": "2.12.1", "purl": "pkg:generic/hello@2.12.1" }
+              },
+              "components": [
+                {
+                  "type": "library",
+                  "name": "openssl",
+                  "version": "3.0.13",
+                  "purl": "pkg:generic/openssl@3.0.13",
+                  "licenses": [{ "license": { "id": "Apache-2.0" } }]
+                },
+                { "type": "library", "name": "zlib", "version": "1.3.1", "purl": "pkg:generic/zlib@1.3.1" },
+                {
+                  "type": "application",
+                  "name": "libraries",
+                  "components": [{ "type": "library", "name": "libxml2", "version": "2.12.6", "purl": "pkg:generic/libxml2@2.12.6" }]
+                }
+              ]
+            }
+            "###,
+        )
+        .unwrap();
+        assert_eq!(old_sbom.components.len(), 4);
+
+        let mut new_sbom = load(
+            r###"
+            [
+              {
+                "id": "/nix/store/a-hello-2.12.1.drv",
+                "name": "hello",
+                "version": "2.12.1",
+                "purl": "pkg:generic/hello@2.12.1",
+                "git_urls": [],
+                "download_urls": [],
+                "homepages": [],
+                "source_derivation": "/nix/store/a-hello-2.12.1.tar.gz.drv"
+              },
+              {
+                "id": "/nix/store/b-openssl-3.0.14.drv",
+                "name": "openssl",
+                "version": "3.0.14",
+                "purl": "pkg:generic/openssl@3.0.14",
+                "git_urls": [],
+                "download_urls": [],
+                "homepages": [],
+                "source_derivation": "/nix/store/b-openssl-3.0.14.tar.gz.drv"
+              },
+              {
+                "id": "/nix/store/c-libxml2-2.11.7.drv",
+                "name": "libxml2",
+                "version": "2.11.7",
+                "purl": "pkg:generic/libxml2@2.11.7",
+                "git_urls": [],
+                "download_urls": [],
+                "homepages": [],
+                "source_derivation": "/nix/store/c-libxml2-2.11.7.tar.xz.drv"
+              },
+              {
+                "id": "/nix/store/d-curl-8.7.1.drv",
+                "name": "curl",
+                "version": "8.7.1",
+                "purl": "pkg:generic/curl@8.7.1",
+                "git_urls": [],
+                "download_urls": [],
+                "homepages": [],
+                "source_derivation": "/nix/store/d-curl-8.7.1.tar.xz.drv"
+              }
+            ]
+            "###,
+        )
+        .unwrap();
+
+        let sbom_diff = diff(&old_sbom, &new_sbom);
+        assert_eq!(sbom_diff.added.len(), 1);
+        assert_eq!(sbom_diff.added[0].name, "curl");
+        assert_eq!(sbom_diff.removed.len(), 1);
+        assert_eq!(sbom_diff.removed[0].name, "zlib");
+        assert_eq!(sbom_diff.upgraded.len(), 1);
+        assert_eq!(sbom_diff.upgraded[0].from, vec!["3.0.13"]);
+        assert_eq!(sbom_diff.upgraded[0].to, vec!["3.0.14"]);
+        assert_eq!(sbom_diff.downgraded.len(), 1);
+        assert_eq!(sbom_diff.downgraded[0].name, "libxml2");
+        assert!(sbom_diff.license_changes.is_empty());
+
+        new_sbom.with_licenses = true;
+        assert_eq!(
+            diff(&old_sbom, &new_sbom).license_changes,
+            vec![LicenseChange {
+                name: "openssl".to_string(),
+                from: Some("Apache-2.0".to_string()),
+                to: None,
+            }]
+        );
+
+        assert!(diff(&old_sbom, &old_sbom).is_empty());
+        assert!(load("{}").is_err());
+    }
+}
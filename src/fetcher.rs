@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// Detection of the nixpkgs fetchers (fetchurl, fetchgit, fetchFromGitHub, ...) from
+// the fixed-output derivations they produce. The fetchers are Nix functions, so they
+// are not recorded in the derivation, and we have to recognize them from the env
+// variables they set and from the URLs they download.
+
+// Names of the env variables in which the fetchers store the URLs.
+pub const URL_FIELD_NAMES: [&str; 2] = ["url", "urls"];
+
+lazy_static! {
+    static ref CRATE_URL_REGEX: Regex = Regex::new(
+        r"^https?://(?:crates\.io/api/v1/crates/([0-9a-zA-Z_-]+)/([^/]+)/download|static\.crates\.io/crates/([0-9a-zA-Z_-]+)/[0-9a-zA-Z_-]+-([^/]+)\.crate)"
+    )
+    .unwrap();
+}
+
+lazy_static! {
+    static ref PYPI_URL_REGEX: Regex = Regex::new(
+        r"^https?://(?:pypi\.io|pypi\.org|pypi\.python\.org|files\.pythonhosted\.org)/packages/source/[0-9a-zA-Z]/([0-9a-zA-Z._-]+)/([^/]+)$"
+    )
+    .unwrap();
+}
+
+lazy_static! {
+    static ref GITHUB_ARCHIVE_URL_REGEX: Regex =
+        Regex::new(r"^https?://github\.com/([0-9a-zA-Z._-]+)/([0-9a-zA-Z._-]+)/archive/(?:refs/tags/)?(.+)\.(?:tar\.gz|zip)$")
+            .unwrap();
+}
+
+lazy_static! {
+    static ref GITHUB_REPOSITORY_URL_REGEX: Regex =
+        Regex::new(r"^https?://github\.com/([0-9a-zA-Z._-]+)/([0-9a-zA-Z._-]+?)(?:\.git)?/?$").unwrap();
+}
+
+lazy_static! {
+    static ref GITLAB_ARCHIVE_URL_REGEX: Regex =
+        Regex::new(r"^https?://([0-9a-zA-Z.-]*gitlab[0-9a-zA-Z.-]*)/(.+)/([0-9a-zA-Z._-]+)/-/archive/([^/]+)/")
+            .unwrap();
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub enum Fetcher {
+    FetchUrl,
+    FetchZip,
+    FetchGit,
+    FetchFromGitHub,
+    FetchFromGitLab,
+    FetchPatch,
+    FetchPypi,
+    FetchCrate,
+}
+
+impl Fetcher {
+    // Returns the name of the nixpkgs function.
+    pub fn get_function_name(&self) -> String {
+        match self {
+            Fetcher::FetchUrl => "fetchurl".to_string(),
+            Fetcher::FetchZip => "fetchzip".to_string(),
+            Fetcher::FetchGit => "fetchgit".to_string(),
+            Fetcher::FetchFromGitHub => "fetchFromGitHub".to_string(),
+            Fetcher::FetchFromGitLab => "fetchFromGitLab".to_string(),
+            Fetcher::FetchPatch => "fetchpatch".to_string(),
+            Fetcher::FetchPypi => "fetchPypi".to_string(),
+            Fetcher::FetchCrate => "fetchCrate".to_string(),
+        }
+    }
+}
+
+/// Hash of the output of a fixed-output derivation.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct OutputHash {
+    /// For example sha256.
+    pub algorithm: String,
+
+    /// flat for the hash of the downloaded file, recursive for the hash of the NAR
+    /// serialization of the unpacked directory.
+    pub mode: String,
+
+    /// The hash, either in the SRI format (sha256-...) or in base16 or base32.
+    pub value: String,
+}
+
+impl OutputHash {
+    // Returns the SHA-256 hash in base16, whatever the format of the hash.
+    pub fn get_sha256_hex(&self) -> Option<String> {
+        if self.algorithm != "sha256" {
+            return None;
+        }
+        self.get_hex()
+    }
+
+    // Returns the hash in base16, whatever the format of the hash. The format is
+    // detected using the size of the digest of the algorithm.
+    pub fn get_hex(&self) -> Option<String> {
+        let digest_size: usize = match self.algorithm.as_str() {
+            "md5" => 16,
+            "sha1" => 20,
+            "sha256" => 32,
+            "sha512" => 64,
+            _ => return None,
+        };
+        let value = self
+            .value
+            .strip_prefix(&format!("{}-", self.algorithm))
+            .or(self.value.strip_prefix(&format!("{}:", self.algorithm)))
+            .unwrap_or(&self.value);
+        let digest = if value.len() == digest_size * 2 {
+            crate::utils::decode_base16(value)?
+        } else if value.len() == (digest_size * 8).div_ceil(5) {
+            crate::utils::decode_nix_base32(value)?
+        } else {
+            crate::utils::decode_base64(value)?
+        };
+        if digest.len() != digest_size {
+            return None;
+        }
+        Some(crate::utils::encode_base16(&digest))
+    }
+
+    // Only the flat hashes can be used to verify the downloaded artifacts. The
+    // recursive hashes are computed on the NAR serialization of the unpacked files.
+    pub fn is_flat(&self) -> bool {
+        self.mode == "flat"
+    }
+}
+
+/// A source fetched by a nixpkgs fetcher, with the coordinates of the
+/// package when the fetcher gives them away.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct FetchedSource {
+    pub fetcher: Fetcher,
+
+    /// The URLs of the source, with the mirror URLs translated.
+    pub urls: Vec<String>,
+
+    /// Type of the purl of the package, e.g. cargo, pypi or github.
+    pub ecosystem: Option<String>,
+
+    /// Owner of the repository, for the fetchers of the git forges.
+    pub namespace: Option<String>,
+
+    pub name: Option<String>,
+
+    pub version: Option<String>,
+
+    /// Commit or tag, for the fetchers downloading from a repository.
+    pub revision: Option<String>,
+
+    pub hash: Option<OutputHash>,
+}
+
+// Returns the URLs of the derivation, with the mirror URLs translated.
+pub fn get_urls(env: &HashMap<String, String>) -> Vec<String> {
+    let mut response: Vec<String> = vec![];
+    for field_name in URL_FIELD_NAMES {
+        if let Some(urls) = env.get(field_name) {
+            for url in urls.split(' ').filter(|u| !u.is_empty()) {
+                response.push(crate::mirrors::translate_url(url));
+            }
+        }
+    }
+    response
+}
+
+pub fn get_output_hash(env: &HashMap<String, String>) -> Option<OutputHash> {
+    let value = env.get("outputHash").filter(|h| !h.is_empty())?;
+    let mut algorithm = env.get("outputHashAlgo").cloned().unwrap_or_default();
+    // The algorithm is not set when the hash uses the SRI format.
+    if algorithm.is_empty() {
+        algorithm = value.split_once('-')?.0.to_string();
+    }
+    Some(OutputHash {
+        algorithm,
+        mode: env.get("outputHashMode").cloned().unwrap_or("flat".to_string()),
+        value: value.to_string(),
+    })
+}
+
+// Returns the hash recorded in the outputs of the derivation, as printed by
+// nix derivation show, for the derivations that have no outputHash in their env.
+pub fn get_output_hash_from_outputs(outputs: &HashMap<String, crate::nix::Output>) -> Option<OutputHash> {
+    let output = outputs.get("out")?;
+    let value = output.hash.as_ref().filter(|h| !h.is_empty())?;
+    let hash_algo = output.hash_algo.as_ref()?;
+    let (algorithm, mode) = match hash_algo.strip_prefix("r:") {
+        Some(a) => (a, "recursive"),
+        None => (hash_algo.as_str(), "flat"),
+    };
+    Some(OutputHash {
+        algorithm: algorithm.to_string(),
+        mode: mode.to_string(),
+        value: value.to_string(),
+    })
+}
+
+// Some derivations do not declare their sources using the standard src field.
+// This is the case for cargo dependencies for example. We can nonetheless detect
+// those using the name of the input derivations.
+pub fn is_source_derivation_path(derivation_path: &str) -> bool {
+    derivation_path.ends_with(".tar.gz.drv") || derivation_path.ends_with("-source.drv")
+}
+
+// Detects the fetcher that produced the derivation. Returns None when the derivation
+// does not download anything.
+pub fn detect(derivation: &crate::nix::Derivation) -> Option<FetchedSource> {
+    let urls = get_urls(&derivation.env);
+    let url = urls.first()?.to_string();
+    let post_fetch = derivation.env.get("postFetch").cloned().unwrap_or_default();
+
+    let mut fetched_source = FetchedSource {
+        fetcher: Fetcher::FetchUrl,
+        urls,
+        ecosystem: crate::identify::get_purl_type_from_url(&url).map(|t| t.to_string()),
+        namespace: None,
+        name: None,
+        version: None,
+        revision: derivation.env.get("rev").cloned(),
+        hash: derivation.get_output_hash(),
+    };
+
+    if derivation.env.contains_key("fetchSubmodules") || derivation.env.contains_key("leaveDotGit") {
+        fetched_source.fetcher = Fetcher::FetchGit;
+        if let Some(captures) = GITHUB_REPOSITORY_URL_REGEX.captures(&url) {
+            fetched_source.fetcher = Fetcher::FetchFromGitHub;
+            fetched_source.ecosystem = Some("github".to_string());
+            fetched_source.namespace = Some(captures[1].to_string());
+            fetched_source.name = Some(captures[2].to_string());
+        }
+    } else if post_fetch.contains("lsdiff") || post_fetch.contains("filterdiff") {
+        fetched_source.fetcher = Fetcher::FetchPatch;
+    } else if let Some(captures) = CRATE_URL_REGEX.captures(&url) {
+        fetched_source.fetcher = Fetcher::FetchCrate;
+        fetched_source.ecosystem = Some("cargo".to_string());
+        fetched_source.name = captures
+            .get(1)
+            .or(captures.get(3))
+            .map(|m| m.as_str().to_string());
+        fetched_source.version = captures
+            .get(2)
+            .or(captures.get(4))
+            .map(|m| m.as_str().to_string());
+    } else if let Some(captures) = PYPI_URL_REGEX.captures(&url) {
+        fetched_source.fetcher = Fetcher::FetchPypi;
+        fetched_source.ecosystem = Some("pypi".to_string());
+        let name = captures[1].to_string();
+        fetched_source.version = get_version_from_file_name(&captures[2], &name);
+        fetched_source.name = Some(name);
+    } else if let Some(captures) = GITHUB_ARCHIVE_URL_REGEX.captures(&url) {
+        fetched_source.fetcher = Fetcher::FetchFromGitHub;
+        fetched_source.ecosystem = Some("github".to_string());
+        fetched_source.namespace = Some(captures[1].to_string());
+        fetched_source.name = Some(captures[2].to_string());
+        fetched_source.revision = Some(captures[3].to_string());
+    } else if let Some(captures) = GITLAB_ARCHIVE_URL_REGEX.captures(&url) {
+        fetched_source.fetcher = Fetcher::FetchFromGitLab;
+        fetched_source.namespace = Some(captures[2].to_string());
+        fetched_source.name = Some(captures[3].to_string());
+        fetched_source.revision = Some(captures[4].to_string());
+    } else if post_fetch.contains("unpackFile") {
+        fetched_source.fetcher = Fetcher::FetchZip;
+    }
+
+    if fetched_source.version.is_none() {
+        if let Some(revision) = &fetched_source.revision {
+            if crate::nix::is_semantic_version(revision.trim_start_matches('v')) {
+                fetched_source.version = Some(revision.trim_start_matches('v').to_string());
+            }
+        }
+    }
+    Some(fetched_source)
+}
+
+// Extracts the version from the name of an archive, e.g. requests-2.31.0.tar.gz.
+fn get_version_from_file_name(file_name: &str, name: &str) -> Option<String> {
+    let version = file_name.strip_prefix(name)?.strip_prefix('-')?;
+    for extension in [".tar.gz", ".tar.bz2", ".tar.xz", ".tgz", ".zip", ".whl"] {
+        if let Some(v) = version.strip_suffix(extension) {
+            return Some(v.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_derivation(env: &str) -> crate::nix::Derivation {
+        serde_json::from_str(&format!(
+            r#"{{
+              "args": [],
+              "builder": "builtin:fetchurl",
+              "env": {},
+              "inputDrvs": {{}},
+              "inputSrcs": [],
+              "outputs": {{ "out": {{ "path": "/nix/store/a-source" }} }},
+              "system": "builtin"
+            }}"#,
+            env
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    pub fn test_detect_fetcher() {
+        let fetched_source = detect(&get_derivation(
+            r#"{
+              "urls": "https://crates.io/api/v1/crates/serde/1.0.197/download",
+              "outputHash": "sha256-PMTdj5xxqXbGZzl2dHf4Bq6u8mHzcM8MK+vDXNbDAOI=",
+              "outputHashAlgo": "",
+              "outputHashMode": "flat"
+            }"#,
+        ))
+        .unwrap();
+        assert_eq!(fetched_source.fetcher, Fetcher::FetchCrate);
+        assert_eq!(fetched_source.ecosystem, Some("cargo".to_string()));
+        assert_eq!(fetched_source.name, Some("serde".to_string()));
+        assert_eq!(fetched_source.version, Some("1.0.197".to_string()));
+        let hash = fetched_source.hash.unwrap();
+        assert_eq!(hash.algorithm, "sha256");
+        assert_eq!(
+            hash.get_sha256_hex(),
+            Some("3cc4dd8f9c71a976c66739767477f806aeaef261f370cf0c2bebc35cd6c300e2".to_string())
+        );
+        assert!(hash.is_flat());
+
+        let hash = get_output_hash(&HashMap::from([
+            (
+                "outputHash".to_string(),
+                "143xibwh31h9bvxzalr0sjvbbvpa6ffs".to_string(),
+            ),
+            ("outputHashAlgo".to_string(), "sha1".to_string()),
+        ]))
+        .unwrap();
+        assert_eq!(hash.get_sha256_hex(), None);
+        assert_eq!(
+            hash.get_hex(),
+            Some("da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string())
+        );
+
+        let fetched_source = detect(&get_derivation(
+            r#"{ "urls": "mirror://pypi/r/requests/requests-2.31.0.tar.gz" }"#,
+        ))
+        .unwrap();
+        assert_eq!(fetched_source.fetcher, Fetcher::FetchPypi);
+        assert_eq!(fetched_source.name, Some("requests".to_string()));
+        assert_eq!(fetched_source.version, Some("2.31.0".to_string()));
+
+        let fetched_source = detect(&get_derivation(
+            r#"{
+              "urls": "https://github.com/madler/zlib/archive/v1.3.1.tar.gz",
+              "postFetch": "unpackFile \"$downloadedFile\""
+            }"#,
+        ))
+        .unwrap();
+        assert_eq!(fetched_source.fetcher, Fetcher::FetchFromGitHub);
+        assert_eq!(fetched_source.namespace, Some("madler".to_string()));
+        assert_eq!(fetched_source.name, Some("zlib".to_string()));
+        assert_eq!(fetched_source.revision, Some("v1.3.1".to_string()));
+        assert_eq!(fetched_source.version, Some("1.3.1".to_string()));
+
+        let fetched_source = detect(&get_derivation(
+            r#"{
+              "url": "https://git.savannah.gnu.org/git/gnulib.git",
+              "rev": "0b38e1d69f03d3977d7ae7926c1efeb461a8a971",
+              "fetchSubmodules": "",
+              "leaveDotGit": ""
+            }"#,
+        ))
+        .unwrap();
+        assert_eq!(fetched_source.fetcher, Fetcher::FetchGit);
+        assert_eq!(fetched_source.version, None);
+
+        let fetched_source = detect(&get_derivation(
+            r#"{
+              "urls": "https://github.com/openssl/openssl/commit/1234.patch",
+              "postFetch": "lsdiff \"$out\""
+            }"#,
+        ))
+        .unwrap();
+        assert_eq!(fetched_source.fetcher, Fetcher::FetchPatch);
+
+        assert!(detect(&get_derivation(r#"{ "name": "hello-2.12.1" }"#)).is_none());
+    }
+}
@@ -0,0 +1,601 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+// Guard against malformed lock files that would make us recurse forever.
+const MAX_INPUT_DEPTH: usize = 64;
+
+// The meta attributes that we deserialize. The other attributes are not evaluated,
+// since some of them cannot be serialized to JSON, for example meta.tests.
+const PACKAGE_META_ATTRIBUTES: [&str; 11] = [
+    "available",
+    "broken",
+    "description",
+    "homepage",
+    "insecure",
+    "knownVulnerabilities",
+    "license",
+    "maintainers",
+    "sourceProvenance",
+    "unfree",
+    "unsupported",
+];
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct FlakeMetadata {
+    pub description: Option<String>,
+
+    // The store path of the flake source.
+    pub path: Option<String>,
+
+    pub revision: Option<String>,
+
+    #[serde(rename = "lastModified")]
+    pub last_modified: Option<u64>,
+
+    pub url: Option<String>,
+
+    pub locks: Option<FlakeLock>,
+}
+
+/// The content of a flake.lock file.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct FlakeLock {
+    pub nodes: BTreeMap<String, FlakeLockNode>,
+    pub root: String,
+    pub version: u32,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct FlakeLockNode {
+    pub inputs: Option<BTreeMap<String, FlakeLockNodeInput>>,
+    pub locked: Option<FlakeLockedRef>,
+    pub flake: Option<bool>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[serde(untagged)]
+#[derive(PartialEq)]
+pub enum FlakeLockNodeInput {
+    // The id of the node in the lock file.
+    Node(String),
+    // The path of the input being followed, starting from the root node.
+    Follows(Vec<String>),
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct FlakeLockedRef {
+    #[serde(rename = "type")]
+    pub type_: String,
+
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    pub rev: Option<String>,
+    pub url: Option<String>,
+    pub path: Option<String>,
+
+    #[serde(rename = "ref")]
+    pub ref_: Option<String>,
+
+    #[serde(rename = "narHash")]
+    pub nar_hash: Option<String>,
+
+    #[serde(rename = "lastModified")]
+    pub last_modified: Option<u64>,
+}
+
+impl FlakeLockedRef {
+    // Returns a URL that can be used to fetch the locked input.
+    pub fn get_url(&self) -> Option<String> {
+        if let Some(url) = &self.url {
+            return Some(url.to_string());
+        }
+        let owner = self.owner.as_ref()?;
+        let repo = self.repo.as_ref()?;
+        match self.type_.as_str() {
+            "github" => Some(format!("https://github.com/{}/{}", owner, repo)),
+            "gitlab" => Some(format!("https://gitlab.com/{}/{}", owner, repo)),
+            "sourcehut" => Some(format!("https://git.sr.ht/{}/{}", owner, repo)),
+            _ => None,
+        }
+    }
+}
+
+/// A flake input, with all the inputs it depends on.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct FlakeInput {
+    /// Name of the input, as declared by the flake using it.
+    pub name: String,
+
+    /// Names of the inputs leading to this input, starting from the root flake.
+    pub path: Vec<String>,
+
+    pub locked: Option<FlakeLockedRef>,
+
+    /// Set when the input follows another input of the graph. In that case, the
+    /// followed input is described elsewhere in the tree and `inputs` is empty.
+    pub follows: Option<Vec<String>>,
+
+    pub inputs: Vec<FlakeInput>,
+}
+
+impl FlakeInput {
+    pub fn get_id(&self) -> String {
+        self.path.join("/")
+    }
+
+    // Returns the id of the input described in the tree, which is the followed
+    // input when this input follows another one.
+    pub fn get_resolved_id(&self) -> String {
+        match &self.follows {
+            Some(follows) => follows.join("/"),
+            None => self.get_id(),
+        }
+    }
+}
+
+impl FlakeLock {
+    /// Returns the resolved input tree of the root flake.
+    pub fn get_input_tree(&self) -> Vec<FlakeInput> {
+        self.get_node_inputs(&self.root, &[])
+    }
+
+    fn get_node_inputs(&self, node_id: &str, parent_path: &[String]) -> Vec<FlakeInput> {
+        let mut response: Vec<FlakeInput> = vec![];
+        if parent_path.len() > MAX_INPUT_DEPTH {
+            log::warn!("Flake input tree is too deep at {}", parent_path.join("/"));
+            return response;
+        }
+        let node = match self.nodes.get(node_id) {
+            Some(n) => n,
+            None => {
+                log::warn!("Could not find node {} in flake lock", node_id);
+                return response;
+            }
+        };
+        let inputs = match &node.inputs {
+            Some(i) => i,
+            None => return response,
+        };
+        for (input_name, input) in inputs {
+            let mut path = parent_path.to_vec();
+            path.push(input_name.to_string());
+            let flake_input = match input {
+                FlakeLockNodeInput::Node(input_node_id) => FlakeInput {
+                    name: input_name.to_string(),
+                    locked: self.nodes.get(input_node_id).and_then(|n| n.locked.clone()),
+                    follows: None,
+                    inputs: self.get_node_inputs(input_node_id, &path),
+                    path,
+                },
+                FlakeLockNodeInput::Follows(followed_path) => FlakeInput {
+                    name: input_name.to_string(),
+                    locked: self
+                        .resolve_follows(followed_path)
+                        .and_then(|n| self.nodes.get(&n))
+                        .and_then(|n| n.locked.clone()),
+                    follows: Some(followed_path.clone()),
+                    inputs: vec![],
+                    path,
+                },
+            };
+            response.push(flake_input);
+        }
+        response
+    }
+
+    // Resolves the path of a followed input to the id of a node of the lock file.
+    pub fn resolve_follows(&self, followed_path: &[String]) -> Option<String> {
+        let mut current_node_id = self.root.to_string();
+        for (depth, input_name) in followed_path.iter().enumerate() {
+            if depth > MAX_INPUT_DEPTH {
+                return None;
+            }
+            let node = self.nodes.get(&current_node_id)?;
+            current_node_id = match node.inputs.as_ref()?.get(input_name)? {
+                FlakeLockNodeInput::Node(n) => n.to_string(),
+                FlakeLockNodeInput::Follows(p) => self.resolve_follows(p)?,
+            };
+        }
+        Some(current_node_id)
+    }
+}
+
+// Returns the flake part of a nix reference, e.g. `.` for `.#package`.
+pub fn get_flake_ref(nix_ref: &str) -> String {
+    match nix_ref.split_once('#') {
+        Some((flake_ref, _)) => flake_ref.to_string(),
+        None => nix_ref.to_string(),
+    }
+}
+
+// Whether the nix reference designates a flake, as opposed to a store path or a Nix file,
+// e.g. /nix/store/...-hello.drv or /path/to/default.nix#hello. Like Nix, the flake.nix file
+// of a local flake is looked up in the parent directories, up to the root of the git repository.
+pub fn is_flake_ref(nix_ref: &str) -> bool {
+    let flake_ref = get_flake_ref(nix_ref);
+    let flake_ref = flake_ref.split('?').next().unwrap_or(&flake_ref);
+    if flake_ref.starts_with("/nix/store/") || flake_ref.ends_with(".nix") || flake_ref.contains(".drv^") {
+        return false;
+    }
+    let path = match get_local_flake_path(flake_ref) {
+        Some(p) => std::path::Path::new(p),
+        None => return !flake_ref.is_empty(),
+    };
+    for directory in path.ancestors() {
+        if directory.join("flake.nix").is_file() {
+            return true;
+        }
+        if directory.join(".git").exists() {
+            return false;
+        }
+    }
+    false
+}
+
+pub fn get_flake_metadata(flake_ref: &str) -> Result<FlakeMetadata, anyhow::Error> {
+    let output = Command::new("nix")
+        .arg("flake")
+        .arg("metadata")
+        .arg("--json")
+        .arg(flake_ref)
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8(output.stderr).unwrap_or_default();
+        return Err(anyhow::format_err!(
+            "Could not get flake metadata for {}: {}",
+            &flake_ref,
+            &stderr
+        ));
+    }
+
+    let flake_metadata: FlakeMetadata = serde_json::from_slice(&output.stdout)?;
+    Ok(flake_metadata)
+}
+
+// Returns the locked revision of the nixpkgs input of the flake.
+pub fn get_nixpkgs_revision(flake_inputs: &[FlakeInput]) -> Option<String> {
+    flake_inputs
+        .iter()
+        .find(|i| i.name == "nixpkgs")
+        .and_then(|i| i.locked.as_ref())
+        .and_then(|l| l.rev.clone())
+}
+
+// Returns the directory of a local flake, e.g. `/src/project` for `path:/src/project`.
+pub fn get_local_flake_path(flake_ref: &str) -> Option<&str> {
+    let path = flake_ref
+        .strip_prefix("path:")
+        .or(flake_ref.strip_prefix("git+file://"))
+        .unwrap_or(flake_ref);
+    let path = path.split('?').next().unwrap_or(path);
+    if !path.starts_with('.') && !path.starts_with('/') {
+        return None;
+    }
+    Some(path)
+}
+
+// Reads the flake.lock file of a local flake. This is used when the metadata of
+// the flake cannot be evaluated, e.g. when the flake is not in a git repository.
+pub fn read_local_flake_lock(flake_ref: &str) -> Result<FlakeLock, anyhow::Error> {
+    let path = match get_local_flake_path(flake_ref) {
+        Some(p) => p,
+        None => return Err(anyhow::format_err!("{} is not a local flake", flake_ref)),
+    };
+    let lock_path = std::path::Path::new(path).join("flake.lock");
+    let content = std::fs::read_to_string(&lock_path)
+        .map_err(|e| anyhow::format_err!("Could not read {}: {}", lock_path.display(), e))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+// Returns a reference to every package exposed by the flake for the current system,
+// e.g. `.#hello`.
+pub fn get_package_refs(flake_ref: &str) -> Result<Vec<String>, anyhow::Error> {
+    let output = Command::new("nix")
+        .arg("eval")
+        .arg("--json")
+        // builtins.currentSystem is only available in impure mode.
+        .arg("--impure")
+        .arg(format!("{}#packages", flake_ref))
+        .arg("--apply")
+        .arg("packages: builtins.attrNames (packages.${builtins.currentSystem} or { })")
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8(output.stderr).unwrap_or_default();
+        return Err(anyhow::format_err!(
+            "Could not list the packages of flake {}: {}",
+            &flake_ref,
+            &stderr
+        ));
+    }
+
+    let package_names: Vec<String> = serde_json::from_slice(&output.stdout)?;
+    Ok(package_names
+        .iter()
+        .map(|package_name| format!("{}#{}", flake_ref, package_name))
+        .collect())
+}
+
+// Evaluates the metadata of a package of a flake, e.g. `.#hello`, and returns it with
+// the .drv path of the package. The packages defined in the flake itself are not part of
+// the nixpkgs metadata, so this is the only way to get their licenses and homepages.
+pub fn get_package_metadata(
+    nix_ref: &str,
+    meta_fields: Option<&[String]>,
+) -> Result<(String, crate::nix::Package), anyhow::Error> {
+    let output = Command::new("nix")
+        .arg("eval")
+        .arg("--json")
+        .arg(nix_ref)
+        .arg("--apply")
+        .arg(format!(
+            "p: ({}) p // {{ drvPath = p.drvPath; }}",
+            get_package_metadata_function(meta_fields)
+        ))
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8(output.stderr).unwrap_or_default();
+        return Err(anyhow::format_err!(
+            "Could not evaluate the metadata of {}: {}",
+            &nix_ref,
+            &stderr
+        ));
+    }
+
+    parse_package_metadata(&output.stdout)
+}
+
+// Nix function returning the metadata of a package in the format of nix-env.
+// Only the selected metadata fields are evaluated, along with the status fields
+// (broken, insecure, unfree, etc.). All the fields are evaluated by default.
+pub fn get_package_metadata_function(meta_fields: Option<&[String]>) -> String {
+    let meta_attributes = PACKAGE_META_ATTRIBUTES
+        .iter()
+        .filter(|a| match meta_fields {
+            Some(fields) => !crate::nix::META_FIELDS.contains(a) || fields.iter().any(|f| f == *a),
+            None => true,
+        })
+        .map(|a| format!("{} = null;", a))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        r#"p: {{
+          name = p.name;
+          pname = p.pname or (builtins.parseDrvName p.name).name;
+          version = p.version or (builtins.parseDrvName p.name).version;
+          system = p.system or "";
+          outputName = p.outputName or "out";
+          outputs = builtins.listToAttrs (map (o: {{ name = o; value = p.${{o}}.outPath or null; }}) (p.outputs or [ "out" ]));
+          meta = builtins.intersectAttrs {{ {} }} (p.meta or {{ }});
+        }}"#,
+        meta_attributes
+    )
+}
+
+fn parse_package_metadata(content: &[u8]) -> Result<(String, crate::nix::Package), anyhow::Error> {
+    let mut package_metadata: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(content)?;
+    let drv_path = match package_metadata.remove("drvPath") {
+        Some(serde_json::Value::String(p)) => p,
+        _ => return Err(anyhow::format_err!("No drvPath in the package metadata")),
+    };
+    let package: crate::nix::Package = serde_json::from_value(serde_json::Value::Object(package_metadata))?;
+    Ok((drv_path, package))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAKE_LOCK: &str = r###"
+      {
+        "nodes": {
+          "flake-utils": {
+            "inputs": {
+              "systems": "systems"
+            },
+            "locked": {
+              "lastModified": 1710146030,
+              "narHash": "sha256-SZ5L6eA7HJ/nmkzGG7/ISclqe6oZdOZTNoesiInkXPQ=",
+              "owner": "numtide",
+              "repo": "flake-utils",
+              "rev": "b1d9ab70662946ef0850d488da1c9019f3a9752a",
+              "type": "github"
+            },
+            "original": {
+              "owner": "numtide",
+              "repo": "flake-utils",
+              "type": "github"
+            }
+          },
+          "home-manager": {
+            "inputs": {
+              "nixpkgs": [
+                "nixpkgs"
+              ]
+            },
+            "locked": {
+              "lastModified": 1716457508,
+              "narHash": "sha256-ZxzffLuWRyuMrkVVq7wastNUqeO0HJL9xqfY1QsYaqo=",
+              "owner": "nix-community",
+              "repo": "home-manager",
+              "rev": "850cb322046ef1a268449cf1ceda5fd24d930b05",
+              "type": "github"
+            },
+            "original": {
+              "owner": "nix-community",
+              "repo": "home-manager",
+              "type": "github"
+            }
+          },
+          "nixpkgs": {
+            "locked": {
+              "lastModified": 1716330097,
+              "narHash": "sha256-8BO3B7e3BiyIDsaKA0tY8O88rClYRTjvAp66y+VBUeU=",
+              "owner": "NixOS",
+              "repo": "nixpkgs",
+              "rev": "5710852ba686cc1fd0d3b8e22b3117d43ba374c2",
+              "type": "github"
+            },
+            "original": {
+              "id": "nixpkgs",
+              "ref": "nixos-unstable",
+              "type": "indirect"
+            }
+          },
+          "root": {
+            "inputs": {
+              "flake-utils": "flake-utils",
+              "home-manager": "home-manager",
+              "nixpkgs": "nixpkgs"
+            }
+          },
+          "systems": {
+            "locked": {
+              "lastModified": 1681028828,
+              "narHash": "sha256-Vy1rq5AaRuLzOxct8nz4T6wlgyUR7zLU309k9mBC768=",
+              "owner": "nix-systems",
+              "repo": "default",
+              "rev": "da67096a3b9bf56a91d16901293e51ba5b49a27e",
+              "type": "github"
+            },
+            "original": {
+              "owner": "nix-systems",
+              "repo": "default",
+              "type": "github"
+            }
+          }
+        },
+        "root": "root",
+        "version": 7
+      }
+    "###;
+
+    #[test]
+    pub fn test_get_input_tree() {
+        let flake_lock: FlakeLock = serde_json::from_str(FLAKE_LOCK).unwrap();
+        let input_tree = flake_lock.get_input_tree();
+        assert_eq!(input_tree.len(), 3);
+
+        let flake_utils = &input_tree[0];
+        assert_eq!(flake_utils.name, "flake-utils");
+        assert_eq!(flake_utils.inputs.len(), 1);
+        assert_eq!(flake_utils.inputs[0].get_id(), "flake-utils/systems");
+        assert_eq!(
+            flake_utils.inputs[0].locked.as_ref().unwrap().nar_hash,
+            Some("sha256-Vy1rq5AaRuLzOxct8nz4T6wlgyUR7zLU309k9mBC768=".to_string())
+        );
+
+        let home_manager = &input_tree[1];
+        assert_eq!(home_manager.inputs.len(), 1);
+        let followed_nixpkgs = &home_manager.inputs[0];
+        assert_eq!(followed_nixpkgs.follows, Some(vec!["nixpkgs".to_string()]));
+        assert_eq!(followed_nixpkgs.get_resolved_id(), "nixpkgs");
+        assert_eq!(
+            get_nixpkgs_revision(&input_tree),
+            Some("5710852ba686cc1fd0d3b8e22b3117d43ba374c2".to_string())
+        );
+        assert_eq!(
+            followed_nixpkgs.locked.as_ref().unwrap().rev,
+            Some("5710852ba686cc1fd0d3b8e22b3117d43ba374c2".to_string())
+        );
+        assert_eq!(
+            followed_nixpkgs.locked.as_ref().unwrap().get_url(),
+            Some("https://github.com/NixOS/nixpkgs".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_read_local_flake_lock() {
+        let directory = std::env::temp_dir().join(format!("nix2sbom-test-flake-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("flake.lock"), FLAKE_LOCK).unwrap();
+        let flake_ref = format!("path:{}", directory.display());
+        let flake_lock = read_local_flake_lock(&flake_ref).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(flake_lock.get_input_tree().len(), 3);
+
+        assert!(read_local_flake_lock("github:louib/nix2sbom").is_err());
+    }
+
+    #[test]
+    pub fn test_get_flake_ref() {
+        assert_eq!(get_flake_ref(".#nix2sbom"), ".");
+        assert_eq!(get_flake_ref("github:louib/nix2sbom"), "github:louib/nix2sbom");
+    }
+
+    #[test]
+    pub fn test_is_flake_ref() {
+        assert!(is_flake_ref("github:louib/nix2sbom#nix2sbom"));
+        assert!(is_flake_ref("nixpkgs#hello"));
+        assert!(is_flake_ref("git+https://example.com/project.git?ref=main#hello"));
+        assert!(!is_flake_ref("/nix/store/a-hello-2.12.1.drv"));
+        assert!(!is_flake_ref("/nix/store/a-hello-2.12.1.drv^out"));
+        assert!(!is_flake_ref("/nix/store/a-hello-2.12.1"));
+        assert!(!is_flake_ref("/path/to/default.nix#hello"));
+        assert!(!is_flake_ref("./default.nix"));
+
+        let flake_dir = std::env::temp_dir().join(format!("nix2sbom-test-is-flake-ref-{}", std::process::id()));
+        let package_dir = flake_dir.join("packages").join("hello");
+        std::fs::create_dir_all(&package_dir).unwrap();
+        let package_ref = format!("{}#hello", package_dir.display());
+        assert!(!is_flake_ref(&package_ref));
+        std::fs::write(flake_dir.join("flake.nix"), "{ outputs = { ... }: { }; }").unwrap();
+        assert!(is_flake_ref(&package_ref));
+        assert!(is_flake_ref(&format!("path:{}#hello", flake_dir.display())));
+        std::fs::remove_dir_all(&flake_dir).unwrap();
+    }
+
+    #[test]
+    pub fn test_parse_package_metadata() {
+        let (drv_path, package) = parse_package_metadata(
+            br#"{
+              "drvPath": "/nix/store/a-acme-tool-1.0.0.drv",
+              "name": "acme-tool-1.0.0",
+              "pname": "acme-tool",
+              "version": "1.0.0",
+              "system": "x86_64-linux",
+              "outputName": "out",
+              "meta": {
+                "description": "An in-house tool",
+                "license": { "spdxId": "Apache-2.0", "shortName": "asl20", "fullName": "Apache License 2.0" }
+              }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(drv_path, "/nix/store/a-acme-tool-1.0.0.drv");
+        assert_eq!(package.pname, "acme-tool");
+        assert_eq!(
+            package.meta.get_spdx_license_expression(),
+            Some("Apache-2.0".to_string())
+        );
+        assert!(parse_package_metadata(br#"{ "name": "acme-tool-1.0.0" }"#).is_err());
+    }
+}
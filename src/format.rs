@@ -1,5 +1,7 @@
+pub mod adjacency;
 pub mod cyclone_dx;
 pub mod native;
+pub mod out_paths;
 pub mod spdx;
 
 pub const CYCLONE_DX_NAME: &str = "CycloneDX";
@@ -7,6 +9,8 @@ pub const SPDX_NAME: &str = "SPDX";
 pub const PRETTY_PRINT_NAME: &str = "pretty-print";
 pub const STATS_NAME: &str = "stats";
 pub const NATIVE_NAME: &str = "Native nix2sbom format";
+pub const OUT_PATHS_NAME: &str = "out-paths";
+pub const ADJACENCY_NAME: &str = "adjacency";
 
 pub enum Format {
     SPDX,
@@ -14,6 +18,8 @@ pub enum Format {
     PrettyPrint,
     Stats,
     Native,
+    OutPaths,
+    Adjacency,
 }
 
 impl Format {
@@ -33,6 +39,12 @@ impl Format {
         if format.ends_with("native") {
             return Some(Format::Native);
         }
+        if format.ends_with("out-paths") {
+            return Some(Format::OutPaths);
+        }
+        if format.ends_with("adjacency") {
+            return Some(Format::Adjacency);
+        }
         None
     }
 
@@ -43,9 +55,37 @@ impl Format {
             Format::PrettyPrint => PRETTY_PRINT_NAME.to_string(),
             Format::Stats => STATS_NAME.to_string(),
             Format::Native => NATIVE_NAME.to_string(),
+            Format::OutPaths => OUT_PATHS_NAME.to_string(),
+            Format::Adjacency => ADJACENCY_NAME.to_string(),
         }
     }
 
+    // Returns the conventional file name for a manifest in this format, e.g. sbom.cdx.json
+    pub fn get_file_name(&self, serialization_format: &SerializationFormat) -> String {
+        let format_name = match self {
+            Format::CycloneDX => "cdx",
+            Format::SPDX => "spdx",
+            Format::PrettyPrint => "pretty",
+            Format::Stats => "stats",
+            Format::Native => "native",
+            Format::OutPaths => "out-paths",
+            Format::Adjacency => "adjacency",
+        };
+        let extension = match self {
+            Format::PrettyPrint => "txt".to_string(),
+            // The conventional extension of the tag-value documents is .spdx
+            Format::SPDX if serialization_format == &SerializationFormat::TagValue => {
+                return "sbom.spdx".to_string();
+            }
+            Format::OutPaths if serialization_format != &SerializationFormat::JSON => "txt".to_string(),
+            Format::Adjacency if serialization_format != &SerializationFormat::JSON => "csv".to_string(),
+            // The native format is always serialized to JSON at the moment.
+            Format::Native => "json".to_string(),
+            _ => serialization_format.to_string(),
+        };
+        format!("sbom.{}.{}", format_name, extension)
+    }
+
     pub fn get_default_serialization_format(&self) -> SerializationFormat {
         match self {
             Format::CycloneDX => SerializationFormat::JSON,
@@ -54,6 +94,10 @@ impl Format {
             // We don't really care which value is returned in those cases.
             Format::PrettyPrint => SerializationFormat::XML,
             Format::Native => SerializationFormat::YAML,
+            // The out paths are printed one per line, unless JSON is requested.
+            Format::OutPaths => SerializationFormat::XML,
+            // The edges are written as CSV, unless JSON is requested.
+            Format::Adjacency => SerializationFormat::XML,
         }
     }
 
@@ -82,21 +126,42 @@ impl Format {
                     Err(s) => Err(anyhow::format_err!("Error dumping manifest: {}", s.to_string())),
                 };
             }
+            Format::OutPaths => match out_paths::dump(package_graph, serialization_format, options) {
+                Ok(d) => Ok(d),
+                Err(s) => Err(anyhow::format_err!("Error dumping manifest: {}", s)),
+            },
+            Format::Adjacency => match adjacency::dump(package_graph, serialization_format, options) {
+                Ok(d) => Ok(d),
+                Err(s) => Err(anyhow::format_err!("Error dumping manifest: {}", s)),
+            },
             Format::PrettyPrint => {
                 let display_options = crate::nix::DisplayOptions {
-                    print_stdenv: false,
+                    print_stdenv: options.print_stdenv,
                     print_only_purl: true,
-                    print_exclude_list: vec![],
-                    max_depth: Some(1),
+                    max_depth: Some(options.max_depth.unwrap_or(1)),
                 };
 
                 return Ok(package_graph.pretty_print(0, &display_options));
             }
-            Format::Stats => {
-                return Ok(serde_json::to_string_pretty(&package_graph.get_stats(options))?);
-            }
+            Format::Stats => Ok(serde_json::to_string_pretty(
+                &package_graph.get_stats(&options.stats),
+            )?),
         }
     }
+
+    // Same as dump, but also returns a report describing the generated SBOM.
+    pub fn dump_with_report(
+        &self,
+        serialization_format: &SerializationFormat,
+        package_graph: &crate::nix::PackageGraph,
+        options: &crate::nix::DumpOptions,
+    ) -> Result<(String, crate::report::GenerationReport), anyhow::Error> {
+        let started_at = std::time::Instant::now();
+        let dump = self.dump(serialization_format, package_graph, options)?;
+        let mut report = crate::report::GenerationReport::new(package_graph);
+        report.add_duration("dump", started_at);
+        Ok((dump, report))
+    }
 }
 
 impl Default for Format {
@@ -112,6 +177,8 @@ pub enum SerializationFormat {
     JSON,
     YAML,
     XML,
+    /// The tag-value format of SPDX.
+    TagValue,
 }
 
 impl SerializationFormat {
@@ -125,6 +192,9 @@ impl SerializationFormat {
         if format.ends_with("xml") {
             return Some(SerializationFormat::XML);
         }
+        if format.ends_with("tag-value") || format.ends_with("tv") {
+            return Some(SerializationFormat::TagValue);
+        }
         None
     }
     pub fn to_string(&self) -> String {
@@ -132,6 +202,88 @@ impl SerializationFormat {
             SerializationFormat::JSON => "json".to_string(),
             SerializationFormat::YAML => "yaml".to_string(),
             SerializationFormat::XML => "xml".to_string(),
+            SerializationFormat::TagValue => "tag-value".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_string(compression: &str) -> Option<Compression> {
+        match compression {
+            "gzip" | "gz" => Some(Compression::Gzip),
+            "zstd" | "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // Infers the compression from the extension of the output file, e.g. sbom.cdx.json.zst
+    pub fn from_path(path: &str) -> Option<Compression> {
+        match std::path::Path::new(path).extension() {
+            Some(extension) => match extension.to_str() {
+                Some("gz") => Some(Compression::Gzip),
+                Some("zst") => Some(Compression::Zstd),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    // Name of the command used to compress the content.
+    pub fn get_command_name(&self) -> &str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    // Conventional extension of the compressed files, e.g. sbom.cdx.json.gz
+    pub fn get_extension(&self) -> &str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    // Compresses the content with the gzip or zstd command, and writes the compressed
+    // content to the destination, which is either a file or stdout. The content is
+    // streamed to the command, so the compressed output is never held in memory.
+    pub fn compress_to(&self, content: &[u8], destination: std::process::Stdio) -> Result<(), anyhow::Error> {
+        let mut command = std::process::Command::new(self.get_command_name());
+        if self == &Compression::Zstd {
+            command.arg("--quiet");
+        }
+        let mut child = command
+            .arg("--stdout")
+            .stdin(std::process::Stdio::piped())
+            .stdout(destination)
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                anyhow::format_err!(
+                    "Could not run {} to compress the SBOM: {}",
+                    self.get_command_name(),
+                    e
+                )
+            })?;
+        let mut stdin = child.stdin.take().unwrap();
+        std::io::Write::write_all(&mut stdin, content)?;
+        drop(stdin);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow::format_err!(
+                "Could not compress the SBOM with {}: {}",
+                self.get_command_name(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
         }
+        Ok(())
     }
 }
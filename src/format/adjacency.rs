@@ -0,0 +1,122 @@
+// Edge list of the package graph, for ingestion into graph analytics tools. Each edge
+// links the purl of a package to the purl of one of its dependencies, sources or patches.
+use serde::Serialize;
+
+pub const RUNTIME_EDGE_KIND: &str = "runtime";
+pub const BUILD_EDGE_KIND: &str = "build";
+pub const SOURCE_EDGE_KIND: &str = "source";
+pub const PATCH_EDGE_KIND: &str = "patch";
+
+const CSV_HEADER: &str = "parent,child,kind";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct Edge {
+    pub parent: String,
+    pub child: String,
+    pub kind: String,
+}
+
+pub fn dump(
+    package_graph: &crate::nix::PackageGraph,
+    format: &crate::format::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, anyhow::Error> {
+    let edges = get_edges(package_graph, options);
+
+    match format {
+        crate::format::SerializationFormat::JSON => {
+            let response = match options.pretty {
+                Some(false) => serde_json::to_string(&edges)?,
+                _ => serde_json::to_string_pretty(&edges)?,
+            };
+            Ok(response)
+        }
+        _ => {
+            let mut lines: Vec<String> = vec![CSV_HEADER.to_string()];
+            for edge in &edges {
+                lines.push(format!(
+                    "{},{},{}",
+                    escape_csv_field(&edge.parent),
+                    escape_csv_field(&edge.child),
+                    edge.kind
+                ));
+            }
+            Ok(lines.join("\n"))
+        }
+    }
+}
+
+// The edges are sorted and deduplicated, since several derivations can share the same purl.
+pub fn get_edges(package_graph: &crate::nix::PackageGraph, options: &crate::nix::DumpOptions) -> Vec<Edge> {
+    let get_purl = |derivation_path: &String| {
+        package_graph
+            .nodes_next
+            .get(derivation_path)
+            .or(package_graph.nodes.get(derivation_path))
+            .map(|n| n.get_purl().to_string())
+    };
+
+    let mut edges: std::collections::BTreeSet<(String, String, &str)> = std::collections::BTreeSet::default();
+    for package_node in package_graph.nodes_next.values() {
+        let parent = package_node.get_purl().to_string();
+        let mut children: Vec<(&String, &str)> = package_node
+            .children
+            .iter()
+            .map(|c| (c, RUNTIME_EDGE_KIND))
+            .collect();
+        if !options.runtime_only {
+            children.extend(package_node.build_inputs.iter().map(|c| (c, BUILD_EDGE_KIND)));
+        }
+        children.extend(
+            package_node
+                .source_derivation
+                .iter()
+                .map(|c| (c, SOURCE_EDGE_KIND)),
+        );
+        children.extend(package_node.patches.iter().map(|c| (c, PATCH_EDGE_KIND)));
+
+        for (child_derivation_path, kind) in children {
+            // The excluded nodes are not part of the graph anymore.
+            if let Some(child) = get_purl(child_derivation_path) {
+                edges.insert((parent.clone(), child, kind));
+            }
+        }
+    }
+    edges
+        .into_iter()
+        .map(|(parent, child, kind)| Edge {
+            parent,
+            child,
+            kind: kind.to_string(),
+        })
+        .collect()
+}
+
+// See https://www.rfc-editor.org/rfc/rfc4180
+fn escape_csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", value.replace('"', "\"\""));
+    }
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_escape_csv_field() {
+        assert_eq!(
+            escape_csv_field("pkg:generic/zlib@1.3.1"),
+            "pkg:generic/zlib@1.3.1"
+        );
+        assert_eq!(
+            escape_csv_field("pkg:generic/a@1?download_url=https://a.org/a,b.tar.gz"),
+            "\"pkg:generic/a@1?download_url=https://a.org/a,b.tar.gz\""
+        );
+        assert_eq!(escape_csv_field("a\"b"), "\"a\"\"b\"");
+    }
+}
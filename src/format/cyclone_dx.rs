@@ -1,15 +1,27 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::SystemTime;
 
 use chrono::{DateTime, Utc};
 
-use serde_cyclonedx::cyclonedx::v_1_4::{
-    Commit, CommitBuilder, Component, ComponentBuilder, ComponentPedigreeBuilder, CycloneDxBuilder, Dependency,
-    DependencyBuilder, ExternalReference, ExternalReferenceBuilder, LicenseBuilder, LicenseChoice, Metadata,
-    ToolBuilder,
+use serde_cyclonedx::cyclonedx::v_1_5::{
+    Annotations, AnnotationsAnnotator, AnnotationsBuilder, Commit, CommitBuilder, Component, ComponentBuilder,
+    ComponentPedigreeBuilder, CycloneDx, CycloneDxBuilder, Dependency, DependencyBuilder, ExternalReference,
+    ExternalReferenceBuilder, Hash, HashAlg, HashBuilder, ImpactAnalysisState, License, LicenseBuilder,
+    LicenseChoice, LicenseChoiceUrlVariant0ItemUrl, Metadata, MetadataTools, OrganizationalContact,
+    OrganizationalEntityBuilder, Property, PropertyBuilder, ToolBuilder, Vulnerability,
+    VulnerabilityAnalysisBuilder, VulnerabilityBuilder, VulnerabilityItemAffectsBuilder,
+    VulnerabilitySourceBuilder,
 };
 
-const CURRENT_SPEC_VERSION: &str = "1.4";
+// Version of the serde-cyclonedx model used to build the BOM.
+pub const CURRENT_SPEC_VERSION: &str = "1.5";
+// Version of the spec emitted when none is requested.
+pub const DEFAULT_SPEC_VERSION: &str = "1.4";
+pub const SUPPORTED_SPEC_VERSIONS: [&str; 4] = ["1.3", "1.4", "1.5", "1.6"];
+const XML_NAMESPACE_PREFIX: &str = "http://cyclonedx.org/schema/bom/";
+// Reference of the component describing the SBOM when it was generated for several derivations.
+pub const ROOT_COMPONENT_REF: &str = "root";
+const STABLE_REF_PREFIX: &str = "component:";
 
 pub fn dump(
     package_graph: &crate::nix::PackageGraph,
@@ -17,45 +29,169 @@ pub fn dump(
     options: &crate::nix::DumpOptions,
 ) -> Result<String, anyhow::Error> {
     let mut metadata = Metadata::default();
-    let now = SystemTime::now();
-    let now: DateTime<Utc> = now.into();
-    metadata.timestamp = Some(now.to_rfc3339());
+    metadata.timestamp = match &options.metadata.timestamp {
+        Some(timestamp) => Some(timestamp.to_string()),
+        None => {
+            let now: DateTime<Utc> = SystemTime::now().into();
+            Some(now.to_rfc3339())
+        }
+    };
 
-    metadata.tools = Some(vec![ToolBuilder::default()
-        .vendor("louib".to_string())
+    // TODO the list of tools is deprecated since CycloneDX 1.5, in favor of
+    // a list of components and services.
+    metadata.tools = Some(MetadataTools::Variant1(vec![ToolBuilder::default()
+        .vendor(
+            options
+                .metadata
+                .tool_vendor
+                .clone()
+                .unwrap_or("louib".to_string()),
+        )
         .name(crate::consts::PROJECT_NAME.to_string())
         .version(env!("CARGO_PKG_VERSION"))
         .build()
-        .unwrap()]);
+        .unwrap()]));
+
+    let mut metadata_properties: Vec<Property> = vec![build_property(
+        crate::properties::API_VERSION,
+        &options.get_api_version(),
+    )];
+    for (reason, count) in package_graph.get_excluded_nodes_count() {
+        metadata_properties.push(build_property(
+            &crate::properties::get_excluded_property_name(&reason),
+            &count.to_string(),
+        ));
+    }
+    metadata_properties.extend(build_properties(crate::properties::get_store_path_properties(
+        &package_graph.store_paths,
+    )));
+    metadata_properties.extend(build_properties(crate::properties::get_evaluation_properties(
+        &options.evaluation,
+    )));
+    for (name, value) in &options.metadata.properties {
+        metadata_properties.push(build_property(name, value));
+    }
+    if let Some(metadata_error) = &options.metadata_error {
+        metadata_properties.push(build_property(crate::properties::METADATA_ERROR, metadata_error));
+    }
+    if !metadata_properties.is_empty() {
+        metadata.properties = Some(metadata_properties);
+    }
 
     let mut components: Vec<Component> = vec![];
+    let mut categories: BTreeMap<crate::classify::Category, Vec<Component>> = BTreeMap::default();
+    let mut annotations: Vec<Annotations> = vec![];
+    // The root derivation is the subject of the SBOM, and is described in the
+    // metadata instead of the list of components.
+    let root_node_path = package_graph
+        .get_root_node()
+        .filter(|p| package_graph.nodes_next.contains_key(p));
+    let component_refs = get_component_refs(package_graph, options);
+    let get_ref = |derivation_path: &str| -> String {
+        match component_refs.get(derivation_path) {
+            Some(r) => r.to_string(),
+            None => derivation_path.to_string(),
+        }
+    };
     for (derivation_path, package) in package_graph.nodes_next.iter() {
-        if let Some(component) = dump_package_node(derivation_path, package, package_graph) {
+        if let Some(mut component) = dump_package_node(derivation_path, package, package_graph, options) {
+            component.bom_ref = Some(get_ref(derivation_path));
+            if root_node_path.as_ref() == Some(derivation_path) {
+                metadata.component = Some(component);
+            } else if options.group_by_category {
+                categories
+                    .entry(crate::classify::classify(package))
+                    .or_default()
+                    .push(component);
+            } else {
+                components.push(component);
+            }
+            for warning in package.get_warnings() {
+                annotations.push(build_annotation(
+                    &get_ref(derivation_path),
+                    &warning,
+                    &metadata.timestamp,
+                ));
+            }
+        }
+    }
+    if let Some(metadata_error) = &options.metadata_error {
+        let text = format!(
+            "Generated without the package metadata (licenses, descriptions, homepages): {}",
+            metadata_error
+        );
+        for root_node in package_graph.root_nodes.iter() {
+            if package_graph.nodes_next.contains_key(root_node) {
+                annotations.push(build_annotation(&get_ref(root_node), &text, &metadata.timestamp));
+            }
+        }
+    }
+    for (category, category_components) in categories {
+        components.push(dump_category(&category, category_components));
+    }
+    for flake_input in package_graph.flake_inputs.iter() {
+        if let Some(component) = dump_flake_input(flake_input) {
             components.push(component);
         }
     }
+    for input_source in package_graph.input_sources.values() {
+        components.push(dump_input_source(input_source));
+    }
 
+    // The flake inputs are evaluation-time dependencies of the root packages.
+    let root_flake_inputs: Vec<String> = package_graph
+        .flake_inputs
+        .iter()
+        .map(|i| get_flake_input_ref(&i.get_resolved_id()))
+        .collect();
     let mut dependencies: Vec<Dependency> = vec![];
+    if metadata.component.is_none() && package_graph.root_nodes.len() > 1 {
+        let (component, dependency) = dump_root_nodes(package_graph, &get_ref);
+        metadata.component = Some(component);
+        dependencies.push(dependency);
+    }
+    add_metadata_overrides(&mut metadata, &options.metadata);
     for (derivation_path, package) in package_graph.nodes_next.iter() {
-        if package.children.len() == 0 {
+        let input_sources = get_input_sources(package_graph, package);
+        let flake_inputs: &[String] = if package_graph.root_nodes.contains(derivation_path) {
+            &root_flake_inputs
+        } else {
+            &[]
+        };
+        if package.children.is_empty() && input_sources.is_empty() && flake_inputs.is_empty() {
             continue;
         }
         let mut dependency_builder = DependencyBuilder::default();
-        dependency_builder.ref_(derivation_path);
-        let mut depends_on: Vec<String> = vec![];
+        dependency_builder.ref_(get_ref(derivation_path));
+        let mut depends_on: Vec<serde_json::Value> = vec![];
         for child in package.children.iter() {
-            depends_on.push(child.to_string());
+            depends_on.push(get_ref(child).into());
         }
         if !options.runtime_only {
             for build_input in package.build_inputs.iter() {
-                depends_on.push(build_input.to_string());
+                depends_on.push(get_ref(build_input).into());
             }
         }
+        for input_source in input_sources {
+            depends_on.push(input_source.as_str().into());
+        }
+        for flake_input in flake_inputs {
+            depends_on.push(flake_input.as_str().into());
+        }
         dependency_builder.depends_on(depends_on);
         dependencies.push(dependency_builder.build().unwrap());
     }
+    dependencies.extend(get_flake_input_dependencies(&package_graph.flake_inputs));
 
-    let cyclonedx = CycloneDxBuilder::default()
+    let mut cyclonedx_builder = CycloneDxBuilder::default();
+    if !annotations.is_empty() {
+        cyclonedx_builder.annotations(annotations);
+    }
+    let vulnerabilities = get_vulnerabilities(package_graph, &get_ref);
+    if !vulnerabilities.is_empty() {
+        cyclonedx_builder.vulnerabilities(vulnerabilities);
+    }
+    let cyclonedx = cyclonedx_builder
         .bom_format(crate::format::CYCLONE_DX_NAME)
         .spec_version(CURRENT_SPEC_VERSION)
         .version(1)
@@ -65,11 +201,39 @@ pub fn dump(
         .build()
         .unwrap();
 
+    let spec_version = options
+        .spec_version
+        .clone()
+        .unwrap_or(DEFAULT_SPEC_VERSION.to_string());
+    let mut cyclonedx = convert_to_spec_version(&cyclonedx, &spec_version)?;
+    // The omniborId field and the license acknowledgements were introduced with
+    // CycloneDX 1.6, so they are not in the model used to build the BOM.
+    if spec_version == "1.6" {
+        let omnibor_ids = get_omnibor_ids(package_graph, &get_ref);
+        let concluded_licenses = get_concluded_licenses(package_graph, &get_ref);
+        if let Some(component) = cyclonedx.pointer_mut("/metadata/component") {
+            add_omnibor_ids(std::slice::from_mut(component), &omnibor_ids);
+            add_license_acknowledgements(std::slice::from_mut(component), &concluded_licenses);
+        }
+        if let Some(components) = cyclonedx.get_mut("components").and_then(|c| c.as_array_mut()) {
+            add_omnibor_ids(components, &omnibor_ids);
+            add_license_acknowledgements(components, &concluded_licenses);
+        }
+    }
+    serialize(&cyclonedx, format, options)
+}
+
+// Serializes a BOM already converted to the requested spec version.
+pub fn serialize(
+    cyclonedx: &serde_json::Value,
+    format: &crate::format::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, anyhow::Error> {
     match format {
         crate::format::SerializationFormat::JSON => {
             let json_dump = match options.pretty {
-                Some(false) => serde_json::to_string(&cyclonedx),
-                _ => serde_json::to_string_pretty(&cyclonedx),
+                Some(false) => serde_json::to_string(cyclonedx),
+                _ => serde_json::to_string_pretty(cyclonedx),
             };
             return match json_dump {
                 Ok(j) => Ok(j),
@@ -77,25 +241,438 @@ pub fn dump(
             };
         }
         crate::format::SerializationFormat::YAML => {
-            serde_yaml::to_string(&cyclonedx).map_err(|e| anyhow::format_err!(e.to_string()))
+            serde_yaml::to_string(cyclonedx).map_err(|e| anyhow::format_err!(e.to_string()))
         }
-        crate::format::SerializationFormat::XML => Err(anyhow::format_err!(
-            "XML is not supported for CycloneDX".to_string()
+        crate::format::SerializationFormat::XML => dump_xml_value(cyclonedx, options.pretty != Some(false)),
+        crate::format::SerializationFormat::TagValue => Err(anyhow::format_err!(
+            "The tag-value format is only supported for SPDX".to_string()
         )),
     }
 }
 
+// The BOM is always built using the model of the current spec version. It is converted
+// to a newer version by going through the serde-cyclonedx model of that version, and to
+// an older version by removing or replacing the fields that the version does not support,
+// as described by the SPEC_BOM table.
+pub fn convert_to_spec_version(
+    cyclonedx: &CycloneDx,
+    spec_version: &str,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let mut value = serde_json::to_value(cyclonedx)?;
+    value["specVersion"] = spec_version.into();
+    match spec_version {
+        CURRENT_SPEC_VERSION => return Ok(value),
+        "1.6" => {
+            let mut value = serde_json::to_value(serde_json::from_value::<
+                serde_cyclonedx::cyclonedx::v_1_6::CycloneDx,
+            >(value)?)?;
+            value["specVersion"] = spec_version.into();
+            return Ok(value);
+        }
+        "1.3" | "1.4" => {}
+        _ => {
+            return Err(anyhow::format_err!(
+                "Unsupported CycloneDX spec version {}. Supported versions are {}",
+                spec_version,
+                SUPPORTED_SPEC_VERSIONS.join(", ")
+            ))
+        }
+    };
+    for annotation in value["annotations"].as_array().into_iter().flatten() {
+        let subjects: Vec<&str> = annotation["subjects"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|s| s.as_str())
+            .collect();
+        log::warn!(
+            "Dropping the annotation on {}, annotations are not supported by CycloneDX {}: {}",
+            subjects.join(", "),
+            spec_version,
+            annotation["text"].as_str().unwrap_or_default()
+        );
+    }
+    if let Some(tools) = value.pointer_mut("/metadata/tools") {
+        downgrade_tools(tools);
+    }
+    remove_unsupported_fields(&mut value, &SPEC_BOM, spec_version);
+    Ok(value)
+}
+
+fn is_before_spec_version(spec_version: &str, version: &str) -> bool {
+    let index = |v: &str| SUPPORTED_SPEC_VERSIONS.iter().position(|s| *s == v);
+    index(spec_version) < index(version)
+}
+
+// Since CycloneDX 1.5, the tools can be described as components and services instead of
+// a list of tools.
+fn downgrade_tools(tools: &mut serde_json::Value) {
+    if !tools.is_object() {
+        return;
+    }
+    let mut response: Vec<serde_json::Value> = vec![];
+    for component in tools["components"].as_array().into_iter().flatten() {
+        let mut tool = serde_json::Map::default();
+        for (tool_field, component_field) in [("vendor", "group"), ("name", "name"), ("version", "version")] {
+            if let Some(value) = component.get(component_field) {
+                tool.insert(tool_field.to_string(), value.clone());
+            }
+        }
+        response.push(tool.into());
+    }
+    *tools = response.into();
+}
+
+fn remove_unsupported_fields(value: &mut serde_json::Value, object: &SpecObject, spec_version: &str) {
+    if let Some(items) = value.as_array_mut() {
+        for item in items {
+            remove_unsupported_fields(item, object, spec_version);
+        }
+        return;
+    }
+    let fields = match value.as_object_mut() {
+        Some(f) => f,
+        None => return,
+    };
+    for (field, version) in object.fields {
+        if is_before_spec_version(spec_version, version) {
+            fields.remove(*field);
+        }
+    }
+    for (field, version, values, replacement) in object.values {
+        let is_unsupported = match fields.get(*field).and_then(|v| v.as_str()) {
+            Some(v) => values.contains(&v) && is_before_spec_version(spec_version, version),
+            None => false,
+        };
+        if is_unsupported {
+            fields.insert(field.to_string(), (*replacement).into());
+        }
+    }
+    for (field, version) in object.required {
+        if !is_before_spec_version(version, spec_version) && !fields.contains_key(*field) {
+            fields.insert(field.to_string(), "".into());
+        }
+    }
+    for (field, child) in object.children {
+        if let Some(child_value) = fields.get_mut(*field) {
+            remove_unsupported_fields(child_value, child, spec_version);
+        }
+    }
+}
+
+// Describes the fields of an object of the CycloneDX JSON model that are not supported
+// by all the spec versions, starting with 1.3.
+struct SpecObject {
+    // Field, and version of the spec that introduced it.
+    fields: &'static [(&'static str, &'static str)],
+    // Field, version of the spec that introduced the values, values, and value to use
+    // with the older versions.
+    values: &'static [(&'static str, &'static str, &'static [&'static str], &'static str)],
+    // Field, and latest version of the spec in which it is required.
+    required: &'static [(&'static str, &'static str)],
+    // Fields holding an object, or a list of objects.
+    children: &'static [(&'static str, &'static SpecObject)],
+}
+
+static SPEC_BOM: SpecObject = SpecObject {
+    fields: &[
+        ("vulnerabilities", "1.4"),
+        ("signature", "1.4"),
+        ("annotations", "1.5"),
+        ("formulation", "1.5"),
+        ("properties", "1.5"),
+    ],
+    values: &[],
+    required: &[],
+    children: &[
+        ("metadata", &SPEC_METADATA),
+        ("components", &SPEC_COMPONENT),
+        ("services", &SPEC_SERVICE),
+        ("externalReferences", &SPEC_EXTERNAL_REFERENCE),
+        ("compositions", &SPEC_COMPOSITION),
+        ("vulnerabilities", &SPEC_VULNERABILITY),
+    ],
+};
+
+static SPEC_METADATA: SpecObject = SpecObject {
+    fields: &[("lifecycles", "1.5")],
+    values: &[],
+    required: &[],
+    children: &[
+        ("tools", &SPEC_TOOL),
+        ("authors", &SPEC_ORGANIZATIONAL_CONTACT),
+        ("component", &SPEC_COMPONENT),
+        ("manufacture", &SPEC_ORGANIZATIONAL_ENTITY),
+        ("supplier", &SPEC_ORGANIZATIONAL_ENTITY),
+        ("licenses", &SPEC_LICENSE_CHOICE),
+    ],
+};
+
+static SPEC_TOOL: SpecObject = SpecObject {
+    fields: &[("externalReferences", "1.4")],
+    values: &[],
+    required: &[],
+    children: &[],
+};
+
+static SPEC_ORGANIZATIONAL_ENTITY: SpecObject = SpecObject {
+    fields: &[("bom-ref", "1.5")],
+    values: &[],
+    required: &[],
+    children: &[("contact", &SPEC_ORGANIZATIONAL_CONTACT)],
+};
+
+static SPEC_ORGANIZATIONAL_CONTACT: SpecObject = SpecObject {
+    fields: &[("bom-ref", "1.5")],
+    values: &[],
+    required: &[],
+    children: &[],
+};
+
+static SPEC_COMPONENT: SpecObject = SpecObject {
+    fields: &[
+        ("releaseNotes", "1.4"),
+        ("signature", "1.4"),
+        ("modelCard", "1.5"),
+        ("data", "1.5"),
+    ],
+    values: &[(
+        "type",
+        "1.5",
+        &["platform", "device-driver", "machine-learning-model", "data"],
+        "library",
+    )],
+    required: &[("version", "1.3")],
+    children: &[
+        ("supplier", &SPEC_ORGANIZATIONAL_ENTITY),
+        ("licenses", &SPEC_LICENSE_CHOICE),
+        ("pedigree", &SPEC_PEDIGREE),
+        ("externalReferences", &SPEC_EXTERNAL_REFERENCE),
+        ("components", &SPEC_COMPONENT),
+        ("evidence", &SPEC_EVIDENCE),
+    ],
+};
+
+static SPEC_PEDIGREE: SpecObject = SpecObject {
+    fields: &[],
+    values: &[],
+    required: &[],
+    children: &[
+        ("ancestors", &SPEC_COMPONENT),
+        ("descendants", &SPEC_COMPONENT),
+        ("variants", &SPEC_COMPONENT),
+    ],
+};
+
+static SPEC_EVIDENCE: SpecObject = SpecObject {
+    fields: &[("identity", "1.5"), ("occurrences", "1.5"), ("callstack", "1.5")],
+    values: &[],
+    required: &[],
+    children: &[("licenses", &SPEC_LICENSE_CHOICE)],
+};
+
+static SPEC_SERVICE: SpecObject = SpecObject {
+    fields: &[
+        ("releaseNotes", "1.4"),
+        ("signature", "1.4"),
+        ("trustZone", "1.5"),
+    ],
+    values: &[],
+    required: &[],
+    children: &[
+        ("provider", &SPEC_ORGANIZATIONAL_ENTITY),
+        ("licenses", &SPEC_LICENSE_CHOICE),
+        ("externalReferences", &SPEC_EXTERNAL_REFERENCE),
+        ("services", &SPEC_SERVICE),
+    ],
+};
+
+// An item of a list of licenses, either a license or an expression.
+static SPEC_LICENSE_CHOICE: SpecObject = SpecObject {
+    fields: &[("bom-ref", "1.5")],
+    values: &[],
+    required: &[],
+    children: &[("license", &SPEC_LICENSE)],
+};
+
+static SPEC_LICENSE: SpecObject = SpecObject {
+    fields: &[("bom-ref", "1.5"), ("licensing", "1.5"), ("properties", "1.5")],
+    values: &[],
+    required: &[],
+    children: &[],
+};
+
+static SPEC_EXTERNAL_REFERENCE: SpecObject = SpecObject {
+    fields: &[],
+    values: &[
+        ("type", "1.4", &["release-notes"], "other"),
+        (
+            "type",
+            "1.5",
+            &[
+                "security-contact",
+                "model-card",
+                "log",
+                "configuration",
+                "evidence",
+                "formulation",
+                "attestation",
+                "threat-model",
+                "adversary-model",
+                "risk-assessment",
+                "vulnerability-assertion",
+                "exploitability-statement",
+                "pentest-report",
+                "static-analysis-report",
+                "dynamic-analysis-report",
+                "runtime-analysis-report",
+                "component-analysis-report",
+                "maturity-report",
+                "certification-report",
+                "codified-infrastructure",
+                "quality-metrics",
+                "poam",
+                "distribution-intake",
+            ],
+            "other",
+        ),
+    ],
+    required: &[],
+    children: &[],
+};
+
+static SPEC_COMPOSITION: SpecObject = SpecObject {
+    fields: &[("bom-ref", "1.5"), ("vulnerabilities", "1.5")],
+    values: &[(
+        "aggregate",
+        "1.5",
+        &[
+            "incomplete_first_party_proprietary_only",
+            "incomplete_first_party_opensource_only",
+            "incomplete_third_party_proprietary_only",
+            "incomplete_third_party_opensource_only",
+        ],
+        "incomplete",
+    )],
+    required: &[],
+    children: &[],
+};
+
+static SPEC_VULNERABILITY: SpecObject = SpecObject {
+    fields: &[
+        ("rejected", "1.5"),
+        ("proofOfConcept", "1.5"),
+        ("workaround", "1.5"),
+    ],
+    values: &[],
+    required: &[],
+    children: &[
+        ("analysis", &SPEC_VULNERABILITY_ANALYSIS),
+        ("ratings", &SPEC_RATING),
+    ],
+};
+
+static SPEC_VULNERABILITY_ANALYSIS: SpecObject = SpecObject {
+    fields: &[("firstIssued", "1.5"), ("lastUpdated", "1.5")],
+    values: &[],
+    required: &[],
+    children: &[],
+};
+
+static SPEC_RATING: SpecObject = SpecObject {
+    fields: &[],
+    values: &[("method", "1.5", &["CVSSv4", "SSVC"], "other")],
+    required: &[],
+    children: &[],
+};
+
+// The licenses concluded for the packages, indexed by bom-ref.
+fn get_concluded_licenses(
+    package_graph: &crate::nix::PackageGraph,
+    get_ref: &dyn Fn(&str) -> String,
+) -> BTreeMap<String, String> {
+    let mut response: BTreeMap<String, String> = BTreeMap::default();
+    for (derivation_path, package_node) in package_graph.nodes_next.iter() {
+        if let Some(concluded_license) = &package_node.concluded_license {
+            response.insert(get_ref(derivation_path), concluded_license.to_string());
+        }
+    }
+    response
+}
+
+// The licenses of the package metadata are the declared licenses, and the licenses
+// concluded after review are added to the list with the concluded acknowledgement.
+fn add_license_acknowledgements(
+    components: &mut [serde_json::Value],
+    concluded_licenses: &BTreeMap<String, String>,
+) {
+    for component in components.iter_mut() {
+        let licenses = component.get_mut("licenses").and_then(|l| l.as_array_mut());
+        for license_choice in licenses.into_iter().flatten() {
+            if let Some(license) = license_choice.get_mut("license") {
+                license["acknowledgement"] = "declared".into();
+            }
+        }
+        let bom_ref = component["bom-ref"].as_str().unwrap_or_default().to_string();
+        if let Some(concluded_license) = concluded_licenses.get(&bom_ref) {
+            let mut license = match crate::license::get_canonical_license_id(concluded_license) {
+                Some(id) => serde_json::json!({ "id": id }),
+                None => serde_json::json!({ "name": concluded_license }),
+            };
+            license["acknowledgement"] = "concluded".into();
+            match component.get_mut("licenses").and_then(|l| l.as_array_mut()) {
+                Some(licenses) => licenses.push(serde_json::json!({ "license": license })),
+                None => component["licenses"] = serde_json::json!([{ "license": license }]),
+            }
+        }
+        if let Some(sub_components) = component.get_mut("components").and_then(|c| c.as_array_mut()) {
+            add_license_acknowledgements(sub_components, concluded_licenses);
+        }
+    }
+}
+
+// The OmniBOR artifact ids of the sources of the packages, indexed by bom-ref.
+fn get_omnibor_ids(
+    package_graph: &crate::nix::PackageGraph,
+    get_ref: &dyn Fn(&str) -> String,
+) -> BTreeMap<String, Vec<String>> {
+    let mut response: BTreeMap<String, Vec<String>> = BTreeMap::default();
+    for (derivation_path, package_node) in package_graph.nodes_next.iter() {
+        let source_derivation_path = match &package_node.source_derivation {
+            Some(p) => p,
+            None => continue,
+        };
+        if let Some(omnibor_ids) = package_graph.omnibor_ids.get(source_derivation_path) {
+            response.insert(get_ref(derivation_path), omnibor_ids.clone());
+        }
+    }
+    response
+}
+
+fn add_omnibor_ids(components: &mut [serde_json::Value], omnibor_ids: &BTreeMap<String, Vec<String>>) {
+    for component in components.iter_mut() {
+        let bom_ref = component["bom-ref"].as_str().unwrap_or_default().to_string();
+        if let Some(ids) = omnibor_ids.get(&bom_ref) {
+            component["omniborId"] = ids.clone().into();
+        }
+        if let Some(sub_components) = component.get_mut("components").and_then(|c| c.as_array_mut()) {
+            add_omnibor_ids(sub_components, omnibor_ids);
+        }
+    }
+}
+
 fn dump_package_node(
     package_derivation_path: &str,
     package_node: &crate::nix::PackageNode,
     package_graph: &crate::nix::PackageGraph,
+    options: &crate::nix::DumpOptions,
 ) -> Option<Component> {
     // FIXME this should be configurable.
     if package_node.is_inline_script() {
         return None;
     }
 
-    let component = dump_derivation(package_graph, package_derivation_path, package_node);
+    let component = dump_derivation(package_graph, package_derivation_path, package_node, options);
     // TODO handle sub-components https://github.com/louib/nix2sbom/issues/14
     component
 }
@@ -104,6 +681,7 @@ fn dump_derivation(
     package_graph: &crate::nix::PackageGraph,
     derivation_path: &str,
     package_node: &crate::nix::PackageNode,
+    options: &crate::nix::DumpOptions,
 ) -> Option<Component> {
     log::debug!("Dumping derivation for {}", &derivation_path);
     let mut component_builder = ComponentBuilder::default();
@@ -117,7 +695,10 @@ fn dump_derivation(
     // component_builder.cpe("TODO".to_string())
     // TODO application is the generic type, but we should also use file and library
     // also, populate the mime_type in case of a file type.
-    component_builder.type_("application".to_string());
+    match crate::classify::get_kernel_component(package_node) {
+        Some(kernel_component) => component_builder.type_(kernel_component.get_cyclonedx_type().to_string()),
+        None => component_builder.type_("application".to_string()),
+    };
     // I'm assuming here that if a package has been installed by Nix, it was required.
     component_builder.scope("required".to_string());
     component_builder.purl(package_node.get_purl().to_string());
@@ -134,8 +715,26 @@ fn dump_derivation(
     if let Some(author) = get_author(&package_node) {
         component_builder.author(author);
     }
+    // Only the packages that are downloaded as is, like the binary distributions,
+    // are fixed-output derivations.
+    let hashes = get_hashes(&package_node.main_derivation);
+    if !hashes.is_empty() {
+        component_builder.hashes(hashes);
+    }
 
-    let external_references: Vec<ExternalReference> = get_external_references(&package_node);
+    let mut external_references: Vec<ExternalReference> = get_external_references(package_node);
+    if let Some(source_reference) = get_source_reference(package_graph, package_node) {
+        external_references.push(source_reference);
+    }
+    if let Some(build_url) = options.get_build_url(derivation_path) {
+        external_references.push(
+            ExternalReferenceBuilder::default()
+                .type_("build-system")
+                .url(build_url)
+                .build()
+                .unwrap(),
+        );
+    }
     if external_references.len() != 0 {
         component_builder.external_references(external_references);
     }
@@ -147,14 +746,243 @@ fn dump_derivation(
         component_builder.pedigree(pedigree_builder.build().unwrap());
     }
 
-    let licenses = get_licenses(&package_node);
-    if licenses.len() != 0 {
+    // The licenses field holds the licenses declared in the package metadata. With
+    // CycloneDX 1.6, the concluded license is added to it, see add_license_acknowledgements.
+    if let Some(licenses) = get_licenses(package_node, options) {
         component_builder.licenses(licenses);
     }
+    let mut properties = build_properties(crate::properties::get_package_node_properties(package_node));
+    properties.extend(build_properties(crate::properties::get_captured_env_properties(
+        package_node,
+        &options.captured_env,
+    )));
+    properties.extend(build_properties(crate::properties::get_license_text_properties(
+        package_node,
+        &options.license_texts,
+    )));
+    if !properties.is_empty() {
+        component_builder.properties(properties);
+    }
+
+    Some(component_builder.build().unwrap())
+}
+
+// The bom-refs of the components, indexed by derivation path. By default the derivation
+// paths are used as bom-refs. The stable refs are derived from the purls, which include
+// the versions, and the derivations sharing a purl are numbered in the order of their paths.
+fn get_component_refs(
+    package_graph: &crate::nix::PackageGraph,
+    options: &crate::nix::DumpOptions,
+) -> BTreeMap<String, String> {
+    let mut response: BTreeMap<String, String> = BTreeMap::default();
+    if !options.stable_refs {
+        return response;
+    }
+    let mut used_refs: BTreeMap<String, usize> = BTreeMap::default();
+    for (derivation_path, package_node) in package_graph.nodes_next.iter() {
+        let digest = crate::utils::get_stable_hash(&format!(
+            "{} {}",
+            package_node.get_purl().to_string(),
+            package_node.version.clone().unwrap_or_default()
+        ));
+        let mut stable_ref = format!("{}{}", STABLE_REF_PREFIX, digest);
+        let count = used_refs.entry(stable_ref.clone()).or_default();
+        *count += 1;
+        if *count > 1 {
+            stable_ref = format!("{}-{}", stable_ref, count);
+        }
+        response.insert(derivation_path.to_string(), stable_ref);
+    }
+    response
+}
+
+// Describes the SBOM generated for several derivations, which all depend on this component.
+fn dump_root_nodes(
+    package_graph: &crate::nix::PackageGraph,
+    get_ref: &dyn Fn(&str) -> String,
+) -> (Component, Dependency) {
+    let root_node_paths: Vec<&String> = package_graph
+        .root_nodes
+        .iter()
+        .filter(|p| package_graph.nodes_next.contains_key(*p))
+        .collect();
+    let name = root_node_paths
+        .iter()
+        .map(|p| match &package_graph.nodes_next[*p].name {
+            Some(n) => n.to_string(),
+            None => crate::nix::get_store_path_name(p),
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    let component = ComponentBuilder::default()
+        .bom_ref(ROOT_COMPONENT_REF.to_string())
+        .name(name)
+        .type_("application".to_string())
+        .scope("required".to_string())
+        .build()
+        .unwrap();
+    let dependency = DependencyBuilder::default()
+        .ref_(ROOT_COMPONENT_REF)
+        .depends_on(
+            root_node_paths
+                .iter()
+                .map(|p| serde_json::Value::from(get_ref(p)))
+                .collect::<Vec<serde_json::Value>>(),
+        )
+        .build()
+        .unwrap();
+    (component, dependency)
+}
+
+// The categories are aggregates of components, which only exist to make the
+// SBOM easier to review. They do not appear in the dependencies.
+fn dump_category(category: &crate::classify::Category, components: Vec<Component>) -> Component {
+    ComponentBuilder::default()
+        .bom_ref(format!("category:{}", category.get_name()))
+        .name(category.get_name().to_string())
+        .description(category.get_description().to_string())
+        .type_("application".to_string())
+        .scope("required".to_string())
+        .properties(vec![build_property(
+            crate::properties::CATEGORY,
+            category.get_name(),
+        )])
+        .components(components)
+        .build()
+        .unwrap()
+}
+
+fn dump_flake_input(flake_input: &crate::flake::FlakeInput) -> Option<Component> {
+    // Inputs following another input are already described where the followed
+    // input is declared.
+    if flake_input.follows.is_some() {
+        return None;
+    }
+    let mut component_builder = ComponentBuilder::default();
+    component_builder.bom_ref(get_flake_input_ref(&flake_input.get_id()));
+    component_builder.name(flake_input.name.to_string());
+    component_builder.type_("application".to_string());
+    component_builder.scope("required".to_string());
+
+    if let Some(locked) = &flake_input.locked {
+        if let Some(rev) = &locked.rev {
+            component_builder.version(rev.to_string());
+        }
+        if let Some(url) = locked.get_url() {
+            let mut external_reference_builder = ExternalReferenceBuilder::default();
+            external_reference_builder.type_("vcs");
+            external_reference_builder.url(url.as_str());
+            component_builder.external_references(vec![external_reference_builder.build().unwrap()]);
+        }
+    }
+    let properties = build_properties(crate::properties::get_flake_input_properties(flake_input));
+    if !properties.is_empty() {
+        component_builder.properties(properties);
+    }
+
+    let mut sub_components: Vec<Component> = vec![];
+    for sub_input in &flake_input.inputs {
+        if let Some(component) = dump_flake_input(sub_input) {
+            sub_components.push(component);
+        }
+    }
+    if !sub_components.is_empty() {
+        component_builder.components(sub_components);
+    }
 
     Some(component_builder.build().unwrap())
 }
 
+fn get_flake_input_ref(flake_input_id: &str) -> String {
+    format!("flake-input:{}", flake_input_id)
+}
+
+// Links the flake inputs to their own inputs, recursively.
+fn get_flake_input_dependencies(flake_inputs: &[crate::flake::FlakeInput]) -> Vec<Dependency> {
+    let mut dependencies: Vec<Dependency> = vec![];
+    for flake_input in flake_inputs {
+        if flake_input.follows.is_some() || flake_input.inputs.is_empty() {
+            continue;
+        }
+        let depends_on: Vec<serde_json::Value> = flake_input
+            .inputs
+            .iter()
+            .map(|i| get_flake_input_ref(&i.get_resolved_id()).into())
+            .collect();
+        dependencies.push(
+            DependencyBuilder::default()
+                .ref_(get_flake_input_ref(&flake_input.get_id()))
+                .depends_on(depends_on)
+                .build()
+                .unwrap(),
+        );
+        dependencies.extend(get_flake_input_dependencies(&flake_input.inputs));
+    }
+    dependencies
+}
+
+// The input sources are only listed if they were populated in the graph.
+fn get_input_sources<'a>(
+    package_graph: &crate::nix::PackageGraph,
+    package_node: &'a crate::nix::PackageNode,
+) -> Vec<&'a String> {
+    package_node
+        .main_derivation
+        .inputs_sources
+        .iter()
+        .filter(|p| package_graph.input_sources.contains_key(*p))
+        .collect()
+}
+
+fn dump_input_source(input_source: &crate::nix::InputSource) -> Component {
+    let mut component_builder = ComponentBuilder::default();
+    component_builder.bom_ref(input_source.path.to_string());
+    component_builder.name(input_source.name.to_string());
+    component_builder.type_("file".to_string());
+    component_builder.scope("required".to_string());
+    if let Some(sha256) = &input_source.sha256 {
+        let hash: Hash = HashBuilder::default()
+            .alg(HashAlg::Sha256)
+            .content(sha256.to_string())
+            .build()
+            .unwrap();
+        component_builder.hashes(vec![hash]);
+    }
+    component_builder.build().unwrap()
+}
+
+fn build_property(name: &str, value: &str) -> Property {
+    PropertyBuilder::default()
+        .name(name)
+        .value(value)
+        .build()
+        .unwrap()
+}
+
+fn build_annotation(derivation_path: &str, text: &str, timestamp: &Option<String>) -> Annotations {
+    let annotator = serde_json::json!({
+        "component": {
+            "type": "application",
+            "name": crate::consts::PROJECT_NAME,
+            "version": env!("CARGO_PKG_VERSION"),
+        }
+    });
+    AnnotationsBuilder::default()
+        .subjects(vec![serde_json::Value::from(derivation_path)])
+        .annotator(AnnotationsAnnotator::Variant0(annotator))
+        .timestamp(timestamp.clone().unwrap_or_default())
+        .text(text)
+        .build()
+        .unwrap()
+}
+
+fn build_properties(properties: crate::properties::Properties) -> Vec<Property> {
+    properties
+        .iter()
+        .map(|(name, value)| build_property(name, value))
+        .collect()
+}
+
 fn get_author(package_node: &crate::nix::PackageNode) -> Option<String> {
     let maintainers = match &package_node.package {
         Some(p) => p.meta.get_maintainers(),
@@ -179,6 +1007,71 @@ fn get_author(package_node: &crate::nix::PackageNode) -> Option<String> {
     None
 }
 
+// The advisories fixed by the patches of nixpkgs are reported as resolved
+// vulnerabilities of the patched components, which makes the SBOM a partial VEX.
+fn get_vulnerabilities(
+    package_graph: &crate::nix::PackageGraph,
+    get_ref: &dyn Fn(&str) -> String,
+) -> Vec<Vulnerability> {
+    // advisory id -> patched component -> patches
+    let mut advisories: BTreeMap<String, BTreeMap<String, BTreeSet<String>>> = BTreeMap::default();
+    for (derivation_path, package_node) in package_graph.nodes_next.iter() {
+        if package_node.is_inline_script() || package_node.name.is_none() {
+            continue;
+        }
+        for (advisory_id, patches) in crate::advisory::get_patched_advisories(package_node, package_graph) {
+            advisories
+                .entry(advisory_id)
+                .or_default()
+                .insert(derivation_path.to_string(), patches);
+        }
+    }
+
+    let mut vulnerabilities: Vec<Vulnerability> = vec![];
+    for (advisory_id, components) in advisories {
+        let (source_name, source_url) = crate::advisory::get_advisory_source(&advisory_id);
+        let patches = components
+            .values()
+            .flatten()
+            .cloned()
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect::<Vec<String>>();
+        let affects = components
+            .keys()
+            .map(|c| {
+                VulnerabilityItemAffectsBuilder::default()
+                    .ref_(get_ref(c))
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        vulnerabilities.push(
+            VulnerabilityBuilder::default()
+                .bom_ref(format!("advisory:{}", advisory_id))
+                .id(advisory_id)
+                .source(
+                    VulnerabilitySourceBuilder::default()
+                        .name(source_name)
+                        .url(source_url)
+                        .build()
+                        .unwrap(),
+                )
+                .analysis(
+                    VulnerabilityAnalysisBuilder::default()
+                        .state(ImpactAnalysisState::Resolved)
+                        .detail(format!("Patched in nixpkgs by {}", patches.join(", ")))
+                        .build()
+                        .unwrap(),
+                )
+                .affects(affects)
+                .build()
+                .unwrap(),
+        );
+    }
+    vulnerabilities
+}
+
 fn get_commits(package_graph: &crate::nix::PackageGraph, patches: &BTreeSet<String>) -> Vec<Commit> {
     let response: Vec<Commit> = vec![];
     if patches.len() != 0 {
@@ -215,7 +1108,7 @@ fn get_external_references(package_node: &crate::nix::PackageNode) -> Vec<Extern
         // See https://docs.rs/serde-cyclonedx/latest/serde_cyclonedx/cyclonedx/v_1_5/struct.ExternalReference.html#structfield.type_
         // for all the available external reference types
         external_reference_builder.type_("website");
-        external_reference_builder.url(homepage.to_string());
+        external_reference_builder.url(homepage.as_str());
         external_references.push(external_reference_builder.build().unwrap());
     }
     // for source in &package_node.sources {
@@ -234,25 +1127,115 @@ fn get_external_references(package_node: &crate::nix::PackageNode) -> Vec<Extern
     for git_url in &package_node.git_urls {
         let mut external_reference_builder = ExternalReferenceBuilder::default();
         external_reference_builder.type_("vcs");
-        external_reference_builder.url(git_url);
+        external_reference_builder.url(git_url.as_str());
         external_references.push(external_reference_builder.build().unwrap());
     }
     external_references
 }
 
-fn get_licenses(package_node: &crate::nix::PackageNode) -> Vec<LicenseChoice> {
-    let mut response: Vec<LicenseChoice> = vec![];
+// The source archive is referenced as a distribution, with its hash so that
+// consumers can verify the archive they download.
+fn get_source_reference(
+    package_graph: &crate::nix::PackageGraph,
+    package_node: &crate::nix::PackageNode,
+) -> Option<ExternalReference> {
+    let source_derivation_path = package_node.source_derivation.as_ref()?;
+    let source_derivation = &package_graph.nodes.get(source_derivation_path)?.main_derivation;
+    let url = source_derivation.get_url()?;
+    let mut external_reference_builder = ExternalReferenceBuilder::default();
+    external_reference_builder.type_("distribution");
+    external_reference_builder.url(url.as_str());
+    let hashes = get_hashes(source_derivation);
+    if !hashes.is_empty() {
+        external_reference_builder.hashes(hashes);
+    }
+    Some(external_reference_builder.build().unwrap())
+}
+
+fn get_hashes(derivation: &crate::nix::Derivation) -> Vec<Hash> {
+    let (algorithm, digest) = match derivation.get_artifact_hash() {
+        Some(h) => h,
+        None => return vec![],
+    };
+    let alg = match algorithm.as_str() {
+        "md5" => HashAlg::Md5,
+        "sha1" => HashAlg::Sha1,
+        "sha256" => HashAlg::Sha256,
+        "sha512" => HashAlg::Sha512,
+        _ => return vec![],
+    };
+    vec![HashBuilder::default().alg(alg).content(digest).build().unwrap()]
+}
+
+// Adds the supplier, the licenses of the BOM and the description of the root
+// component set in the configuration or the metadata template.
+fn add_metadata_overrides(metadata: &mut Metadata, metadata_config: &crate::config::MetadataConfig) {
+    if let Some(supplier) = &metadata_config.supplier {
+        let mut supplier_builder = OrganizationalEntityBuilder::default();
+        if let Some(name) = &supplier.name {
+            supplier_builder.name(name);
+        }
+        if !supplier.url.is_empty() {
+            supplier_builder.url(supplier.url.clone());
+        }
+        if !supplier.contact.is_empty() {
+            supplier_builder.contact(
+                supplier
+                    .contact
+                    .iter()
+                    .map(|c| OrganizationalContact {
+                        bom_ref: None,
+                        email: c.email.clone(),
+                        name: c.name.clone(),
+                        phone: c.phone.clone(),
+                    })
+                    .collect::<Vec<OrganizationalContact>>(),
+            );
+        }
+        metadata.supplier = Some(supplier_builder.build().unwrap());
+    }
+    if !metadata_config.licenses.is_empty() {
+        let licenses = metadata_config
+            .licenses
+            .iter()
+            .map(|l| {
+                let mut license_builder = LicenseBuilder::default();
+                match crate::license::get_canonical_license_id(l) {
+                    Some(license_id) => license_builder.id(license_id),
+                    None => license_builder.name(l),
+                };
+                LicenseChoiceUrlVariant0ItemUrl {
+                    license: license_builder.build().unwrap(),
+                }
+            })
+            .collect::<Vec<LicenseChoiceUrlVariant0ItemUrl>>();
+        metadata.licenses = Some(LicenseChoice::Variant0(licenses));
+    }
+    if let (Some(description), Some(component)) = (&metadata_config.description, metadata.component.as_mut()) {
+        component.description = Some(description.to_string());
+    }
+}
+
+fn get_licenses(
+    package_node: &crate::nix::PackageNode,
+    options: &crate::nix::DumpOptions,
+) -> Option<LicenseChoice> {
+    let mut response: Vec<LicenseChoiceUrlVariant0ItemUrl> = vec![];
     let licenses = match &package_node.package {
         Some(p) => p.meta.get_licenses(),
         None => vec![],
     };
     for license in licenses {
-        match license {
+        let license: License = match license {
             crate::nix::PackageLicense::Name(n) => {
-                response.push(LicenseChoice {
-                    expression: Some(n.to_string()),
-                    license: None,
-                });
+                let mut license_builder = LicenseBuilder::default();
+                license_builder.name(&n);
+                if let Some(license_text_path) = crate::license::get_canonical_license_id(&n)
+                    .and_then(|id| options.license_texts.get(id.trim_end_matches('+')))
+                {
+                    license_builder.url(license_text_path);
+                }
+                license_builder.build().unwrap()
             }
             crate::nix::PackageLicense::Details(license_details) => {
                 let mut license_builder = LicenseBuilder::default();
@@ -263,12 +1246,669 @@ fn get_licenses(package_node: &crate::nix::PackageNode) -> Vec<LicenseChoice> {
                 if let Some(full_name) = &license_details.full_name {
                     license_builder.name(full_name);
                 }
-                response.push(LicenseChoice {
-                    expression: None,
-                    license: Some(license_builder.build().unwrap()),
-                });
+                // The license text written with --license-texts, for offline use. The texts
+                // of the licenses of an expression are referenced in the properties.
+                if let Some(license_text_path) = license_details
+                    .spdx_id
+                    .as_ref()
+                    .and_then(|id| crate::license::get_canonical_license_id(id))
+                    .and_then(|id| options.license_texts.get(id.trim_end_matches('+')))
+                {
+                    license_builder.url(license_text_path);
+                }
+                license_builder.build().unwrap()
+            }
+        };
+        response.push(LicenseChoiceUrlVariant0ItemUrl { license });
+    }
+    if response.is_empty() {
+        return None;
+    }
+    Some(LicenseChoice::Variant0(response))
+}
+
+// Describes how the fields of an object of the CycloneDX JSON model map to the
+// XML representation. The fields must be listed in the order of the XML schema.
+// See https://cyclonedx.org/docs/1.5/xml/
+enum XmlField {
+    // JSON field, XML attribute.
+    Attribute(&'static str, &'static str),
+    // Same as an attribute, but the value is a number in the JSON model.
+    NumberAttribute(&'static str, &'static str),
+    // JSON field stored as a child element with the same name.
+    Text(&'static str),
+    // JSON field stored as the text of the element itself.
+    Content(&'static str),
+    Element(&'static str, &'static XmlKind),
+    // JSON array stored in a wrapper element with the same name, with one
+    // child element per item.
+    List(&'static str, &'static str, &'static XmlKind),
+    // JSON array stored as child elements, without a wrapper element.
+    UnwrappedList(&'static str, &'static str, &'static XmlKind),
+}
+
+enum XmlKind {
+    Object(&'static [XmlField]),
+    Text,
+    // A reference to a bom-ref, stored in the ref attribute of the element.
+    Ref,
+    // An object with a single field, the name of the field being the name of the element.
+    Choice(&'static [(&'static str, &'static XmlKind)]),
+}
+
+static XML_BOM: XmlKind = XmlKind::Object(&[
+    XmlField::Attribute("serialNumber", "serialNumber"),
+    XmlField::NumberAttribute("version", "version"),
+    XmlField::Element("metadata", &XML_METADATA),
+    XmlField::List("components", "component", &XML_COMPONENT),
+    XmlField::List("externalReferences", "reference", &XML_EXTERNAL_REFERENCE),
+    XmlField::List("dependencies", "dependency", &XML_DEPENDENCY),
+    XmlField::List("properties", "property", &XML_PROPERTY),
+    XmlField::List("vulnerabilities", "vulnerability", &XML_VULNERABILITY),
+    XmlField::List("annotations", "annotation", &XML_ANNOTATION),
+]);
+
+static XML_METADATA: XmlKind = XmlKind::Object(&[
+    XmlField::Text("timestamp"),
+    XmlField::List("tools", "tool", &XML_TOOL),
+    XmlField::Element("component", &XML_COMPONENT),
+    XmlField::Element("supplier", &XML_ORGANIZATIONAL_ENTITY),
+    XmlField::List("licenses", "license", &XML_LICENSE_CHOICE),
+    XmlField::List("properties", "property", &XML_PROPERTY),
+]);
+
+static XML_ORGANIZATIONAL_ENTITY: XmlKind = XmlKind::Object(&[
+    XmlField::Text("name"),
+    XmlField::UnwrappedList("url", "url", &XmlKind::Text),
+    XmlField::UnwrappedList("contact", "contact", &XML_ORGANIZATIONAL_CONTACT),
+]);
+
+static XML_ORGANIZATIONAL_CONTACT: XmlKind = XmlKind::Object(&[
+    XmlField::Text("name"),
+    XmlField::Text("email"),
+    XmlField::Text("phone"),
+]);
+
+static XML_TOOL: XmlKind = XmlKind::Object(&[
+    XmlField::Text("vendor"),
+    XmlField::Text("name"),
+    XmlField::Text("version"),
+]);
+
+static XML_COMPONENT: XmlKind = XmlKind::Object(&[
+    XmlField::Attribute("type", "type"),
+    XmlField::Attribute("mime-type", "mime-type"),
+    XmlField::Attribute("bom-ref", "bom-ref"),
+    XmlField::Text("author"),
+    XmlField::Text("publisher"),
+    XmlField::Text("group"),
+    XmlField::Text("name"),
+    XmlField::Text("version"),
+    XmlField::Text("description"),
+    XmlField::Text("scope"),
+    XmlField::List("hashes", "hash", &XML_HASH),
+    XmlField::List("licenses", "license", &XML_LICENSE_CHOICE),
+    XmlField::Text("copyright"),
+    XmlField::Text("cpe"),
+    XmlField::Text("purl"),
+    XmlField::UnwrappedList("omniborId", "omniborId", &XmlKind::Text),
+    XmlField::Element("pedigree", &XML_PEDIGREE),
+    XmlField::List("externalReferences", "reference", &XML_EXTERNAL_REFERENCE),
+    XmlField::List("properties", "property", &XML_PROPERTY),
+    XmlField::List("components", "component", &XML_COMPONENT),
+]);
+
+static XML_HASH: XmlKind = XmlKind::Object(&[XmlField::Attribute("alg", "alg"), XmlField::Content("content")]);
+
+static XML_PROPERTY: XmlKind =
+    XmlKind::Object(&[XmlField::Attribute("name", "name"), XmlField::Content("value")]);
+
+static XML_LICENSE_CHOICE: XmlKind =
+    XmlKind::Choice(&[("license", &XML_LICENSE), ("expression", &XmlKind::Text)]);
+
+static XML_LICENSE: XmlKind = XmlKind::Object(&[
+    XmlField::Attribute("bom-ref", "bom-ref"),
+    XmlField::Attribute("acknowledgement", "acknowledgement"),
+    XmlField::Text("id"),
+    XmlField::Text("name"),
+    XmlField::Text("url"),
+]);
+
+static XML_PEDIGREE: XmlKind = XmlKind::Object(&[
+    XmlField::List("commits", "commit", &XML_COMMIT),
+    XmlField::Text("notes"),
+]);
+
+static XML_COMMIT: XmlKind = XmlKind::Object(&[
+    XmlField::Text("uid"),
+    XmlField::Text("url"),
+    XmlField::Text("message"),
+]);
+
+static XML_EXTERNAL_REFERENCE: XmlKind = XmlKind::Object(&[
+    XmlField::Attribute("type", "type"),
+    XmlField::Text("url"),
+    XmlField::Text("comment"),
+    XmlField::List("hashes", "hash", &XML_HASH),
+]);
+
+static XML_DEPENDENCY: XmlKind = XmlKind::Object(&[
+    XmlField::Attribute("ref", "ref"),
+    XmlField::UnwrappedList("dependsOn", "dependency", &XmlKind::Ref),
+]);
+
+static XML_VULNERABILITY: XmlKind = XmlKind::Object(&[
+    XmlField::Attribute("bom-ref", "bom-ref"),
+    XmlField::Text("id"),
+    XmlField::Element("source", &XML_VULNERABILITY_SOURCE),
+    XmlField::Element("analysis", &XML_VULNERABILITY_ANALYSIS),
+    XmlField::List("affects", "target", &XML_VULNERABILITY_TARGET),
+]);
+
+static XML_VULNERABILITY_SOURCE: XmlKind = XmlKind::Object(&[XmlField::Text("name"), XmlField::Text("url")]);
+
+static XML_VULNERABILITY_ANALYSIS: XmlKind =
+    XmlKind::Object(&[XmlField::Text("state"), XmlField::Text("detail")]);
+
+static XML_VULNERABILITY_TARGET: XmlKind = XmlKind::Object(&[XmlField::Text("ref")]);
+
+static XML_ANNOTATION: XmlKind = XmlKind::Object(&[
+    XmlField::Attribute("bom-ref", "bom-ref"),
+    XmlField::List("subjects", "subject", &XmlKind::Ref),
+    XmlField::Element("annotator", &XML_ANNOTATOR),
+    XmlField::Text("timestamp"),
+    XmlField::Text("text"),
+]);
+
+static XML_ANNOTATOR: XmlKind = XmlKind::Object(&[XmlField::Element("component", &XML_COMPONENT)]);
+
+fn get_xml_text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.to_string()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn to_xml_element(name: &str, value: &serde_json::Value, kind: &XmlKind) -> crate::xml::XmlElement {
+    let mut element = crate::xml::XmlElement::new(name);
+    match kind {
+        XmlKind::Text => element.text = get_xml_text(value),
+        XmlKind::Ref => {
+            if let Some(reference) = get_xml_text(value) {
+                element.attributes.push(("ref".to_string(), reference));
+            }
+        }
+        XmlKind::Choice(variants) => {
+            if let Some((variant_name, variant_value)) = value.as_object().and_then(|o| o.iter().next()) {
+                if let Some((_, variant_kind)) = variants.iter().find(|(n, _)| n == variant_name) {
+                    return to_xml_element(variant_name, variant_value, variant_kind);
+                }
+            }
+        }
+        XmlKind::Object(fields) => {
+            for field in fields.iter() {
+                match field {
+                    XmlField::Attribute(key, attribute_name)
+                    | XmlField::NumberAttribute(key, attribute_name) => {
+                        if let Some(v) = value.get(key).and_then(get_xml_text) {
+                            element.attributes.push((attribute_name.to_string(), v));
+                        }
+                    }
+                    XmlField::Text(key) => {
+                        if let Some(v) = value.get(key).and_then(get_xml_text) {
+                            let mut child = crate::xml::XmlElement::new(key);
+                            child.text = Some(v);
+                            element.children.push(child);
+                        }
+                    }
+                    XmlField::Content(key) => {
+                        element.text = value.get(key).and_then(get_xml_text);
+                    }
+                    XmlField::Element(key, child_kind) => {
+                        if let Some(v) = value.get(key).filter(|v| !v.is_null()) {
+                            element.children.push(to_xml_element(key, v, child_kind));
+                        }
+                    }
+                    XmlField::List(key, item_name, item_kind) => {
+                        if let Some(items) = value.get(key).and_then(|v| v.as_array()) {
+                            let mut wrapper = crate::xml::XmlElement::new(key);
+                            for item in items {
+                                wrapper.children.push(to_xml_element(item_name, item, item_kind));
+                            }
+                            element.children.push(wrapper);
+                        }
+                    }
+                    XmlField::UnwrappedList(key, item_name, item_kind) => {
+                        if let Some(items) = value.get(key).and_then(|v| v.as_array()) {
+                            for item in items {
+                                element.children.push(to_xml_element(item_name, item, item_kind));
+                            }
+                        }
+                    }
+                }
             }
         }
     }
-    response
+    element
+}
+
+fn from_xml_element(
+    element: &crate::xml::XmlElement,
+    kind: &XmlKind,
+) -> Result<serde_json::Value, anyhow::Error> {
+    match kind {
+        XmlKind::Text => Ok(element.text.clone().unwrap_or_default().into()),
+        XmlKind::Ref => match element.get_attribute("ref") {
+            Some(r) => Ok(r.as_str().into()),
+            None => Err(anyhow::format_err!("Missing ref attribute in {}", element.name)),
+        },
+        XmlKind::Choice(variants) => match variants.iter().find(|(n, _)| *n == element.name) {
+            Some((variant_name, variant_kind)) => Ok(serde_json::json!({
+                *variant_name: from_xml_element(element, variant_kind)?
+            })),
+            None => Err(anyhow::format_err!("Unexpected element {}", element.name)),
+        },
+        XmlKind::Object(fields) => {
+            let mut response = serde_json::Map::default();
+            for field in fields.iter() {
+                match field {
+                    XmlField::Attribute(key, attribute_name) => {
+                        if let Some(v) = element.get_attribute(attribute_name) {
+                            response.insert(key.to_string(), v.as_str().into());
+                        }
+                    }
+                    XmlField::NumberAttribute(key, attribute_name) => {
+                        if let Some(v) = element.get_attribute(attribute_name) {
+                            let number: i64 = v.parse().map_err(|_| {
+                                anyhow::format_err!("Invalid number {} for attribute {}", v, attribute_name)
+                            })?;
+                            response.insert(key.to_string(), number.into());
+                        }
+                    }
+                    XmlField::Text(key) => {
+                        if let Some(child) = element.get_child(key) {
+                            response.insert(key.to_string(), child.text.clone().unwrap_or_default().into());
+                        }
+                    }
+                    XmlField::Content(key) => {
+                        if let Some(text) = &element.text {
+                            response.insert(key.to_string(), text.as_str().into());
+                        }
+                    }
+                    XmlField::Element(key, child_kind) => {
+                        if let Some(child) = element.get_child(key) {
+                            response.insert(key.to_string(), from_xml_element(child, child_kind)?);
+                        }
+                    }
+                    XmlField::List(key, item_name, item_kind) => {
+                        let wrapper = match element.get_child(key) {
+                            Some(w) => w,
+                            None => continue,
+                        };
+                        let mut items: Vec<serde_json::Value> = vec![];
+                        for child in &wrapper.children {
+                            // The choices are the only lists with items of different names.
+                            if let XmlKind::Choice(_) = item_kind {
+                            } else if child.name != *item_name {
+                                continue;
+                            }
+                            items.push(from_xml_element(child, item_kind)?);
+                        }
+                        response.insert(key.to_string(), items.into());
+                    }
+                    XmlField::UnwrappedList(key, item_name, item_kind) => {
+                        let children = element.get_children(item_name);
+                        if children.is_empty() {
+                            continue;
+                        }
+                        let mut items: Vec<serde_json::Value> = vec![];
+                        for child in children {
+                            items.push(from_xml_element(child, item_kind)?);
+                        }
+                        response.insert(key.to_string(), items.into());
+                    }
+                }
+            }
+            Ok(response.into())
+        }
+    }
+}
+
+pub fn dump_xml(cyclonedx: &CycloneDx, pretty: bool) -> Result<String, anyhow::Error> {
+    dump_xml_value(&serde_json::to_value(cyclonedx)?, pretty)
+}
+
+// Same as dump_xml, for a BOM of any spec version.
+fn dump_xml_value(cyclonedx: &serde_json::Value, pretty: bool) -> Result<String, anyhow::Error> {
+    let spec_version = match cyclonedx["specVersion"].as_str() {
+        Some(v) => v,
+        None => return Err(anyhow::format_err!("Missing CycloneDX spec version")),
+    };
+    let mut bom = to_xml_element("bom", cyclonedx, &XML_BOM);
+    bom.attributes.insert(
+        0,
+        (
+            "xmlns".to_string(),
+            format!("{}{}", XML_NAMESPACE_PREFIX, spec_version),
+        ),
+    );
+    Ok(bom.to_document(pretty))
+}
+
+// Loads a CycloneDX XML document. Only the fields that nix2sbom generates are read.
+pub fn load_xml(document: &str) -> Result<CycloneDx, anyhow::Error> {
+    let bom = crate::xml::XmlElement::parse(document)?;
+    if bom.name != "bom" {
+        return Err(anyhow::format_err!("Expected a bom element, found {}", bom.name));
+    }
+    let spec_version = match bom
+        .get_attribute("xmlns")
+        .and_then(|n| n.strip_prefix(XML_NAMESPACE_PREFIX))
+    {
+        Some(v) => v.to_string(),
+        None => return Err(anyhow::format_err!("Missing CycloneDX namespace")),
+    };
+    let mut value = from_xml_element(&bom, &XML_BOM)?;
+    value["bomFormat"] = crate::format::CYCLONE_DX_NAME.into();
+    value["specVersion"] = spec_version.into();
+    Ok(serde_json::from_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_xml_round_trip() {
+        let cyclonedx: CycloneDx = serde_json::from_str(
+            r###"
+            {
+              "bomFormat": "CycloneDX",
+              "specVersion": "1.5",
+              "serialNumber": "urn:uuid:3e671687-395b-41f5-a30f-a58921a69b79",
+              "version": 1,
+              "metadata": {
+                "timestamp": "2024-01-01T00:00:00Z",
+                "tools": [{ "vendor": "louib", "name": "nix2sbom", "version": "1.0.0" }],
+                "supplier": {
+                  "name": "ACME",
+                  "url": ["https://acme.example.com"],
+                  "contact": [{ "name": "Security team", "email": "security@acme.example.com" }]
+                },
+                "licenses": [{ "license": { "id": "MIT" } }],
+                "properties": [{ "name": "nix:flake:url", "value": "github:louib/nix2sbom" }]
+              },
+              "components": [
+                {
+                  "type": "library",
+                  "bom-ref": "/nix/store/a-openssl-3.0.13.drv",
+                  "name": "openssl",
+                  "version": "3.0.13",
+                  "description": "Cryptographic library that implements the SSL & TLS protocols",
+                  "licenses": [{ "license": { "id": "Apache-2.0" } }, { "license": { "name": "<unknown>" } }],
+                  "purl": "pkg:generic/openssl@3.0.13",
+                  "externalReferences": [{ "type": "website", "url": "https://www.openssl.org/?a=1&b=2" }],
+                  "properties": [{ "name": "nix:drv_path", "value": "/nix/store/a-openssl-3.0.13.drv" }],
+                  "components": [
+                    {
+                      "type": "file",
+                      "bom-ref": "/nix/store/b-patch.diff",
+                      "name": "patch.diff",
+                      "hashes": [{ "alg": "SHA-256", "content": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855" }]
+                    }
+                  ]
+                },
+                { "type": "library", "bom-ref": "/nix/store/c-zlib-1.3.1.drv", "name": "zlib" }
+              ],
+              "dependencies": [
+                { "ref": "/nix/store/a-openssl-3.0.13.drv", "dependsOn": ["/nix/store/c-zlib-1.3.1.drv"] },
+                { "ref": "/nix/store/c-zlib-1.3.1.drv" }
+              ]
+            }
+            "###,
+        )
+        .unwrap();
+
+        for pretty in [true, false] {
+            let document = dump_xml(&cyclonedx, pretty).unwrap();
+            assert!(document.contains(r#"<bom xmlns="http://cyclonedx.org/schema/bom/1.5""#));
+            assert!(document.contains(r#"<dependency ref="/nix/store/c-zlib-1.3.1.drv"/>"#));
+            assert!(document.contains("SSL &amp; TLS"));
+            let loaded = load_xml(&document).unwrap();
+            assert_eq!(
+                serde_json::to_value(&loaded).unwrap(),
+                serde_json::to_value(&cyclonedx).unwrap()
+            );
+        }
+
+        assert!(load_xml("<bom/>").is_err());
+    }
+
+    #[test]
+    pub fn test_convert_to_spec_version() {
+        let cyclonedx: CycloneDx = serde_json::from_str(
+            r###"
+            {
+              "bomFormat": "CycloneDX",
+              "specVersion": "1.5",
+              "version": 1,
+              "metadata": {
+                "lifecycles": [{ "phase": "build" }],
+                "tools": {
+                  "components": [{ "type": "application", "group": "louib", "name": "nix2sbom", "version": "1.0.0" }]
+                },
+                "supplier": { "bom-ref": "supplier", "name": "louib" }
+              },
+              "components": [
+                {
+                  "type": "library",
+                  "bom-ref": "/nix/store/a-zlib-1.3.1.drv",
+                  "name": "zlib",
+                  "licenses": [{ "license": { "id": "Zlib", "properties": [{ "name": "a", "value": "b" }] } }],
+                  "externalReferences": [
+                    { "type": "release-notes", "url": "https://www.zlib.net/ChangeLog.txt" },
+                    { "type": "security-contact", "url": "mailto:info@zlib.net" },
+                    { "type": "website", "url": "https://www.zlib.net/" }
+                  ],
+                  "releaseNotes": { "type": "patch" }
+                },
+                {
+                  "type": "device-driver",
+                  "bom-ref": "/nix/store/a-zfs-kernel-2.2.4.drv",
+                  "name": "zfs-kernel",
+                  "version": "2.2.4"
+                }
+              ],
+              "annotations": [
+                {
+                  "subjects": ["/nix/store/a-zlib-1.3.1.drv"],
+                  "annotator": { "component": { "type": "application", "name": "nix2sbom" } },
+                  "timestamp": "2024-01-01T00:00:00Z",
+                  "text": "No package metadata was found."
+                }
+              ],
+              "vulnerabilities": [
+                {
+                  "id": "CVE-2023-45853",
+                  "source": { "name": "NVD", "url": "https://nvd.nist.gov/vuln/detail/CVE-2023-45853" },
+                  "ratings": [{ "method": "CVSSv4" }],
+                  "workaround": "Do not use the minizip library.",
+                  "analysis": { "state": "not_affected", "firstIssued": "2024-01-01T00:00:00Z" },
+                  "affects": [{ "ref": "/nix/store/a-zlib-1.3.1.drv" }]
+                }
+              ]
+            }
+            "###,
+        )
+        .unwrap();
+
+        // Fields of the BOM, and version of the spec that introduced them.
+        let fields = [
+            ("/annotations", "1.5"),
+            ("/metadata/lifecycles", "1.5"),
+            ("/metadata/tools/components", "1.5"),
+            ("/metadata/supplier/bom-ref", "1.5"),
+            ("/components/0/releaseNotes", "1.4"),
+            ("/components/0/licenses/0/license/properties", "1.5"),
+            ("/vulnerabilities", "1.4"),
+            ("/vulnerabilities/0/workaround", "1.5"),
+            ("/vulnerabilities/0/analysis/firstIssued", "1.5"),
+        ];
+        // Values of the BOM, version of the spec that introduced them, and value
+        // used with the older versions.
+        let values = [
+            (
+                "/components/0/externalReferences/0/type",
+                "release-notes",
+                "1.4",
+                "other",
+            ),
+            (
+                "/components/0/externalReferences/1/type",
+                "security-contact",
+                "1.5",
+                "other",
+            ),
+            (
+                "/components/0/externalReferences/2/type",
+                "website",
+                "1.3",
+                "website",
+            ),
+            ("/components/1/type", "device-driver", "1.5", "library"),
+            ("/vulnerabilities/0/ratings/0/method", "CVSSv4", "1.5", "other"),
+        ];
+        for spec_version in SUPPORTED_SPEC_VERSIONS {
+            let converted = convert_to_spec_version(&cyclonedx, spec_version).unwrap();
+            assert_eq!(converted["specVersion"], spec_version);
+            assert_eq!(converted["components"][0]["name"], "zlib");
+            for (pointer, version) in fields {
+                assert_eq!(
+                    converted.pointer(pointer).is_some(),
+                    !is_before_spec_version(spec_version, version),
+                    "{} with CycloneDX {}",
+                    pointer,
+                    spec_version
+                );
+            }
+            for (pointer, value, version, previous_value) in values {
+                if converted.pointer(pointer).is_none() {
+                    assert!(pointer.starts_with("/vulnerabilities") && spec_version == "1.3");
+                    continue;
+                }
+                let expected_value = match is_before_spec_version(spec_version, version) {
+                    true => previous_value,
+                    false => value,
+                };
+                assert_eq!(converted.pointer(pointer).unwrap(), expected_value);
+            }
+            // The version of the components is required with CycloneDX 1.3.
+            assert_eq!(
+                converted["components"][0].get("version").is_some(),
+                spec_version == "1.3"
+            );
+            assert!(converted["components"][0].get("components").is_none());
+        }
+        let converted = convert_to_spec_version(&cyclonedx, "1.4").unwrap();
+        assert_eq!(converted["metadata"]["tools"][0]["vendor"], "louib");
+        assert_eq!(converted["metadata"]["tools"][0]["name"], "nix2sbom");
+        let document = dump_xml_value(&converted, false).unwrap();
+        assert!(document.contains(r#"<bom xmlns="http://cyclonedx.org/schema/bom/1.4""#));
+        assert!(convert_to_spec_version(&cyclonedx, "1.2").is_err());
+    }
+
+    #[test]
+    pub fn test_add_omnibor_ids() {
+        let mut components: Vec<serde_json::Value> = serde_json::from_str(
+            r###"
+            [
+              { "type": "library", "bom-ref": "/nix/store/a-zlib-1.3.1.drv", "name": "zlib" },
+              {
+                "type": "library",
+                "bom-ref": "category:libraries",
+                "name": "libraries",
+                "components": [{ "type": "library", "bom-ref": "/nix/store/b-openssl-3.0.13.drv", "name": "openssl" }]
+              }
+            ]
+            "###,
+        )
+        .unwrap();
+        let omnibor_ids: BTreeMap<String, Vec<String>> = BTreeMap::from([(
+            "/nix/store/b-openssl-3.0.13.drv".to_string(),
+            vec!["gitoid:blob:sha1:e69de29bb2d1d6434b8b29ae775ad8c2e48c5391".to_string()],
+        )]);
+        add_omnibor_ids(&mut components, &omnibor_ids);
+        assert!(components[0].get("omniborId").is_none());
+        assert_eq!(
+            components[1]["components"][0]["omniborId"][0],
+            "gitoid:blob:sha1:e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+
+        let document = to_xml_element("component", &components[1], &XML_COMPONENT).to_document(false);
+        assert!(document
+            .contains("<omniborId>gitoid:blob:sha1:e69de29bb2d1d6434b8b29ae775ad8c2e48c5391</omniborId>"));
+    }
+
+    #[test]
+    pub fn test_add_license_acknowledgements() {
+        let mut components: Vec<serde_json::Value> = serde_json::from_str(
+            r###"
+            [
+              {
+                "type": "library",
+                "bom-ref": "/nix/store/a-zlib-1.3.1.drv",
+                "name": "zlib",
+                "licenses": [{ "license": { "id": "Zlib" } }]
+              },
+              {
+                "type": "library",
+                "bom-ref": "category:libraries",
+                "name": "libraries",
+                "components": [
+                  {
+                    "type": "library",
+                    "bom-ref": "/nix/store/b-openssl-3.0.13.drv",
+                    "name": "openssl",
+                    "licenses": [{ "license": { "name": "OpenSSL License" } }]
+                  },
+                  { "type": "library", "bom-ref": "/nix/store/c-unrar-7.0.9.drv", "name": "unrar" }
+                ]
+              }
+            ]
+            "###,
+        )
+        .unwrap();
+        let concluded_licenses: BTreeMap<String, String> = BTreeMap::from([
+            (
+                "/nix/store/b-openssl-3.0.13.drv".to_string(),
+                "apache-2.0".to_string(),
+            ),
+            (
+                "/nix/store/c-unrar-7.0.9.drv".to_string(),
+                "unRAR restriction".to_string(),
+            ),
+        ]);
+        add_license_acknowledgements(&mut components, &concluded_licenses);
+        assert_eq!(
+            components[0]["licenses"],
+            serde_json::json!([{ "license": { "id": "Zlib", "acknowledgement": "declared" } }])
+        );
+        assert_eq!(
+            components[1]["components"][0]["licenses"],
+            serde_json::json!([
+                { "license": { "name": "OpenSSL License", "acknowledgement": "declared" } },
+                { "license": { "id": "Apache-2.0", "acknowledgement": "concluded" } }
+            ])
+        );
+        assert_eq!(
+            components[1]["components"][1]["licenses"],
+            serde_json::json!([{ "license": { "name": "unRAR restriction", "acknowledgement": "concluded" } }])
+        );
+        assert!(components[1].get("licenses").is_none());
+
+        let document = to_xml_element("component", &components[0], &XML_COMPONENT).to_document(false);
+        assert!(document.contains(r#"<license acknowledgement="declared"><id>Zlib</id></license>"#));
+    }
 }
@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize)]
 #[derive(Clone)]
 #[derive(PartialEq)]
-struct NativePackage {
+pub struct NativePackage {
     pub id: String,
     pub name: String,
     pub version: Option<String>,
@@ -22,11 +22,9 @@ struct NativePackage {
     // TODO add build derivations and input derivations
 }
 
-pub fn dump(
+pub fn get_native_packages(
     package_graph: &crate::nix::PackageGraph,
-    _format: &crate::format::SerializationFormat,
-    options: &crate::nix::DumpOptions,
-) -> Result<String, anyhow::Error> {
+) -> Result<Vec<NativePackage>, anyhow::Error> {
     let mut native_packages: Vec<NativePackage> = vec![];
 
     for package in package_graph.nodes.values() {
@@ -58,6 +56,16 @@ pub fn dump(
     // Sort the native_packages by id
     native_packages.sort_by(|a, b| a.id.cmp(&b.id));
 
+    Ok(native_packages)
+}
+
+pub fn dump(
+    package_graph: &crate::nix::PackageGraph,
+    _format: &crate::format::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, anyhow::Error> {
+    let native_packages = get_native_packages(package_graph)?;
+
     let response = match options.pretty {
         Some(false) => serde_json::to_string(&native_packages)?,
         _ => serde_json::to_string_pretty(&native_packages)?,
@@ -65,3 +73,8 @@ pub fn dump(
 
     Ok(response)
 }
+
+pub fn load(content: &str) -> Result<Vec<NativePackage>, anyhow::Error> {
+    let native_packages: Vec<NativePackage> = serde_json::from_str(content)?;
+    Ok(native_packages)
+}
@@ -0,0 +1,27 @@
+use std::collections::BTreeMap;
+
+pub fn dump(
+    package_graph: &crate::nix::PackageGraph,
+    format: &crate::format::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, anyhow::Error> {
+    // Maps every output path of the graph to the purl of the package it belongs to.
+    let mut out_paths: BTreeMap<String, String> = BTreeMap::default();
+    for package_node in package_graph.nodes.values() {
+        let purl = package_node.get_purl().to_string();
+        for out_path in package_node.main_derivation.get_output_paths() {
+            out_paths.insert(out_path, purl.clone());
+        }
+    }
+
+    match format {
+        crate::format::SerializationFormat::JSON => {
+            let response = match options.pretty {
+                Some(false) => serde_json::to_string(&out_paths)?,
+                _ => serde_json::to_string_pretty(&out_paths)?,
+            };
+            Ok(response)
+        }
+        _ => Ok(out_paths.into_keys().collect::<Vec<String>>().join("\n")),
+    }
+}
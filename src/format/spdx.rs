@@ -1,6 +1,9 @@
-use chrono::Utc;
+use std::collections::BTreeMap;
+
 use serde_spdx::spdx::v_2_3::{
-    SpdxBuilder, SpdxCreationInfoBuilder, SpdxItemPackages, SpdxItemPackagesBuilder,
+    Spdx, SpdxBuilder, SpdxCreationInfoBuilder, SpdxItemFiles, SpdxItemFilesBuilder,
+    SpdxItemFilesItemChecksums, SpdxItemPackages, SpdxItemPackagesBuilder, SpdxItemPackagesItemChecksums,
+    SpdxItemPackagesItemExternalRefs, SpdxItemRelationships, SpdxItemRelationshipsBuilder,
 };
 
 // This is the only license accepted in the data_license field. See
@@ -8,16 +11,79 @@ use serde_spdx::spdx::v_2_3::{
 // for details.
 pub const CREATIVE_COMMONS_LICENSE: &str = "http://spdx.org/licenses/CC0-1.0";
 
+// Used when a field is required but we have no information about it.
+pub const NO_ASSERTION: &str = "NOASSERTION";
+
+pub const GENERATED_FROM_RELATIONSHIP: &str = "GENERATED_FROM";
+pub const PATCH_APPLIED_RELATIONSHIP: &str = "PATCH_APPLIED";
+pub const CONTAINS_RELATIONSHIP: &str = "CONTAINS";
+pub const DEPENDS_ON_RELATIONSHIP: &str = "DEPENDS_ON";
+pub const DESCRIBES_RELATIONSHIP: &str = "DESCRIBES";
+
+pub const DOCUMENT_SPDX_ID: &str = "SPDXRef-DOCUMENT";
+
+pub const PACKAGE_MANAGER_CATEGORY: &str = "PACKAGE-MANAGER";
+pub const PURL_REFERENCE_TYPE: &str = "purl";
+
+// The OmniBOR artifact ids are persistent identifiers of type gitoid, as listed in
+// the external repository identifiers of the SPDX 2.3 specification.
+pub const PERSISTENT_ID_CATEGORY: &str = "PERSISTENT-ID";
+pub const GITOID_REFERENCE_TYPE: &str = "gitoid";
+
 pub fn dump(
     package_graph: &crate::nix::PackageGraph,
-    _format: &crate::format::SerializationFormat,
+    format: &crate::format::SerializationFormat,
     options: &crate::nix::DumpOptions,
 ) -> Result<String, anyhow::Error> {
-    let creation_info = SpdxCreationInfoBuilder::default()
-        // .created(&Utc::now().to_rfc3339())
-        .created(&Utc::now().format("%Y-%m-%dT%H:%M:%S").to_string())
-        .creators(vec!["Tool: nix2sbom".to_string()])
-        .build()?;
+    let mut creation_info_builder = SpdxCreationInfoBuilder::default();
+    creation_info_builder
+        .created(options.get_timestamp().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .creators(get_creators(&options.metadata));
+    let mut creation_comment_lines: Vec<String> = vec![];
+    let excluded_nodes_count = package_graph.get_excluded_nodes_count();
+    if !excluded_nodes_count.is_empty() {
+        let exclusions = excluded_nodes_count
+            .iter()
+            .map(|(reason, count)| format!("{} ({})", reason, count))
+            .collect::<Vec<String>>()
+            .join(", ");
+        creation_comment_lines.push(format!("Derivations excluded from this document: {}", exclusions));
+    }
+    // SPDX 2.3 has no generic properties, so the evaluation is described in the comment.
+    for (name, value) in crate::properties::get_evaluation_properties(&options.evaluation) {
+        creation_comment_lines.push(format!("{}: {}", name, value));
+    }
+    // SPDX 2.3 cannot reference the text of the listed licenses.
+    for (license_id, license_text_path) in &options.license_texts {
+        creation_comment_lines.push(format!("License text of {}: {}", license_id, license_text_path));
+    }
+    // The data license of SPDX documents is always CC0-1.0.
+    if !options.metadata.licenses.is_empty() {
+        creation_comment_lines.push(format!(
+            "Licenses of this document: {}",
+            options.metadata.licenses.join(", ")
+        ));
+    }
+    if !creation_comment_lines.is_empty() {
+        creation_info_builder.comment(creation_comment_lines.join("\n"));
+    }
+    let mut document_comment = format!(
+        "{}: {}",
+        crate::properties::API_VERSION,
+        options.get_api_version()
+    );
+    if let Some(metadata_error) = &options.metadata_error {
+        document_comment.push_str(&format!(
+            "\n{}: {}",
+            crate::properties::METADATA_ERROR,
+            metadata_error
+        ));
+    }
+    // The store paths are not files, and have no SHA-1 checksum.
+    for (name, value) in crate::properties::get_store_path_properties(&package_graph.store_paths) {
+        document_comment.push_str(&format!("\n{}: {}", name, value));
+    }
+    let creation_info = creation_info_builder.build()?;
     let root_node_id = match package_graph.get_root_node() {
         Some(n) => n,
         None => return Ok("Expected to find a single root node when dumping to sdpx format".to_string()),
@@ -26,44 +92,280 @@ pub fn dump(
 
     let mut spdx_builder = SpdxBuilder::default();
 
-    // Generate a new uuid for this manifest
-    let uuid = uuid::Uuid::new_v4();
     let name = root_package.id.clone();
+    let document_namespace = get_document_namespace(&name, options);
 
     let spdx_builder = spdx_builder
         .creation_info(creation_info)
         .packages(vec![])
-        // DISCUSS Should the document namespace be something like the path of the root derivation?
-        // This would make the namespace content-addressed, and thus allow other SPDX documents
-        // to reference this one.
-        // .document_namespace()
-        .document_namespace(format!("https://spdx.org/spdxdocs{}-{}", name, uuid))
+        .document_namespace(document_namespace)
         .relationships(vec![])
         .data_license(CREATIVE_COMMONS_LICENSE)
         .spdx_version("SPDX-2.3")
-        .spdxid("SPDXRef-DOCUMENT")
+        .spdxid(DOCUMENT_SPDX_ID)
+        // SPDX 2.3 has no generic properties, see the package comments.
+        .comment(document_comment)
         .name(name.clone());
 
     let mut packages = vec![];
+    // Sources and patches can be shared between packages, so we index them
+    // by SPDX id to avoid duplicates.
+    let mut source_artifacts: BTreeMap<String, SpdxItemPackages> = BTreeMap::default();
+    let mut relationships: Vec<SpdxItemRelationships> = vec![];
     for (_package_id, package) in &package_graph.nodes_next {
-        let spdx_package = dump_package(package, &options)?;
+        let mut spdx_package = dump_package(package, options)?;
+        if package.id == root_package.id {
+            if let Some(supplier) = options.metadata.supplier.as_ref().and_then(get_organization) {
+                spdx_package.supplier = Some(supplier);
+            }
+            if let Some(description) = &options.metadata.description {
+                spdx_package.description = Some(description.to_string());
+            }
+        }
+
+        if let Some(source_derivation_path) = &package.source_derivation {
+            if let Some(source_node) = package_graph.nodes.get(source_derivation_path) {
+                let source_package = dump_source_artifact(source_node, &package_graph.omnibor_ids)?;
+                relationships.push(build_relationship(
+                    &spdx_package.spdxid,
+                    GENERATED_FROM_RELATIONSHIP,
+                    &source_package.spdxid,
+                )?);
+                source_artifacts.insert(source_package.spdxid.clone(), source_package);
+            }
+        }
+        for patch_path in &package.patches {
+            if let Some(patch_node) = package_graph.nodes.get(patch_path) {
+                let patch_package = dump_source_artifact(patch_node, &package_graph.omnibor_ids)?;
+                relationships.push(build_relationship(
+                    &patch_package.spdxid,
+                    PATCH_APPLIED_RELATIONSHIP,
+                    &spdx_package.spdxid,
+                )?);
+                source_artifacts.insert(patch_package.spdxid.clone(), patch_package);
+            }
+        }
+
+        for input_source_path in &package.main_derivation.inputs_sources {
+            if package_graph
+                .input_sources
+                .get(input_source_path)
+                .is_some_and(|s| s.sha1.is_some())
+            {
+                relationships.push(build_relationship(
+                    &spdx_package.spdxid,
+                    CONTAINS_RELATIONSHIP,
+                    &get_spdx_id(input_source_path),
+                )?);
+            }
+        }
+
+        // The flake inputs are evaluation-time dependencies of the root packages.
+        if package_graph.root_nodes.contains(&package.id) {
+            for flake_input in &package_graph.flake_inputs {
+                relationships.push(build_relationship(
+                    &spdx_package.spdxid,
+                    DEPENDS_ON_RELATIONSHIP,
+                    &get_flake_input_spdx_id(&flake_input.get_resolved_id()),
+                )?);
+            }
+        }
+
         packages.push(spdx_package);
     }
+    packages.extend(source_artifacts.into_values());
+    dump_flake_inputs(&package_graph.flake_inputs, &mut packages, &mut relationships)?;
+
+    let mut files: Vec<SpdxItemFiles> = vec![];
+    for input_source in package_graph.input_sources.values() {
+        if let Some(file) = dump_input_source(input_source)? {
+            files.push(file);
+        }
+    }
+    if !files.is_empty() {
+        spdx_builder.files(files);
+    }
 
     spdx_builder.packages(packages);
+    spdx_builder.relationships(relationships);
     let spdx_manifest = spdx_builder.build()?;
 
-    let response = match options.pretty {
-        Some(false) => serde_json::to_string(&spdx_manifest)?,
-        _ => serde_json::to_string_pretty(&spdx_manifest)?,
+    serialize(&spdx_manifest, format, options)
+}
+
+fn get_creators(metadata_config: &crate::config::MetadataConfig) -> Vec<String> {
+    let mut creators = vec!["Tool: nix2sbom".to_string()];
+    if let Some(organization) = metadata_config.supplier.as_ref().and_then(get_organization) {
+        creators.push(organization);
+    }
+    creators
+}
+
+// Formats the supplier as an SPDX organization, e.g. `Organization: ACME Corp (security@acme.example.com)`.
+fn get_organization(supplier: &crate::config::MetadataSupplier) -> Option<String> {
+    let name = supplier.name.as_ref()?;
+    Some(match supplier.contact.iter().find_map(|c| c.email.as_ref()) {
+        Some(email) => format!("Organization: {} ({})", name, email),
+        None => format!("Organization: {}", name),
+    })
+}
+
+pub fn serialize(
+    spdx: &Spdx,
+    format: &crate::format::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, anyhow::Error> {
+    match format {
+        crate::format::SerializationFormat::JSON => match options.pretty {
+            Some(false) => Ok(serde_json::to_string(spdx)?),
+            _ => Ok(serde_json::to_string_pretty(spdx)?),
+        },
+        crate::format::SerializationFormat::YAML => Ok(serde_yaml::to_string(spdx)?),
+        crate::format::SerializationFormat::TagValue => Ok(dump_tag_value(spdx)),
+        crate::format::SerializationFormat::XML => {
+            Err(anyhow::format_err!("XML is not supported for SPDX".to_string()))
+        }
+    }
+}
+
+// The uuid is derived from the name of the document, which is the path of the root
+// derivation, so that the namespace is content-addressed and the same for every run
+// over the same derivation.
+pub fn get_document_namespace(name: &str, options: &crate::nix::DumpOptions) -> String {
+    if let Some(document_namespace) = &options.document_namespace {
+        return document_namespace.to_string();
+    }
+    let url = match name.starts_with('/') {
+        true => format!("https://spdx.org/spdxdocs{}", name),
+        false => format!("https://spdx.org/spdxdocs/{}", name),
     };
+    let uuid = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, url.as_bytes());
+    format!("{}-{}", url, uuid)
+}
+
+// Writes the document in the tag-value format. See
+// https://spdx.github.io/spdx-spec/v2.3/ for the list of tags.
+pub fn dump_tag_value(spdx: &Spdx) -> String {
+    let mut lines: Vec<String> = vec![];
+    add_tag(&mut lines, "SPDXVersion", &spdx.spdx_version);
+    // The data license is the short identifier in the tag-value format.
+    add_tag(
+        &mut lines,
+        "DataLicense",
+        spdx.data_license.rsplit('/').next().unwrap_or_default(),
+    );
+    add_tag(&mut lines, "SPDXID", &spdx.spdxid);
+    add_tag(&mut lines, "DocumentName", &spdx.name);
+    add_tag(&mut lines, "DocumentNamespace", &spdx.document_namespace);
+    add_optional_tag(&mut lines, "DocumentComment", &spdx.comment);
+    for creator in &spdx.creation_info.creators {
+        add_tag(&mut lines, "Creator", creator);
+    }
+    add_tag(&mut lines, "Created", &spdx.creation_info.created);
+    add_optional_tag(&mut lines, "CreatorComment", &spdx.creation_info.comment);
+
+    for package in spdx.packages.iter().flatten() {
+        lines.push(String::default());
+        lines.push("##### Package".to_string());
+        add_tag(&mut lines, "PackageName", &package.name);
+        add_tag(&mut lines, "SPDXID", &package.spdxid);
+        add_optional_tag(&mut lines, "PackageVersion", &package.version_info);
+        add_optional_tag(&mut lines, "PackageSupplier", &package.supplier);
+        add_optional_tag(&mut lines, "PackageOriginator", &package.originator);
+        add_tag(&mut lines, "PackageDownloadLocation", &package.download_location);
+        if let Some(files_analyzed) = package.files_analyzed {
+            add_tag(&mut lines, "FilesAnalyzed", &files_analyzed.to_string());
+        }
+        for checksum in package.checksums.iter().flatten() {
+            add_tag(
+                &mut lines,
+                "PackageChecksum",
+                &format!("{}: {}", checksum.algorithm, checksum.checksum_value),
+            );
+        }
+        add_optional_tag(&mut lines, "PackageHomePage", &package.homepage);
+        add_optional_tag(&mut lines, "PackageLicenseConcluded", &package.license_concluded);
+        add_optional_tag(&mut lines, "PackageLicenseDeclared", &package.license_declared);
+        add_optional_tag(&mut lines, "PackageCopyrightText", &package.copyright_text);
+        add_optional_tag(&mut lines, "PackageSummary", &package.summary);
+        add_optional_tag(&mut lines, "PackageDescription", &package.description);
+        add_optional_tag(&mut lines, "PackageComment", &package.comment);
+        add_optional_tag(
+            &mut lines,
+            "PrimaryPackagePurpose",
+            &package.primary_package_purpose,
+        );
+        for external_ref in package.external_refs.iter().flatten() {
+            add_tag(
+                &mut lines,
+                "ExternalRef",
+                &format!(
+                    "{} {} {}",
+                    external_ref.reference_category,
+                    external_ref.reference_type,
+                    external_ref.reference_locator
+                ),
+            );
+            add_optional_tag(&mut lines, "ExternalRefComment", &external_ref.comment);
+        }
+    }
+
+    for file in spdx.files.iter().flatten() {
+        lines.push(String::default());
+        lines.push("##### File".to_string());
+        add_tag(&mut lines, "FileName", &file.file_name);
+        add_tag(&mut lines, "SPDXID", &file.spdxid);
+        for checksum in &file.checksums {
+            add_tag(
+                &mut lines,
+                "FileChecksum",
+                &format!("{}: {}", checksum.algorithm, checksum.checksum_value),
+            );
+        }
+        add_optional_tag(&mut lines, "LicenseConcluded", &file.license_concluded);
+        add_optional_tag(&mut lines, "FileCopyrightText", &file.copyright_text);
+        add_optional_tag(&mut lines, "FileComment", &file.comment);
+    }
 
-    Ok(response)
+    let relationships = spdx.relationships.clone().unwrap_or_default();
+    if !relationships.is_empty() {
+        lines.push(String::default());
+        lines.push("##### Relationships".to_string());
+    }
+    for relationship in relationships {
+        add_tag(
+            &mut lines,
+            "Relationship",
+            &format!(
+                "{} {} {}",
+                relationship.spdx_element_id, relationship.relationship_type, relationship.related_spdx_element
+            ),
+        );
+        add_optional_tag(&mut lines, "RelationshipComment", &relationship.comment);
+    }
+
+    lines.push(String::default());
+    lines.join("\n")
+}
+
+// Values spanning multiple lines must be wrapped in a text element.
+fn add_tag(lines: &mut Vec<String>, tag: &str, value: &str) {
+    if value.contains('\n') {
+        lines.push(format!("{}: <text>{}</text>", tag, value));
+    } else {
+        lines.push(format!("{}: {}", tag, value));
+    }
+}
+
+fn add_optional_tag(lines: &mut Vec<String>, tag: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        add_tag(lines, tag, value);
+    }
 }
 
 fn dump_package(
     package_node: &crate::nix::PackageNode,
-    _options: &crate::nix::DumpOptions,
+    options: &crate::nix::DumpOptions,
 ) -> Result<SpdxItemPackages, anyhow::Error> {
     let package_name = match package_node.name.clone() {
         Some(n) => n,
@@ -72,16 +374,23 @@ fn dump_package(
 
     let mut package_builder = SpdxItemPackagesBuilder::default();
 
-    // The SPDX package identifier can only container letters, numbers,
-    // and the characters `.` and `-`. This should probably be encapsulated
-    // into a builder from the spdx crate.
-    let spdx_id = format!("SPDXRef-{}", package_node.id.replace("/nix/store/", ""));
+    let spdx_id = get_spdx_id(&package_node.id);
     let package_builder = package_builder.name(package_name).spdxid(spdx_id);
 
     if let Some(package_version) = package_node.get_version() {
         package_builder.version_info(package_version);
     }
 
+    // Only the packages that are downloaded as is, like the binary distributions,
+    // are fixed-output derivations.
+    if let Some(checksum) = get_checksum(&package_node.main_derivation) {
+        package_builder.checksums(vec![checksum]);
+    }
+
+    if let Some(kernel_component) = crate::classify::get_kernel_component(package_node) {
+        package_builder.primary_package_purpose(kernel_component.get_spdx_purpose());
+    }
+
     if let Some(url) = &package_node.url {
         package_builder.download_location(url);
     } else {
@@ -91,6 +400,18 @@ fn dump_package(
         );
     }
 
+    let declared_license = match &package_node.package {
+        Some(p) => p.meta.get_spdx_license_expression(),
+        None => None,
+    };
+    package_builder.license_declared(declared_license.unwrap_or(NO_ASSERTION.to_string()));
+    package_builder.license_concluded(
+        package_node
+            .concluded_license
+            .clone()
+            .unwrap_or(NO_ASSERTION.to_string()),
+    );
+
     let homepages = match &package_node.package {
         Some(p) => p.meta.get_homepages(),
         None => vec![],
@@ -100,6 +421,340 @@ fn dump_package(
     }
     // TODO add the available git URLs somewhere.
 
+    // SPDX 2.3 has no generic properties, so the Nix-specific data is recorded
+    // in the package comment.
+    let mut properties = crate::properties::get_package_node_properties(package_node);
+    properties.extend(crate::properties::get_captured_env_properties(
+        package_node,
+        &options.captured_env,
+    ));
+    properties.extend(crate::properties::get_license_text_properties(
+        package_node,
+        &options.license_texts,
+    ));
+    if !properties.is_empty() {
+        package_builder.comment(
+            properties
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        );
+    }
+
     let package = package_builder.build()?;
     Ok(package)
 }
+
+// Dumps a source archive or a patch, which are not packages per se but are
+// still represented as SPDX packages so that relationships can point to them.
+fn dump_source_artifact(
+    package_node: &crate::nix::PackageNode,
+    omnibor_ids: &BTreeMap<String, Vec<String>>,
+) -> Result<SpdxItemPackages, anyhow::Error> {
+    let url = package_node.main_derivation.get_url();
+    let name = match package_node.main_derivation.get_name() {
+        Some(n) => n,
+        None => match &url {
+            Some(u) => u.rsplit('/').next().unwrap_or(u).to_string(),
+            None => package_node.id.replace("/nix/store/", ""),
+        },
+    };
+
+    let mut package_builder = SpdxItemPackagesBuilder::default();
+    package_builder
+        .name(name)
+        .spdxid(get_spdx_id(&package_node.id))
+        .download_location(url.unwrap_or(NO_ASSERTION.to_string()));
+
+    if let Some(version) = package_node.main_derivation.get_version() {
+        package_builder.version_info(version);
+    }
+    if let Some(checksum) = get_checksum(&package_node.main_derivation) {
+        package_builder.checksums(vec![checksum]);
+    }
+    if let Some(omnibor_ids) = omnibor_ids.get(&package_node.id) {
+        package_builder.external_refs(
+            omnibor_ids
+                .iter()
+                .map(|id| SpdxItemPackagesItemExternalRefs {
+                    comment: None,
+                    reference_category: PERSISTENT_ID_CATEGORY.to_string(),
+                    reference_locator: id.to_string(),
+                    reference_type: GITOID_REFERENCE_TYPE.to_string(),
+                })
+                .collect::<Vec<SpdxItemPackagesItemExternalRefs>>(),
+        );
+    }
+
+    Ok(package_builder.build()?)
+}
+
+fn get_checksum(derivation: &crate::nix::Derivation) -> Option<SpdxItemPackagesItemChecksums> {
+    let (algorithm, digest) = derivation.get_artifact_hash()?;
+    Some(SpdxItemPackagesItemChecksums {
+        algorithm: algorithm.to_uppercase(),
+        checksum_value: digest,
+    })
+}
+
+// SPDX files must have a SHA-1 checksum, so the input sources that could not be
+// hashed, e.g. directories or paths missing from the local store, are not dumped.
+fn dump_input_source(input_source: &crate::nix::InputSource) -> Result<Option<SpdxItemFiles>, anyhow::Error> {
+    let sha1 = match &input_source.sha1 {
+        Some(sha1) => sha1,
+        None => return Ok(None),
+    };
+    let mut checksums: Vec<SpdxItemFilesItemChecksums> = vec![SpdxItemFilesItemChecksums {
+        algorithm: "SHA1".to_string(),
+        checksum_value: sha1.to_string(),
+    }];
+    if let Some(sha256) = &input_source.sha256 {
+        checksums.push(SpdxItemFilesItemChecksums {
+            algorithm: "SHA256".to_string(),
+            checksum_value: sha256.to_string(),
+        });
+    }
+    Ok(Some(
+        SpdxItemFilesBuilder::default()
+            .file_name(input_source.path.to_string())
+            .spdxid(get_spdx_id(&input_source.path))
+            .checksums(checksums)
+            .build()?,
+    ))
+}
+
+// Dumps the flake inputs as packages, recursively. The inputs following another
+// input are only linked to the input they follow.
+fn dump_flake_inputs(
+    flake_inputs: &[crate::flake::FlakeInput],
+    packages: &mut Vec<SpdxItemPackages>,
+    relationships: &mut Vec<SpdxItemRelationships>,
+) -> Result<(), anyhow::Error> {
+    for flake_input in flake_inputs {
+        if flake_input.follows.is_some() {
+            continue;
+        }
+        let spdx_id = get_flake_input_spdx_id(&flake_input.get_id());
+        let mut package_builder = SpdxItemPackagesBuilder::default();
+        package_builder
+            .name(flake_input.name.to_string())
+            .spdxid(spdx_id.clone());
+        let url = flake_input.locked.as_ref().and_then(|l| l.get_url());
+        package_builder.download_location(url.unwrap_or(NO_ASSERTION.to_string()));
+        if let Some(rev) = flake_input.locked.as_ref().and_then(|l| l.rev.clone()) {
+            package_builder.version_info(rev);
+        }
+        let properties = crate::properties::get_flake_input_properties(flake_input);
+        if !properties.is_empty() {
+            package_builder.comment(
+                properties
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            );
+        }
+        packages.push(package_builder.build()?);
+
+        for sub_input in &flake_input.inputs {
+            relationships.push(build_relationship(
+                &spdx_id,
+                DEPENDS_ON_RELATIONSHIP,
+                &get_flake_input_spdx_id(&sub_input.get_resolved_id()),
+            )?);
+        }
+        dump_flake_inputs(&flake_input.inputs, packages, relationships)?;
+    }
+    Ok(())
+}
+
+fn get_flake_input_spdx_id(flake_input_id: &str) -> String {
+    get_spdx_id(&format!("flake-input-{}", flake_input_id))
+}
+
+pub fn build_relationship(
+    element_id: &str,
+    relationship_type: &str,
+    related_element_id: &str,
+) -> Result<SpdxItemRelationships, anyhow::Error> {
+    Ok(SpdxItemRelationshipsBuilder::default()
+        .spdx_element_id(element_id)
+        .relationship_type(relationship_type)
+        .related_spdx_element(related_element_id)
+        .build()?)
+}
+
+// The SPDX package identifier can only contain letters, numbers,
+// and the characters `.` and `-`.
+pub fn get_spdx_id(derivation_path: &str) -> String {
+    let id = derivation_path
+        .replace("/nix/store/", "")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    format!("SPDXRef-{}", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_dump_tag_value() {
+        let spdx: Spdx = serde_json::from_str(
+            r###"
+            {
+              "SPDXID": "SPDXRef-DOCUMENT",
+              "spdxVersion": "SPDX-2.3",
+              "dataLicense": "http://spdx.org/licenses/CC0-1.0",
+              "name": "/nix/store/a-app-1.0.0.drv",
+              "documentNamespace": "https://spdx.org/spdxdocs/nix/store/a-app-1.0.0.drv-1234",
+              "creationInfo": { "created": "2024-01-01T00:00:00Z", "creators": ["Tool: nix2sbom"] },
+              "packages": [
+                {
+                  "SPDXID": "SPDXRef-a-app-1.0.0.drv",
+                  "name": "app",
+                  "versionInfo": "1.0.0",
+                  "downloadLocation": "https://example.com/app-1.0.0.tar.gz",
+                  "licenseDeclared": "MIT",
+                  "comment": "nix:drv_path: /nix/store/a-app-1.0.0.drv\nnix:system: x86_64-linux"
+                }
+              ],
+              "files": [
+                {
+                  "SPDXID": "SPDXRef-b-builder.sh",
+                  "fileName": "/nix/store/b-builder.sh",
+                  "checksums": [{ "algorithm": "SHA256", "checksumValue": "e3b0c442" }]
+                }
+              ],
+              "relationships": [
+                {
+                  "spdxElementId": "SPDXRef-a-app-1.0.0.drv",
+                  "relationshipType": "CONTAINS",
+                  "relatedSpdxElement": "SPDXRef-b-builder.sh"
+                }
+              ]
+            }
+            "###,
+        )
+        .unwrap();
+        let document = dump_tag_value(&spdx);
+        assert!(document.starts_with("SPDXVersion: SPDX-2.3\nDataLicense: CC0-1.0\n"));
+        assert!(
+            document.contains("\nPackageName: app\nSPDXID: SPDXRef-a-app-1.0.0.drv\nPackageVersion: 1.0.0\n")
+        );
+        assert!(document.contains(
+            "\nPackageComment: <text>nix:drv_path: /nix/store/a-app-1.0.0.drv\nnix:system: x86_64-linux</text>\n"
+        ));
+        assert!(document.contains("\nFileChecksum: SHA256: e3b0c442\n"));
+        assert!(document.contains("\nRelationship: SPDXRef-a-app-1.0.0.drv CONTAINS SPDXRef-b-builder.sh\n"));
+    }
+
+    #[test]
+    pub fn test_dump_sources_and_patches() {
+        let derivations: crate::nix::Derivations = serde_json::from_str(
+            r###"
+            {
+              "/nix/store/a-hello-2.12.1.drv": {
+                "args": [],
+                "builder": "/bin/sh",
+                "env": {
+                  "name": "hello-2.12.1",
+                  "pname": "hello",
+                  "version": "2.12.1",
+                  "src": "/nix/store/b-hello-2.12.1.tar.gz",
+                  "patches": "/nix/store/c-fix-build.patch"
+                },
+                "inputDrvs": {
+                  "/nix/store/b-hello-2.12.1.tar.gz.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                  "/nix/store/c-fix-build.patch.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+                },
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/a-hello-2.12.1" } },
+                "system": "x86_64-linux"
+              },
+              "/nix/store/b-hello-2.12.1.tar.gz.drv": {
+                "args": [],
+                "builder": "builtin:fetchurl",
+                "env": { "name": "hello-2.12.1.tar.gz", "url": "mirror://gnu/hello/hello-2.12.1.tar.gz" },
+                "inputDrvs": {},
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/b-hello-2.12.1.tar.gz", "hash": "8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20", "hashAlgo": "sha256" } },
+                "system": "builtin"
+              },
+              "/nix/store/c-fix-build.patch.drv": {
+                "args": [],
+                "builder": "builtin:fetchurl",
+                "env": { "name": "fix-build.patch", "url": "https://example.com/fix-build.patch" },
+                "inputDrvs": {},
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/c-fix-build.patch", "hash": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", "hashAlgo": "sha256" } },
+                "system": "builtin"
+              }
+            }
+            "###,
+        )
+        .unwrap();
+        let mut package_graph = crate::nix::get_package_graph(&derivations);
+        package_graph.transform(&crate::nix::Packages::default()).unwrap();
+
+        let document = dump(
+            &package_graph,
+            &crate::format::SerializationFormat::JSON,
+            &crate::nix::DumpOptions::default(),
+        )
+        .unwrap();
+        let spdx: Spdx = serde_json::from_str(&document).unwrap();
+        let packages = spdx.packages.unwrap();
+        let source_id = get_spdx_id("/nix/store/b-hello-2.12.1.tar.gz.drv");
+        let patch_id = get_spdx_id("/nix/store/c-fix-build.patch.drv");
+        assert!(packages.iter().any(|p| p.spdxid == source_id));
+        assert!(packages.iter().any(|p| p.spdxid == patch_id));
+
+        let hello_id = get_spdx_id("/nix/store/a-hello-2.12.1.drv");
+        let relationships = spdx.relationships.unwrap();
+        let has_relationship = |element_id: &str, relationship_type: &str, related_element_id: &str| {
+            relationships.iter().any(|r| {
+                r.spdx_element_id == element_id
+                    && r.relationship_type == relationship_type
+                    && r.related_spdx_element == related_element_id
+            })
+        };
+        assert!(has_relationship(
+            &hello_id,
+            GENERATED_FROM_RELATIONSHIP,
+            &source_id
+        ));
+        assert!(has_relationship(&patch_id, PATCH_APPLIED_RELATIONSHIP, &hello_id));
+    }
+
+    #[test]
+    pub fn test_dump_input_source() {
+        let directory = std::env::temp_dir().join(format!("nix2sbom-test-spdx-files-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let builder_path = directory.join("b-builder.sh").display().to_string();
+        std::fs::write(&builder_path, "abc").unwrap();
+
+        let file = dump_input_source(&crate::nix::InputSource::from_path(&builder_path))
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.checksums[0].algorithm, "SHA1");
+        assert_eq!(
+            file.checksums[0].checksum_value,
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(file.checksums[1].algorithm, "SHA256");
+
+        // A directory cannot be hashed, so it is not an SPDX file.
+        let input_source = crate::nix::InputSource::from_path(&directory.display().to_string());
+        assert!(dump_input_source(&input_source).unwrap().is_none());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
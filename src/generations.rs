@@ -0,0 +1,258 @@
+// Differences between two generations of a NixOS system profile, for example before
+// and after a `nixos-rebuild switch`. The components are matched by name, and a
+// component is upgraded or downgraded when its versions differ between the generations.
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+pub const SYSTEM_PROFILES_DIR: &str = "/nix/var/nix/profiles";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct Component {
+    pub name: String,
+
+    pub version: Option<String>,
+
+    pub purl: String,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct VersionChange {
+    pub name: String,
+
+    /// The versions of the component in the first generation.
+    pub from: Vec<String>,
+
+    /// The versions of the component in the second generation.
+    pub to: Vec<String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct GenerationDiff {
+    pub added: Vec<Component>,
+    pub removed: Vec<Component>,
+    pub upgraded: Vec<VersionChange>,
+    pub downgraded: Vec<VersionChange>,
+}
+
+impl GenerationDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.upgraded.is_empty()
+            && self.downgraded.is_empty()
+    }
+
+    // One line per change, grouped by kind of change.
+    pub fn to_text(&self) -> String {
+        let mut lines: Vec<String> = vec![];
+        if !self.added.is_empty() {
+            lines.push("Added:".to_string());
+            for component in &self.added {
+                lines.push(format!("  + {} ({})", component.name, component.purl));
+            }
+        }
+        if !self.removed.is_empty() {
+            lines.push("Removed:".to_string());
+            for component in &self.removed {
+                lines.push(format!("  - {} ({})", component.name, component.purl));
+            }
+        }
+        for (title, changes) in [("Upgraded:", &self.upgraded), ("Downgraded:", &self.downgraded)] {
+            if changes.is_empty() {
+                continue;
+            }
+            lines.push(title.to_string());
+            for change in changes {
+                lines.push(format!(
+                    "  ~ {} {} -> {}",
+                    change.name,
+                    change.from.join(", "),
+                    change.to.join(", ")
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+// Returns the path of a generation of the system profile. The generation is either
+// a generation number, e.g. 42 for /nix/var/nix/profiles/system-42-link, or a path.
+pub fn get_generation_path(generation: &str) -> Result<String, anyhow::Error> {
+    let generation_path = if !generation.is_empty() && generation.chars().all(|c| c.is_ascii_digit()) {
+        format!("{}/system-{}-link", SYSTEM_PROFILES_DIR, generation)
+    } else {
+        generation.to_string()
+    };
+    if !std::path::Path::new(&generation_path).exists() {
+        return Err(anyhow::format_err!(
+            "Could not find generation {} at {}",
+            generation,
+            generation_path
+        ));
+    }
+    Ok(generation_path)
+}
+
+pub fn diff(
+    package_graph_a: &crate::nix::PackageGraph,
+    package_graph_b: &crate::nix::PackageGraph,
+) -> GenerationDiff {
+    let components_a = get_components(package_graph_a);
+    let components_b = get_components(package_graph_b);
+
+    let mut response = GenerationDiff::default();
+    for (name, versions_b) in &components_b {
+        let versions_a = match components_a.get(name) {
+            Some(v) => v,
+            None => {
+                response.added.extend(versions_b.values().cloned());
+                continue;
+            }
+        };
+        if versions_a.keys().eq(versions_b.keys()) {
+            continue;
+        }
+        let change = VersionChange {
+            name: name.to_string(),
+            from: versions_a.keys().map(|v| v.clone().unwrap_or_default()).collect(),
+            to: versions_b.keys().map(|v| v.clone().unwrap_or_default()).collect(),
+        };
+        let latest_a = change.from.iter().max_by(|a, b| compare_versions(a, b));
+        let latest_b = change.to.iter().max_by(|a, b| compare_versions(a, b));
+        match (latest_a, latest_b) {
+            (Some(a), Some(b)) if compare_versions(b, a) == Ordering::Less => response.downgraded.push(change),
+            _ => response.upgraded.push(change),
+        };
+    }
+    for (name, versions_a) in &components_a {
+        if !components_b.contains_key(name) {
+            response.removed.extend(versions_a.values().cloned());
+        }
+    }
+    response
+}
+
+// Indexes the named components of the graph by name and by version.
+fn get_components(
+    package_graph: &crate::nix::PackageGraph,
+) -> BTreeMap<String, BTreeMap<Option<String>, Component>> {
+    let mut response: BTreeMap<String, BTreeMap<Option<String>, Component>> = BTreeMap::default();
+    for package_node in package_graph.nodes_next.values() {
+        if package_node.is_inline_script() {
+            continue;
+        }
+        let name = match &package_node.name {
+            Some(n) => n,
+            None => continue,
+        };
+        let version = package_node.get_version();
+        response
+            .entry(name.to_string())
+            .or_default()
+            .entry(version.clone())
+            .or_insert_with(|| Component {
+                name: name.to_string(),
+                version,
+                purl: package_node.get_purl().to_string(),
+            });
+    }
+    response
+}
+
+// Compares two versions with the rules of builtins.compareVersions: the versions are
+// split in components of digits or letters, the numeric components are compared as
+// numbers, `pre` is lower than any other component and a number is higher than a word.
+pub fn compare_versions(version_a: &str, version_b: &str) -> Ordering {
+    let components_a = get_version_components(version_a);
+    let components_b = get_version_components(version_b);
+    for i in 0..components_a.len().max(components_b.len()) {
+        let component_a = components_a.get(i).map(|c| c.as_str()).unwrap_or_default();
+        let component_b = components_b.get(i).map(|c| c.as_str()).unwrap_or_default();
+        if is_version_component_lower(component_a, component_b) {
+            return Ordering::Less;
+        }
+        if is_version_component_lower(component_b, component_a) {
+            return Ordering::Greater;
+        }
+    }
+    Ordering::Equal
+}
+
+fn get_version_components(version: &str) -> Vec<String> {
+    let mut response: Vec<String> = vec![];
+    let mut current_component = String::new();
+    for c in version.chars() {
+        if !c.is_ascii_alphanumeric() {
+            if !current_component.is_empty() {
+                response.push(std::mem::take(&mut current_component));
+            }
+            continue;
+        }
+        let same_kind = current_component
+            .chars()
+            .last()
+            .is_none_or(|l| l.is_ascii_digit() == c.is_ascii_digit());
+        if !same_kind {
+            response.push(std::mem::take(&mut current_component));
+        }
+        current_component.push(c);
+    }
+    if !current_component.is_empty() {
+        response.push(current_component);
+    }
+    response
+}
+
+fn is_version_component_lower(component_a: &str, component_b: &str) -> bool {
+    let number_a = component_a.parse::<u64>().ok();
+    let number_b = component_b.parse::<u64>().ok();
+    match (number_a, number_b) {
+        (Some(a), Some(b)) => a < b,
+        _ if component_a.is_empty() && number_b.is_some() => true,
+        _ if component_a == "pre" => component_b != "pre",
+        _ if component_b == "pre" => false,
+        (Some(_), None) => false,
+        (None, Some(_)) => true,
+        (None, None) => component_a < component_b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_compare_versions() {
+        assert_eq!(compare_versions("1.0", "2.3"), Ordering::Less);
+        assert_eq!(compare_versions("2.1", "2.3"), Ordering::Less);
+        assert_eq!(compare_versions("2.3", "2.3"), Ordering::Equal);
+        assert_eq!(compare_versions("2.5", "2.3"), Ordering::Greater);
+        assert_eq!(compare_versions("3.1", "2.3"), Ordering::Greater);
+        assert_eq!(compare_versions("2.3.1", "2.3"), Ordering::Greater);
+        assert_eq!(compare_versions("2.3.1", "2.3a"), Ordering::Greater);
+        assert_eq!(compare_versions("2.3pre1", "2.3"), Ordering::Less);
+        assert_eq!(compare_versions("2.3pre3", "2.3pre12"), Ordering::Less);
+        assert_eq!(compare_versions("2.3a", "2.3c"), Ordering::Less);
+        assert_eq!(compare_versions("2.3pre1", "2.3c"), Ordering::Less);
+        assert_eq!(compare_versions("2.3pre1", "2.3q"), Ordering::Less);
+        assert_eq!(compare_versions("3.0.13", "3.0.9"), Ordering::Greater);
+    }
+
+    #[test]
+    pub fn test_get_generation_path() {
+        assert!(get_generation_path("/nix/store/does-not-exist-system").is_err());
+        assert!(get_generation_path("/").is_ok());
+    }
+}
@@ -0,0 +1,82 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+// Type of the build product registered for the SBOM in Hydra.
+pub const SBOM_PRODUCT_TYPE: &str = "sbom";
+
+const STORE_DIR: &str = "/nix/store";
+
+/// Environment available to the post-build hooks.
+/// See https://nixos.org/manual/nix/stable/advanced-topics/post-build-hook
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct HookEnvironment {
+    pub derivation_path: String,
+    pub out_paths: Vec<String>,
+}
+
+impl HookEnvironment {
+    pub fn from_env() -> Result<HookEnvironment, anyhow::Error> {
+        let derivation_path = std::env::var("DRV_PATH").map_err(|_| {
+            anyhow::format_err!("DRV_PATH is not set. Is nix2sbom running as a post-build hook?")
+        })?;
+        let out_paths = std::env::var("OUT_PATHS").unwrap_or_default();
+        Ok(HookEnvironment {
+            derivation_path,
+            out_paths: out_paths.split_whitespace().map(|p| p.to_string()).collect(),
+        })
+    }
+}
+
+// Writes the file in the output directory and registers it in the Hydra build products
+// of that directory. The outputs of the derivation are already registered in the store
+// when the hook runs, so writing into them would invalidate their NAR hash.
+// See https://github.com/NixOS/hydra/blob/master/doc/manual/src/jobs.md#build-products
+pub fn write_build_product(output_dir: &str, file_name: &str, content: &str) -> Result<String, anyhow::Error> {
+    if Path::new(output_dir).starts_with(STORE_DIR) {
+        return Err(anyhow::format_err!(
+            "Cannot write the build products to {}, which is in the Nix store",
+            output_dir
+        ));
+    }
+    fs::create_dir_all(output_dir)?;
+    let product_path = Path::new(output_dir).join(file_name);
+    crate::utils::write_file_atomically(&product_path.display().to_string(), content)?;
+
+    let nix_support_path = Path::new(output_dir).join("nix-support");
+    fs::create_dir_all(&nix_support_path)?;
+    let mut build_products = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(nix_support_path.join("hydra-build-products"))?;
+    writeln!(
+        build_products,
+        "file {} {}",
+        SBOM_PRODUCT_TYPE,
+        product_path.display()
+    )?;
+
+    Ok(product_path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_write_build_product() {
+        let directory = std::env::temp_dir().join(format!("nix2sbom-test-hydra-{}", std::process::id()));
+        let output_dir = directory.display().to_string();
+        let product_path = write_build_product(&output_dir, "sbom.cdx.json", "{}").unwrap();
+        assert_eq!(fs::read_to_string(&product_path).unwrap(), "{}");
+        assert_eq!(
+            fs::read_to_string(directory.join("nix-support/hydra-build-products")).unwrap(),
+            format!("file sbom {}\n", product_path)
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert!(write_build_product("/nix/store/a-hello-2.12.1", "sbom.cdx.json", "{}").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+// Translations of the human-facing outputs, like the generation summary. The
+// machine-readable outputs (SBOMs, stats, reports in JSON) are never translated.
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub enum Language {
+    #[default]
+    English,
+    French,
+    German,
+}
+
+pub const SUPPORTED_LANGUAGES: [&str; 3] = ["en", "fr", "de"];
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum Message {
+    Components,
+    Nodes,
+    Skipped,
+    Incomplete,
+    /// Label of the lines listing the reasons of the exclusions.
+    SkippedReason,
+    Coverage,
+    Version,
+    License,
+    PackageMetadata,
+    PurlType,
+    InvalidPackageMetadata,
+    EntriesSkipped,
+    KernelComponent,
+    Warning,
+    Durations,
+}
+
+impl Language {
+    // Also accepts the locales, e.g. fr_CA.UTF-8
+    pub fn from_string(language: &str) -> Option<Language> {
+        let language = language
+            .split(['_', '-', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match language.as_str() {
+            "en" => Some(Language::English),
+            "fr" => Some(Language::French),
+            "de" => Some(Language::German),
+            _ => None,
+        }
+    }
+
+    pub fn get_text(&self, message: &Message) -> &'static str {
+        match self {
+            Language::English => match message {
+                Message::Components => "Components",
+                Message::Nodes => "nodes",
+                Message::Skipped => "skipped",
+                Message::Incomplete => "incomplete",
+                Message::SkippedReason => "Skipped",
+                Message::Coverage => "Coverage",
+                Message::Version => "version",
+                Message::License => "license",
+                Message::PackageMetadata => "package metadata",
+                Message::PurlType => "purl type",
+                Message::InvalidPackageMetadata => "Invalid package metadata",
+                Message::EntriesSkipped => "entries skipped",
+                Message::KernelComponent => "Kernel component",
+                Message::Warning => "Warning",
+                Message::Durations => "Durations",
+            },
+            Language::French => match message {
+                Message::Components => "Composants",
+                Message::Nodes => "nœuds",
+                Message::Skipped => "exclus",
+                Message::Incomplete => "incomplets",
+                Message::SkippedReason => "Exclus",
+                Message::Coverage => "Couverture",
+                Message::Version => "version",
+                Message::License => "licence",
+                Message::PackageMetadata => "métadonnées des paquets",
+                Message::PurlType => "type de purl",
+                Message::InvalidPackageMetadata => "Métadonnées de paquets invalides",
+                Message::EntriesSkipped => "entrées ignorées",
+                Message::KernelComponent => "Composant du noyau",
+                Message::Warning => "Avertissement",
+                Message::Durations => "Durées",
+            },
+            Language::German => match message {
+                Message::Components => "Komponenten",
+                Message::Nodes => "Knoten",
+                Message::Skipped => "ausgeschlossen",
+                Message::Incomplete => "unvollständig",
+                Message::SkippedReason => "Ausgeschlossen",
+                Message::Coverage => "Abdeckung",
+                Message::Version => "Version",
+                Message::License => "Lizenz",
+                Message::PackageMetadata => "Paketmetadaten",
+                Message::PurlType => "purl-Typ",
+                Message::InvalidPackageMetadata => "Ungültige Paketmetadaten",
+                Message::EntriesSkipped => "Einträge übersprungen",
+                Message::KernelComponent => "Kernelkomponente",
+                Message::Warning => "Warnung",
+                Message::Durations => "Dauer",
+            },
+        }
+    }
+
+    // French and German use a decimal comma.
+    pub fn format_number(&self, number: f64, precision: usize) -> String {
+        let formatted_number = format!("{:.*}", precision, number);
+        match self {
+            Language::English => formatted_number,
+            Language::French | Language::German => formatted_number.replace('.', ","),
+        }
+    }
+
+    pub fn format_percentage(&self, ratio: f64) -> String {
+        let percentage = self.format_number(ratio * 100.0, 1);
+        match self {
+            // A narrow no-break space precedes the percent sign in French.
+            Language::French => format!("{}\u{202f}%", percentage),
+            Language::English | Language::German => format!("{}%", percentage),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_language() {
+        assert_eq!(Language::from_string("fr"), Some(Language::French));
+        assert_eq!(Language::from_string("de_DE.UTF-8"), Some(Language::German));
+        assert_eq!(Language::from_string("EN-us"), Some(Language::English));
+        assert_eq!(Language::from_string("es"), None);
+        assert_eq!(Language::French.format_percentage(0.5), "50,0\u{202f}%");
+        assert_eq!(Language::German.format_number(1.234, 2), "1,23");
+        assert_eq!(Language::English.format_percentage(1.0), "100.0%");
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// The name, version and purl type of a package are resolved by trying a list of
+// resolvers in order, the first resolver returning a value winning. The default
+// resolvers look at the env of the derivation, then at its URLs, then at its
+// store path. Library users can insert their own resolvers in the list, for
+// example to support an internal artifact naming convention. The fallback resolvers
+// are only tried when neither the derivation nor its source derivation could be named.
+
+// The names that do not identify a package, for example the name of the derivations
+// created by fetchFromGitHub or by makeSetupHook.
+const GENERIC_NAMES: [&str; 4] = ["source", "src", "hook", "setup-hook"];
+
+pub fn is_generic_name(name: &str) -> bool {
+    GENERIC_NAMES.contains(&name)
+}
+
+pub trait Resolver: Send + Sync {
+    // Name of the resolver, used for logging.
+    fn get_name(&self) -> &str;
+
+    fn resolve_name(&self, _derivation: &crate::nix::Derivation) -> Option<String> {
+        None
+    }
+
+    fn resolve_version(&self, _derivation: &crate::nix::Derivation) -> Option<String> {
+        None
+    }
+
+    // Returns the purl type of the package, e.g. cargo or pypi.
+    fn resolve_purl_type(&self, _derivation: &crate::nix::Derivation) -> Option<String> {
+        None
+    }
+}
+
+pub struct Resolvers {
+    resolvers: Vec<Box<dyn Resolver>>,
+    fallback_resolvers: Vec<Box<dyn Resolver>>,
+}
+
+impl Default for Resolvers {
+    fn default() -> Resolvers {
+        Resolvers {
+            resolvers: vec![
+                Box::new(EnvResolver {}),
+                Box::new(UrlResolver {}),
+                Box::new(StorePathResolver {}),
+            ],
+            fallback_resolvers: vec![Box::new(BuilderScriptResolver {})],
+        }
+    }
+}
+
+impl Resolvers {
+    // Creates the default resolvers, preceded by the overrides if there are any.
+    pub fn with_overrides(overrides: &BTreeMap<String, IdentityOverride>) -> Resolvers {
+        let mut resolvers = Resolvers::default();
+        if !overrides.is_empty() {
+            resolvers.insert(
+                0,
+                Box::new(OverrideResolver {
+                    overrides: overrides.clone(),
+                }),
+            );
+        }
+        resolvers
+    }
+
+    // Inserts a resolver at the given position. Position 0 is tried first.
+    pub fn insert(&mut self, position: usize, resolver: Box<dyn Resolver>) {
+        self.resolvers.insert(position, resolver);
+    }
+
+    pub fn push(&mut self, resolver: Box<dyn Resolver>) {
+        self.resolvers.push(resolver);
+    }
+
+    pub fn push_fallback(&mut self, resolver: Box<dyn Resolver>) {
+        self.fallback_resolvers.push(resolver);
+    }
+
+    pub fn get_names(&self) -> Vec<&str> {
+        self.resolvers.iter().map(|r| r.get_name()).collect()
+    }
+
+    pub fn resolve_name(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        self.resolvers.iter().find_map(|r| r.resolve_name(derivation))
+    }
+
+    pub fn resolve_fallback_name(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        self.fallback_resolvers
+            .iter()
+            .find_map(|r| r.resolve_name(derivation))
+    }
+
+    pub fn resolve_version(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        self.resolvers.iter().find_map(|r| r.resolve_version(derivation))
+    }
+
+    pub fn resolve_purl_type(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        self.resolvers
+            .iter()
+            .find_map(|r| r.resolve_purl_type(derivation))
+    }
+}
+
+// Uses the pname, name and version declared in the env of the derivation.
+pub struct EnvResolver {}
+
+impl Resolver for EnvResolver {
+    fn get_name(&self) -> &str {
+        "env"
+    }
+
+    fn resolve_name(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        if let Some(pname) = derivation.env.get("pname") {
+            return Some(pname.to_string());
+        }
+
+        let name = derivation.env.get("name")?;
+        if let Some(version) = derivation.get_version_from_env() {
+            if name.contains(&version) {
+                let package_version_suffix = "-".to_string() + &version;
+                return Some(name.replace(&package_version_suffix, ""));
+            }
+        }
+        if let Some(possible_version) = name.split("-").last() {
+            if crate::nix::is_semantic_version(possible_version) {
+                let package_version_suffix = "-".to_string() + possible_version;
+                return Some(name.replace(&package_version_suffix, ""));
+            }
+        }
+        if !is_generic_name(name) {
+            return Some(name.to_string());
+        }
+        None
+    }
+
+    fn resolve_version(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        derivation.get_version_from_env()
+    }
+}
+
+// Uses the URLs of the derivation, which are usually the URLs of the sources.
+pub struct UrlResolver {}
+
+impl Resolver for UrlResolver {
+    fn get_name(&self) -> &str {
+        "url"
+    }
+
+    fn resolve_name(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        let fetched_source = derivation.get_fetched_source()?;
+        if let Some(name) = fetched_source.name {
+            return Some(name);
+        }
+        for url in fetched_source.urls {
+            if let Some(project_name) = crate::utils::get_project_name_from_generic_url(&url) {
+                return Some(project_name.to_string());
+            }
+            if let Some(project_name) = crate::utils::get_project_name_from_archive_url(&url) {
+                return Some(project_name.to_string());
+            }
+        }
+        None
+    }
+
+    fn resolve_version(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        let fetched_source = derivation.get_fetched_source()?;
+        if let Some(version) = fetched_source.version {
+            return Some(version);
+        }
+        for url in fetched_source.urls {
+            if let Some(commit_sha) = crate::utils::get_git_sha_from_archive_url(&url) {
+                return Some(commit_sha);
+            }
+            if let Some(version) = crate::utils::get_semver_from_archive_url(&url) {
+                return Some(version);
+            }
+        }
+        None
+    }
+
+    fn resolve_purl_type(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        let fetched_source = derivation.get_fetched_source()?;
+        // The purls are generated without a namespace, which the purls of the git forges require.
+        if fetched_source.namespace.is_some() {
+            return None;
+        }
+        fetched_source.ecosystem
+    }
+}
+
+// Uses the name of the derivation, or the name of its main output path when
+// the derivation has no name, which is formatted as `name-version`.
+pub struct StorePathResolver {}
+
+impl StorePathResolver {
+    fn get_full_name(derivation: &crate::nix::Derivation) -> Option<String> {
+        if let Some(name) = derivation.env.get("name") {
+            return Some(name.to_string());
+        }
+        let output = derivation.outputs.get("out")?;
+        Some(crate::nix::get_store_path_name(&output.path))
+    }
+}
+
+impl Resolver for StorePathResolver {
+    fn get_name(&self) -> &str {
+        "store-path"
+    }
+
+    fn resolve_name(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        // The name from the env is already handled by the env resolver.
+        if derivation.env.contains_key("name") {
+            return None;
+        }
+        let full_name = StorePathResolver::get_full_name(derivation)?;
+        match full_name.rsplit_once('-') {
+            Some((name, version)) if crate::nix::is_semantic_version(version) => Some(name.to_string()),
+            _ if !is_generic_name(&full_name) => Some(full_name),
+            _ => None,
+        }
+    }
+
+    fn resolve_version(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        let full_name = StorePathResolver::get_full_name(derivation)?;
+        if let Some(possible_version) = full_name.split("-").last() {
+            if crate::nix::is_semantic_version(possible_version) {
+                return Some(possible_version.to_string());
+            }
+        }
+        let pname = derivation.env.get("pname")?;
+        if full_name.contains(pname) {
+            let package_name_prefix = pname.to_string() + "-";
+            return Some(full_name.replace(&package_name_prefix, ""));
+        }
+        None
+    }
+}
+
+// Names the derivations that only run a builder script, for example the setup hooks,
+// which have no pname, no meaningful name and no URLs. The name is taken from the
+// first of the inputSrcs, e.g. auto-patchelf for /nix/store/<hash>-auto-patchelf.sh,
+// or else from the main output path.
+pub struct BuilderScriptResolver {}
+
+impl Resolver for BuilderScriptResolver {
+    fn get_name(&self) -> &str {
+        "builder-script"
+    }
+
+    fn resolve_name(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        // The fixed-output derivations are sources, which use the builder of their fetcher.
+        if derivation.get_output_hash().is_some() {
+            return None;
+        }
+        if let Some(input_source_path) = derivation.inputs_sources.first() {
+            let file_name = crate::nix::get_store_path_name(input_source_path);
+            let name = match file_name.rsplit_once('.') {
+                Some((name, extension)) if extension.chars().all(|c| c.is_ascii_alphabetic()) => name,
+                _ => &file_name,
+            };
+            if !name.is_empty() && !is_generic_name(name) {
+                return Some(name.to_string());
+            }
+        }
+        let output = derivation.outputs.get("out")?;
+        let name = crate::nix::get_store_path_name(&output.path);
+        if is_generic_name(&name) {
+            return None;
+        }
+        Some(name)
+    }
+}
+
+/// Identity of a package, set by the user for the packages that cannot be
+/// identified automatically.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct IdentityOverride {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub purl_type: Option<String>,
+}
+
+// Uses the identities set by the user, indexed by derivation name (e.g. openssl-3.0.13)
+// or by the name of the main output path.
+pub struct OverrideResolver {
+    pub overrides: BTreeMap<String, IdentityOverride>,
+}
+
+impl OverrideResolver {
+    fn get_override(&self, derivation: &crate::nix::Derivation) -> Option<&IdentityOverride> {
+        let full_name = StorePathResolver::get_full_name(derivation)?;
+        self.overrides.get(&full_name)
+    }
+}
+
+impl Resolver for OverrideResolver {
+    fn get_name(&self) -> &str {
+        "override"
+    }
+
+    fn resolve_name(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        self.get_override(derivation)?.name.clone()
+    }
+
+    fn resolve_version(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        self.get_override(derivation)?.version.clone()
+    }
+
+    fn resolve_purl_type(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+        self.get_override(derivation)?.purl_type.clone()
+    }
+}
+
+// See https://github.com/package-url/purl-spec/blob/master/PURL-TYPES.rst
+// for the accepted types.
+// TODO How can we detect go and swift packages? The url will just be another git URL
+// TODO gitlab ??
+// TODO openwrt ??
+pub fn get_purl_type_from_url(url: &str) -> Option<&'static str> {
+    if url.starts_with("https://crates.io") {
+        return Some("cargo");
+    }
+    if url.starts_with("https://www.cpan.org/") {
+        return Some("cpan");
+    }
+    if url.starts_with("https://rubygems.org") {
+        return Some("gem");
+    }
+    if url.starts_with("https://hackage.haskell.org/") {
+        return Some("hackage");
+    }
+    if url.starts_with("https://repo.maven.apache.org/maven2") {
+        return Some("maven");
+    }
+    if url.starts_with("https://registry.npmjs.org") {
+        return Some("npm");
+    }
+    if url.starts_with("https://www.nuget.org") {
+        return Some("nuget");
+    }
+    if url.starts_with("https://bitbucket.org") {
+        return Some("bitbucket");
+    }
+    if url.starts_with("https://hub.docker.com") {
+        return Some("docker");
+    }
+    if url.starts_with("https://pypi.org") || url.starts_with("https://pypi.python.org") {
+        return Some("pypi");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ArtifactResolver {}
+
+    impl Resolver for ArtifactResolver {
+        fn get_name(&self) -> &str {
+            "artifact"
+        }
+
+        fn resolve_name(&self, derivation: &crate::nix::Derivation) -> Option<String> {
+            let name = derivation.env.get("name")?;
+            name.strip_prefix("acme-artifact-").map(|n| n.to_string())
+        }
+    }
+
+    #[test]
+    pub fn test_resolvers() {
+        let derivation: crate::nix::Derivation = serde_json::from_str(
+            r###"
+            {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {
+                "name": "acme-artifact-widgets-1.2.3",
+                "urls": "https://crates.io/api/v1/crates/widgets/1.2.3/download"
+              },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-acme-artifact-widgets-1.2.3" } },
+              "system": "x86_64-linux"
+            }
+            "###,
+        )
+        .unwrap();
+
+        let mut resolvers = Resolvers::default();
+        assert_eq!(
+            resolvers.resolve_name(&derivation),
+            Some("acme-artifact-widgets".to_string())
+        );
+        assert_eq!(resolvers.resolve_version(&derivation), Some("1.2.3".to_string()));
+        assert_eq!(
+            resolvers.resolve_purl_type(&derivation),
+            Some("cargo".to_string())
+        );
+
+        resolvers.insert(0, Box::new(ArtifactResolver {}));
+        assert_eq!(
+            resolvers.get_names(),
+            vec!["artifact", "env", "url", "store-path"]
+        );
+        assert_eq!(
+            resolvers.resolve_name(&derivation),
+            Some("widgets-1.2.3".to_string())
+        );
+
+        let resolvers = Resolvers::with_overrides(&BTreeMap::from([(
+            "acme-artifact-widgets-1.2.3".to_string(),
+            IdentityOverride {
+                name: Some("widgets".to_string()),
+                purl_type: Some("generic".to_string()),
+                ..Default::default()
+            },
+        )]));
+        assert_eq!(resolvers.resolve_name(&derivation), Some("widgets".to_string()));
+        assert_eq!(resolvers.resolve_version(&derivation), Some("1.2.3".to_string()));
+        assert_eq!(
+            resolvers.resolve_purl_type(&derivation),
+            Some("generic".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_builder_script_resolver() {
+        let mut derivation: crate::nix::Derivation = serde_json::from_str(
+            r###"
+            {
+              "args": ["-e", "/nix/store/b-builder.sh"],
+              "builder": "/nix/store/c-bash-5.2p26/bin/bash",
+              "env": {
+                "name": "hook"
+              },
+              "inputDrvs": {},
+              "inputSrcs": ["/nix/store/a-auto-patchelf.sh", "/nix/store/b-builder.sh"],
+              "outputs": { "out": { "path": "/nix/store/d-hook" } },
+              "system": "x86_64-linux"
+            }
+            "###,
+        )
+        .unwrap();
+
+        let resolvers = Resolvers::default();
+        assert_eq!(resolvers.resolve_name(&derivation), None);
+        assert_eq!(
+            resolvers.resolve_fallback_name(&derivation),
+            Some("auto-patchelf".to_string())
+        );
+
+        derivation.inputs_sources = vec![];
+        assert_eq!(resolvers.resolve_fallback_name(&derivation), None);
+        derivation.outputs.get_mut("out").unwrap().path = "/nix/store/d-wrap-gapps-hook".to_string();
+        assert_eq!(
+            resolvers.resolve_fallback_name(&derivation),
+            Some("wrap-gapps-hook".to_string())
+        );
+    }
+}
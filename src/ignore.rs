@@ -0,0 +1,98 @@
+// Components excluded from the published SBOMs, using rules in the style of the
+// .gitignore files:
+//
+//   # Comments start with a hash.
+//   pkg:cargo/*-sys@*
+//   !pkg:cargo/openssl-sys@*
+//   test-fixture-*
+//
+// The patterns are matched against the name and the purl of the components. `*`
+// matches any sequence of characters and `?` matches a single character. A pattern
+// starting with `!` includes again the components matched by a previous pattern.
+// As with .gitignore, the last matching rule takes precedence.
+use regex::Regex;
+
+pub const IGNORE_FILE_NAME: &str = ".nix2sbomignore";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct IgnoreRules {
+    rules: Vec<(Regex, bool)>,
+}
+
+impl IgnoreRules {
+    pub fn from_file(path: &str) -> Result<IgnoreRules, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::format_err!("Could not read the ignore rules {}: {}", path, e))?;
+        IgnoreRules::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<IgnoreRules, anyhow::Error> {
+        let mut rules: Vec<(Regex, bool)> = vec![];
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, ignored) = match line.strip_prefix('!') {
+                Some(p) => (p, false),
+                None => (line.strip_prefix('\\').unwrap_or(line), true),
+            };
+            let regex = format!(
+                "^{}$",
+                regex::escape(pattern).replace("\\*", ".*").replace("\\?", ".")
+            );
+            rules.push((Regex::new(&regex)?, ignored));
+        }
+        Ok(IgnoreRules { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_ignored(&self, name: Option<&str>, purl: &str) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern.is_match(purl) || name.is_some_and(|n| pattern.is_match(n)))
+            .is_some_and(|(_, ignored)| *ignored)
+    }
+}
+
+// Returns the path of the ignore file at the root of a local flake, if there is one.
+pub fn find_ignore_file(flake_ref: &str) -> Option<String> {
+    let flake_path = crate::flake::get_local_flake_path(flake_ref)?;
+    let ignore_file_path = std::path::Path::new(flake_path).join(IGNORE_FILE_NAME);
+    if !ignore_file_path.is_file() {
+        return None;
+    }
+    Some(ignore_file_path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_is_ignored() {
+        let ignore_rules = IgnoreRules::parse(
+            r###"
+            # Vendored bindings
+            pkg:cargo/*-sys@*
+            !pkg:cargo/openssl-sys@*
+            test-fixture-?
+            "###,
+        )
+        .unwrap();
+        assert!(ignore_rules.is_ignored(Some("libz-sys"), "pkg:cargo/libz-sys@1.1.15"));
+        assert!(!ignore_rules.is_ignored(Some("openssl-sys"), "pkg:cargo/openssl-sys@0.9.101"));
+        assert!(ignore_rules.is_ignored(Some("test-fixture-a"), "pkg:generic/test-fixture-a"));
+        assert!(!ignore_rules.is_ignored(Some("test-fixture-ab"), "pkg:generic/test-fixture-ab"));
+        assert!(!ignore_rules.is_ignored(None, "pkg:generic/zlib@1.3.1"));
+
+        assert!(IgnoreRules::default().is_empty());
+        assert!(!IgnoreRules::default().is_ignored(Some("zlib"), "pkg:generic/zlib@1.3.1"));
+    }
+}
@@ -1,6 +1,33 @@
+pub mod advisory;
+pub mod aterm;
+pub mod attestation;
+pub mod backend;
+pub mod batch;
+pub mod classify;
+pub mod config;
 pub mod consts;
+pub mod convert;
+pub mod daemon;
+pub mod diff;
+pub mod fetcher;
+pub mod flake;
 pub mod format;
+pub mod generations;
+pub mod hydra;
+pub mod i18n;
+pub mod identify;
+pub mod ignore;
+pub mod license;
 pub mod logger;
+pub mod manifest;
+pub mod merge;
 pub mod mirrors;
 pub mod nix;
+pub mod owners;
+pub mod profile;
+pub mod properties;
+pub mod report;
+pub mod search;
+pub mod self_sbom;
 pub mod utils;
+pub mod xml;
@@ -0,0 +1,339 @@
+// Filtering of the packages by license, using the SPDX license expressions of the
+// packages. See https://spdx.github.io/spdx-spec/v2.3/SPDX-license-expressions/
+const OPERATORS: &[&str] = &["AND", "OR", "WITH"];
+
+// Suffixes of the SPDX license identifiers of the GNU licenses.
+const VERSION_SUFFIXES: &[&str] = &["-only", "-or-later"];
+
+// Identifiers and texts of the SPDX license list, including the deprecated identifiers,
+// bundled with the spdx crate. Every identifier of the list has a text.
+// See https://github.com/spdx/license-list-data
+const LICENSE_IDS: &[(&str, &str, u8)] = spdx::identifiers::LICENSES;
+const LICENSE_TEXTS: &[(&str, &str)] = spdx::text::LICENSE_TEXTS;
+
+// Returns the canonical identifier of a license of the SPDX license list. The license
+// identifiers are case-insensitive, and can end with the + operator.
+pub fn get_canonical_license_id(license_id: &str) -> Option<String> {
+    let (license_id, suffix) = match license_id.strip_suffix('+') {
+        Some(l) => (l, "+"),
+        None => (license_id, ""),
+    };
+    LICENSE_IDS
+        .iter()
+        .find(|(id, _, _)| id.eq_ignore_ascii_case(license_id))
+        .map(|(id, _, _)| format!("{}{}", id, suffix))
+}
+
+// Returns the license identifiers of an SPDX license expression. The exceptions
+// are not returned, and the + operator is removed from the identifiers.
+pub fn get_license_ids(expression: &str) -> Vec<String> {
+    let mut response: Vec<String> = vec![];
+    let mut is_exception = false;
+    for token in expression.replace(['(', ')'], " ").split_whitespace() {
+        if OPERATORS.contains(&token.to_uppercase().as_str()) {
+            is_exception = token.eq_ignore_ascii_case("WITH");
+            continue;
+        }
+        if is_exception {
+            is_exception = false;
+            continue;
+        }
+        let license_id = token.trim_end_matches('+').to_string();
+        if !response.contains(&license_id) {
+            response.push(license_id);
+        }
+    }
+    response
+}
+
+// Patterns are case-insensitive license identifiers, and can end with a * to match
+// every identifier with that prefix, e.g. GPL-*. A pattern without a version suffix
+// also matches the -only and -or-later identifiers, e.g. GPL-2.0 matches GPL-2.0-only.
+pub fn matches_license_pattern(license_id: &str, pattern: &str) -> bool {
+    let license_id = license_id.to_lowercase();
+    let pattern = pattern.trim().to_lowercase();
+    if let Some(prefix) = pattern.strip_suffix('*') {
+        return license_id.starts_with(prefix);
+    }
+    if license_id == pattern {
+        return true;
+    }
+    VERSION_SUFFIXES
+        .iter()
+        .any(|suffix| license_id.strip_suffix(suffix) == Some(pattern.as_str()))
+}
+
+// Returns the license of a package, the concluded license taking precedence
+// over the declared license.
+pub fn get_package_license_expression(package_node: &crate::nix::PackageNode) -> Option<String> {
+    if let Some(concluded_license) = &package_node.concluded_license {
+        return Some(concluded_license.to_string());
+    }
+    match &package_node.package {
+        Some(p) => p.meta.get_spdx_license_expression(),
+        None => None,
+    }
+}
+
+// The license identifiers are case-insensitive, so the canonical identifier is
+// returned along with the text.
+pub fn get_license_text(license_id: &str) -> Option<(&'static str, &'static str)> {
+    LICENSE_TEXTS
+        .iter()
+        .find(|(id, _)| id.eq_ignore_ascii_case(license_id))
+        .copied()
+}
+
+// Returns the identifiers of the licenses declared and concluded for the package.
+pub fn get_package_license_ids(package_node: &crate::nix::PackageNode) -> Vec<String> {
+    let declared_license = package_node
+        .package
+        .as_ref()
+        .and_then(|p| p.meta.get_spdx_license_expression());
+    let mut response: Vec<String> = vec![];
+    for license_expression in declared_license
+        .iter()
+        .chain(package_node.concluded_license.iter())
+    {
+        for license_id in get_license_ids(license_expression) {
+            if !response.contains(&license_id) {
+                response.push(license_id);
+            }
+        }
+    }
+    response
+}
+
+// Returns the paths of the license texts written for the licenses of the package,
+// indexed by license identifier.
+pub fn get_package_license_texts<'a>(
+    package_node: &crate::nix::PackageNode,
+    license_texts: &'a std::collections::BTreeMap<String, String>,
+) -> std::collections::BTreeMap<String, &'a String> {
+    let mut response: std::collections::BTreeMap<String, &'a String> = std::collections::BTreeMap::default();
+    for license_id in get_package_license_ids(package_node) {
+        let canonical_id = match get_canonical_license_id(&license_id) {
+            Some(id) => id,
+            None => continue,
+        };
+        if let Some(path) = license_texts.get(&canonical_id) {
+            response.insert(canonical_id, path);
+        }
+    }
+    response
+}
+
+// Writes the text of every license declared or concluded for the packages of the graph in
+// the directory, and returns the paths of the files written indexed by canonical license
+// identifier. The licenses that are not on the SPDX license list, like the LicenseRefs,
+// have no text and are reported.
+pub fn write_license_texts(
+    package_graph: &crate::nix::PackageGraph,
+    directory: &str,
+) -> Result<std::collections::BTreeMap<String, String>, anyhow::Error> {
+    let mut license_ids: std::collections::BTreeSet<String> = std::collections::BTreeSet::default();
+    for package_node in package_graph.nodes_next.values() {
+        license_ids.extend(get_package_license_ids(package_node));
+    }
+
+    std::fs::create_dir_all(directory).map_err(|e| {
+        anyhow::format_err!(
+            "Could not create the license texts directory {}: {}",
+            directory,
+            e
+        )
+    })?;
+    let mut response: std::collections::BTreeMap<String, String> = std::collections::BTreeMap::default();
+    let mut missing_license_ids: Vec<String> = vec![];
+    for license_id in license_ids {
+        let (canonical_id, text) = match get_license_text(&license_id) {
+            Some(t) => t,
+            None => {
+                missing_license_ids.push(license_id);
+                continue;
+            }
+        };
+        let path = std::path::Path::new(directory).join(format!("{}.txt", canonical_id));
+        std::fs::write(&path, text)?;
+        response.insert(canonical_id.to_string(), path.to_string_lossy().to_string());
+    }
+    if !missing_license_ids.is_empty() {
+        log::warn!(
+            "The license texts are incomplete, {} licenses are not on the SPDX license list: {}",
+            missing_license_ids.len(),
+            missing_license_ids.join(", ")
+        );
+    }
+    Ok(response)
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct LicenseFilter {
+    /// Only keep the packages with at least one license matching these patterns.
+    pub only_licenses: Vec<String>,
+    /// Exclude the packages with at least one license matching these patterns.
+    pub exclude_licenses: Vec<String>,
+}
+
+impl LicenseFilter {
+    pub fn is_empty(&self) -> bool {
+        self.only_licenses.is_empty() && self.exclude_licenses.is_empty()
+    }
+
+    // The packages without a known license are excluded when only some licenses
+    // are requested, and kept otherwise.
+    pub fn is_allowed(&self, license_expression: Option<&str>) -> bool {
+        let license_ids = match license_expression {
+            Some(e) => get_license_ids(e),
+            None => vec![],
+        };
+        let matches = |patterns: &[String]| {
+            license_ids
+                .iter()
+                .any(|l| patterns.iter().any(|p| matches_license_pattern(l, p)))
+        };
+        if !self.only_licenses.is_empty() && !matches(&self.only_licenses) {
+            return false;
+        }
+        !matches(&self.exclude_licenses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_license_ids() {
+        assert_eq!(get_license_ids("MIT"), vec!["MIT"]);
+        assert_eq!(
+            get_license_ids("(MIT OR Apache-2.0) AND GPL-2.0+ WITH Classpath-exception-2.0"),
+            vec!["MIT", "Apache-2.0", "GPL-2.0"]
+        );
+        assert_eq!(
+            get_license_ids("LicenseRef-Unfree and MIT"),
+            vec!["LicenseRef-Unfree", "MIT"]
+        );
+    }
+
+    #[test]
+    pub fn test_get_canonical_license_id() {
+        assert_eq!(get_canonical_license_id("MIT"), Some("MIT".to_string()));
+        assert_eq!(
+            get_canonical_license_id("apache-2.0"),
+            Some("Apache-2.0".to_string())
+        );
+        assert_eq!(
+            get_canonical_license_id("LGPL-2.1+"),
+            Some("LGPL-2.1+".to_string())
+        );
+        assert_eq!(get_canonical_license_id("unfree"), None);
+        assert_eq!(get_canonical_license_id("free"), None);
+        assert_eq!(get_canonical_license_id(""), None);
+    }
+
+    #[test]
+    pub fn test_get_license_text() {
+        let (license_id, text) = get_license_text("mit").unwrap();
+        assert_eq!(license_id, "MIT");
+        assert!(text.starts_with("MIT License"));
+        let (license_id, text) = get_license_text("GPL-3.0-or-later").unwrap();
+        assert_eq!(license_id, "GPL-3.0-or-later");
+        assert!(text.contains("GNU GENERAL PUBLIC LICENSE"));
+        assert!(get_license_text("LicenseRef-Unfree").is_none());
+
+        for (license_id, _, _) in LICENSE_IDS {
+            let canonical_id = get_canonical_license_id(license_id).unwrap();
+            assert!(
+                get_license_text(canonical_id.trim_end_matches('+')).is_some(),
+                "{}",
+                license_id
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_get_package_license_texts() {
+        let package_node: crate::nix::PackageNode = serde_json::from_str(
+            r#"{
+              "id": "/nix/store/a-app-1.0.drv",
+              "git_urls": [],
+              "main_derivation": {
+                "args": [],
+                "builder": "/bin/sh",
+                "env": { "name": "app-1.0" },
+                "inputDrvs": {},
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/a-app-1.0" } },
+                "system": "x86_64-linux"
+              },
+              "package": {
+                "name": "app-1.0",
+                "pname": "app",
+                "version": "1.0",
+                "meta": {
+                  "license": [
+                    { "spdxId": "MIT" },
+                    { "spdxId": "GPL-2.0+" },
+                    { "fullName": "Unfree" }
+                  ]
+                }
+              },
+              "patches": [],
+              "build_inputs": [],
+              "children": [],
+              "concluded_license": "apache-2.0 OR MIT"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            get_package_license_ids(&package_node),
+            vec!["MIT", "GPL-2.0", "LicenseRef-Unfree", "apache-2.0"]
+        );
+
+        let license_texts: std::collections::BTreeMap<String, String> = [
+            ("Apache-2.0", "licenses/Apache-2.0.txt"),
+            ("GPL-2.0", "licenses/GPL-2.0.txt"),
+            ("MIT", "licenses/MIT.txt"),
+        ]
+        .iter()
+        .map(|(id, path)| (id.to_string(), path.to_string()))
+        .collect();
+        assert_eq!(
+            get_package_license_texts(&package_node, &license_texts)
+                .into_values()
+                .collect::<Vec<&String>>(),
+            vec![
+                "licenses/Apache-2.0.txt",
+                "licenses/GPL-2.0.txt",
+                "licenses/MIT.txt"
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_license_filter() {
+        assert!(matches_license_pattern("GPL-3.0-or-later", "gpl-*"));
+        assert!(matches_license_pattern("GPL-2.0-only", "GPL-2.0"));
+        assert!(!matches_license_pattern("LGPL-2.1-only", "GPL-*"));
+        assert!(!matches_license_pattern("GPL-2.0-only", "GPL-2"));
+
+        let license_filter = LicenseFilter {
+            only_licenses: vec!["GPL-*".to_string(), "LGPL-*".to_string()],
+            exclude_licenses: vec![],
+        };
+        assert!(license_filter.is_allowed(Some("MIT AND LGPL-2.1-or-later")));
+        assert!(!license_filter.is_allowed(Some("MIT")));
+        assert!(!license_filter.is_allowed(None));
+
+        let license_filter = LicenseFilter {
+            only_licenses: vec![],
+            exclude_licenses: vec!["GPL-3.0".to_string()],
+        };
+        assert!(!license_filter.is_allowed(Some("GPL-3.0-only")));
+        assert!(license_filter.is_allowed(Some("GPL-2.0-only")));
+        assert!(license_filter.is_allowed(None));
+    }
+}
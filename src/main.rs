@@ -6,7 +6,9 @@
 
 extern crate clap;
 
-use clap::Parser;
+use clap::builder::BoolishValueParser;
+use clap::{Parser, Subcommand};
+use nix2sbom::backend::NixBackend;
 
 /// nix2sbom extracts the SBOM (Software Bill of Materials) from a Nix derivation
 #[derive(Parser)]
@@ -14,110 +16,1624 @@ use clap::Parser;
 #[clap(version = env!("CARGO_PKG_VERSION"))]
 #[clap(about = "nix2sbom extracts the SBOM (Software Bill of Materials) from a Nix derivation", long_about = None)]
 struct NixToSBOM {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Reference to a nix derivation. The reference includes the path to the nix
     /// file and the path of the nix derivation within the file.
     /// Example: /path/to/default.nix#derivation
-    nix_ref: Option<String>,
+    /// Multiple references can be given to generate a single SBOM, with one root
+    /// component per reference.
+    nix_refs: Vec<String>,
+
+    /// Include every package exposed by the flake for the current system.
+    /// The first reference is then used as the flake reference.
+    #[clap(long, env = "NIX2SBOM_ALL_OUTPUTS", value_parser = BoolishValueParser::new())]
+    all_outputs: bool,
+
+    /// Maximum number of references to evaluate at the same time.
+    #[clap(long, short, default_value_t = 1, env = "NIX2SBOM_JOBS")]
+    jobs: usize,
+
+    /// Path of a file containing the .drv paths to include in the SBOM, one per line.
+    #[clap(long, env = "NIX2SBOM_DRV_LIST")]
+    drv_list: Option<String>,
+
+    /// Path of the output of `nix derivation show -r`, or - to read it from stdin. The
+    /// derivations are then not evaluated, so the SBOM can be generated on a machine
+    /// without Nix.
+    #[clap(long, env = "NIX2SBOM_DERIVATIONS_JSON")]
+    derivations_json: Option<String>,
+
+    /// Path of a .drv file to read directly from the store, with the .drv files of its
+    /// dependencies, without the nix CLI or the Nix daemon. Can be repeated.
+    #[clap(long, env = "NIX2SBOM_FROM_DRV")]
+    from_drv: Vec<String>,
 
     /// Output format for the SBOM manifest. Defaults to cdx (CycloneDX).
-    #[clap(short, long)]
+    /// Use out-paths to list the output paths, and out-paths with -s json
+    /// to map the output paths to their purls. Use adjacency to export the edges
+    /// of the dependency graph as CSV (parent purl, child purl, edge kind), or as JSON with -s json.
+    /// Several formats can be given, separated by commas, e.g. cdx,spdx,native,
+    /// in which case --output-dir is required.
+    #[clap(short, long, env = "NIX2SBOM_FORMAT")]
     format: Option<String>,
 
-    /// Which format to use for serializing the SBOM. CycloneDX supports yaml and json.
-    #[clap(short, long)]
+    /// Which format to use for serializing the SBOM. CycloneDX supports json, yaml and xml.
+    /// SPDX supports json, yaml and tag-value.
+    #[clap(short, long, env = "NIX2SBOM_SERIALIZATION_FORMAT")]
     serialization_format: Option<String>,
 
+    /// Version of the CycloneDX specification to use: 1.3, 1.4, 1.5 or 1.6. Defaults to 1.4.
+    /// The annotations describing the package warnings are only emitted with 1.5 and later.
+    #[clap(long, env = "NIX2SBOM_SPEC_VERSION")]
+    spec_version: Option<String>,
+
     /// Path of an existing package metadata file.
     ///
     /// This file can be generated by using the following command:
-    /// nix-env -q -a --meta --json '.*'
-    #[clap(long)]
-    metadata_path: Option<String>,
+    /// nix2sbom metadata generate
+    /// The metadata files generated with nix-env -q -a --meta --json --out-path '.*'
+    /// are matched to the derivations by output path, the other files by name.
+    /// The packages.json.br files published with the channels are also accepted.
+    /// Can be repeated to merge the metadata of several channels, the later files
+    /// taking precedence.
+    #[clap(long, env = "NIX2SBOM_METADATA_PATH")]
+    metadata_path: Vec<String>,
+
+    /// URL of the package metadata of a channel, for example
+    /// https://channels.nixos.org/nixos-24.05/packages.json.br
+    /// This does not require nix-env, and implies --meta.
+    #[clap(long, env = "NIX2SBOM_METADATA_URL")]
+    metadata_url: Option<String>,
+
+    /// How to get the package metadata when no metadata file is given: nix-env
+    /// (default) evaluates the whole package set, nix-eval only evaluates the
+    /// packages of the SBOM in the nixpkgs flake, nix-eval:<flake-ref> in another flake.
+    #[clap(long, env = "NIX2SBOM_METADATA_BACKEND")]
+    metadata_backend: Option<String>,
+
+    /// Ignore the package metadata cached from a previous run. The output of nix-env
+    /// is cached in $XDG_CACHE_HOME/nix2sbom, for each revision of nixpkgs.
+    #[clap(long, env = "NIX2SBOM_REFRESH_METADATA", value_parser = BoolishValueParser::new())]
+    refresh_metadata: bool,
+
+    /// Fail when a package metadata entry cannot be parsed. By default, the
+    /// malformed entries are skipped and reported, and the SBOM is generated
+    /// without metadata if the metadata cannot be loaded at all.
+    #[clap(long, env = "NIX2SBOM_STRICT_METADATA", value_parser = BoolishValueParser::new())]
+    strict_metadata: bool,
 
     /// Use the metadata from the store to help generating the SBOM.
-    #[clap(long, short)]
+    #[clap(long, short, env = "NIX2SBOM_META", value_parser = BoolishValueParser::new())]
     meta: bool,
 
+    /// Comma-separated list of the metadata fields to include in the SBOM, for
+    /// example license,homepage. All the fields are included by default.
+    /// Valid fields are description, homepage, license, maintainers and sourceProvenance.
+    #[clap(long, value_delimiter = ',', env = "NIX2SBOM_META_FIELDS")]
+    meta_fields: Option<Vec<String>>,
+
+    /// Comma-separated list of the derivation env variables to record as properties
+    /// of the components, for example configureFlags,cmakeFlags. No variable is
+    /// recorded by default.
+    #[clap(long, value_delimiter = ',', env = "NIX2SBOM_CAPTURE_ENV")]
+    capture_env: Vec<String>,
+
+    /// Comma-separated list of the licenses of the packages to include in the SBOM,
+    /// for example GPL-*,LGPL-*. The packages without a known license are excluded.
+    /// The concluded licenses take precedence over the declared licenses.
+    #[clap(long, value_delimiter = ',', env = "NIX2SBOM_ONLY_LICENSES")]
+    only_licenses: Vec<String>,
+
+    /// Comma-separated list of the licenses of the packages to exclude from the SBOM,
+    /// for example GPL-3.0,AGPL-*.
+    #[clap(long, value_delimiter = ',', env = "NIX2SBOM_EXCLUDE_LICENSES")]
+    exclude_licenses: Vec<String>,
+
+    /// Regular expression matched against the name, the purl and the store paths of the
+    /// packages to exclude from the SBOM, in every output format. Can be repeated. The
+    /// dependencies of the excluded packages are kept.
+    #[clap(long, env = "NIX2SBOM_EXCLUDE")]
+    exclude: Vec<String>,
+
+    /// Regular expression matched against the name, the purl and the store paths of the
+    /// packages to keep in the SBOM. Can be repeated. The dependencies between the kept
+    /// packages are preserved.
+    #[clap(long, env = "NIX2SBOM_ONLY")]
+    only: Vec<String>,
+
+    /// Comma-separated list of the purl types of the packages to keep in the SBOM, for
+    /// example cargo,pypi.
+    #[clap(long, value_delimiter = ',', env = "NIX2SBOM_ONLY_PURL_TYPES")]
+    only_purl_type: Vec<String>,
+
+    /// Replace the email of the package maintainers with a hashed identifier.
+    #[clap(long, env = "NIX2SBOM_REDACT_MAINTAINERS", value_parser = BoolishValueParser::new())]
+    redact_maintainers: bool,
+
+    /// Secret key of the identifiers of the redacted maintainers. The same key gives the
+    /// same identifiers across runs. Defaults to a random key.
+    #[clap(long, env = "NIX2SBOM_REDACTION_KEY")]
+    redaction_key: Option<String>,
+
+    /// With the stats format, list the derivations for which no package metadata
+    /// was found, grouped by reason.
+    #[clap(long, env = "NIX2SBOM_STATS_WITH_META", value_parser = BoolishValueParser::new())]
+    stats_with_meta: bool,
+
+    /// With the stats format, count the packages by license.
+    #[clap(long, env = "NIX2SBOM_STATS_LICENSES", value_parser = BoolishValueParser::new())]
+    stats_licenses: bool,
+
+    /// Path of the file to write the SBOM to. The file is replaced atomically.
+    /// Defaults to -, which prints the SBOM to stdout.
+    #[clap(long, short, conflicts_with = "output_dir", env = "NIX2SBOM_OUTPUT")]
+    output: Option<String>,
+
+    /// Directory to write the SBOMs to, one file per format, e.g. sbom.cdx.json.
+    #[clap(long, env = "NIX2SBOM_OUTPUT_DIR")]
+    output_dir: Option<String>,
+
+    /// Also write one SBOM per root derivation, in a sub-directory of --output-dir named
+    /// after the root, e.g. hello-2.12.1/sbom.cdx.json. The roots sharing a name are suffixed
+    /// with their store path hash. The per-root SBOMs are generated in parallel from the
+    /// graph built for the combined SBOM.
+    #[clap(
+        long,
+        requires = "output_dir",
+        env = "NIX2SBOM_SPLIT_ROOTS",
+        value_parser = BoolishValueParser::new()
+    )]
+    split_roots: bool,
+
+    /// Compress the SBOM with gzip or zstd. The compressed SBOM is streamed to the
+    /// output file, or to stdout if no output file is given. Inferred from the
+    /// extension of the output file when it ends with .gz or .zst.
+    #[clap(long, env = "NIX2SBOM_COMPRESS")]
+    compress: Option<String>,
+
+    /// Fixed timestamp of the SBOM, in RFC 3339 format, so that two runs over the same
+    /// closure produce identical SBOMs. Takes precedence over the timestamp of the
+    /// configuration file and over SOURCE_DATE_EPOCH.
+    #[clap(long, env = "NIX2SBOM_TIMESTAMP")]
+    timestamp: Option<String>,
+
+    /// Namespace of the SPDX document. By default, the namespace is derived from
+    /// the path of the root derivation, and is the same for every run.
+    #[clap(long, env = "NIX2SBOM_DOCUMENT_NAMESPACE")]
+    document_namespace: Option<String>,
+
+    /// Group the components of the CycloneDX SBOM by category (toolchain, libraries,
+    /// applications, kernel and firmware, fonts and data). The categories are guessed
+    /// from the names and the outputs of the packages.
+    #[clap(long, env = "NIX2SBOM_GROUP_BY_CATEGORY", value_parser = BoolishValueParser::new())]
+    group_by_category: bool,
+
+    /// Derive the bom-refs of the CycloneDX components from a digest of their purl and
+    /// version instead of the derivation paths, which change on every nixpkgs update.
+    /// This makes the diffs between two SBOMs of the same project meaningful.
+    /// The derivation paths are kept in the nix:drvPath property.
+    #[clap(long, env = "NIX2SBOM_STABLE_REFS", value_parser = BoolishValueParser::new())]
+    stable_refs: bool,
+
+    /// Add the arch and os qualifiers of the system of the derivations to the purls,
+    /// e.g. ?arch=aarch64&os=darwin, to distinguish the packages built for several systems.
+    #[clap(
+        long,
+        env = "NIX2SBOM_PURL_PLATFORM_QUALIFIERS",
+        value_parser = BoolishValueParser::new()
+    )]
+    purl_platform_qualifiers: bool,
+
     /// Do not pretty print the generated SBOM manifest
-    #[clap(long)]
+    #[clap(long, env = "NIX2SBOM_NO_PRETTY", value_parser = BoolishValueParser::new())]
     no_pretty: bool,
 
-    /// Include only the runtime dependencies in the SBOM.
-    #[clap(long, short)]
+    /// Include only the runtime dependencies in the SBOM. The runtime closure is
+    /// computed from the references of the outputs of the requested derivations,
+    /// which must be present in the local store. Otherwise only the build inputs
+    /// are excluded from the dependencies.
+    #[clap(long, short, env = "NIX2SBOM_RUNTIME_ONLY", value_parser = BoolishValueParser::new())]
     runtime_only: bool,
 
     /// Generate a SBOM for the current system.
-    #[clap(long, short)]
+    #[clap(long, short, env = "NIX2SBOM_CURRENT_SYSTEM", value_parser = BoolishValueParser::new())]
     current_system: bool,
+
+    /// Generate a SBOM for the packages installed with nix profile install, in the
+    /// profile at this path. Defaults to the profile of the user when no path is given.
+    #[clap(long, env = "NIX2SBOM_PROFILE", num_args = 0..=1, default_missing_value = nix2sbom::profile::DEFAULT_PROFILE_NAME)]
+    profile: Option<String>,
+
+    /// Exclude the check hooks, documentation outputs and test fixtures from the SBOM.
+    /// The derivations with only documentation outputs, the NixOS tests and the passthru
+    /// tests are always excluded. The derivations named like tests or documentation are
+    /// only excluded when no other package has them as runtime input. Every excluded
+    /// derivation is logged at the info level.
+    #[clap(long, env = "NIX2SBOM_PRUNE_TEST_AND_DOC", value_parser = BoolishValueParser::new())]
+    prune_test_and_doc: bool,
+
+    /// Only include the dependencies that are at most N levels away from the
+    /// requested derivations. A depth of 1 only includes the direct dependencies.
+    #[clap(long, env = "NIX2SBOM_EVAL_DEPTH")]
+    eval_depth: Option<usize>,
+
+    /// Maximum depth of the dependencies printed with the pretty-print format. Defaults to 1.
+    #[clap(long, env = "NIX2SBOM_MAX_DEPTH")]
+    max_depth: Option<usize>,
+
+    /// Print the stdenv packages with the pretty-print format.
+    #[clap(long, env = "NIX2SBOM_INCLUDE_STDENV", value_parser = BoolishValueParser::new())]
+    include_stdenv: bool,
+
+    /// Record the hostname, the operating system, the Nix version and the current system
+    /// of the machine generating the SBOM in the SBOM metadata.
+    #[clap(long, env = "NIX2SBOM_RECORD_HOST", value_parser = BoolishValueParser::new())]
+    record_host: bool,
+
+    /// Only include the requested derivations and their direct dependencies. Unlike
+    /// --eval-depth 1, the transitive dependencies are still used to identify the
+    /// direct dependencies.
+    #[clap(long, env = "NIX2SBOM_DIRECT_ONLY", value_parser = BoolishValueParser::new())]
+    direct_only: bool,
+
+    /// Exclude the derivations that are not built for this system, for example x86_64-linux.
+    #[clap(long, env = "NIX2SBOM_ONLY_SYSTEM")]
+    only_system: Option<String>,
+
+    /// Record the NAR hash, the NAR size and the closure size of the packages whose
+    /// outputs are present in the local store, using nix path-info.
+    #[clap(long, env = "NIX2SBOM_CLOSURE_SIZE", value_parser = BoolishValueParser::new())]
+    closure_size: bool,
+
+    /// Template of the URL of the build of the packages, linked from the CycloneDX
+    /// components as a build-system external reference, for example
+    /// https://hydra.example.com/build/{drvhash}. The placeholders are {drvhash},
+    /// {drvname} and {drvpath}.
+    #[clap(long, env = "NIX2SBOM_BUILD_URL_TEMPLATE")]
+    build_url_template: Option<String>,
+
+    /// Record whether the packages were built locally or substituted from a binary
+    /// cache, using the path info of their outputs in the local store. The totals
+    /// are reported in the stats.
+    #[clap(long, env = "NIX2SBOM_BUILD_PROVENANCE", value_parser = BoolishValueParser::new())]
+    build_provenance: bool,
+
+    /// Include the inputSrcs of the derivations (builder scripts, local patches,
+    /// setup hooks) as files in the SBOM, with their hashes when present in the local store.
+    #[clap(long, env = "NIX2SBOM_INPUT_SOURCES", value_parser = BoolishValueParser::new())]
+    input_sources: bool,
+
+    /// Record the OmniBOR artifact ids of the sources and the patches that are files present
+    /// in the local store. They are emitted in the omniborId field with CycloneDX 1.6, and
+    /// as gitoid external references with SPDX.
+    #[clap(long, env = "NIX2SBOM_OMNIBOR", value_parser = BoolishValueParser::new())]
+    omnibor: bool,
+
+    /// How to query the Nix store: cli (default) spawns nix commands, daemon
+    /// talks to the Nix daemon through its socket.
+    #[clap(long, env = "NIX2SBOM_STORE_BACKEND")]
+    store_backend: Option<String>,
+
+    /// List every store path of the closure in the SBOM, with its NAR hash when the
+    /// path is present in the local store.
+    #[clap(long, env = "NIX2SBOM_INCLUDE_STORE_PATHS", value_parser = BoolishValueParser::new())]
+    include_store_paths: bool,
+
+    /// Fail if some of the derivations are missing from the store, instead of
+    /// adding incomplete placeholder components to the SBOM, and if the package
+    /// metadata cannot be loaded, instead of generating the SBOM without metadata.
+    #[clap(long, env = "NIX2SBOM_STRICT", value_parser = BoolishValueParser::new())]
+    strict: bool,
+
+    /// Version of the output contract. Within an API version, the exit codes, the
+    /// content printed on stdout and the schema of the native format stay the same.
+    /// Defaults to the latest version, which is 1.
+    #[clap(long, env = "NIX2SBOM_API_VERSION")]
+    api_version: Option<String>,
+
+    /// Print a summary of the generation (coverage, warnings, skipped packages and
+    /// durations) to stderr.
+    #[clap(long, env = "NIX2SBOM_SUMMARY", value_parser = BoolishValueParser::new())]
+    summary: bool,
+
+    /// Language of the summary: en (default), fr or de.
+    #[clap(long, env = "NIX2SBOM_LANG")]
+    lang: Option<String>,
+
+    /// Write a JSON file describing the inputs, outputs and timing of the SBOM generation.
+    #[clap(long, env = "NIX2SBOM_MANIFEST")]
+    manifest: Option<String>,
+
+    /// Path of a file mapping the components to the teams owning them, in the style
+    /// of the CODEOWNERS files: one pattern per line, matched against the names and
+    /// the purls, followed by the owners. The owners are recorded with the org:owner property.
+    #[clap(long, env = "NIX2SBOM_OWNERS")]
+    owners: Option<String>,
+
+    /// Path of a file listing the components to exclude from the SBOM, in the style of
+    /// the .gitignore files: one pattern per line, matched against the names and the purls.
+    /// Defaults to the .nix2sbomignore file at the root of the flake, if any. The ignored
+    /// components are still counted in the stats.
+    #[clap(long, env = "NIX2SBOM_IGNORE_FILE")]
+    ignore_file: Option<String>,
+
+    /// Write the text of every license used by the components in this directory, and
+    /// reference the files from the SBOM. The texts of every license of the SPDX license list
+    /// are bundled with nix2sbom, the other licenses are reported.
+    #[clap(long, env = "NIX2SBOM_LICENSE_TEXTS")]
+    license_texts: Option<String>,
+
+    /// Write the SBOM wrapped in an in-toto statement in this directory, one statement per
+    /// output path of the root derivations, for example to attach it with cosign. The image
+    /// built with dockerTools is also a subject of the statements when --image-digest is used.
+    /// Requires the cdx or spdx format, serialized as JSON. The statements are not signed.
+    #[clap(long, env = "NIX2SBOM_ATTESTATION_BUNDLE")]
+    attestation_bundle: Option<String>,
+
+    /// Digest of the manifest of the image built with dockerTools, as pushed to the registry,
+    /// e.g. sha256:<digest>. This is the digest used by cosign to find the attestations of
+    /// the image. It is not the digest of the config of the image found in the docker archive
+    /// (the image id), which cosign would not match.
+    #[clap(long, requires = "attestation_bundle", env = "NIX2SBOM_IMAGE_DIGEST")]
+    image_digest: Option<String>,
+
+    /// Path of a nix2sbom configuration file (YAML or JSON).
+    #[clap(long, env = "NIX2SBOM_CONFIG")]
+    config: Option<String>,
+
+    /// Path of a metadata template (YAML or JSON) merged into the metadata of the SBOM,
+    /// for example the supplier, the licenses of the SBOM, custom properties or the
+    /// description of the root component. Overrides the metadata of the configuration file.
+    #[clap(long, env = "NIX2SBOM_METADATA_TEMPLATE")]
+    metadata_template: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate the SBOM from a Nix post-build hook, for example on Hydra. The
+    /// derivation is read from $DRV_PATH and the SBOM is written to --output-dir,
+    /// which must be outside of the Nix store, and registered as a Hydra build product
+    /// in its nix-support/hydra-build-products file.
+    HydraHook,
+
+    /// Print the components of the package graph with a name or a purl matching
+    /// a regular expression, with their version, license and dependency path.
+    /// Use -s json to print the results as JSON.
+    Search {
+        /// Reference to a nix derivation, as for the SBOM generation.
+        nix_ref: String,
+
+        /// Regular expression matched against the names and the purls of the components.
+        pattern: String,
+    },
+
+    /// Report the components added, removed, upgraded or downgraded between two SBOMs
+    /// generated by nix2sbom, in the CycloneDX (JSON or XML) or the native format, and the
+    /// components whose license changed. Use -s json to print the differences as JSON.
+    Diff {
+        /// Path of the older SBOM.
+        old_sbom: String,
+
+        /// Path of the newer SBOM.
+        new_sbom: String,
+    },
+
+    /// Report the components added, removed, upgraded or downgraded between two
+    /// generations of the NixOS system profile. The generations are given by number,
+    /// e.g. 42 for /nix/var/nix/profiles/system-42-link, or by path.
+    /// Use -s json to print the differences as JSON.
+    DiffGenerations {
+        /// The older generation.
+        generation_a: String,
+
+        /// The newer generation.
+        generation_b: String,
+    },
+
+    /// Merge several SBOMs generated by nix2sbom, in the CycloneDX (JSON or XML) or the
+    /// native format, into one. The components are deduplicated by purl and the dependency
+    /// graphs are unioned. The merged SBOM is in the native format if all the SBOMs are,
+    /// and in the CycloneDX format otherwise.
+    Merge {
+        /// Paths of the SBOMs to merge.
+        #[clap(required = true, num_args = 2..)]
+        sboms: Vec<String>,
+    },
+
+    /// Convert a SBOM generated by nix2sbom, in the CycloneDX (JSON or XML) or the native
+    /// format, to the format selected with --format, cdx or spdx, without evaluating the
+    /// derivations again.
+    Convert {
+        /// Path of the SBOM to convert.
+        sbom: String,
+    },
+
+    /// Print the SBOM of nix2sbom itself, in the CycloneDX format, built from the
+    /// Cargo.lock file embedded in the binary.
+    SelfSbom,
+
+    /// Generate several SBOMs from a job file (YAML or JSON), each job giving its references,
+    /// formats, outputs and filters. The jobs share the package metadata, and a summary of
+    /// the jobs is printed at the end. The flags of the batch command do not apply to the
+    /// jobs, but the NIX2SBOM_* environment variables do.
+    Batch {
+        /// Path of the job file.
+        job_file: String,
+    },
+
+    /// Manage the package metadata files used with --metadata-path.
+    Metadata {
+        #[clap(subcommand)]
+        command: MetadataCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum MetadataCommand {
+    /// Generate a package metadata file by evaluating the whole package set with
+    /// nix-env -q -a --meta --json --out-path '.*', which takes several minutes.
+    Generate {
+        /// Path of the generated metadata file.
+        #[clap(
+            long,
+            short,
+            env = "NIX2SBOM_METADATA_OUTPUT",
+            default_value = "packages-metadata.json"
+        )]
+        output: String,
+
+        /// Path of the nixpkgs to evaluate, for example a nixpkgs checkout. Defaults
+        /// to the nixpkgs of the channels.
+        #[clap(long, env = "NIX2SBOM_NIXPKGS")]
+        nixpkgs: Option<String>,
+    },
+}
+
+// Prints the differences between the components of two SBOM files.
+fn diff_sboms(
+    old_sbom_path: &str,
+    new_sbom_path: &str,
+    serialization_format: Option<&nix2sbom::format::SerializationFormat>,
+) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    let mut sboms: Vec<nix2sbom::diff::Sbom> = vec![];
+    for sbom_path in [old_sbom_path, new_sbom_path] {
+        match nix2sbom::diff::load_file(sbom_path) {
+            Ok(s) => sboms.push(s),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_FAILURE));
+            }
+        };
+    }
+    let sbom_diff = nix2sbom::diff::diff(&sboms[0], &sboms[1]);
+    if serialization_format == Some(&nix2sbom::format::SerializationFormat::JSON) {
+        println!("{}", serde_json::to_string_pretty(&sbom_diff)?);
+    } else if !sbom_diff.is_empty() {
+        println!("{}", sbom_diff.to_text());
+    }
+    log::info!(
+        "{} components added, {} removed, {} upgraded, {} downgraded and {} with a license change",
+        sbom_diff.added.len(),
+        sbom_diff.removed.len(),
+        sbom_diff.upgraded.len(),
+        sbom_diff.downgraded.len(),
+        sbom_diff.license_changes.len()
+    );
+    Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS))
+}
+
+// Prints the SBOM of nix2sbom itself, which is always in the CycloneDX format.
+fn self_sbom(
+    args: &NixToSBOM,
+    serialization_format: Option<&nix2sbom::format::SerializationFormat>,
+) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    let dump_options = get_standalone_dump_options(args)?;
+    let serialization_format = serialization_format
+        .cloned()
+        .unwrap_or(nix2sbom::format::SerializationFormat::JSON);
+    write_standalone_output(
+        args,
+        &nix2sbom::self_sbom::dump(&serialization_format, &dump_options)?,
+    )?;
+    Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS))
+}
+
+fn merge_sboms(
+    sbom_paths: &[String],
+    args: &NixToSBOM,
+    serialization_format: Option<&nix2sbom::format::SerializationFormat>,
+) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    let mut sboms: Vec<nix2sbom::merge::Sbom> = vec![];
+    for sbom_path in sbom_paths {
+        match nix2sbom::merge::load_file(sbom_path) {
+            Ok(s) => sboms.push(s),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_FAILURE));
+            }
+        };
+    }
+    let dump_options = get_standalone_dump_options(args)?;
+    let serialization_format = serialization_format
+        .cloned()
+        .unwrap_or(nix2sbom::format::SerializationFormat::JSON);
+    write_standalone_output(
+        args,
+        &nix2sbom::merge::dump(&sboms, &serialization_format, &dump_options)?,
+    )?;
+    Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS))
+}
+
+fn convert_sbom(
+    sbom_path: &str,
+    output_format: &nix2sbom::format::Format,
+    args: &NixToSBOM,
+    serialization_format: Option<&nix2sbom::format::SerializationFormat>,
+) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    if !matches!(
+        output_format,
+        nix2sbom::format::Format::CycloneDX | nix2sbom::format::Format::SPDX
+    ) {
+        eprintln!("Error: SBOMs can only be converted to the cdx and spdx formats");
+        return Ok(std::process::ExitCode::from(
+            nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+        ));
+    }
+    let sbom = match nix2sbom::merge::load_file(sbom_path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_FAILURE));
+        }
+    };
+    let mut dump_options = get_standalone_dump_options(args)?;
+    dump_options.document_namespace = args.document_namespace.clone();
+    let serialization_format = serialization_format
+        .cloned()
+        .unwrap_or(output_format.get_default_serialization_format());
+    write_standalone_output(
+        args,
+        &nix2sbom::convert::dump(&sbom, output_format, &serialization_format, &dump_options)?,
+    )?;
+    Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS))
+}
+
+// Writes the SBOM of the commands that do not generate the SBOM from a package graph
+// to the output file, or prints it.
+fn write_standalone_output(args: &NixToSBOM, sbom_dump: &str) -> Result<(), anyhow::Error> {
+    match args.output.as_deref() {
+        Some(output_path) if output_path != "-" => {
+            nix2sbom::utils::write_file_atomically(output_path, sbom_dump)?;
+            log::info!("SBOM written to {}", output_path);
+        }
+        _ => println!("{}", sbom_dump),
+    };
+    Ok(())
+}
+
+// The dump options of the commands that do not generate the SBOM from a package graph.
+fn get_standalone_dump_options(args: &NixToSBOM) -> Result<nix2sbom::nix::DumpOptions, anyhow::Error> {
+    let mut dump_options = nix2sbom::nix::DumpOptions {
+        spec_version: args.spec_version.clone(),
+        ..Default::default()
+    };
+    if let Some(timestamp) = &args.timestamp {
+        chrono::DateTime::parse_from_rfc3339(timestamp)
+            .map_err(|e| anyhow::format_err!("Invalid timestamp {}: {}", timestamp, e))?;
+        dump_options.metadata.timestamp = Some(timestamp.to_string());
+    } else if let Some(source_date_epoch) = nix2sbom::utils::get_source_date_epoch()? {
+        dump_options.metadata.timestamp = Some(source_date_epoch.to_rfc3339());
+    }
+    if args.no_pretty {
+        dump_options.pretty = Some(false);
+    }
+    Ok(dump_options)
+}
+
+// Builds the package graph of a generation of the system profile. The package metadata
+// is not needed to identify the components, so it is not loaded.
+fn get_generation_package_graph(
+    generation: &str,
+    resolvers: &nix2sbom::identify::Resolvers,
+    backend: &dyn nix2sbom::backend::NixBackend,
+) -> Result<nix2sbom::nix::PackageGraph, anyhow::Error> {
+    let generation_path = nix2sbom::generations::get_generation_path(generation)?;
+    log::info!("Getting the derivations from generation {}", &generation_path);
+    let derivations = nix2sbom::nix::Derivation::get_derivations_for_system(&generation_path, backend)?;
+    log::info!("Found {} derivations", derivations.len());
+    let mut package_graph = nix2sbom::nix::get_package_graph(&derivations);
+    package_graph.transform_with_resolvers(&nix2sbom::nix::Packages::default(), resolvers)?;
+    Ok(package_graph)
+}
+
+// Prints the differences between the components of two generations of the system profile.
+fn diff_generations(
+    generation_a: &str,
+    generation_b: &str,
+    serialization_format: Option<&nix2sbom::format::SerializationFormat>,
+    config: &nix2sbom::config::Config,
+    backend: &dyn nix2sbom::backend::NixBackend,
+) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    let resolvers = nix2sbom::identify::Resolvers::with_overrides(&config.identity_overrides);
+    let mut package_graphs: Vec<nix2sbom::nix::PackageGraph> = vec![];
+    for generation in [generation_a, generation_b] {
+        match get_generation_package_graph(generation, &resolvers, backend) {
+            Ok(g) => package_graphs.push(g),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_FAILURE));
+            }
+        };
+    }
+    let generation_diff = nix2sbom::generations::diff(&package_graphs[0], &package_graphs[1]);
+    if serialization_format == Some(&nix2sbom::format::SerializationFormat::JSON) {
+        println!("{}", serde_json::to_string_pretty(&generation_diff)?);
+    } else if !generation_diff.is_empty() {
+        println!("{}", generation_diff.to_text());
+    }
+    log::info!(
+        "{} components added, {} removed, {} upgraded and {} downgraded",
+        generation_diff.added.len(),
+        generation_diff.removed.len(),
+        generation_diff.upgraded.len(),
+        generation_diff.downgraded.len()
+    );
+    Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS))
+}
+
+// Generates the package metadata file that can then be used with --metadata-path.
+fn generate_metadata(
+    output: &str,
+    nixpkgs: Option<&str>,
+) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    let content = match nix2sbom::backend::CliBackend::default().get_nix_env_metadata(nixpkgs) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_FAILURE));
+        }
+    };
+    let (packages, invalid_packages) = nix2sbom::nix::parse_packages_with_mode(&content, false)?;
+    if !invalid_packages.is_empty() {
+        log::warn!(
+            "{} package metadata entries could not be parsed",
+            invalid_packages.len()
+        );
+    }
+    nix2sbom::utils::write_file_atomically(output, &String::from_utf8_lossy(&content))?;
+    log::info!("Wrote the metadata of {} packages to {}", packages.len(), output);
+    Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS))
+}
+
+// The package metadata already loaded, indexed by the metadata options used to load it,
+// to only load it once in batch mode.
+type MetadataCache = std::collections::HashMap<
+    String,
+    (
+        nix2sbom::nix::Packages,
+        nix2sbom::nix::MetadataSource,
+        std::collections::BTreeMap<String, String>,
+    ),
+>;
+
+// Runs the jobs of a job file, in order. A failed job does not stop the next ones.
+fn run_batch(job_file: &str) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    let batch_file = nix2sbom::batch::BatchFile::from_file(job_file)?;
+    let mut metadata_cache = MetadataCache::default();
+    let mut results: Vec<nix2sbom::batch::JobResult> = vec![];
+    for job in &batch_file.jobs {
+        let job_started_at = std::time::Instant::now();
+        log::info!("Running job {}", job.get_name());
+        let job_args = std::iter::once(nix2sbom::consts::PROJECT_NAME.to_string()).chain(job.to_args());
+        let (exit_code, error) = match NixToSBOM::try_parse_from(job_args) {
+            Ok(args) if args.command.is_some() => (
+                nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                Some("Jobs cannot run a subcommand".to_string()),
+            ),
+            Ok(args) => match run(args, &mut metadata_cache) {
+                // The exit codes cannot be read back from an ExitCode.
+                Ok(exit_code) => (
+                    [
+                        nix2sbom::consts::EXIT_SUCCESS,
+                        nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                        nix2sbom::consts::EXIT_INCOMPLETE,
+                    ]
+                    .into_iter()
+                    .find(|c| std::process::ExitCode::from(*c) == exit_code)
+                    .unwrap_or(nix2sbom::consts::EXIT_FAILURE),
+                    None,
+                ),
+                Err(e) => (nix2sbom::consts::EXIT_FAILURE, Some(e.to_string())),
+            },
+            Err(e) => (
+                nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                Some(e.to_string().lines().next().unwrap_or_default().to_string()),
+            ),
+        };
+        results.push(nix2sbom::batch::JobResult {
+            name: job.get_name(),
+            exit_code,
+            duration: job_started_at.elapsed(),
+            error,
+        });
+    }
+    eprintln!("{}", nix2sbom::batch::format_summary(&results));
+    if results
+        .iter()
+        .any(|r| r.exit_code != nix2sbom::consts::EXIT_SUCCESS)
+    {
+        return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_FAILURE));
+    }
+    Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS))
 }
 
 fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
     nix2sbom::logger::init();
     let args = NixToSBOM::parse();
+    if let Some(Command::Batch { job_file }) = &args.command {
+        return run_batch(job_file);
+    }
+    run(args, &mut MetadataCache::default())
+}
 
-    let output_format = match args.format {
-        Some(f) => match nix2sbom::format::Format::from_string(&f) {
-            Some(f) => f,
+fn run(
+    args: NixToSBOM,
+    metadata_cache: &mut MetadataCache,
+) -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
+    let started_at = std::time::SystemTime::now();
+    let mut durations: Vec<(&str, std::time::Instant)> = vec![];
+    if let Some(Command::Metadata {
+        command: MetadataCommand::Generate { output, nixpkgs },
+    }) = &args.command
+    {
+        return generate_metadata(output, nixpkgs.as_deref());
+    }
+    let mut manifest_inputs = nix2sbom::manifest::ManifestInputs {
+        nix_refs: args.nix_refs.clone(),
+        ..Default::default()
+    };
+
+    let mut output_formats: Vec<nix2sbom::format::Format> = vec![];
+    for f in args.format.as_deref().unwrap_or_default().split(',') {
+        if f.is_empty() {
+            continue;
+        }
+        match nix2sbom::format::Format::from_string(f) {
+            Some(f) => output_formats.push(f),
             None => {
                 eprintln!("Invalid format {}", &f);
-                return Ok(std::process::ExitCode::FAILURE);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        };
+    }
+    if output_formats.is_empty() {
+        output_formats.push(nix2sbom::format::Format::default());
+    }
+    if output_formats.len() > 1 && args.output_dir.is_none() && args.command.is_none() {
+        eprintln!("Error: --output-dir is required when several formats are requested");
+        return Ok(std::process::ExitCode::from(
+            nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+        ));
+    }
+
+    if let Some(meta_fields) = &args.meta_fields {
+        if let Err(e) = nix2sbom::nix::validate_meta_fields(meta_fields) {
+            eprintln!("{}", e);
+            return Ok(std::process::ExitCode::from(
+                nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+            ));
+        }
+    }
+
+    // The serialization format applies to all the formats, each format using its
+    // default serialization format otherwise.
+    let serialization_format = match &args.serialization_format {
+        Some(f) => match nix2sbom::format::SerializationFormat::from_string(f) {
+            Some(f) => Some(f),
+            None => {
+                eprintln!("Invalid serialization format {}", &f);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        },
+        None => None,
+    };
+
+    if let Some(Command::SelfSbom | Command::Merge { .. } | Command::Convert { .. }) = &args.command {
+        if let Some(spec_version) = &args.spec_version {
+            if !nix2sbom::format::cyclone_dx::SUPPORTED_SPEC_VERSIONS.contains(&spec_version.as_str()) {
+                eprintln!("Invalid CycloneDX spec version {}", spec_version);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        }
+    }
+    if let Some(Command::SelfSbom) = &args.command {
+        return self_sbom(&args, serialization_format.as_ref());
+    }
+    if let Some(Command::Merge { sboms }) = &args.command {
+        return merge_sboms(sboms, &args, serialization_format.as_ref());
+    }
+    if let Some(Command::Convert { sbom }) = &args.command {
+        if output_formats.len() != 1 {
+            eprintln!("Error: A SBOM can only be converted to one format at a time");
+            return Ok(std::process::ExitCode::from(
+                nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+            ));
+        }
+        return convert_sbom(sbom, &output_formats[0], &args, serialization_format.as_ref());
+    }
+    if let Some(Command::Diff { old_sbom, new_sbom }) = &args.command {
+        return diff_sboms(old_sbom, new_sbom, serialization_format.as_ref());
+    }
+
+    if let Some(spec_version) = &args.spec_version {
+        if !output_formats
+            .iter()
+            .any(|f| matches!(f, nix2sbom::format::Format::CycloneDX))
+        {
+            eprintln!("The spec version can only be selected for the CycloneDX format");
+            return Ok(std::process::ExitCode::from(
+                nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+            ));
+        }
+        if !nix2sbom::format::cyclone_dx::SUPPORTED_SPEC_VERSIONS.contains(&spec_version.as_str()) {
+            eprintln!("Invalid CycloneDX spec version {}", spec_version);
+            return Ok(std::process::ExitCode::from(
+                nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+            ));
+        }
+    }
+
+    let image_digest = match &args.image_digest {
+        Some(d) => match nix2sbom::attestation::parse_image_digest(d) {
+            Ok(d) => Some(d),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
             }
         },
-        None => nix2sbom::format::Format::default(),
+        None => None,
     };
 
-    let serialization_format = match args.serialization_format {
-        Some(f) => match nix2sbom::format::SerializationFormat::from_string(&f) {
-            Some(f) => f,
+    let compression = match &args.compress {
+        Some(c) => match nix2sbom::format::Compression::from_string(c) {
+            Some(c) => Some(c),
             None => {
-                eprintln!("Invalid serialization format {}", &f);
-                return Ok(std::process::ExitCode::FAILURE);
+                eprintln!(
+                    "Invalid compression {}. Supported compressions are gzip and zstd",
+                    c
+                );
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        },
+        None => args
+            .output
+            .as_deref()
+            .and_then(nix2sbom::format::Compression::from_path),
+    };
+
+    if let Some(api_version) = &args.api_version {
+        if !nix2sbom::consts::SUPPORTED_API_VERSIONS.contains(&api_version.as_str()) {
+            eprintln!(
+                "Unsupported API version {}. Supported versions are {}",
+                api_version,
+                nix2sbom::consts::SUPPORTED_API_VERSIONS.join(", ")
+            );
+            return Ok(std::process::ExitCode::from(
+                nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+            ));
+        }
+    }
+
+    let language = match &args.lang {
+        Some(l) => match nix2sbom::i18n::Language::from_string(l) {
+            Some(l) => l,
+            None => {
+                eprintln!(
+                    "Unsupported language {}. Supported languages are {}",
+                    l,
+                    nix2sbom::i18n::SUPPORTED_LANGUAGES.join(", ")
+                );
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
             }
         },
-        None => output_format.get_default_serialization_format(),
+        None => nix2sbom::i18n::Language::default(),
     };
 
-    let derivations: nix2sbom::nix::Derivations = if let Some(nix_ref) = args.nix_ref {
-        log::info!("Getting the derivations from {}", &nix_ref);
-        nix2sbom::nix::Derivation::get_derivations(&nix_ref)?
+    let store_backend = match &args.store_backend {
+        Some(b) => match nix2sbom::nix::StoreBackend::from_string(b) {
+            Some(b) => b,
+            None => {
+                eprintln!("Invalid store backend {}", &b);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        },
+        None => nix2sbom::nix::StoreBackend::default(),
+    };
+    let nix_backend = store_backend.get_nix_backend();
+
+    let metadata_backend = match &args.metadata_backend {
+        Some(b) => match nix2sbom::nix::MetadataBackend::from_string(b) {
+            Some(b) => b,
+            None => {
+                eprintln!("Invalid metadata backend {}", &b);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        },
+        None => nix2sbom::nix::MetadataBackend::default(),
+    };
+
+    let config = match &args.config {
+        Some(config_path) => nix2sbom::config::Config::from_file(config_path)?,
+        None => nix2sbom::config::Config::default(),
+    };
+    nix2sbom::mirrors::register_mirrors(&config.mirrors)?;
+    if let Some(Command::DiffGenerations {
+        generation_a,
+        generation_b,
+    }) = &args.command
+    {
+        return diff_generations(
+            generation_a,
+            generation_b,
+            serialization_format.as_ref(),
+            &config,
+            nix_backend.as_ref(),
+        );
+    }
+    let url_rewriter = config.get_url_rewriter()?;
+    let ownership_rules = match &args.owners {
+        Some(owners_path) => nix2sbom::owners::OwnershipRules::from_file(owners_path)?,
+        None => nix2sbom::owners::OwnershipRules::default(),
+    };
+    let ignore_file = args.ignore_file.clone().or_else(|| {
+        args.nix_refs
+            .first()
+            .and_then(|r| nix2sbom::ignore::find_ignore_file(&nix2sbom::flake::get_flake_ref(r)))
+    });
+    let ignore_rules = match &ignore_file {
+        Some(ignore_file_path) => {
+            log::info!("Using the ignore rules from {}", ignore_file_path);
+            nix2sbom::ignore::IgnoreRules::from_file(ignore_file_path)?
+        }
+        None => nix2sbom::ignore::IgnoreRules::default(),
+    };
+
+    let mut exclude_patterns: Vec<regex::Regex> = vec![];
+    for pattern in &args.exclude {
+        match regex::Regex::new(pattern) {
+            Ok(p) => exclude_patterns.push(p),
+            Err(e) => {
+                eprintln!("Invalid exclude pattern {}: {}", pattern, e);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        }
+    }
+
+    let mut only_patterns: Vec<regex::Regex> = vec![];
+    for pattern in &args.only {
+        match regex::Regex::new(pattern) {
+            Ok(p) => only_patterns.push(p),
+            Err(e) => {
+                eprintln!("Invalid only pattern {}: {}", pattern, e);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        }
+    }
+
+    let hook_environment = match args.command {
+        Some(Command::HydraHook) => Some(nix2sbom::hydra::HookEnvironment::from_env()?),
+        _ => None,
+    };
+    if hook_environment.is_some() && args.output_dir.is_none() {
+        eprintln!("Error: --output-dir is required with the hydra-hook command");
+        return Ok(std::process::ExitCode::from(
+            nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+        ));
+    }
+    let search_pattern = match &args.command {
+        Some(Command::Search { pattern, .. }) => match regex::Regex::new(pattern) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                eprintln!("Invalid search pattern {}: {}", pattern, e);
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        },
+        _ => None,
+    };
+
+    durations.push(("evaluation", std::time::Instant::now()));
+    let mut nix_refs = args.nix_refs.clone();
+    if let Some(Command::Search { nix_ref, .. }) = &args.command {
+        nix_refs = vec![nix_ref.to_string()];
+    }
+    if args.all_outputs && hook_environment.is_none() && !nix_refs.is_empty() {
+        let flake_ref = nix2sbom::flake::get_flake_ref(&nix_refs[0]);
+        nix_refs = nix_backend.get_flake_package_refs(&flake_ref)?;
+        log::info!("Found {} packages in flake {}", nix_refs.len(), &flake_ref);
+    }
+    // One root per reference, when several references are given.
+    let mut ref_root_paths: std::collections::BTreeSet<String> = std::collections::BTreeSet::default();
+    let derivations: nix2sbom::nix::Derivations = if let Some(hook_environment) = &hook_environment {
+        log::info!(
+            "Getting the derivations from {}",
+            &hook_environment.derivation_path
+        );
+        nix_backend.get_derivations_from_paths(&[hook_environment.derivation_path.to_string()])?
+    } else if !nix_refs.is_empty() {
+        let mut derivations = nix2sbom::nix::Derivations::default();
+        for ref_derivations in
+            nix2sbom::nix::Derivation::get_derivations_by_ref(&nix_refs, args.jobs, nix_backend.as_ref())?
+                .into_values()
+        {
+            if nix_refs.len() > 1 {
+                ref_root_paths.extend(nix2sbom::nix::get_root_derivation_paths(&ref_derivations));
+            }
+            derivations.extend(ref_derivations);
+        }
+        derivations
+    } else if let Some(derivations_json) = &args.derivations_json {
+        log::info!("Reading the derivations from {}", &derivations_json);
+        nix2sbom::nix::Derivation::get_derivations_from_json_file(derivations_json)?
+    } else if !args.from_drv.is_empty() {
+        log::info!("Reading the derivations from {}", args.from_drv.join(", "));
+        nix2sbom::aterm::read_derivations(&args.from_drv)?
+    } else if let Some(drv_list) = &args.drv_list {
+        log::info!("Getting the derivations listed in {}", &drv_list);
+        nix2sbom::nix::Derivation::get_derivations_from_list_file(drv_list, nix_backend.as_ref())?
     } else if args.current_system {
         log::info!("Getting the derivations from the current system");
-        nix2sbom::nix::Derivation::get_derivations_for_current_system()?
+        nix2sbom::nix::Derivation::get_derivations_for_current_system(nix_backend.as_ref())?
+    } else if let Some(profile) = &args.profile {
+        let profile_path = nix2sbom::profile::get_profile_path(profile)?;
+        log::info!("Getting the derivations from profile {}", &profile_path);
+        nix2sbom::profile::get_derivations_for_profile(&profile_path, nix_backend.as_ref())?
     } else {
-        eprintln!("Error: Must provide a file or use the --curent-system argument");
-        return Ok(std::process::ExitCode::FAILURE);
+        eprintln!("Error: Must provide a file or use the --derivations-json, --from-drv, --drv-list, --current-system or --profile argument");
+        return Ok(std::process::ExitCode::from(
+            nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+        ));
     };
     log::info!("Found {} derivations", derivations.len());
 
-    let packages = nix2sbom::nix::get_packages(args.metadata_path, !args.meta)?;
+    durations.push(("metadata", std::time::Instant::now()));
+    let metadata_options = nix2sbom::nix::MetadataOptions {
+        metadata_paths: args
+            .metadata_path
+            .iter()
+            .chain(args.metadata_url.iter())
+            .cloned()
+            .collect(),
+        no_meta: !args.meta && args.metadata_url.is_none(),
+        strict: args.strict_metadata,
+        backend: metadata_backend,
+        refresh: args.refresh_metadata,
+        meta_fields: args.meta_fields.clone(),
+    };
+    // Unless strict, a SBOM without metadata is better than no SBOM at all.
+    let mut metadata_error: Option<String> = None;
+    // The metadata evaluated for the derivations cannot be reused for other derivations.
+    let metadata_cache_key = match &metadata_options.backend {
+        nix2sbom::nix::MetadataBackend::NixEval(_) => None,
+        _ => Some(format!("{:?}", &metadata_options)),
+    };
+    let cached_metadata = metadata_cache_key
+        .as_ref()
+        .and_then(|k| metadata_cache.get(k))
+        .cloned();
+    let metadata = match cached_metadata {
+        Some(m) => {
+            log::info!("Using the package metadata loaded by a previous job");
+            Ok(m)
+        }
+        None => nix2sbom::nix::get_packages_with_source(&metadata_options, &derivations, nix_backend.as_ref()),
+    };
+    let (mut packages, metadata_source, invalid_packages) = match metadata {
+        Ok(p) => {
+            if let Some(metadata_cache_key) = metadata_cache_key {
+                metadata_cache.insert(metadata_cache_key, p.clone());
+            }
+            p
+        }
+        Err(e) if !args.strict && !args.strict_metadata => {
+            log::warn!("Could not load the package metadata: {}", e);
+            log::warn!("Generating the SBOM WITHOUT the package metadata. Licenses, descriptions and homepages will be missing. Use --strict to fail instead.");
+            metadata_error = Some(e);
+            (
+                nix2sbom::nix::Packages::default(),
+                nix2sbom::nix::MetadataSource::None,
+                std::collections::BTreeMap::default(),
+            )
+        }
+        Err(e) => return Err(e.into()),
+    };
+    manifest_inputs.metadata_source = Some(metadata_source);
+    // The packages defined in the flake itself are not in the nixpkgs metadata. Their
+    // metadata is indexed by .drv path, which takes precedence over the other entries.
+    if args.meta || args.metadata_url.is_some() {
+        for nix_ref in nix_refs.iter().filter(|r| r.contains('#')) {
+            match nix_backend.get_flake_package_metadata(nix_ref, args.meta_fields.as_deref()) {
+                Ok((drv_path, package)) => {
+                    packages.insert(drv_path, package);
+                }
+                Err(e) => log::warn!("{}", e),
+            };
+        }
+    }
+    if args.redact_maintainers {
+        let redaction_key = match &args.redaction_key {
+            Some(k) => k.to_string(),
+            None => uuid::Uuid::new_v4().to_string(),
+        };
+        nix2sbom::nix::redact_maintainers(&mut packages, &redaction_key);
+    }
     log::debug!("Found {} packages in the Nix store", packages.len());
 
     log::info!("Building the package graph");
+    durations.push(("graph", std::time::Instant::now()));
     let mut package_graph = nix2sbom::nix::get_package_graph(&derivations);
+    package_graph.add_root_nodes(&ref_root_paths);
     log::info!("{} nodes in the package graph", package_graph.nodes.len());
+    let incomplete_nodes = package_graph.get_incomplete_nodes();
+    if !incomplete_nodes.is_empty() {
+        if args.strict {
+            eprintln!(
+                "Error: {} derivations are missing from the store: {}",
+                incomplete_nodes.len(),
+                incomplete_nodes.join(", ")
+            );
+            return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_INCOMPLETE));
+        }
+        log::warn!(
+            "{} derivations are missing from the store, the SBOM will be incomplete",
+            incomplete_nodes.len()
+        );
+    }
     log::debug!(
         "{} root nodes in the package graph",
         package_graph.root_nodes.len()
     );
-    package_graph.transform(&packages)?;
+    if let Some(system) = &args.only_system {
+        package_graph.limit_to_system(system);
+    }
+    if let Some(eval_depth) = args.eval_depth {
+        package_graph.limit_depth(eval_depth);
+    }
+    let resolvers = nix2sbom::identify::Resolvers::with_overrides(&config.identity_overrides);
+    package_graph.transform_with_resolvers(&packages, &resolvers)?;
+    if args.purl_platform_qualifiers {
+        package_graph.add_platform_qualifiers();
+    }
+    if args.prune_test_and_doc {
+        package_graph.prune_test_and_doc_nodes();
+    }
+    if args.runtime_only {
+        if let Err(e) = package_graph.limit_to_runtime_closure(nix_backend.as_ref()) {
+            log::warn!("Could not compute the runtime closure: {}", e);
+        }
+    }
+    if args.direct_only {
+        package_graph.limit_to_direct_dependencies(!args.runtime_only);
+    }
+
+    // All the references are expected to come from the same flake. The store paths and
+    // the Nix files have no flake inputs.
+    if let Some(nix_ref) = args.nix_refs.first().filter(|r| nix2sbom::flake::is_flake_ref(r)) {
+        let flake_ref = nix2sbom::flake::get_flake_ref(nix_ref);
+        match nix_backend.get_flake_metadata(&flake_ref) {
+            Ok(flake_metadata) => {
+                manifest_inputs.revision = flake_metadata.revision.clone();
+                if let Some(flake_lock) = flake_metadata.locks {
+                    package_graph.flake_inputs = flake_lock.get_input_tree();
+                    log::info!("Found {} flake inputs", package_graph.flake_inputs.len());
+                }
+            }
+            Err(e) => match nix2sbom::flake::read_local_flake_lock(&flake_ref) {
+                Ok(flake_lock) => {
+                    log::debug!("Using the lock file of {}: {}", &flake_ref, e);
+                    package_graph.flake_inputs = flake_lock.get_input_tree();
+                    log::info!("Found {} flake inputs", package_graph.flake_inputs.len());
+                }
+                Err(_) => log::debug!("Not recording flake inputs for {}: {}", &flake_ref, e),
+            },
+        };
+    }
+    if args.closure_size {
+        if let Err(e) = package_graph.populate_output_path_infos(nix_backend.as_ref()) {
+            log::warn!("{}", e);
+        }
+    }
+    if args.build_provenance {
+        if let Err(e) = package_graph.populate_build_provenance(nix_backend.as_ref()) {
+            log::warn!("Could not get the build provenance of the packages: {}", e);
+        }
+    }
+    if args.include_store_paths {
+        if let Err(e) = package_graph.populate_store_paths(nix_backend.as_ref()) {
+            log::warn!("Could not get the hashes of the store paths: {}", e);
+        }
+        log::info!("Found {} store paths", package_graph.store_paths.len());
+    }
+    if args.input_sources {
+        package_graph.populate_input_sources();
+        log::info!("Found {} input sources", package_graph.input_sources.len());
+    }
+    if args.omnibor {
+        package_graph.populate_omnibor_ids();
+        log::info!(
+            "Found the OmniBOR ids of {} artifacts",
+            package_graph.omnibor_ids.len()
+        );
+    }
+    package_graph.rewrite_urls(&url_rewriter);
+    package_graph.apply_concluded_licenses(&config.concluded_licenses);
+    package_graph.apply_owners(&ownership_rules);
+    package_graph.filter_by_licenses(&nix2sbom::license::LicenseFilter {
+        only_licenses: args.only_licenses.clone(),
+        exclude_licenses: args.exclude_licenses.clone(),
+    });
+    package_graph.keep_only(&only_patterns, &args.only_purl_type);
+    package_graph.exclude_by_patterns(&exclude_patterns);
+
+    if let Some(search_pattern) = &search_pattern {
+        let search_results = nix2sbom::search::search(&package_graph, search_pattern, args.runtime_only);
+        if serialization_format == Some(nix2sbom::format::SerializationFormat::JSON) {
+            println!("{}", serde_json::to_string_pretty(&search_results)?);
+        } else if !search_results.is_empty() {
+            println!("{}", nix2sbom::search::to_text(&search_results));
+        }
+        log::info!("Found {} matching components", search_results.len());
+        return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS));
+    }
+
+    // The stats are computed on the complete graph, so the ignored packages are
+    // only removed from the published SBOMs.
+    let published_package_graph = if ignore_rules.is_empty() {
+        None
+    } else {
+        let mut published_package_graph = package_graph.clone();
+        published_package_graph.apply_ignore_rules(&ignore_rules);
+        Some(published_package_graph)
+    };
 
     log::debug!("Creating the SBOM");
 
     let mut dump_options = nix2sbom::nix::DumpOptions::default();
     dump_options.runtime_only = args.runtime_only;
+    dump_options.stats = nix2sbom::nix::StatsOptions {
+        with_meta: args.stats_with_meta,
+        licenses: args.stats_licenses,
+        runtime_only: args.runtime_only,
+        ..Default::default()
+    };
+    dump_options.metadata = config.metadata.clone();
+    if let Some(metadata_template_path) = &args.metadata_template {
+        dump_options
+            .metadata
+            .merge(nix2sbom::config::MetadataConfig::from_template_file(
+                metadata_template_path,
+            )?);
+    }
+    if let Some(timestamp) = &args.timestamp {
+        chrono::DateTime::parse_from_rfc3339(timestamp)
+            .map_err(|e| anyhow::format_err!("Invalid timestamp {}: {}", timestamp, e))?;
+        dump_options.metadata.timestamp = Some(timestamp.to_string());
+    } else if dump_options.metadata.timestamp.is_none() {
+        if let Some(source_date_epoch) = nix2sbom::utils::get_source_date_epoch()? {
+            dump_options.metadata.timestamp = Some(source_date_epoch.to_rfc3339());
+        }
+    }
+    dump_options.spec_version = args.spec_version.clone();
+    dump_options.api_version = args.api_version.clone();
+    dump_options.group_by_category = args.group_by_category;
+    dump_options.stable_refs = args.stable_refs;
+    dump_options.captured_env = args.capture_env.clone();
+    dump_options.document_namespace = args.document_namespace.clone();
+    dump_options.max_depth = args.max_depth;
+    dump_options.print_stdenv = args.include_stdenv;
+    dump_options.build_url_template = args.build_url_template.clone();
+    dump_options.metadata_error = metadata_error;
+    dump_options.evaluation = get_evaluation_info(&package_graph, args.record_host, nix_backend.as_ref());
+    if let Some(license_texts_dir) = &args.license_texts {
+        dump_options.license_texts = nix2sbom::license::write_license_texts(
+            published_package_graph.as_ref().unwrap_or(&package_graph),
+            license_texts_dir,
+        )?;
+        log::info!(
+            "Wrote {} license texts to {}",
+            dump_options.license_texts.len(),
+            license_texts_dir
+        );
+    }
     if args.no_pretty {
         dump_options.pretty = Some(false);
     };
 
-    let sbom_dump = match output_format.dump(&serialization_format, &package_graph, &dump_options) {
-        Ok(d) => d,
-        Err(e) => {
-            eprintln!("{}", e.to_string());
-            return Ok(std::process::ExitCode::FAILURE);
+    let ended_at = std::time::Instant::now();
+    // The package graph is built once and dumped in every requested format.
+    let mut dumps: Vec<(
+        nix2sbom::format::Format,
+        nix2sbom::format::SerializationFormat,
+        String,
+    )> = vec![];
+    let mut report: Option<nix2sbom::report::GenerationReport> = None;
+    for output_format in output_formats {
+        let serialization_format = serialization_format
+            .clone()
+            .unwrap_or(output_format.get_default_serialization_format());
+        let output_package_graph = match &published_package_graph {
+            Some(g) if !matches!(output_format, nix2sbom::format::Format::Stats) => g,
+            _ => &package_graph,
+        };
+        let (sbom_dump, format_report) =
+            match output_format.dump_with_report(&serialization_format, output_package_graph, &dump_options) {
+                Ok(d) => d,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_FAILURE));
+                }
+            };
+        match report.as_mut() {
+            Some(r) => {
+                let dump_duration = format_report.durations.get("dump").cloned().unwrap_or_default();
+                *r.durations.entry("dump".to_string()).or_default() += dump_duration;
+            }
+            None => report = Some(format_report),
+        };
+        dumps.push((output_format, serialization_format, sbom_dump));
+    }
+    let mut report = report.unwrap_or_default();
+    report.invalid_packages = invalid_packages;
+
+    // Each phase lasts until the next one starts.
+    for (i, (phase, phase_started_at)) in durations.iter().enumerate() {
+        let phase_ended_at = durations.get(i + 1).map(|(_, s)| *s).unwrap_or(ended_at);
+        report.durations.insert(
+            phase.to_string(),
+            (phase_ended_at - *phase_started_at).as_secs_f64(),
+        );
+    }
+    if args.summary {
+        eprintln!("{}", report.to_localized_summary(&language));
+    }
+
+    let mut manifest: Option<nix2sbom::manifest::GenerationManifest> = None;
+    if args.manifest.is_some() {
+        manifest_inputs.nix_version = dump_options.evaluation.nix_version.clone();
+        let mut generation_manifest = nix2sbom::manifest::GenerationManifest::new(started_at, manifest_inputs);
+        generation_manifest.add_package_graph_counts(&package_graph);
+        manifest = Some(generation_manifest);
+    }
+
+    if let Some(output_dir) = &args.output_dir {
+        std::fs::create_dir_all(output_dir)?;
+    }
+    for (output_format, serialization_format, sbom_dump) in &dumps {
+        let mut sbom_path: Option<String> = None;
+        if let (Some(_), Some(output_dir)) = (&hook_environment, &args.output_dir) {
+            let file_name = output_format.get_file_name(serialization_format);
+            let product_path = nix2sbom::hydra::write_build_product(output_dir, &file_name, sbom_dump)?;
+            log::info!("SBOM written to {}", product_path);
+            sbom_path = Some(product_path);
+        } else if let Some(output_path) =
+            get_output_path(&args, output_format, serialization_format, &compression)
+        {
+            match &compression {
+                Some(c) => nix2sbom::utils::write_compressed_file_atomically(&output_path, sbom_dump, c)?,
+                None => nix2sbom::utils::write_file_atomically(&output_path, sbom_dump)?,
+            };
+            log::info!("SBOM written to {}", output_path);
+            sbom_path = Some(output_path);
+        } else if let Some(compression) = &compression {
+            compression.compress_to(sbom_dump.as_bytes(), std::process::Stdio::inherit())?;
+        } else {
+            println!("{}", sbom_dump);
         }
+        if let Some(manifest) = manifest.as_mut() {
+            manifest.add_output(sbom_path, output_format, serialization_format, sbom_dump);
+        }
+    }
+
+    if let (Some(output_dir), true) = (&args.output_dir, args.split_roots) {
+        let output_formats: Vec<(&nix2sbom::format::Format, &nix2sbom::format::SerializationFormat)> =
+            dumps.iter().map(|(f, s, _)| (f, s)).collect();
+        let root_sbom_paths = write_root_sboms(
+            output_dir,
+            &package_graph,
+            published_package_graph.as_ref(),
+            &output_formats,
+            &dump_options,
+            &compression,
+        )?;
+        log::info!(
+            "{} per-root SBOMs written to {}",
+            root_sbom_paths.len(),
+            output_dir
+        );
+    }
+
+    if let Some(attestation_bundle) = &args.attestation_bundle {
+        let attested_dump = dumps.iter().find_map(|(f, s, d)| {
+            nix2sbom::attestation::get_predicate_type(f, s).map(|predicate_type| (predicate_type, d))
+        });
+        let (predicate_type, sbom_dump) = match attested_dump {
+            Some(d) => d,
+            None => {
+                eprintln!("Error: The attestation bundle requires the cdx or spdx format, serialized as JSON");
+                return Ok(std::process::ExitCode::from(
+                    nix2sbom::consts::EXIT_INVALID_ARGUMENTS,
+                ));
+            }
+        };
+        let statement_paths = nix2sbom::attestation::write_attestation_bundle(
+            attestation_bundle,
+            &package_graph,
+            nix_backend.as_ref(),
+            predicate_type,
+            sbom_dump,
+            image_digest.as_deref(),
+        )?;
+        log::info!(
+            "{} attestations written to {}",
+            statement_paths.len(),
+            attestation_bundle
+        );
+    }
+
+    if let (Some(manifest_path), Some(mut manifest)) = (&args.manifest, manifest) {
+        manifest.finish(started_at);
+        manifest.write(manifest_path)?;
+        log::info!("Generation manifest written to {}", manifest_path);
+    }
+
+    Ok(std::process::ExitCode::from(nix2sbom::consts::EXIT_SUCCESS))
+}
+
+// The nixpkgs of the channels is only used when the SBOM is not generated from a flake.
+fn get_evaluation_info(
+    package_graph: &nix2sbom::nix::PackageGraph,
+    record_host: bool,
+    nix_backend: &dyn nix2sbom::backend::NixBackend,
+) -> nix2sbom::nix::EvaluationInfo {
+    let mut evaluation = nix2sbom::nix::EvaluationInfo {
+        nixpkgs_revision: nix2sbom::flake::get_nixpkgs_revision(&package_graph.flake_inputs),
+        nix_version: nix_backend.get_nix_version(),
+        command: Some(nix2sbom::utils::get_command_line(
+            &std::env::args().collect::<Vec<String>>(),
+        )),
+        ..Default::default()
     };
+    if evaluation.nixpkgs_revision.is_none() && package_graph.flake_inputs.is_empty() {
+        match nix_backend.get_nixpkgs_version() {
+            Ok(v) => evaluation.nixpkgs_version = Some(v),
+            Err(e) => log::debug!("Not recording the version of nixpkgs: {}", e),
+        };
+    }
+    if record_host {
+        evaluation.host = Some(nix2sbom::nix::HostInfo::get());
+    }
+    evaluation
+}
 
-    println!("{}", sbom_dump);
+// Writes the SBOMs of each root derivation, in every requested format, and returns their
+// paths. The subgraphs are extracted from the graph built for the combined SBOM, and are
+// dumped in parallel, each thread taking the next root until there are none left.
+fn write_root_sboms(
+    output_dir: &str,
+    package_graph: &nix2sbom::nix::PackageGraph,
+    published_package_graph: Option<&nix2sbom::nix::PackageGraph>,
+    output_formats: &[(&nix2sbom::format::Format, &nix2sbom::format::SerializationFormat)],
+    dump_options: &nix2sbom::nix::DumpOptions,
+    compression: &Option<nix2sbom::format::Compression>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let root_nodes: Vec<&String> = package_graph.root_nodes.iter().collect();
+    let root_dir_names = package_graph.get_root_dir_names();
+    let jobs = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let next_root_index = std::sync::atomic::AtomicUsize::new(0);
+    let results: std::sync::Mutex<Vec<Result<String, anyhow::Error>>> = std::sync::Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(root_nodes.len()) {
+            scope.spawn(|| {
+                while let Some(root_node) =
+                    root_nodes.get(next_root_index.fetch_add(1, std::sync::atomic::Ordering::SeqCst))
+                {
+                    let root_dir = std::path::Path::new(output_dir).join(&root_dir_names[*root_node]);
+                    let root_results = write_root_sbom(
+                        &root_dir,
+                        root_node,
+                        package_graph,
+                        published_package_graph,
+                        output_formats,
+                        dump_options,
+                        compression,
+                    );
+                    let mut results = results.lock().unwrap();
+                    match root_results {
+                        Ok(paths) => results.extend(paths.into_iter().map(Ok)),
+                        Err(e) => results.push(Err(e)),
+                    };
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().collect()
+}
+
+fn write_root_sbom(
+    root_dir: &std::path::Path,
+    root_node: &str,
+    package_graph: &nix2sbom::nix::PackageGraph,
+    published_package_graph: Option<&nix2sbom::nix::PackageGraph>,
+    output_formats: &[(&nix2sbom::format::Format, &nix2sbom::format::SerializationFormat)],
+    dump_options: &nix2sbom::nix::DumpOptions,
+    compression: &Option<nix2sbom::format::Compression>,
+) -> Result<Vec<String>, anyhow::Error> {
+    std::fs::create_dir_all(root_dir)?;
+    let subgraph = package_graph.get_root_subgraph(root_node);
+    let published_subgraph = published_package_graph.map(|g| g.get_root_subgraph(root_node));
+    let mut response: Vec<String> = vec![];
+    for (output_format, serialization_format) in output_formats {
+        // As with the combined SBOM, the stats include the ignored components.
+        let output_subgraph = match &published_subgraph {
+            Some(g) if !matches!(output_format, nix2sbom::format::Format::Stats) => g,
+            _ => &subgraph,
+        };
+        let sbom_dump = output_format.dump(serialization_format, output_subgraph, dump_options)?;
+        let mut file_name = output_format.get_file_name(serialization_format);
+        if let Some(compression) = compression {
+            file_name = format!("{}.{}", file_name, compression.get_extension());
+        }
+        let sbom_path = root_dir.join(file_name).display().to_string();
+        match compression {
+            Some(c) => nix2sbom::utils::write_compressed_file_atomically(&sbom_path, &sbom_dump, c)?,
+            None => nix2sbom::utils::write_file_atomically(&sbom_path, &sbom_dump)?,
+        };
+        response.push(sbom_path);
+    }
+    Ok(response)
+}
 
-    Ok(std::process::ExitCode::SUCCESS)
+// Returns the path of the file to write the SBOM to, or None to print it on stdout.
+fn get_output_path(
+    args: &NixToSBOM,
+    output_format: &nix2sbom::format::Format,
+    serialization_format: &nix2sbom::format::SerializationFormat,
+    compression: &Option<nix2sbom::format::Compression>,
+) -> Option<String> {
+    if let Some(output_dir) = &args.output_dir {
+        let mut file_name = output_format.get_file_name(serialization_format);
+        if let Some(compression) = compression {
+            file_name = format!("{}.{}", file_name, compression.get_extension());
+        }
+        return Some(
+            std::path::Path::new(output_dir)
+                .join(file_name)
+                .display()
+                .to_string(),
+        );
+    }
+    args.output.clone().filter(|p| p != "-")
 }
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Describes how a SBOM was generated. This is written as a JSON sidecar
+/// file next to the SBOM so that it can be catalogued.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct GenerationManifest {
+    pub tool: ManifestTool,
+    pub inputs: ManifestInputs,
+    pub outputs: Vec<ManifestOutput>,
+    pub timing: ManifestTiming,
+
+    /// Number of components with a warning, indexed by warning.
+    pub warnings: BTreeMap<String, usize>,
+
+    /// Number of derivations excluded from the SBOM, by reason of exclusion.
+    pub excluded: BTreeMap<String, usize>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct ManifestTool {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct ManifestInputs {
+    pub nix_refs: Vec<String>,
+
+    /// Revision of the flake the SBOM was generated from.
+    pub revision: Option<String>,
+
+    pub metadata_source: Option<crate::nix::MetadataSource>,
+
+    /// Output of nix --version.
+    pub nix_version: Option<String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct ManifestOutput {
+    /// Path of the SBOM. Not set when the SBOM was printed to stdout.
+    pub path: Option<String>,
+    pub format: String,
+    pub serialization_format: String,
+    pub digest: String,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct ManifestTiming {
+    pub started_at: String,
+    pub finished_at: String,
+    pub duration_seconds: f64,
+}
+
+impl GenerationManifest {
+    pub fn new(started_at: SystemTime, inputs: ManifestInputs) -> GenerationManifest {
+        GenerationManifest {
+            tool: ManifestTool {
+                name: crate::consts::PROJECT_NAME.to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            inputs,
+            outputs: vec![],
+            timing: ManifestTiming {
+                started_at: DateTime::<Utc>::from(started_at).to_rfc3339(),
+                finished_at: String::default(),
+                duration_seconds: 0.0,
+            },
+            warnings: BTreeMap::default(),
+            excluded: BTreeMap::default(),
+        }
+    }
+
+    pub fn add_output(
+        &mut self,
+        path: Option<String>,
+        format: &crate::format::Format,
+        serialization_format: &crate::format::SerializationFormat,
+        content: &str,
+    ) {
+        self.outputs.push(ManifestOutput {
+            path,
+            format: format.to_pretty_name(),
+            serialization_format: serialization_format.to_string(),
+            digest: format!("sha256:{}", crate::utils::get_sha256_digest(content.as_bytes())),
+        });
+    }
+
+    pub fn add_package_graph_counts(&mut self, package_graph: &crate::nix::PackageGraph) {
+        for package_node in package_graph.nodes_next.values() {
+            for warning in package_node.get_warnings() {
+                *self.warnings.entry(warning).or_insert(0) += 1;
+            }
+        }
+        self.excluded = package_graph.get_excluded_nodes_count();
+    }
+
+    pub fn finish(&mut self, started_at: SystemTime) {
+        let now = SystemTime::now();
+        self.timing.finished_at = DateTime::<Utc>::from(now).to_rfc3339();
+        self.timing.duration_seconds = now
+            .duration_since(started_at)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), anyhow::Error> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+// Merges several SBOMs generated by nix2sbom, for example one per host of a flake, in
+// the CycloneDX (JSON or XML) or the native format. The components are deduplicated by
+// purl and the dependency graphs are unioned. The merged SBOM is in the native format
+// when all the SBOMs are, and in the CycloneDX format otherwise. Only the components,
+// the dependencies and the tools of the metadata are merged.
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_cyclonedx::cyclonedx::v_1_5::{
+    Component, ComponentBuilder, CycloneDx, CycloneDxBuilder, Dependency, DependencyBuilder, Metadata,
+};
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum Sbom {
+    CycloneDX(Box<CycloneDx>),
+    Native(Vec<crate::format::native::NativePackage>),
+}
+
+pub fn load_file(path: &str) -> Result<Sbom, anyhow::Error> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| anyhow::format_err!("Could not read {}: {}", path, e))?;
+    load(&content).map_err(|e| anyhow::format_err!("Could not load the SBOM {}: {}", path, e))
+}
+
+// Loads a SBOM, detecting its format from its content.
+pub fn load(content: &str) -> Result<Sbom, anyhow::Error> {
+    if content.trim_start().starts_with('<') {
+        return Ok(Sbom::CycloneDX(Box::new(crate::format::cyclone_dx::load_xml(
+            content,
+        )?)));
+    }
+    let value: serde_json::Value = serde_json::from_str(content)?;
+    if value.is_array() {
+        return Ok(Sbom::Native(serde_json::from_value(value)?));
+    }
+    if value["bomFormat"] == crate::format::CYCLONE_DX_NAME {
+        return Ok(Sbom::CycloneDX(Box::new(serde_json::from_value(value)?)));
+    }
+    Err(anyhow::format_err!(
+        "Only the CycloneDX and the native formats are supported"
+    ))
+}
+
+pub fn dump(
+    sboms: &[Sbom],
+    format: &crate::format::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, anyhow::Error> {
+    if sboms.iter().all(|s| matches!(s, Sbom::Native(_))) {
+        if format != &crate::format::SerializationFormat::JSON {
+            return Err(anyhow::format_err!(
+                "The native format can only be serialized as JSON"
+            ));
+        }
+        let native_packages = merge_native(sboms);
+        return Ok(match options.pretty {
+            Some(false) => serde_json::to_string(&native_packages)?,
+            _ => serde_json::to_string_pretty(&native_packages)?,
+        });
+    }
+    let cyclonedx = merge_cyclonedx(sboms, options);
+    let spec_version = options
+        .spec_version
+        .clone()
+        .unwrap_or(crate::format::cyclone_dx::DEFAULT_SPEC_VERSION.to_string());
+    let cyclonedx = crate::format::cyclone_dx::convert_to_spec_version(&cyclonedx, &spec_version)?;
+    crate::format::cyclone_dx::serialize(&cyclonedx, format, options)
+}
+
+// The URLs of the packages with the same purl are unioned.
+pub fn merge_native(sboms: &[Sbom]) -> Vec<crate::format::native::NativePackage> {
+    let mut response: BTreeMap<String, crate::format::native::NativePackage> = BTreeMap::default();
+    for sbom in sboms {
+        let native_packages = match sbom {
+            Sbom::Native(p) => p,
+            Sbom::CycloneDX(_) => continue,
+        };
+        for native_package in native_packages {
+            let merged_package = match response.get_mut(&native_package.purl) {
+                Some(p) => p,
+                None => {
+                    response.insert(native_package.purl.to_string(), native_package.clone());
+                    continue;
+                }
+            };
+            merged_package
+                .git_urls
+                .extend(native_package.git_urls.iter().cloned());
+            for (merged_urls, urls) in [
+                (&mut merged_package.download_urls, &native_package.download_urls),
+                (&mut merged_package.homepages, &native_package.homepages),
+            ] {
+                for url in urls {
+                    if !merged_urls.contains(url) {
+                        merged_urls.push(url.to_string());
+                    }
+                }
+            }
+        }
+    }
+    let mut native_packages: Vec<crate::format::native::NativePackage> = response.into_values().collect();
+    native_packages.sort_by(|a, b| a.id.cmp(&b.id));
+    native_packages
+}
+
+pub fn merge_cyclonedx(sboms: &[Sbom], options: &crate::nix::DumpOptions) -> CycloneDx {
+    let mut merged_components = MergedComponents::default();
+    let mut dependencies: BTreeMap<String, BTreeSet<String>> = BTreeMap::default();
+    let mut root_components: Vec<Component> = vec![];
+    let mut tools = None;
+
+    for (sbom_index, sbom) in sboms.iter().enumerate() {
+        // The bom-refs are only unique within a SBOM, so they are renamed when they
+        // collide with the bom-ref of a component of another SBOM.
+        let mut refs: BTreeMap<String, String> = BTreeMap::default();
+        match sbom {
+            Sbom::CycloneDX(cyclonedx) => {
+                let metadata = cyclonedx.metadata.clone().unwrap_or_default();
+                if tools.is_none() {
+                    tools = metadata.tools;
+                }
+                if let Some(component) = metadata.component {
+                    if let Some(component) = merged_components.add(component, sbom_index, &mut refs) {
+                        root_components.push(component);
+                    }
+                }
+                for component in cyclonedx.components.iter().flatten() {
+                    merged_components.add_all(component.clone(), sbom_index, &mut refs);
+                }
+                for dependency in cyclonedx.dependencies.iter().flatten() {
+                    let depends_on = dependencies.entry(get_ref(&refs, &dependency.ref_)).or_default();
+                    for dependency_ref in dependency.depends_on.iter().flatten() {
+                        depends_on.insert(get_ref(&refs, dependency_ref));
+                    }
+                }
+            }
+            Sbom::Native(native_packages) => {
+                for native_package in native_packages {
+                    let component = crate::convert::dump_native_package(native_package);
+                    merged_components.add(component, sbom_index, &mut refs);
+                }
+            }
+        };
+    }
+
+    let mut metadata = Metadata::default();
+    metadata.timestamp = match &options.metadata.timestamp {
+        Some(timestamp) => Some(timestamp.to_string()),
+        None => Some(chrono::Utc::now().to_rfc3339()),
+    };
+    metadata.tools = tools;
+    // The subjects of the merged SBOMs all become dependencies of a new root component.
+    if !root_components.is_empty() {
+        let name = root_components
+            .iter()
+            .map(|c| c.name.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let root_ref =
+            merged_components.get_unique_ref(crate::format::cyclone_dx::ROOT_COMPONENT_REF, sboms.len());
+        metadata.component = Some(
+            ComponentBuilder::default()
+                .bom_ref(root_ref.to_string())
+                .name(name)
+                .type_("application".to_string())
+                .scope("required".to_string())
+                .build()
+                .unwrap(),
+        );
+        dependencies.insert(
+            root_ref,
+            root_components.iter().filter_map(|c| c.bom_ref.clone()).collect(),
+        );
+    }
+
+    let dependencies: Vec<Dependency> = dependencies
+        .into_iter()
+        .map(|(dependency_ref, depends_on)| {
+            DependencyBuilder::default()
+                .ref_(dependency_ref)
+                .depends_on(
+                    depends_on
+                        .into_iter()
+                        .map(serde_json::Value::from)
+                        .collect::<Vec<serde_json::Value>>(),
+                )
+                .build()
+                .unwrap()
+        })
+        .collect();
+
+    CycloneDxBuilder::default()
+        .bom_format(crate::format::CYCLONE_DX_NAME)
+        .spec_version(crate::format::cyclone_dx::CURRENT_SPEC_VERSION)
+        .version(1)
+        .metadata(metadata)
+        .components(merged_components.components)
+        .dependencies(dependencies)
+        .build()
+        .unwrap()
+}
+
+#[derive(Default)]
+struct MergedComponents {
+    components: Vec<Component>,
+    /// The bom-ref of the component kept for each purl.
+    purls: BTreeMap<String, String>,
+    refs: BTreeSet<String>,
+}
+
+impl MergedComponents {
+    // Adds a component and its nested components. The nested components are flattened,
+    // and the components only used to group them, without a purl, are dropped.
+    fn add_all(&mut self, mut component: Component, sbom_index: usize, refs: &mut BTreeMap<String, String>) {
+        let nested_components = component.components.take().unwrap_or_default();
+        if nested_components.is_empty() || component.purl.is_some() {
+            self.add(component, sbom_index, refs);
+        }
+        for nested_component in nested_components {
+            self.add_all(nested_component, sbom_index, refs);
+        }
+    }
+
+    // Returns the component added, or None if a component with the same purl was
+    // already added.
+    fn add(
+        &mut self,
+        mut component: Component,
+        sbom_index: usize,
+        refs: &mut BTreeMap<String, String>,
+    ) -> Option<Component> {
+        if let Some(kept_ref) = component.purl.as_ref().and_then(|p| self.purls.get(p)) {
+            if let Some(bom_ref) = &component.bom_ref {
+                refs.insert(bom_ref.to_string(), kept_ref.to_string());
+            }
+            return None;
+        }
+        let bom_ref = match &component.bom_ref {
+            Some(r) => r.to_string(),
+            None => component.purl.clone().unwrap_or(component.name.to_string()),
+        };
+        let unique_ref = self.get_unique_ref(&bom_ref, sbom_index);
+        refs.insert(bom_ref, unique_ref.to_string());
+        self.refs.insert(unique_ref.to_string());
+        if let Some(purl) = &component.purl {
+            self.purls.insert(purl.to_string(), unique_ref.to_string());
+        }
+        component.bom_ref = Some(unique_ref);
+        self.components.push(component.clone());
+        Some(component)
+    }
+
+    fn get_unique_ref(&self, bom_ref: &str, sbom_index: usize) -> String {
+        if !self.refs.contains(bom_ref) {
+            return bom_ref.to_string();
+        }
+        let mut unique_ref = format!("{}-{}", bom_ref, sbom_index + 1);
+        let mut count = 1;
+        while self.refs.contains(&unique_ref) {
+            count += 1;
+            unique_ref = format!("{}-{}-{}", bom_ref, sbom_index + 1, count);
+        }
+        unique_ref
+    }
+}
+
+fn get_ref(refs: &BTreeMap<String, String>, bom_ref: &serde_json::Value) -> String {
+    let bom_ref = bom_ref.as_str().unwrap_or_default();
+    refs.get(bom_ref).cloned().unwrap_or(bom_ref.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_merge_cyclonedx() {
+        let sbom_a = load(
+            r###"
+            {
+              "bomFormat": "CycloneDX",
+              "specVersion": "1.5",
+              "version": 1,
+              "metadata": {
+                "component": { "bom-ref": "root", "type": "application", "name": "host-a" }
+              },
+              "components": [
+                { "bom-ref": "/nix/store/a-openssl-3.0.13.drv", "type": "library", "name": "openssl", "purl": "pkg:generic/openssl@3.0.13" },
+                { "bom-ref": "/nix/store/a-zlib-1.3.1.drv", "type": "library", "name": "zlib", "purl": "pkg:generic/zlib@1.3.1" }
+              ],
+              "dependencies": [
+                { "ref": "root", "dependsOn": ["/nix/store/a-openssl-3.0.13.drv"] },
+                { "ref": "/nix/store/a-openssl-3.0.13.drv", "dependsOn": ["/nix/store/a-zlib-1.3.1.drv"] }
+              ]
+            }
+            "###,
+        )
+        .unwrap();
+        let sbom_b = load(
+            r###"
+            {
+              "bomFormat": "CycloneDX",
+              "specVersion": "1.5",
+              "version": 1,
+              "metadata": {
+                "component": { "bom-ref": "root", "type": "application", "name": "host-b" }
+              },
+              "components": [
+                { "bom-ref": "category:library", "type": "library", "name": "library", "components": [
+                  { "bom-ref": "/nix/store/b-zlib-1.3.1.drv", "type": "library", "name": "zlib", "purl": "pkg:generic/zlib@1.3.1" }
+                ]}
+              ],
+              "dependencies": [
+                { "ref": "root", "dependsOn": ["/nix/store/b-zlib-1.3.1.drv"] }
+              ]
+            }
+            "###,
+        )
+        .unwrap();
+        let cyclonedx = merge_cyclonedx(&[sbom_a, sbom_b], &crate::nix::DumpOptions::default());
+
+        let component_refs: Vec<String> = cyclonedx
+            .components
+            .iter()
+            .flatten()
+            .filter_map(|c| c.bom_ref.clone())
+            .collect();
+        assert_eq!(
+            component_refs,
+            vec![
+                "root",
+                "/nix/store/a-openssl-3.0.13.drv",
+                "/nix/store/a-zlib-1.3.1.drv",
+                "root-2"
+            ]
+        );
+        let metadata_component = cyclonedx.metadata.unwrap().component.unwrap();
+        assert_eq!(metadata_component.name, "host-a, host-b");
+        assert_eq!(metadata_component.bom_ref, Some("root-3".to_string()));
+
+        let dependencies: BTreeMap<String, Vec<serde_json::Value>> = cyclonedx
+            .dependencies
+            .unwrap()
+            .into_iter()
+            .map(|d| {
+                (
+                    d.ref_.as_str().unwrap().to_string(),
+                    d.depends_on.unwrap_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(dependencies["root-2"], vec!["/nix/store/a-zlib-1.3.1.drv"]);
+        assert_eq!(dependencies["root-3"], vec!["root", "root-2"]);
+    }
+}
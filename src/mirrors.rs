@@ -1,6 +1,7 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::collections::HashMap;
+use std::sync::RwLock;
 
 lazy_static! {
     // This mapping is taken from
@@ -55,8 +56,42 @@ lazy_static! {
     };
     static ref MIRROR_URL_REGEX: Regex =
         Regex::new(r"mirror://([0-9a-zA-Z_-]+)/(.*)?").unwrap();
+    static ref MIRROR_NAME_REGEX: Regex = Regex::new(r"^[0-9a-zA-Z_-]+$").unwrap();
+    // Mirrors registered at runtime, for example the mirrors of private overlays. They
+    // take precedence over the mirrors of nixpkgs.
+    static ref REGISTERED_MIRRORS: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+// Registers the URL of a mirror, so that the mirror://<name>/ URLs are translated
+// to URLs starting with it.
+pub fn register_mirror(name: &str, url: &str) -> Result<(), anyhow::Error> {
+    if !MIRROR_NAME_REGEX.is_match(name) {
+        return Err(anyhow::format_err!("Invalid mirror name: {}", name));
+    }
+    let mut url = url.to_string();
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    REGISTERED_MIRRORS
+        .write()
+        .map_err(|_| anyhow::format_err!("Could not register mirror {}", name))?
+        .insert(name.to_string(), url);
+    Ok(())
+}
+
+pub fn register_mirrors(mirrors: &std::collections::BTreeMap<String, String>) -> Result<(), anyhow::Error> {
+    for (name, url) in mirrors {
+        register_mirror(name, url)?;
+    }
+    Ok(())
+}
+
+fn get_mirror_url(mirror_name: &str) -> Option<String> {
+    if let Some(mirror_url) = REGISTERED_MIRRORS.read().ok()?.get(mirror_name) {
+        return Some(mirror_url.to_string());
+    }
+    MIRRORS.get(mirror_name).map(|u| u.to_string())
 }
-lazy_static! {}
 
 pub fn translate_url(url: &str) -> String {
     if !url.starts_with("mirror://") {
@@ -68,8 +103,8 @@ pub fn translate_url(url: &str) -> String {
         }
 
         let mirror_name = &g[1];
-        if let Some(mirror_url) = MIRRORS.get(mirror_name) {
-            return url.replace(&format!("mirror://{}/", mirror_name), mirror_url);
+        if let Some(mirror_url) = get_mirror_url(mirror_name) {
+            return url.replace(&format!("mirror://{}/", mirror_name), &mirror_url);
         } else {
             panic!("Unknown mirror name: {}", mirror_name);
         }
@@ -87,4 +122,16 @@ mod tests {
         let url = crate::mirrors::translate_url("mirror://gnu/autoconf/autoconf-2.72.tar.xz");
         assert_eq!(url, "https://ftp.gnu.org/pub/gnu/autoconf/autoconf-2.72.tar.xz");
     }
+
+    #[test]
+    pub fn test_register_mirror() {
+        crate::mirrors::register_mirror("internal", "https://artifacts.example.com/mirror").unwrap();
+        let url = crate::mirrors::translate_url("mirror://internal/acme/widgets-1.0.tar.gz");
+        assert_eq!(
+            url,
+            "https://artifacts.example.com/mirror/acme/widgets-1.0.tar.gz"
+        );
+
+        assert!(crate::mirrors::register_mirror("not/a/name", "https://example.com/").is_err());
+    }
 }
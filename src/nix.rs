@@ -1,6 +1,9 @@
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs;
-use std::process::Command;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -8,8 +11,19 @@ use serde::{Deserialize, Deserializer, Serialize};
 // This is a special file used By NixOS to represent the derivations
 // that were used to build the current system.
 const CURRENT_SYSTEM_PATH: &str = "/run/current-system";
-
-fn is_semantic_version(possible_version: &str) -> bool {
+// Version of the schema of the stats format, bumped when a field is removed or changes meaning.
+pub const STATS_SCHEMA_VERSION: &str = "1";
+const OS_RELEASE_PATH: &str = "/etc/os-release";
+// Number of .drv paths passed to a single nix derivation show invocation.
+pub const DERIVATION_PATHS_BATCH_SIZE: usize = 500;
+const PACKAGE_NAMES_BATCH_SIZE: usize = 500;
+pub const DEFAULT_NIXPKGS_REF: &str = "nixpkgs";
+// The system of the derivations using the builtin fetchers, like fetchurl.
+const BUILTIN_SYSTEM: &str = "builtin";
+// Evaluating the whole package set with nix-env takes minutes.
+const NIX_ENV_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+pub fn is_semantic_version(possible_version: &str) -> bool {
     let semver_regex = Regex::new(r"^(0|[1-9]\d*)\.(0|[1-9]\d*)\.(0|[1-9]\d*)(?:-((?:0|[1-9]\d*|\d*[a-zA-Z-][0-9a-zA-Z-]*)(?:\.(?:0|[1-9]\d*|\d*[a-zA-Z-][0-9a-zA-Z-]*))*))?(?:\+([0-9a-zA-Z-]+(?:\.[0-9a-zA-Z-]+)*))?$").unwrap();
     semver_regex.is_match(possible_version)
 }
@@ -31,11 +45,37 @@ pub enum DerivationBuilder {
 #[derive(Default)]
 pub struct DisplayOptions {
     pub print_stdenv: bool,
-    pub print_exclude_list: Vec<String>,
     pub print_only_purl: bool,
     pub max_depth: Option<usize>,
 }
 
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct StatsOptions {
+    /// Whether or not to include the reachable nodes and the longest path of each root node.
+    pub per_root: bool,
+    /// Whether or not to include the number of packages by purl type.
+    pub purl_types: bool,
+    /// Whether or not to include the number of packages by license.
+    pub licenses: bool,
+    /// Whether or not to include the metadata coverage.
+    pub with_meta: bool,
+    /// Whether or not to ignore the build inputs, as with --runtime-only.
+    pub runtime_only: bool,
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        StatsOptions {
+            per_root: true,
+            purl_types: true,
+            licenses: false,
+            with_meta: false,
+            runtime_only: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 #[derive(Clone)]
 #[derive(Default)]
@@ -43,6 +83,221 @@ pub struct DumpOptions {
     pub runtime_only: bool,
     /// Whether or not to pretty print the manifests when dumping.
     pub pretty: Option<bool>,
+    /// What to include in the stats format.
+    pub stats: StatsOptions,
+    /// Overrides for the metadata of the SBOM.
+    pub metadata: crate::config::MetadataConfig,
+    /// Version of the specification of the SBOM format. Defaults to the
+    /// latest version supported for the format.
+    pub spec_version: Option<String>,
+    /// Version of the output contract of nix2sbom. Defaults to the current version.
+    pub api_version: Option<String>,
+    /// Whether or not to group the packages by category, in the formats that
+    /// support nested components.
+    pub group_by_category: bool,
+    /// Use bom-refs derived from the purls and the versions instead of the derivation paths.
+    pub stable_refs: bool,
+    /// Names of the derivation env variables recorded as properties of the components.
+    pub captured_env: Vec<String>,
+    /// Namespace of the SPDX document. Derived from the root derivation by default.
+    pub document_namespace: Option<String>,
+    /// Template of the URL of the build of the packages, for example on Hydra.
+    /// See DumpOptions::get_build_url for the placeholders.
+    pub build_url_template: Option<String>,
+    /// Error that prevented loading the package metadata, when the SBOM was
+    /// generated without metadata instead.
+    pub metadata_error: Option<String>,
+    /// Describes the evaluation that produced the SBOM.
+    pub evaluation: EvaluationInfo,
+    /// Paths of the license text files, indexed by SPDX license identifier.
+    pub license_texts: BTreeMap<String, String>,
+    /// Maximum depth of the dependencies printed by the pretty-print format. Defaults to 1.
+    pub max_depth: Option<usize>,
+    /// Whether or not to print the stdenv packages in the pretty-print format.
+    pub print_stdenv: bool,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct EvaluationInfo {
+    /// Revision of the nixpkgs input of the flake.
+    pub nixpkgs_revision: Option<String>,
+    /// Version of the nixpkgs of the channels, when the SBOM is not generated from a flake.
+    pub nixpkgs_version: Option<String>,
+    /// Output of nix --version.
+    pub nix_version: Option<String>,
+    /// Command line used to generate the SBOM.
+    pub command: Option<String>,
+    /// The machine that generated the SBOM, with --record-host.
+    pub host: Option<HostInfo>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct HostInfo {
+    pub hostname: Option<String>,
+    /// Pretty name of the operating system, e.g. NixOS 24.05 (Uakari).
+    pub os: Option<String>,
+    /// Store path of the current system, on NixOS.
+    pub current_system: Option<String>,
+}
+
+impl HostInfo {
+    pub fn get() -> HostInfo {
+        let hostname = ["/proc/sys/kernel/hostname", "/etc/hostname"]
+            .iter()
+            .find_map(|p| fs::read_to_string(p).ok())
+            .map(|h| h.trim().to_string())
+            .filter(|h| !h.is_empty());
+        let os = fs::read_to_string(OS_RELEASE_PATH)
+            .ok()
+            .and_then(|c| get_os_pretty_name(&c))
+            .or(Some(std::env::consts::OS.to_string()));
+        let current_system = fs::canonicalize(CURRENT_SYSTEM_PATH)
+            .ok()
+            .map(|p| p.display().to_string());
+        HostInfo {
+            hostname,
+            os,
+            current_system,
+        }
+    }
+}
+
+// Returns the PRETTY_NAME of an os-release file, or its NAME and VERSION.
+// See https://www.freedesktop.org/software/systemd/man/latest/os-release.html
+pub fn get_os_pretty_name(os_release: &str) -> Option<String> {
+    let mut fields: HashMap<&str, String> = HashMap::default();
+    for line in os_release.lines() {
+        if let Some((key, value)) = line.trim().split_once('=') {
+            fields.insert(key, value.trim_matches('"').trim_matches('\'').to_string());
+        }
+    }
+    if let Some(pretty_name) = fields.get("PRETTY_NAME") {
+        return Some(pretty_name.to_string());
+    }
+    let name = fields.get("NAME")?;
+    match fields.get("VERSION") {
+        Some(version) => Some(format!("{} {}", name, version)),
+        None => Some(name.to_string()),
+    }
+}
+
+// How the store is queried for the information that is not part of the derivations.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub enum StoreBackend {
+    /// Spawn the nix command.
+    #[default]
+    Cli,
+    /// Talk to the Nix daemon through its unix socket.
+    Daemon(String),
+}
+
+impl DumpOptions {
+    pub fn get_api_version(&self) -> String {
+        self.api_version
+            .clone()
+            .unwrap_or(crate::consts::API_VERSION.to_string())
+    }
+
+    // Returns the time of the generation, unless a fixed timestamp was requested
+    // for reproducible SBOMs.
+    pub fn get_timestamp(&self) -> chrono::DateTime<chrono::Utc> {
+        self.metadata
+            .timestamp
+            .as_ref()
+            .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&chrono::Utc))
+            .unwrap_or_else(chrono::Utc::now)
+    }
+
+    // Returns the build URL of a derivation from the build URL template. The
+    // placeholders are {drvhash}, {drvname} and {drvpath}.
+    pub fn get_build_url(&self, derivation_path: &str) -> Option<String> {
+        let build_url_template = self.build_url_template.as_ref()?;
+        Some(
+            build_url_template
+                .replace("{drvhash}", &get_store_path_hash(derivation_path))
+                .replace("{drvname}", &get_store_path_name(derivation_path))
+                .replace("{drvpath}", derivation_path),
+        )
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct MetadataOptions {
+    /// Paths or URLs of the package metadata files. The files are merged, the later
+    /// files taking precedence.
+    pub metadata_paths: Vec<String>,
+    pub no_meta: bool,
+    /// Whether or not to fail on the package entries that cannot be deserialized.
+    pub strict: bool,
+    pub backend: MetadataBackend,
+    /// Whether or not to ignore the cached package metadata.
+    pub refresh: bool,
+    /// The metadata fields to load, see META_FIELDS. All the fields are loaded by default.
+    pub meta_fields: Option<Vec<String>>,
+}
+
+// Where the package metadata comes from, when no metadata file is given.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub enum MetadataBackend {
+    /// Evaluate the whole package set with nix-env.
+    #[default]
+    NixEnv,
+    /// Evaluate the metadata of the packages of the graph only, with nix eval,
+    /// in the given nixpkgs flake.
+    NixEval(String),
+}
+
+impl MetadataBackend {
+    // nix-eval uses the nixpkgs flake of the registry, nix-eval:<flake-ref> another flake.
+    pub fn from_string(backend: &str) -> Option<MetadataBackend> {
+        match backend.split_once(':') {
+            Some(("nix-eval", nixpkgs_ref)) if !nixpkgs_ref.is_empty() => {
+                Some(MetadataBackend::NixEval(nixpkgs_ref.to_string()))
+            }
+            Some(_) => None,
+            None => match backend {
+                "nix-env" => Some(MetadataBackend::NixEnv),
+                "nix-eval" => Some(MetadataBackend::NixEval(DEFAULT_NIXPKGS_REF.to_string())),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl StoreBackend {
+    pub fn get_nix_backend(&self) -> Box<dyn crate::backend::NixBackend> {
+        match self {
+            StoreBackend::Cli => Box::new(crate::backend::CliBackend::default()),
+            StoreBackend::Daemon(socket_path) => Box::new(crate::backend::DaemonBackend {
+                socket_path: socket_path.to_string(),
+            }),
+        }
+    }
+
+    pub fn from_string(backend: &str) -> Option<StoreBackend> {
+        match backend {
+            "cli" => Some(StoreBackend::Cli),
+            "daemon" => Some(StoreBackend::Daemon(
+                crate::daemon::DEFAULT_DAEMON_SOCKET_PATH.to_string(),
+            )),
+            _ => None,
+        }
+    }
 }
 
 pub enum PackageScope {
@@ -166,11 +421,35 @@ pub struct Derivation {
 
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
+
+    /// NAR hash of the output, for the derivations synthesized from the path
+    /// info of a store path that was substituted from a binary cache.
+    #[serde(skip)]
+    pub nar_hash: Option<String>,
 }
 
 pub type Derivations = HashMap<String, Derivation>;
 pub type Packages = HashMap<String, Package>;
 
+pub const CHECK_HOOK_EXCLUSION_REASON: &str = "check-hook";
+pub const DOCUMENTATION_EXCLUSION_REASON: &str = "documentation";
+pub const TEST_FIXTURE_EXCLUSION_REASON: &str = "test-fixture";
+pub const EVAL_DEPTH_EXCLUSION_REASON: &str = "eval-depth";
+pub const SYSTEM_EXCLUSION_REASON: &str = "system";
+pub const LICENSE_EXCLUSION_REASON: &str = "license";
+// Used for the nodes matching the rules of the .nix2sbomignore file.
+pub const IGNORE_EXCLUSION_REASON: &str = "ignored";
+// Used for the nodes matching the --exclude patterns.
+pub const PATTERN_EXCLUSION_REASON: &str = "pattern";
+// Used for the nodes not matching the --only and --only-purl-type filters.
+pub const ONLY_EXCLUSION_REASON: &str = "not-selected";
+// Used for the transitive dependencies when only the direct dependencies are requested.
+pub const TRANSITIVE_EXCLUSION_REASON: &str = "transitive";
+// Used for the nodes that are not in the runtime closure of the root nodes.
+pub const BUILD_ONLY_EXCLUSION_REASON: &str = "build-only";
+// Used for the nodes that were only reachable through excluded nodes.
+pub const UNREACHABLE_EXCLUSION_REASON: &str = "unreachable";
+
 pub const BUILD_INPUTS_FIELD_NAME: &str = "buildInputs";
 pub const NATIVE_BUILD_INPUTS_FIELD_NAME: &str = "nativeBuildInputs";
 pub const PROPAGATED_BUILD_INPUTS_FIELD_NAME: &str = "propagatedBuildInputs";
@@ -219,8 +498,110 @@ impl BuildInputType {
 }
 
 impl Derivation {
-    pub fn get_derivations_for_current_system() -> Result<Derivations, anyhow::Error> {
-        Derivation::get_derivations(CURRENT_SYSTEM_PATH)
+    pub fn get_derivations_for_current_system(
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<Derivations, anyhow::Error> {
+        Derivation::get_derivations_for_system(CURRENT_SYSTEM_PATH, backend)
+    }
+
+    // Returns the derivations of a NixOS system, for example a generation of the
+    // system profile. The store paths without a local derivation are synthesized.
+    pub fn get_derivations_for_system(
+        system_path: &str,
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<Derivations, anyhow::Error> {
+        let mut derivations = match backend.get_derivations(system_path) {
+            Ok(d) => d,
+            Err(e) => {
+                log::warn!("{}", e);
+                Derivations::default()
+            }
+        };
+        let path_infos = backend.get_path_infos(&[system_path.to_string()], true)?;
+        let substituted_derivations = Derivation::get_substituted_derivations(&derivations, &path_infos);
+        log::info!(
+            "Found {} store paths without a local derivation",
+            substituted_derivations.len()
+        );
+        derivations.extend(substituted_derivations);
+        Ok(derivations)
+    }
+
+    // Synthesizes a minimal derivation for the store paths that were substituted
+    // from a binary cache, and for which the .drv file is not available locally.
+    // The derivations are indexed by the deriver recorded in the path info, and the
+    // references of the store paths are used as input derivations.
+    pub fn get_substituted_derivations(
+        derivations: &Derivations,
+        path_infos: &BTreeMap<String, PathInfo>,
+    ) -> Derivations {
+        let mut derivation_paths: HashMap<String, String> = HashMap::default();
+        for (derivation_path, derivation) in derivations {
+            for output_path in derivation.get_output_paths() {
+                derivation_paths.insert(output_path, derivation_path.to_string());
+            }
+        }
+        for (path, path_info) in path_infos {
+            if derivation_paths.contains_key(path) {
+                continue;
+            }
+            let derivation_path = match &path_info.deriver {
+                Some(deriver) if !derivations.contains_key(deriver) => deriver.to_string(),
+                _ => path.to_string(),
+            };
+            derivation_paths.insert(path.to_string(), derivation_path);
+        }
+
+        let mut response = Derivations::default();
+        for (path, path_info) in path_infos {
+            let derivation_path = &derivation_paths[path];
+            if derivations.contains_key(derivation_path) {
+                continue;
+            }
+            let derivation_name = get_store_path_name(derivation_path);
+            let derivation_name = derivation_name.strip_suffix(".drv").unwrap_or(&derivation_name);
+            let output_name = match get_store_path_name(path).strip_prefix(derivation_name) {
+                Some(suffix) if suffix.starts_with('-') => suffix[1..].to_string(),
+                _ => "out".to_string(),
+            };
+
+            let derivation = response.entry(derivation_path.to_string()).or_insert(Derivation {
+                outputs: HashMap::default(),
+                inputs_sources: vec![],
+                input_derivations: HashMap::default(),
+                system: String::default(),
+                builder: DerivationBuilder::Unknown,
+                args: vec![],
+                env: HashMap::from([("name".to_string(), derivation_name.to_string())]),
+                extra: HashMap::default(),
+                nar_hash: None,
+            });
+            if output_name == "out" || derivation.nar_hash.is_none() {
+                derivation.nar_hash = path_info.nar_hash.clone();
+            }
+            derivation.outputs.insert(
+                output_name,
+                Output {
+                    path: path.to_string(),
+                    hash: None,
+                    hash_algo: None,
+                },
+            );
+            for reference in &path_info.references {
+                let reference_derivation_path = match derivation_paths.get(reference) {
+                    Some(p) => p,
+                    None => continue,
+                };
+                if reference_derivation_path == derivation_path {
+                    continue;
+                }
+                derivation.input_derivations.insert(
+                    reference_derivation_path.to_string(),
+                    InputDerivation::List(vec!["out".to_string()]),
+                );
+            }
+        }
+        response
     }
 
     pub fn get_scope(&self) -> Option<PackageScope> {
@@ -254,6 +635,133 @@ impl Derivation {
         Ok(flat_derivations)
     }
 
+    // Gets the derivations for multiple references, evaluating at most `jobs`
+    // references at the same time.
+    pub fn get_derivations_from_refs(
+        nix_refs: &[String],
+        jobs: usize,
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<Derivations, anyhow::Error> {
+        let mut response = Derivations::default();
+        for derivations in Derivation::get_derivations_by_ref(nix_refs, jobs, backend)?.into_values() {
+            response.extend(derivations);
+        }
+        Ok(response)
+    }
+
+    // Same as get_derivations_from_refs, with the derivations of each reference kept apart,
+    // indexed by reference.
+    pub fn get_derivations_by_ref(
+        nix_refs: &[String],
+        jobs: usize,
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<BTreeMap<String, Derivations>, anyhow::Error> {
+        let next_ref_index = AtomicUsize::new(0);
+        let results: Mutex<Vec<(String, Result<Derivations, anyhow::Error>)>> = Mutex::new(vec![]);
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.max(1).min(nix_refs.len()) {
+                scope.spawn(|| {
+                    while let Some(nix_ref) = nix_refs.get(next_ref_index.fetch_add(1, Ordering::SeqCst)) {
+                        log::info!("Getting the derivations from {}", &nix_ref);
+                        let derivations = backend.get_derivations(nix_ref);
+                        results.lock().unwrap().push((nix_ref.to_string(), derivations));
+                    }
+                });
+            }
+        });
+
+        let mut response: BTreeMap<String, Derivations> = BTreeMap::default();
+        for (nix_ref, derivations) in results.into_inner().unwrap() {
+            response.insert(nix_ref, derivations?);
+        }
+        Ok(response)
+    }
+
+    // Gets the derivations for a list of .drv paths. The paths are evaluated in
+    // batches to stay below the maximum length of a command line.
+    pub fn get_derivations_from_paths(derivation_paths: &[String]) -> Result<Derivations, anyhow::Error> {
+        let mut response = Derivations::default();
+        for batch in derivation_paths.chunks(DERIVATION_PATHS_BATCH_SIZE) {
+            let output = Command::new("nix")
+                .arg("derivation")
+                .arg("show")
+                .arg("-r")
+                .args(batch)
+                .output()?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8(output.stderr).unwrap_or_default();
+                return Err(anyhow::format_err!(
+                    "Could not get derivations from {}: {}",
+                    batch.join(" "),
+                    &stderr
+                ));
+            }
+
+            let flat_derivations: Derivations = serde_json::from_slice(&output.stdout)?;
+            response.extend(flat_derivations);
+        }
+        Ok(response)
+    }
+
+    // Builds a placeholder for a derivation that could not be found, for example
+    // because it was garbage collected. Only the name can be recovered from the path.
+    pub fn get_placeholder(derivation_path: &str) -> Derivation {
+        let name = get_store_path_name(derivation_path);
+        Derivation {
+            outputs: HashMap::default(),
+            inputs_sources: vec![],
+            input_derivations: HashMap::default(),
+            system: String::default(),
+            builder: DerivationBuilder::Unknown,
+            args: vec![],
+            env: HashMap::from([(
+                "name".to_string(),
+                name.strip_suffix(".drv").unwrap_or(&name).to_string(),
+            )]),
+            extra: HashMap::default(),
+            nar_hash: None,
+        }
+    }
+
+    // Reads a file containing one .drv path per line. Empty lines and lines
+    // starting with # are ignored.
+    pub fn get_derivations_from_list_file(
+        file_path: &str,
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<Derivations, anyhow::Error> {
+        let content = fs::read_to_string(file_path)?;
+        let derivation_paths = content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| l.to_string())
+            .collect::<Vec<String>>();
+        if derivation_paths.is_empty() {
+            return Err(anyhow::format_err!("No derivation paths found in {}", file_path));
+        }
+        backend.get_derivations_from_paths(&derivation_paths)
+    }
+
+    // Reads the output of `nix derivation show -r`, generated beforehand, from a
+    // file or from stdin when the path is -.
+    pub fn get_derivations_from_json_file(file_path: &str) -> Result<Derivations, anyhow::Error> {
+        let content = if file_path == "-" {
+            let mut content = String::default();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut content)?;
+            content
+        } else {
+            fs::read_to_string(file_path)
+                .map_err(|e| anyhow::format_err!("Could not read the derivations {}: {}", file_path, e))?
+        };
+        let derivations: Derivations = serde_json::from_str(&content)
+            .map_err(|e| anyhow::format_err!("Invalid derivations in {}: {}", file_path, e))?;
+        if derivations.is_empty() {
+            return Err(anyhow::format_err!("No derivations found in {}", file_path));
+        }
+        Ok(derivations)
+    }
+
     pub fn to_json(&self) -> Result<String, String> {
         return serde_json::to_string_pretty(self).map_err(|e| e.to_string());
     }
@@ -277,38 +785,7 @@ impl Derivation {
     }
 
     pub fn get_name(&self) -> Option<String> {
-        if let Some(pname) = self.env.get("pname") {
-            return Some(pname.to_string());
-        }
-
-        if let Some(name) = self.env.get("name") {
-            if let Some(version) = self.get_version_from_env() {
-                if name.contains(&version) {
-                    let package_version_suffix = "-".to_string() + &version;
-                    return Some(name.replace(&package_version_suffix, ""));
-                }
-            }
-            if let Some(possible_version) = name.split("-").last() {
-                if is_semantic_version(possible_version) {
-                    let package_version_suffix = "-".to_string() + &possible_version;
-                    return Some(name.replace(&package_version_suffix, ""));
-                }
-            }
-            if name != "source" {
-                return Some(name.to_string());
-            }
-        }
-
-        for url in self.get_urls() {
-            if let Some(project_name) = crate::utils::get_project_name_from_generic_url(&url) {
-                return Some(project_name.to_string());
-            }
-            if let Some(project_name) = crate::utils::get_project_name_from_archive_url(&url) {
-                return Some(project_name.to_string());
-            }
-        }
-
-        None
+        crate::identify::Resolvers::default().resolve_name(self)
     }
 
     // Returns the store path of the stdenv used.
@@ -323,18 +800,30 @@ impl Derivation {
 
     // For some reason, some derivations do not declare their source inputs using the
     // standard src field. This is the case for cargo dependencies for example.
-    // We can nonetheless detect those using the name of the input derivations.
     pub fn get_undeclared_source_paths(&self) -> Vec<&String> {
-        let mut response: Vec<&String> = vec![];
-        for input_derivation_path in self.input_derivations.keys() {
-            if input_derivation_path.ends_with(".tar.gz.drv") {
-                response.push(&input_derivation_path);
-            }
-            if input_derivation_path.ends_with("-source.drv") {
-                response.push(&input_derivation_path);
-            }
-        }
-        response
+        self.input_derivations
+            .keys()
+            .filter(|p| crate::fetcher::is_source_derivation_path(p))
+            .collect()
+    }
+
+    // Returns the source fetched by this derivation, if it was produced by a nixpkgs fetcher.
+    pub fn get_fetched_source(&self) -> Option<crate::fetcher::FetchedSource> {
+        crate::fetcher::detect(self)
+    }
+
+    // Returns the hash of a fixed-output derivation.
+    pub fn get_output_hash(&self) -> Option<crate::fetcher::OutputHash> {
+        crate::fetcher::get_output_hash(&self.env)
+            .or(crate::fetcher::get_output_hash_from_outputs(&self.outputs))
+    }
+
+    // Returns the algorithm and the base16 digest of the artifact downloaded by a
+    // fixed-output derivation, when it can be used to verify that artifact.
+    pub fn get_artifact_hash(&self) -> Option<(String, String)> {
+        let output_hash = self.get_output_hash().filter(|h| h.is_flat())?;
+        let digest = output_hash.get_hex()?;
+        Some((output_hash.algorithm, digest))
     }
 
     // Returns the main url of the derivation
@@ -343,20 +832,26 @@ impl Derivation {
         return urls.get(0).cloned();
     }
 
-    // Returns the store path of the stdenv used.
+    // Returns the URLs downloaded by the derivation.
     pub fn get_urls(&self) -> Vec<String> {
-        let mut response: Vec<String> = vec![];
-        if let Some(url) = self.env.get("url") {
-            for url in url.split(" ").collect::<Vec<_>>() {
-                response.push(crate::mirrors::translate_url(url));
-            }
-        }
-        if let Some(urls) = self.env.get("urls") {
-            for url in urls.split(" ").collect::<Vec<_>>() {
-                response.push(crate::mirrors::translate_url(url));
-            }
+        crate::fetcher::get_urls(&self.env)
+    }
+
+    // Rewrites the URLs of the derivation in place. The mirror URLs are translated
+    // before being rewritten, so that the rules can target the actual hostnames.
+    pub fn rewrite_urls(&mut self, url_rewriter: &crate::config::UrlRewriter) {
+        for field_name in crate::fetcher::URL_FIELD_NAMES {
+            let field_value = match self.env.get(field_name) {
+                Some(v) => v,
+                None => continue,
+            };
+            let rewritten_urls = field_value
+                .split(' ')
+                .map(|u| url_rewriter.rewrite(&crate::mirrors::translate_url(u)))
+                .collect::<Vec<String>>()
+                .join(" ");
+            self.env.insert(field_name.to_string(), rewritten_urls);
         }
-        response
     }
 
     // Returns the out path of the patches for that derivation
@@ -406,7 +901,7 @@ impl Derivation {
     // version (like parsing the URLs) are less reliable. We need a high certainty that
     // this is the correct version if we want to use the version to extract the package name
     // (pname) from the name of the derivation.
-    fn get_version_from_env(&self) -> Option<String> {
+    pub fn get_version_from_env(&self) -> Option<String> {
         if let Some(revision) = self.env.get("rev") {
             if revision.starts_with("v") {
                 return Some(revision[1..].to_string());
@@ -420,39 +915,93 @@ impl Derivation {
     }
 
     pub fn get_version(&self) -> Option<String> {
-        if let Some(version) = self.get_version_from_env() {
-            return Some(version);
+        crate::identify::Resolvers::default().resolve_version(self)
+    }
+
+    pub fn is_inline_script(&self) -> bool {
+        self.env.get("text").is_some()
+    }
+
+    // Returns the architecture and the operating system of the system the derivation
+    // is built for, e.g. x86_64 and linux for x86_64-linux.
+    pub fn get_platform(&self) -> Option<(String, String)> {
+        if self.system == BUILTIN_SYSTEM {
+            return None;
         }
-        for url in self.get_urls() {
-            if let Some(commit_sha) = crate::utils::get_git_sha_from_archive_url(&url) {
-                return Some(commit_sha);
-            }
-            if let Some(version) = crate::utils::get_semver_from_archive_url(&url) {
-                return Some(version);
-            }
+        let (arch, os) = self.system.split_once('-')?;
+        if arch.is_empty() || os.is_empty() {
+            return None;
         }
-        let name = match self.env.get("name") {
-            Some(n) => n,
-            None => return None,
-        };
-        if let Some(possible_version) = name.split("-").last() {
-            if is_semantic_version(possible_version) {
-                return Some(possible_version.to_string());
-            }
+        Some((arch.to_string(), os.to_string()))
+    }
+
+    // Returns the full name of the derivation, including the version.
+    fn get_full_name(&self) -> Option<&String> {
+        self.env.get("name")
+    }
+
+    // Returns the name of the derivation without its version, e.g. zstd-man for
+    // zstd-1.5.5-man.
+    fn get_name_without_version(&self) -> Option<String> {
+        let name = self.get_full_name()?;
+        Some(match self.get_version_from_env() {
+            Some(version) if !version.is_empty() => name.replace(&format!("-{}", version), ""),
+            _ => name.to_string(),
+        })
+    }
+
+    pub fn is_check_hook(&self) -> bool {
+        match self.get_name_without_version() {
+            Some(name) => name.contains("check-hook") || name.ends_with("-check.sh"),
+            None => false,
+        }
+    }
+
+    // Whether every output of the derivation is a documentation output.
+    pub fn has_documentation_outputs_only(&self) -> bool {
+        let documentation_outputs = ["doc", "devdoc", "man", "devman", "info"];
+        !self.outputs.is_empty()
+            && self
+                .outputs
+                .keys()
+                .all(|o| documentation_outputs.contains(&o.as_str()))
+    }
+
+    // Whether the derivation is a NixOS VM test, which requires the nixos-test
+    // system feature to be built.
+    pub fn is_nixos_test(&self) -> bool {
+        match self.env.get("requiredSystemFeatures") {
+            Some(features) => features.split_whitespace().any(|f| f == "nixos-test"),
+            None => false,
         }
-        let pname = match self.env.get("pname") {
+    }
+
+    // Whether the name of the derivation, without its version, looks like the name of
+    // a documentation derivation. This is less reliable than the outputs of the derivation.
+    pub fn has_documentation_name(&self) -> bool {
+        let name = match self.get_name_without_version() {
             Some(n) => n,
-            None => return None,
+            None => return false,
         };
-        if name.contains(pname) {
-            let package_name_prefix = pname.to_string() + "-";
-            return Some(name.replace(&package_name_prefix, ""));
-        }
-        None
+        ["-doc", "-docs", "-man", "-manual", "-devdoc"]
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
     }
 
-    pub fn is_inline_script(&self) -> bool {
-        self.env.get("text").is_some()
+    // Whether the name of the derivation, without its version, looks like the name of
+    // a test derivation.
+    pub fn has_test_name(&self) -> bool {
+        let name = match self.get_name_without_version() {
+            Some(n) => n,
+            None => return false,
+        };
+        if name.contains("test-fixture") || name.starts_with("nixos-test-") || name.starts_with("vm-test-run-")
+        {
+            return true;
+        }
+        ["-test", "-tests", "-fixtures"]
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
     }
 
     pub fn get_output_paths(&self) -> Vec<String> {
@@ -470,76 +1019,711 @@ impl Derivation {
 #[derive(Clone)]
 #[derive(PartialEq)]
 pub struct Output {
-    path: String,
+    pub path: String,
+
+    /// Hash of the output of a fixed-output derivation, in base16.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+
+    /// For example sha256, or r:sha256 when the hash is the hash of the NAR serialization.
+    #[serde(default)]
+    #[serde(rename = "hashAlgo")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_algo: Option<String>,
+}
+
+// Where the package metadata was loaded from.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataSource {
+    None,
+    File(String),
+    Url(String),
+    NixEnv,
+    NixSearch,
+    NixEval(String),
+    /// Several metadata files merged together.
+    Merged(Vec<MetadataSource>),
 }
 
 pub fn get_packages(metadata_path: Option<String>, no_meta: bool) -> Result<Packages, String> {
+    let metadata_options = MetadataOptions {
+        metadata_paths: metadata_path.into_iter().collect(),
+        no_meta,
+        strict: true,
+        ..Default::default()
+    };
+    let (packages, _metadata_source, _invalid_packages) = get_packages_with_source(
+        &metadata_options,
+        &Derivations::default(),
+        &crate::backend::CliBackend::default(),
+    )?;
+    Ok(packages)
+}
+
+// Loads the package metadata. Unless strict, the package entries that cannot be
+// deserialized are skipped, and returned with their error indexed by attribute path.
+pub fn get_packages_with_source(
+    options: &MetadataOptions,
+    derivations: &Derivations,
+    backend: &dyn crate::backend::NixBackend,
+) -> Result<(Packages, MetadataSource, BTreeMap<String, String>), String> {
     let mut packages: Packages = Packages::default();
+    let strict = options.strict;
 
-    if no_meta {
-        return Ok(packages);
+    if options.no_meta {
+        return Ok((packages, MetadataSource::None, BTreeMap::default()));
     }
 
-    let content: Vec<u8> = if let Some(path) = metadata_path {
-        log::info!("Using the package metadata from {}", &path);
-        fs::read(path).map_err(|e| e.to_string())?
+    let (raw_packages, metadata_source, invalid_packages) = if !options.metadata_paths.is_empty() {
+        read_merged_packages(&options.metadata_paths, strict).map_err(|e| e.to_string())?
+    } else if let MetadataBackend::NixEval(nixpkgs_ref) = &options.backend {
+        let (raw_packages, invalid_packages) = get_packages_from_nix_eval(
+            backend,
+            nixpkgs_ref,
+            derivations,
+            strict,
+            options.meta_fields.as_deref(),
+        )
+        .map_err(|e| e.to_string())?;
+        (
+            raw_packages,
+            MetadataSource::NixEval(nixpkgs_ref.to_string()),
+            invalid_packages,
+        )
     } else {
-        log::info!("Getting the metadata for packages in the Nix store");
-        // There is currently no way with Nix to generate the meta information
-        // only for a single derivation. We need to generate the meta for
-        // all the derivations in the store and then extract the information
-        // we want from the global meta database.
-        let output = Command::new("nix-env")
-            .arg("-q")
-            .arg("-a")
-            .arg("--meta")
-            .arg("--json")
-            .arg(".*")
-            .output()
-            .map_err(|e| e.to_string())?;
-        output.stdout
+        match get_packages_from_nix_env(backend, strict, options.refresh) {
+            Ok((p, invalid_packages)) => (p, MetadataSource::NixEnv, invalid_packages),
+            Err(e) => {
+                log::warn!("Could not get the package metadata using nix-env: {}", e);
+                log::warn!("Falling back to nix search and nix eval.");
+                let mut packages = get_packages_from_nix_search(backend)?;
+                add_package_metas(&mut packages, derivations, backend);
+                (packages, MetadataSource::NixSearch, BTreeMap::default())
+            }
+        }
     };
+    for (attribute_path, error) in &invalid_packages {
+        log::debug!(
+            "Skipping the invalid package metadata for {}: {}",
+            attribute_path,
+            error
+        );
+    }
+    if !invalid_packages.is_empty() {
+        log::warn!(
+            "Skipped {} package metadata entries that could not be parsed",
+            invalid_packages.len()
+        );
+    }
 
-    let raw_packages: Packages = serde_json::from_slice(&content).map_err(|e| e.to_string())?;
-
-    // Re-index the packages using the internal package name.
-    for package in raw_packages.values() {
-        packages.insert(package.name.to_string(), package.clone());
+    // The packages stay indexed by attribute path, since different attribute paths
+    // can have the same name. See PackageIndex for the lookups by name.
+    for (attribute_path, package) in raw_packages {
+        let mut package = package.clone();
+        if package.attribute_path.is_none() {
+            package.attribute_path = Some(attribute_path.to_string());
+        }
+        if let Some(meta_fields) = &options.meta_fields {
+            package.meta.retain_fields(meta_fields);
+        }
+        packages.insert(attribute_path, package);
     }
 
-    Ok(packages)
+    Ok((packages, metadata_source, invalid_packages))
 }
 
-#[derive(Debug)]
-#[derive(Deserialize)]
-#[derive(Serialize)]
-pub struct Meta {
-    pub packages: HashMap<String, PackageMeta>,
+// Reads and merges several package metadata files, for example the metadata of
+// the stable and unstable channels. The entries of the later files take precedence.
+fn read_merged_packages(
+    locations: &[String],
+    strict: bool,
+) -> Result<(Packages, MetadataSource, BTreeMap<String, String>), anyhow::Error> {
+    let mut packages = Packages::default();
+    let mut metadata_sources: Vec<MetadataSource> = vec![];
+    let mut invalid_packages: BTreeMap<String, String> = BTreeMap::default();
+    for location in locations {
+        log::info!("Using the package metadata from {}", &location);
+        let (location_packages, location_invalid_packages) = read_packages(location, strict)?;
+        for attribute_path in location_packages.keys() {
+            invalid_packages.remove(attribute_path);
+        }
+        invalid_packages.extend(location_invalid_packages);
+        packages.extend(location_packages);
+        metadata_sources.push(if is_url(location) {
+            MetadataSource::Url(location.to_string())
+        } else {
+            MetadataSource::File(location.to_string())
+        });
+    }
+    let metadata_source = if metadata_sources.len() == 1 {
+        metadata_sources.remove(0)
+    } else {
+        MetadataSource::Merged(metadata_sources)
+    };
+    Ok((packages, metadata_source, invalid_packages))
 }
 
-#[derive(Debug)]
-#[derive(Default)]
-pub struct PackageURL {
-    pub scheme: String,
-    pub host: String,
-    pub version: Option<String>,
-    pub path: Vec<String>,
-    pub query_params: HashMap<String, String>,
+fn is_url(location: &str) -> bool {
+    location.starts_with("https://") || location.starts_with("http://")
 }
 
-impl PackageURL {
-    pub fn to_string(&self) -> String {
-        let mut response = format!("{}://", self.scheme);
-        response += &self.host.clone();
+// Reads the package metadata from a file or a URL. The files compressed with brotli,
+// like the packages.json.br files published with the channels, must end with .br
+pub fn read_packages(
+    location: &str,
+    strict: bool,
+) -> Result<(Packages, BTreeMap<String, String>), anyhow::Error> {
+    let mut content = if is_url(location) {
+        download(location)?
+    } else {
+        fs::read(location).map_err(|e| anyhow::format_err!("Could not read {}: {}", location, e))?
+    };
+    if location.ends_with(".br") {
+        content = decompress_brotli(content)?;
+    }
+    parse_packages_with_mode(&content, strict)
+}
 
-        let full_path = self.path.join("/");
-        if !full_path.is_empty() {
-            response += &full_path;
-        }
+// Parses the output of nix-env, or the packages.json file of a channel.
+pub fn parse_packages(content: &[u8]) -> Result<Packages, anyhow::Error> {
+    let (packages, _invalid_packages) = parse_packages_with_mode(content, true)?;
+    Ok(packages)
+}
 
-        if let Some(version) = &self.version {
+// The package entries are deserialized independently, so that a single malformed
+// entry does not prevent using the metadata of the other packages. Unless strict,
+// the malformed entries are returned with their error, indexed by attribute path.
+pub fn parse_packages_with_mode(
+    content: &[u8],
+    strict: bool,
+) -> Result<(Packages, BTreeMap<String, String>), anyhow::Error> {
+    let raw_packages = get_raw_packages(content)?;
+    let mut packages = Packages::default();
+    let mut invalid_packages: BTreeMap<String, String> = BTreeMap::default();
+    for (attribute_path, raw_package) in raw_packages {
+        match serde_json::from_value::<Package>(raw_package) {
+            Ok(package) => {
+                packages.insert(attribute_path, package);
+            }
+            Err(e) if strict => {
+                return Err(anyhow::format_err!(
+                    "Invalid package metadata for {}: {}",
+                    attribute_path,
+                    e
+                ))
+            }
+            Err(e) => {
+                invalid_packages.insert(attribute_path, e.to_string());
+            }
+        };
+    }
+    Ok((packages, invalid_packages))
+}
+
+fn get_raw_packages(content: &[u8]) -> Result<serde_json::Map<String, serde_json::Value>, anyhow::Error> {
+    let mut raw_packages: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(content)?;
+    // The channels wrap the packages in a versioned object.
+    if let Some(serde_json::Value::Object(packages)) = raw_packages.get("packages") {
+        if raw_packages.contains_key("version") {
+            raw_packages = packages.clone();
+        }
+    }
+    Ok(raw_packages)
+}
+
+fn download(url: &str) -> Result<Vec<u8>, anyhow::Error> {
+    log::info!("Downloading {}", url);
+    let output = Command::new("curl")
+        .arg("--fail")
+        .arg("--silent")
+        .arg("--show-error")
+        .arg("--location")
+        .arg(url)
+        .output()
+        .map_err(|e| anyhow::format_err!("Could not run curl to download {}: {}", url, e))?;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not download {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+fn decompress_brotli(content: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+    let mut child = Command::new("brotli")
+        .arg("--decompress")
+        .arg("--stdout")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::format_err!("Could not run brotli to decompress the package metadata: {}", e))?;
+    let mut stdin = child.stdin.take().unwrap();
+    // The input is written from another thread, so that brotli does not block on
+    // a full stdout while we are still writing.
+    let writer = std::thread::spawn(move || stdin.write_all(&content));
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| anyhow::format_err!("Could not write the package metadata to brotli"))??;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not decompress the package metadata: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+// Deserializes the package metadata entries one by one, and returns the entries that
+// could not be deserialized with the error, indexed by attribute path. Both the output
+// of nix-env and the packages.json files published with the channels are accepted.
+pub fn get_invalid_packages(content: &[u8]) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let (_packages, invalid_packages) = parse_packages_with_mode(content, false)?;
+    Ok(invalid_packages)
+}
+
+pub fn get_nix_version() -> Option<String> {
+    let output = Command::new("nix").arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let version = String::from_utf8(output.stdout).ok()?;
+    Some(version.trim().to_string())
+}
+
+pub fn validate_meta_fields(fields: &[String]) -> Result<(), anyhow::Error> {
+    for field in fields {
+        if !META_FIELDS.contains(&field.as_str()) {
+            return Err(anyhow::format_err!(
+                "Invalid metadata field {}. Valid fields are {}",
+                field,
+                META_FIELDS.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Replaces the email of the maintainers of all the packages with an identifier
+// derived from the email and the key, so that the emails cannot be recovered by
+// hashing a list of known emails. The maintainers only listed by a string are
+// replaced entirely. The other maintainer fields are kept.
+pub fn redact_maintainers(packages: &mut Packages, key: &str) {
+    for package in packages.values_mut() {
+        let maintainers = match &mut package.meta.maintainers {
+            Some(m) => m,
+            None => continue,
+        };
+        for maintainer_item in maintainers.iter_mut() {
+            match maintainer_item {
+                PackageManagerItem::String(maintainer) => *maintainer = get_redacted_id(maintainer, key),
+                PackageManagerItem::Details(maintainer) => maintainer.redact_email(key),
+                PackageManagerItem::List(list) => list.iter_mut().for_each(|m| m.redact_email(key)),
+                PackageManagerItem::EmbeddedList(lists) => {
+                    lists.iter_mut().flatten().for_each(|m| m.redact_email(key))
+                }
+            }
+        }
+    }
+}
+
+// The output of nix-env is cached, since it takes minutes to evaluate the whole
+// package set. The cache is keyed by the version of nixpkgs, which includes its revision.
+fn get_packages_from_nix_env(
+    backend: &dyn crate::backend::NixBackend,
+    strict: bool,
+    refresh: bool,
+) -> Result<(Packages, BTreeMap<String, String>), String> {
+    let cache_path = match backend.get_nixpkgs_version() {
+        Ok(version) => get_metadata_cache_path(&version),
+        Err(e) => {
+            log::debug!("Not caching the package metadata: {}", e);
+            None
+        }
+    };
+    if let (Some(cache_path), false) = (&cache_path, refresh) {
+        if let Ok(content) = fs::read(cache_path) {
+            log::info!("Using the cached package metadata from {}", cache_path.display());
+            return parse_packages_with_mode(&content, strict).map_err(|e| e.to_string());
+        }
+    }
+
+    let content = backend.get_nix_env_metadata(None).map_err(|e| e.to_string())?;
+    if let Some(cache_path) = &cache_path {
+        let written = fs::create_dir_all(cache_path.parent().unwrap())
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                crate::utils::write_file_atomically(
+                    &cache_path.display().to_string(),
+                    &String::from_utf8_lossy(&content),
+                )
+            });
+        if let Err(e) = written {
+            log::warn!("Could not cache the package metadata: {}", e);
+        }
+    }
+    parse_packages_with_mode(&content, strict).map_err(|e| e.to_string())
+}
+
+// Returns the version of the nixpkgs of the channels, e.g. 24.05.20240601.abcdef0
+pub fn get_nixpkgs_version() -> Result<String, anyhow::Error> {
+    let output = Command::new("nix-instantiate")
+        .arg("--eval")
+        .arg("--json")
+        .arg("--expr")
+        .arg("(import <nixpkgs> { }).lib.version")
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not get the version of nixpkgs: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+// The versions of the nixpkgs checkouts do not include a revision, e.g. 24.11pre-git,
+// so their metadata is not cached.
+pub fn get_metadata_cache_path(nixpkgs_version: &str) -> Option<std::path::PathBuf> {
+    if nixpkgs_version.contains("pre-git")
+        || nixpkgs_version.is_empty()
+        || !nixpkgs_version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+    {
+        return None;
+    }
+    let cache_dir = crate::utils::get_cache_dir()?;
+    Some(cache_dir.join(format!("metadata-{}.json", nixpkgs_version)))
+}
+
+// Evaluates the metadata of the whole package set with nix-env, using the nixpkgs
+// of the channels or the given nixpkgs expression. The progress is reported
+// periodically, since the evaluation takes minutes.
+pub fn get_nix_env_metadata(nixpkgs_path: Option<&str>) -> Result<Vec<u8>, anyhow::Error> {
+    log::info!("Getting the metadata for packages in the Nix store");
+    // There is currently no way with Nix to generate the meta information
+    // only for a single derivation. We need to generate the meta for
+    // all the derivations in the store and then extract the information
+    // we want from the global meta database.
+    let mut command = Command::new("nix-env");
+    if let Some(nixpkgs_path) = nixpkgs_path {
+        command.arg("--file").arg(nixpkgs_path);
+    }
+    let mut child = command
+        .arg("-q")
+        .arg("-a")
+        .arg("--meta")
+        .arg("--json")
+        .arg("--out-path")
+        .arg(".*")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::format_err!("Could not run nix-env: {}", e))?;
+
+    // The outputs are read from other threads, so that nix-env does not block on
+    // a full pipe while the progress is reported.
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout_reader = std::thread::spawn(move || {
+        let mut content: Vec<u8> = vec![];
+        stdout.read_to_end(&mut content).map(|_| content)
+    });
+    let stderr_reader = std::thread::spawn(move || {
+        let mut content: Vec<u8> = vec![];
+        stderr.read_to_end(&mut content).map(|_| content)
+    });
+
+    let started_at = std::time::Instant::now();
+    let mut reported_at = started_at;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if reported_at.elapsed() >= NIX_ENV_PROGRESS_INTERVAL {
+            log::info!(
+                "Still evaluating the package set, {} seconds elapsed",
+                started_at.elapsed().as_secs()
+            );
+            reported_at = std::time::Instant::now();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    };
+    let content = stdout_reader.join().unwrap()?;
+    let errors = stderr_reader.join().unwrap()?;
+    if !status.success() {
+        return Err(anyhow::format_err!(
+            "Could not evaluate the package set with nix-env: {}",
+            String::from_utf8_lossy(&errors)
+        ));
+    }
+    log::info!(
+        "Evaluated the package set in {} seconds",
+        started_at.elapsed().as_secs()
+    );
+    Ok(content)
+}
+
+// Returns the names of the packages to evaluate in nixpkgs. The names are only
+// candidates: the metadata is still matched to the derivations by name and version.
+pub fn get_metadata_attribute_names(derivations: &Derivations) -> BTreeSet<String> {
+    derivations
+        .values()
+        .filter(|d| d.get_fetched_source().is_none())
+        .filter_map(|d| d.env.get("pname").cloned().or(d.get_name()))
+        .filter(|n| !n.is_empty() && !n.contains('"') && !n.contains('\\'))
+        .collect()
+}
+
+// Evaluates the metadata of the packages of the derivations only, instead of the whole
+// package set like nix-env does. The packages that do not exist in nixpkgs, or that fail
+// to evaluate, are ignored.
+fn get_packages_from_nix_eval(
+    backend: &dyn crate::backend::NixBackend,
+    nixpkgs_ref: &str,
+    derivations: &Derivations,
+    strict: bool,
+    meta_fields: Option<&[String]>,
+) -> Result<(Packages, BTreeMap<String, String>), anyhow::Error> {
+    let attribute_names = get_metadata_attribute_names(derivations)
+        .into_iter()
+        .collect::<Vec<String>>();
+    log::info!(
+        "Evaluating the metadata of {} packages in {}",
+        attribute_names.len(),
+        nixpkgs_ref
+    );
+    let mut packages = Packages::default();
+    let mut invalid_packages: BTreeMap<String, String> = BTreeMap::default();
+    for batch in attribute_names.chunks(PACKAGE_NAMES_BATCH_SIZE) {
+        let content = backend.get_nix_eval_metadata(nixpkgs_ref, batch, meta_fields)?;
+        let (batch_packages, batch_invalid_packages) = parse_packages_with_mode(&content, strict)?;
+        packages.extend(batch_packages);
+        invalid_packages.extend(batch_invalid_packages);
+    }
+    Ok((packages, invalid_packages))
+}
+
+// Evaluates the metadata of the packages of a nixpkgs flake with nix eval, in the
+// format of nix-env. The packages that do not exist, or fail to evaluate, are skipped.
+pub fn get_nix_eval_metadata(
+    nixpkgs_ref: &str,
+    attribute_names: &[String],
+    meta_fields: Option<&[String]>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let output = Command::new("nix")
+        .arg("eval")
+        .arg("--json")
+        // builtins.currentSystem and the flake registry are only available in impure mode.
+        .arg("--impure")
+        .arg("--expr")
+        .arg(get_nix_eval_metadata_expression(
+            nixpkgs_ref,
+            attribute_names,
+            meta_fields,
+        ))
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not evaluate the package metadata in {}: {}",
+            nixpkgs_ref,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+fn get_nix_eval_metadata_expression(
+    nixpkgs_ref: &str,
+    attribute_names: &[String],
+    meta_fields: Option<&[String]>,
+) -> String {
+    let attribute_names = attribute_names
+        .iter()
+        .map(|n| format!("\"{}\"", n))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        r#"
+        let
+          pkgs = (builtins.getFlake "{}").legacyPackages.${{builtins.currentSystem}};
+          getMetadata = {};
+          tryMetadata = name:
+            let
+              result = builtins.tryEval (
+                if pkgs ? ${{name}} && builtins.isAttrs pkgs.${{name}} && pkgs.${{name}} ? name
+                then builtins.deepSeq (getMetadata pkgs.${{name}}) (getMetadata pkgs.${{name}})
+                else null
+              );
+            in if result.success then result.value else null;
+        in
+          builtins.listToAttrs (builtins.filter (e: e.value != null)
+            (map (name: {{ inherit name; value = tryMetadata name; }}) [ {} ]))
+        "#,
+        nixpkgs_ref,
+        crate::flake::get_package_metadata_function(meta_fields),
+        attribute_names
+    )
+}
+
+// Used on installations where nix-env is not available, for example when
+// only the new-style CLI is installed and no channel is configured.
+fn get_packages_from_nix_search(backend: &dyn crate::backend::NixBackend) -> Result<Packages, String> {
+    log::info!("Getting the metadata for packages in nixpkgs using nix search");
+    let content = backend.get_nix_search_metadata().map_err(|e| e.to_string())?;
+    parse_nix_search_output(&content)
+}
+
+pub fn get_nix_search_metadata() -> Result<Vec<u8>, anyhow::Error> {
+    let output = Command::new("nix")
+        .arg("search")
+        .arg("nixpkgs")
+        .arg("--json")
+        .arg("^")
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not search the packages of nixpkgs: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct SearchResult {
+    pub pname: String,
+    pub version: String,
+    pub description: Option<String>,
+}
+
+pub fn parse_nix_search_output(content: &[u8]) -> Result<Packages, String> {
+    let search_results: HashMap<String, SearchResult> =
+        serde_json::from_slice(content).map_err(|e| e.to_string())?;
+
+    let mut packages = Packages::default();
+    for (attribute_path, search_result) in search_results {
+        // The attribute paths look like legacyPackages.x86_64-linux.hello
+        let system = attribute_path.split('.').nth(1).unwrap_or_default().to_string();
+        let package_attribute_path = attribute_path.splitn(3, '.').nth(2).map(|p| p.to_string());
+        let name = if search_result.version.is_empty() {
+            search_result.pname.to_string()
+        } else {
+            format!("{}-{}", search_result.pname, search_result.version)
+        };
+        let package = Package {
+            name,
+            pname: search_result.pname,
+            version: search_result.version,
+            system,
+            output_name: "out".to_string(),
+            attribute_path: package_attribute_path,
+            outputs: BTreeMap::default(),
+            meta: PackageMeta {
+                description: search_result.description.filter(|d| !d.is_empty()),
+                ..Default::default()
+            },
+        };
+        packages.insert(attribute_path, package);
+    }
+    Ok(packages)
+}
+
+// nix search only returns the name, the version and the description of the packages,
+// so the meta attribute of the packages of the derivations is evaluated separately.
+// Evaluating the meta of the whole package set one package at a time would be too slow.
+pub fn add_package_metas(
+    packages: &mut Packages,
+    derivations: &Derivations,
+    backend: &dyn crate::backend::NixBackend,
+) {
+    let derivation_names: HashSet<&String> = derivations.values().filter_map(|d| d.env.get("name")).collect();
+    for package in packages.values_mut() {
+        if !derivation_names.contains(&package.name) {
+            continue;
+        }
+        let attribute_path = match &package.attribute_path {
+            Some(p) => p,
+            None => continue,
+        };
+        match backend.get_package_meta(&format!("nixpkgs#{}", attribute_path)) {
+            Ok(meta) => package.meta = meta,
+            Err(e) => log::debug!("Could not evaluate the meta of {}: {}", attribute_path, e),
+        }
+    }
+}
+
+// Evaluates the meta attribute of a single installable, for example nixpkgs#hello.
+pub fn get_package_meta(installable: &str) -> Result<PackageMeta, anyhow::Error> {
+    let output = Command::new("nix")
+        .arg("eval")
+        .arg("--json")
+        .arg(format!("{}.meta", installable))
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not evaluate the meta of {}: {}",
+            installable,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+pub struct Meta {
+    pub packages: HashMap<String, PackageMeta>,
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+pub struct PackageURL {
+    pub scheme: String,
+    pub host: String,
+    pub version: Option<String>,
+    pub path: Vec<String>,
+    pub query_params: HashMap<String, String>,
+    /// The qualifiers written in the purl, sorted by key as required by the purl spec.
+    pub qualifiers: BTreeMap<String, String>,
+}
+
+impl PackageURL {
+    pub fn to_string(&self) -> String {
+        let mut response = format!("{}://", self.scheme);
+        response += &self.host.clone();
+
+        let full_path = self.path.join("/");
+        if !full_path.is_empty() {
+            response += &full_path;
+        }
+
+        if let Some(version) = &self.version {
             response += &("@".to_string() + version);
         }
+
+        let qualifiers = self
+            .qualifiers
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<String>>();
+        if !qualifiers.is_empty() {
+            response += &("?".to_string() + &qualifiers.join("&"));
+        }
         response
     }
 }
@@ -560,12 +1744,26 @@ pub struct Package {
     pub version: String,
 
     // name of the system for which this package was built
+    #[serde(default)]
     pub system: String,
 
     // name of the output
+    #[serde(default)]
     #[serde(rename = "outputName")]
     pub output_name: String,
 
+    // attribute path of the package in the package set, e.g. nixpkgs.hello
+    #[serde(default)]
+    #[serde(rename = "attributePath")]
+    pub attribute_path: Option<String>,
+
+    // store paths of the outputs, indexed by output name. Only present when the
+    // metadata was generated with nix-env --out-path. The paths of the outputs
+    // that were not evaluated are null.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, Option<String>>,
+
     pub meta: PackageMeta,
 }
 impl Package {
@@ -589,6 +1787,7 @@ impl Package {
 
 #[derive(Debug)]
 #[derive(Clone)]
+#[derive(Default)]
 #[derive(Deserialize)]
 #[derive(Serialize)]
 #[derive(PartialEq)]
@@ -599,6 +1798,11 @@ pub struct PackageMeta {
 
     pub insecure: Option<bool>,
 
+    /// Notes explaining why the package is insecure, often because it reached its end of life.
+    #[serde(rename = "knownVulnerabilities")]
+    #[serde(default)]
+    pub known_vulnerabilities: Option<Vec<String>>,
+
     pub description: Option<String>,
 
     pub unfree: Option<bool>,
@@ -610,8 +1814,71 @@ pub struct PackageMeta {
     pub maintainers: Option<PackageMaintainers>,
 
     pub license: Option<License>,
+
+    #[serde(rename = "sourceProvenance")]
+    pub source_provenance: Option<Vec<SourceProvenance>>,
+}
+
+// The lifecycle signals of a package, from its meta attributes.
+pub const END_OF_LIFE_SIGNAL: &str = "end-of-life";
+pub const INSECURE_SIGNAL: &str = "insecure";
+pub const BROKEN_SIGNAL: &str = "broken";
+pub const DEPRECATED_LICENSE_SIGNAL: &str = "deprecated-license";
+// Phrases of the knownVulnerabilities notes announcing the end of life of a package,
+// e.g. "Support for Python 2.7 ended on 2020-01-01" or "OpenSSL 1.1 is EOL".
+const END_OF_LIFE_PHRASES: [&str; 5] = [
+    "end of life",
+    "end-of-life",
+    "no longer supported",
+    "no longer maintained",
+    "unmaintained",
+];
+
+// Names of the metadata fields that can be selected with --meta-fields.
+pub const META_FIELDS: [&str; 5] = [
+    "description",
+    "homepage",
+    "license",
+    "maintainers",
+    "sourceProvenance",
+];
+
+// See https://nixos.org/manual/nixpkgs/stable/#var-meta-sourceProvenance
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+#[derive(PartialEq)]
+pub struct SourceProvenance {
+    #[serde(rename = "shortName")]
+    pub short_name: String,
+
+    #[serde(rename = "isSource")]
+    pub is_source: Option<bool>,
 }
+
 impl PackageMeta {
+    // Clears the metadata fields that were not selected. The status fields
+    // (broken, insecure, unfree, etc.) are always kept.
+    pub fn retain_fields(&mut self, fields: &[String]) {
+        let is_selected = |field_name: &str| fields.iter().any(|f| f == field_name);
+        if !is_selected("description") {
+            self.description = None;
+        }
+        if !is_selected("homepage") {
+            self.homepage = None;
+        }
+        if !is_selected("license") {
+            self.license = None;
+        }
+        if !is_selected("maintainers") {
+            self.maintainers = None;
+        }
+        if !is_selected("sourceProvenance") {
+            self.source_provenance = None;
+        }
+    }
+
     pub fn get_maintainers(&self) -> Vec<PackageMaintainer> {
         let mut response: Vec<PackageMaintainer> = vec![];
         let maintainers = match &self.maintainers {
@@ -643,6 +1910,38 @@ impl PackageMeta {
         return response;
     }
 
+    // Returns the lifecycle and risk signals of the package, so that the end-of-life
+    // software can be found without parsing the descriptions.
+    pub fn get_lifecycle_signals(&self) -> Vec<&'static str> {
+        let mut response: Vec<&'static str> = vec![];
+        let is_end_of_life = self.known_vulnerabilities.iter().flatten().any(|note| {
+            let note = note.to_lowercase();
+            END_OF_LIFE_PHRASES.iter().any(|p| note.contains(p))
+                || note
+                    .split(|c: char| !c.is_ascii_alphanumeric())
+                    .any(|w| w == "eol")
+                || (note.starts_with("support for") && note.contains(" ended"))
+        });
+        if is_end_of_life {
+            response.push(END_OF_LIFE_SIGNAL);
+        }
+        if self.insecure.unwrap_or(false) || self.known_vulnerabilities.as_ref().is_some_and(|v| !v.is_empty())
+        {
+            response.push(INSECURE_SIGNAL);
+        }
+        if self.broken.unwrap_or(false) {
+            response.push(BROKEN_SIGNAL);
+        }
+        let has_deprecated_license = self.get_licenses().iter().any(|l| match l {
+            PackageLicense::Details(details) => details.deprecated.unwrap_or(false),
+            PackageLicense::Name(_) => false,
+        });
+        if has_deprecated_license {
+            response.push(DEPRECATED_LICENSE_SIGNAL);
+        }
+        response
+    }
+
     pub fn get_licenses(&self) -> Vec<PackageLicense> {
         match &self.license {
             Some(h) => match h {
@@ -652,6 +1951,31 @@ impl PackageMeta {
             None => vec![],
         }
     }
+    // Returns the declared licenses as an SPDX license expression. Licenses
+    // without an SPDX identifier are referenced using a LicenseRef.
+    pub fn get_spdx_license_expression(&self) -> Option<String> {
+        let mut license_ids: Vec<String> = vec![];
+        for license in self.get_licenses() {
+            let license_id = match license {
+                PackageLicense::Name(n) => get_spdx_license_id(&n),
+                PackageLicense::Details(details) => match &details.spdx_id {
+                    Some(id) => id.to_string(),
+                    None => match details.short_name.as_ref().or(details.full_name.as_ref()) {
+                        Some(n) => get_license_ref(n),
+                        None => continue,
+                    },
+                },
+            };
+            if !license_ids.contains(&license_id) {
+                license_ids.push(license_id);
+            }
+        }
+        if license_ids.is_empty() {
+            return None;
+        }
+        Some(license_ids.join(" AND "))
+    }
+
     pub fn get_homepages(&self) -> Vec<String> {
         match &self.homepage {
             Some(h) => match h {
@@ -663,11 +1987,172 @@ impl PackageMeta {
     }
 }
 
+pub fn is_spdx_license_id(license_id: &str) -> bool {
+    crate::license::get_canonical_license_id(license_id).is_some()
+}
+
+// Returns the SPDX identifier of a license name, or a LicenseRef when the
+// name is not in the SPDX license list, e.g. for unfree.
+pub fn get_spdx_license_id(license_name: &str) -> String {
+    match crate::license::get_canonical_license_id(license_name) {
+        Some(license_id) => license_id,
+        None => get_license_ref(license_name),
+    }
+}
+
+fn get_license_ref(license_name: &str) -> String {
+    let license_ref = license_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    format!("LicenseRef-{}", license_ref)
+}
+
 pub fn get_package_for_derivation(derivation_name: &str, packages: &Packages) -> Option<Package> {
     if let Some(package) = packages.get(derivation_name) {
         return Some(package.clone());
     }
-    None
+    packages
+        .values()
+        .filter(|p| p.name == derivation_name)
+        .min_by_key(|p| p.attribute_path.clone())
+        .cloned()
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum PackageMatch<'a> {
+    Found(&'a Package),
+    // The attribute paths of the packages that could not be told apart.
+    Ambiguous(Vec<String>),
+    NotFound(&'static str),
+}
+
+// Reasons for which no package metadata was attached to a derivation.
+pub const NO_NAME_META_REASON: &str = "no-name";
+pub const NO_METADATA_ENTRY_META_REASON: &str = "no-metadata-entry";
+pub const VERSION_MISMATCH_META_REASON: &str = "version-mismatch";
+pub const AMBIGUOUS_META_REASON: &str = "ambiguous";
+
+// Index of the package metadata used to find the metadata of a derivation.
+pub struct PackageIndex<'a> {
+    by_out_path: HashMap<String, Vec<&'a Package>>,
+    by_name: HashMap<String, Vec<&'a Package>>,
+    by_pname: HashMap<String, Vec<&'a Package>>,
+}
+
+impl<'a> PackageIndex<'a> {
+    pub fn new(packages: &'a Packages) -> PackageIndex<'a> {
+        let mut by_name: HashMap<String, Vec<&'a Package>> = HashMap::default();
+        let mut by_pname: HashMap<String, Vec<&'a Package>> = HashMap::default();
+        let mut by_out_path: HashMap<String, Vec<&'a Package>> = HashMap::default();
+        for package in packages.values() {
+            for out_path in package.outputs.values().flatten() {
+                by_out_path.entry(out_path.to_string()).or_default().push(package);
+            }
+            by_name.entry(package.name.to_string()).or_default().push(package);
+            by_pname
+                .entry(package.pname.to_string())
+                .or_default()
+                .push(package);
+        }
+        PackageIndex {
+            by_out_path,
+            by_name,
+            by_pname,
+        }
+    }
+
+    pub fn find(&self, package_node: &PackageNode) -> PackageMatch<'a> {
+        let derivation = &package_node.main_derivation;
+        // The output paths identify the derivation, unlike the names which can be
+        // changed by the packages, e.g. when overriding the pname.
+        for output in derivation.outputs.values() {
+            if let Some(candidates) = self.by_out_path.get(&output.path) {
+                return select_package(candidates);
+            }
+        }
+        if let Some(candidates) = derivation.env.get("name").and_then(|n| self.by_name.get(n)) {
+            return select_package(candidates);
+        }
+
+        // The derivation names are usually the pname, but can also be
+        // the full name when no pname was declared.
+        let pname = match self.get_pname(package_node) {
+            Some(p) => p,
+            None => return PackageMatch::NotFound(NO_NAME_META_REASON),
+        };
+        let version = package_node.version.clone().or(derivation.get_version());
+        if let Some(version) = &version {
+            if let Some(candidates) = self.by_name.get(&format!("{}-{}", pname, version)) {
+                return select_package(candidates);
+            }
+        }
+        let candidates = match self.by_pname.get(&pname) {
+            Some(c) => c,
+            None => return PackageMatch::NotFound(NO_METADATA_ENTRY_META_REASON),
+        };
+        let version = match version {
+            Some(v) => v,
+            None if candidates.len() == 1 => return PackageMatch::Found(candidates[0]),
+            None => return select_package(candidates),
+        };
+        let matching_candidates = candidates
+            .iter()
+            .filter(|p| p.version == version)
+            .cloned()
+            .collect::<Vec<&Package>>();
+        if !matching_candidates.is_empty() {
+            return select_package(&matching_candidates);
+        }
+        if candidates.len() > 1 {
+            return select_package(candidates);
+        }
+        // The only package with that name is for another version, so its
+        // metadata might not apply.
+        PackageMatch::NotFound(VERSION_MISMATCH_META_REASON)
+    }
+
+    fn get_pname(&self, package_node: &PackageNode) -> Option<String> {
+        let derivation = &package_node.main_derivation;
+        let possible_pnames = [
+            derivation.env.get("pname").cloned(),
+            package_node.name.clone(),
+            derivation.get_name(),
+        ];
+        let possible_pnames = possible_pnames.into_iter().flatten().collect::<Vec<String>>();
+        possible_pnames
+            .iter()
+            .find(|p| self.by_pname.contains_key(*p))
+            .or(possible_pnames.first())
+            .cloned()
+    }
+}
+
+// Selects a package from candidates, which is only possible if they all have the same metadata.
+fn select_package<'a>(candidates: &[&'a Package]) -> PackageMatch<'a> {
+    let mut candidates = candidates.to_vec();
+    candidates.sort_by_key(|p| p.attribute_path.clone());
+    let first_candidate = match candidates.first() {
+        Some(c) => c,
+        None => return PackageMatch::NotFound(NO_METADATA_ENTRY_META_REASON),
+    };
+    if candidates.iter().all(|c| c.meta == first_candidate.meta) {
+        return PackageMatch::Found(first_candidate);
+    }
+    PackageMatch::Ambiguous(
+        candidates
+            .iter()
+            .map(|c| c.attribute_path.clone().unwrap_or(c.name.to_string()))
+            .collect(),
+    )
 }
 
 #[derive(Debug)]
@@ -730,6 +2215,19 @@ pub struct PackageMaintainer {
     pub keys: Option<Vec<GpgKey>>,
 }
 
+impl PackageMaintainer {
+    pub fn redact_email(&mut self, key: &str) {
+        if let Some(email) = &self.email {
+            self.email = Some(get_redacted_id(email, key));
+        }
+    }
+}
+
+fn get_redacted_id(value: &str, key: &str) -> String {
+    let digest = crate::utils::get_hmac_sha256_digest(key.as_bytes(), value.to_lowercase().as_bytes());
+    format!("redacted-{}", &digest[..16])
+}
+
 #[derive(Debug)]
 #[derive(Clone)]
 #[derive(Deserialize)]
@@ -806,6 +2304,52 @@ pub struct PackageNode {
     pub build_inputs: BTreeSet<String>,
 
     pub children: BTreeSet<String>,
+
+    /// License concluded for the package, as opposed to the license declared
+    /// in the package metadata. This is only known when provided by the user.
+    #[serde(default)]
+    pub concluded_license: Option<String>,
+
+    /// Teams owning the package in the organization, from the ownership rules.
+    #[serde(default)]
+    pub owners: Vec<String>,
+
+    /// Size in bytes of the closure of the main output. Only known when the
+    /// output is present in the local store.
+    #[serde(default)]
+    pub closure_size: Option<u64>,
+
+    /// Size in bytes of the NAR serialization of the main output. Only known when
+    /// the output is present in the local store.
+    #[serde(default)]
+    pub nar_size: Option<u64>,
+
+    /// Attribute paths of the package metadata entries that matched this package
+    /// but could not be told apart. In that case, no metadata is attached.
+    #[serde(default)]
+    pub ambiguous_packages: Vec<String>,
+
+    /// Why no package metadata could be attached to this package.
+    #[serde(default)]
+    pub missing_meta_reason: Option<String>,
+
+    /// Whether the derivation was missing from the store, in which case the
+    /// node is only a placeholder with the information found in its path.
+    #[serde(default)]
+    pub incomplete: bool,
+
+    /// Whether the derivation was synthesized from the path info of a store path
+    /// substituted from a binary cache, because its .drv file was not in the store.
+    #[serde(default)]
+    pub substituted: bool,
+
+    /// Type of the purl of the package, e.g. cargo or pypi.
+    #[serde(default)]
+    pub purl_type: Option<String>,
+
+    /// Qualifiers added to the purl of the package, e.g. arch and os.
+    #[serde(default)]
+    pub purl_qualifiers: BTreeMap<String, String>,
 }
 
 impl PackageNode {
@@ -840,11 +2384,11 @@ impl PackageNode {
         name: &str,
         package_nodes: &BTreeMap<String, PackageNode>,
         visited_children: &mut HashMap<String, Vec<String>>,
-        dump_options: &DumpOptions,
+        runtime_only: bool,
     ) -> Vec<String> {
         let mut longest_path = vec![];
         let mut children = self.children.clone();
-        if !dump_options.runtime_only {
+        if !runtime_only {
             for build_input in &self.build_inputs {
                 children.insert(build_input.to_string());
             }
@@ -858,7 +2402,7 @@ impl PackageNode {
                         &child_derivation_path,
                         package_nodes,
                         visited_children,
-                        dump_options,
+                        runtime_only,
                     )
                 }
             };
@@ -873,16 +2417,101 @@ impl PackageNode {
         response
     }
 
+    // Returns the limitations of the information we have about this package,
+    // for example when the name or the version had to be guessed.
+    pub fn get_warnings(&self) -> Vec<String> {
+        let mut response: Vec<String> = vec![];
+        if self.incomplete {
+            response
+                .push("The derivation was missing from the store, so the package is incomplete.".to_string());
+        }
+        if !self.main_derivation.env.contains_key("pname") {
+            response.push("The package name was inferred from the derivation name.".to_string());
+        }
+        if self.get_version().is_none() {
+            response.push("No version could be found for the package.".to_string());
+        } else if self.main_derivation.get_version_from_env().is_none() {
+            response.push(
+                "The package version was inferred from the source URL or the derivation name.".to_string(),
+            );
+        }
+        if self.get_purl().scheme == "generic" {
+            response
+                .push("The package type could not be detected, so the purl uses the generic type.".to_string());
+        }
+        if !self.ambiguous_packages.is_empty() {
+            response.push(format!(
+                "Several package metadata entries matched the package ({}), so none of them was used.",
+                self.ambiguous_packages.join(", ")
+            ));
+        } else if self.package.is_none() {
+            response.push(
+                "No package metadata was found, so the description, licenses and maintainers are missing."
+                    .to_string(),
+            );
+        }
+        response
+    }
+
     pub fn is_inline_script(&self) -> bool {
         self.main_derivation.is_inline_script()
     }
 
-    pub fn get_purl(&self) -> PackageURL {
-        let mut package_url = PackageURL::default();
+    // Whether the package is a test of another package, i.e. its attribute path goes
+    // through the passthru.tests of a package or through nixosTests.
+    pub fn is_passthru_test(&self) -> bool {
+        let attribute_path = match self.package.as_ref().and_then(|p| p.attribute_path.as_ref()) {
+            Some(a) => a,
+            None => return false,
+        };
+        attribute_path.starts_with("nixosTests.") || attribute_path.split('.').any(|a| a == "tests")
+    }
 
-        let mut name: Option<String> = self.name.clone();
-        if let Some(n) = &name {
-            log::debug!("Found package name from source: {}", &n);
+    // Returns the reason why the node should be excluded from a runtime SBOM, if
+    // it is a test-only or a documentation-only derivation. The structural signals
+    // are used first. The names of the derivations are only used for the nodes that
+    // are not a (non-native) input of another package, so that the libraries whose
+    // name happens to end with -doc or -test are kept.
+    pub fn get_test_or_doc_exclusion_reason(&self, is_runtime_dependency: bool) -> Option<&'static str> {
+        if self.main_derivation.is_check_hook() {
+            return Some(CHECK_HOOK_EXCLUSION_REASON);
+        }
+        if self.main_derivation.has_documentation_outputs_only() {
+            return Some(DOCUMENTATION_EXCLUSION_REASON);
+        }
+        if self.main_derivation.is_nixos_test() || self.is_passthru_test() {
+            return Some(TEST_FIXTURE_EXCLUSION_REASON);
+        }
+        if is_runtime_dependency {
+            return None;
+        }
+        if self.main_derivation.has_documentation_name() {
+            return Some(DOCUMENTATION_EXCLUSION_REASON);
+        }
+        if self.main_derivation.has_test_name() {
+            return Some(TEST_FIXTURE_EXCLUSION_REASON);
+        }
+        None
+    }
+
+    // Whether the name, the purl, the derivation path or an output path of the
+    // package matches one of the patterns.
+    pub fn matches_patterns(&self, patterns: &[regex::Regex]) -> bool {
+        let purl = self.get_purl().to_string();
+        patterns.iter().any(|p| {
+            p.is_match(&purl)
+                || p.is_match(&self.id)
+                || self.name.as_deref().is_some_and(|name| p.is_match(name))
+                || self.main_derivation.outputs.values().any(|o| p.is_match(&o.path))
+        })
+    }
+
+    pub fn get_purl(&self) -> PackageURL {
+        let mut package_url = PackageURL::default();
+
+        let mut name: Option<String> = self.name.clone();
+        if let Some(n) = &name {
+            log::debug!("Found package name from source: {}", &n);
         } else {
             log::debug!(
                 "Could not find package name anywhere for {}",
@@ -909,12 +2538,21 @@ impl PackageNode {
         }
 
         // FIXME this cannot use the nix scope, which does not actually exist.
-        // See https://github.com/package-url/purl-spec/blob/master/PURL-TYPES.rst
-        // for the accepted scopes.
         package_url.scheme = "generic".to_string();
+        package_url.qualifiers = self.purl_qualifiers.clone();
 
         let urls = self.main_derivation.get_urls();
-        let url = match urls.get(0) {
+        let url = urls.first();
+        match (&self.purl_type, url) {
+            (Some(purl_type), _) => package_url.scheme = purl_type.to_string(),
+            (None, Some(url)) => {
+                if let Some(purl_type) = crate::identify::get_purl_type_from_url(url) {
+                    package_url.scheme = purl_type.to_string();
+                }
+            }
+            (None, None) => {}
+        };
+        let url = match url {
             Some(u) => u,
             None => {
                 log::trace!("{}", self.to_json().unwrap());
@@ -922,50 +2560,6 @@ impl PackageNode {
             }
         };
 
-        // TODO detect the scheme using the url.
-        if url.starts_with("https://crates.io") {
-            package_url.scheme = "cargo".to_string();
-        }
-        if url.starts_with("https://www.cpan.org/") {
-            package_url.scheme = "cpan".to_string();
-        }
-        if url.starts_with("https://rubygems.org") {
-            package_url.scheme = "gem".to_string();
-        }
-        if url.starts_with("https://hackage.haskell.org/") {
-            package_url.scheme = "hackage".to_string();
-        }
-        if url.starts_with("https://repo.maven.apache.org/maven2") {
-            package_url.scheme = "maven".to_string();
-        }
-        if url.starts_with("https://registry.npmjs.org") {
-            package_url.scheme = "npm".to_string();
-        }
-        if url.starts_with("https://www.nuget.org") {
-            package_url.scheme = "nuget".to_string();
-        }
-        if url.starts_with("https://bitbucket.org") {
-            package_url.scheme = "bitbucket".to_string();
-        }
-        if url.starts_with("https://hub.docker.com") {
-            package_url.scheme = "docker".to_string();
-        }
-        if url.starts_with("https://pypi.org") || url.starts_with("https://pypi.python.org") {
-            package_url.scheme = "pypi".to_string();
-        }
-        // if url.starts_with("https://github.com") {
-        //     package_url.scheme = "gem".to_string();
-        // }
-        // if url.starts_with("https://crates.io") {}
-        // https://crates.io/api/v1/crates/project-name/1.0.2/download
-        // if url.starts_with("https://bitbucket.org") {}
-        // if url.starts_with("https://registry.npmjs.org") {}
-        // if url.starts_with("https://pypi.python.org") {}
-        // if url.starts_with("https://github.com") {}
-        // TODO How can we detect go and swift packages? The url will just be another git URL
-        // TODO gitlab ??
-        // TODO openwrt ??
-
         // According to the PURL doc, for the generic scope:
         // > There is no default repository. A download_url and checksum may be provided in qualifiers
         // > or as separate attributes outside of a purl for proper identification and location.
@@ -974,9 +2568,16 @@ impl PackageNode {
             .query_params
             .insert("download_url".to_string(), url.to_string());
         // Format should be sha256:de4d501267da...
-        // package_url
-        //     .query_params
-        //     .insert("checksum".to_string(), url.to_string());
+        let sha256 = self
+            .main_derivation
+            .get_fetched_source()
+            .and_then(|s| s.hash)
+            .and_then(|h| h.get_sha256_hex());
+        if let Some(sha256) = sha256 {
+            package_url
+                .query_params
+                .insert("checksum".to_string(), format!("sha256:{}", sha256));
+        }
         return package_url;
     }
 
@@ -1049,6 +2650,21 @@ impl PackageNode {
         lines
     }
 
+    pub fn rewrite_urls(&mut self, url_rewriter: &crate::config::UrlRewriter) {
+        self.url = self.url.as_ref().map(|u| url_rewriter.rewrite(u));
+        self.git_urls = self.git_urls.iter().map(|u| url_rewriter.rewrite(u)).collect();
+        self.main_derivation.rewrite_urls(url_rewriter);
+        if let Some(package) = self.package.as_mut() {
+            package.meta.homepage = match &package.meta.homepage {
+                Some(Homepage::One(h)) => Some(Homepage::One(url_rewriter.rewrite(h))),
+                Some(Homepage::Many(h)) => Some(Homepage::Many(
+                    h.iter().map(|u| url_rewriter.rewrite(u)).collect(),
+                )),
+                None => None,
+            };
+        }
+    }
+
     pub fn get_version(&self) -> Option<String> {
         if let Some(v) = &self.version {
             return Some(v.clone());
@@ -1060,12 +2676,18 @@ impl PackageNode {
     }
 }
 
+/// Output of the stats format. Within a schema version, fields are only added, and the
+/// optional fields are omitted when they were not requested.
 #[derive(Debug)]
 #[derive(Default)]
 #[derive(Serialize)]
 #[derive(Deserialize)]
 #[derive(PartialEq)]
 pub struct PackageGraphStats {
+    /// Version of the schema of the stats, see STATS_SCHEMA_VERSION.
+    #[serde(default)]
+    pub schema_version: String,
+
     pub nodes_count: usize,
 
     /// Number of nodes that are reachable from the root nodes.
@@ -1084,10 +2706,245 @@ pub struct PackageGraphStats {
     /// Number of derivations which had an associated entry in the package meta dictionnary.
     pub package_meta_count: usize,
 
+    /// Number of packages reachable from the root nodes, by purl type.
     pub purl_scope_count: BTreeMap<String, usize>,
+
+    /// Number of packages by SPDX license identifier. The packages without a known
+    /// license are counted under NOASSERTION.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub license_count: Option<BTreeMap<String, usize>>,
+
+    /// Number of nodes excluded from the graph, by reason of exclusion.
+    pub excluded_nodes_count: BTreeMap<String, usize>,
+
+    /// Derivations for which no package metadata was found, by reason.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub missing_meta: Option<BTreeMap<String, Vec<String>>>,
+
+    /// Number of packages built locally, substituted from a binary cache, or
+    /// not present in the local store.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub build_provenance_count: Option<BTreeMap<String, usize>>,
+
+    /// Number of packages with each lifecycle signal, e.g. insecure or broken.
+    #[serde(default)]
+    pub lifecycle_signals_count: BTreeMap<String, usize>,
+
+    /// Versions of the compilers present in the build graph, indexed by compiler,
+    /// e.g. gcc or rustc.
+    #[serde(default)]
+    pub toolchains: BTreeMap<String, BTreeSet<String>>,
+
+    /// Packages present at several versions in the graph, indexed by name and by
+    /// version, with the dependency chains leading to each version.
+    #[serde(default)]
+    pub duplicated_packages: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+/// A file from the inputSrcs of a derivation, for example a builder script,
+/// a local patch or a setup hook.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(PartialEq)]
+pub struct InputSource {
+    pub path: String,
+    pub name: String,
+
+    /// SHA-256 digest of the file, if it is a regular file present in the local store.
+    pub sha256: Option<String>,
+
+    /// SHA-1 digest of the file, which is required for the SPDX files.
+    #[serde(default)]
+    pub sha1: Option<String>,
+}
+
+impl InputSource {
+    pub fn from_path(path: &str) -> InputSource {
+        let content = match std::fs::metadata(path) {
+            Ok(m) if m.is_file() => match std::fs::read(path) {
+                Ok(content) => Some(content),
+                Err(e) => {
+                    log::warn!("Could not read input source {}: {}", path, e);
+                    None
+                }
+            },
+            Ok(_) => {
+                log::debug!("Not hashing input source {} since it is not a file", path);
+                None
+            }
+            Err(_) => {
+                log::debug!("Input source {} is not in the local store", path);
+                None
+            }
+        };
+        InputSource {
+            path: path.to_string(),
+            name: get_store_path_name(path),
+            sha256: content.as_ref().map(|c| crate::utils::get_sha256_digest(c)),
+            sha1: content.as_ref().map(|c| crate::utils::get_sha1_digest(c)),
+        }
+    }
+}
+
+// Returns the name part of a store path, e.g. builder.sh for
+// /nix/store/9krlzvny65gdc8s7kpb6lkx8cd02c25b-builder.sh
+pub fn get_store_path_name(path: &str) -> String {
+    let base_name = path.strip_prefix("/nix/store/").unwrap_or(path);
+    match base_name.split_once('-') {
+        Some((_hash, name)) => name.to_string(),
+        None => base_name.to_string(),
+    }
+}
+
+// Returns the references of every path of the runtime closure of the paths,
+// indexed by store path.
+pub fn get_runtime_references(
+    paths: &[String],
+    backend: &dyn crate::backend::NixBackend,
+) -> Result<BTreeMap<String, Vec<String>>, anyhow::Error> {
+    Ok(backend
+        .get_path_infos(paths, true)?
+        .into_iter()
+        .map(|(path, path_info)| (path, path_info.references))
+        .collect())
+}
+
+// Returns the hash part of a store path, e.g. 9krlzvny65gdc8s7kpb6lkx8cd02c25b for
+// /nix/store/9krlzvny65gdc8s7kpb6lkx8cd02c25b-builder.sh
+pub fn get_store_path_hash(path: &str) -> String {
+    let base_name = path.strip_prefix("/nix/store/").unwrap_or(path);
+    match base_name.split_once('-') {
+        Some((hash, _name)) => hash.to_string(),
+        None => base_name.to_string(),
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+pub struct PathInfo {
+    pub path: Option<String>,
+
+    #[serde(rename = "closureSize")]
+    pub closure_size: Option<u64>,
+
+    pub deriver: Option<String>,
+
+    #[serde(rename = "narHash")]
+    pub nar_hash: Option<String>,
+
+    #[serde(rename = "narSize")]
+    pub nar_size: Option<u64>,
+
+    #[serde(default)]
+    pub references: Vec<String>,
+
+    /// Whether the path was built locally, rather than substituted.
+    #[serde(default)]
+    pub ultimate: bool,
+
+    #[serde(default)]
+    pub signatures: Vec<String>,
+}
+
+pub const BUILT_PROVENANCE: &str = "built";
+pub const SUBSTITUTED_PROVENANCE: &str = "substituted";
+// The path is neither built locally nor signed, e.g. copied with nix copy --no-check-sigs.
+pub const UNKNOWN_PROVENANCE: &str = "unknown";
+pub const NOT_IN_STORE_PROVENANCE: &str = "not-in-store";
+
+// The paths built locally are marked as ultimate by Nix, and the paths
+// substituted from a binary cache carry the signatures of the cache.
+pub fn get_build_provenance(ultimate: bool, signatures: &[String]) -> &'static str {
+    if ultimate {
+        BUILT_PROVENANCE
+    } else if !signatures.is_empty() {
+        SUBSTITUTED_PROVENANCE
+    } else {
+        UNKNOWN_PROVENANCE
+    }
+}
+
+pub fn get_closure_sizes(paths: &[String]) -> Result<Vec<(String, u64)>, anyhow::Error> {
+    Ok(get_output_path_infos(paths)?
+        .into_iter()
+        .filter_map(|(path, path_info)| Some((path, path_info.closure_size?)))
+        .collect())
+}
+
+// Gets the path info of the outputs, with the size of their closure.
+pub fn get_output_path_infos(paths: &[String]) -> Result<BTreeMap<String, PathInfo>, anyhow::Error> {
+    let output = Command::new("nix")
+        .arg("path-info")
+        .arg("--json")
+        .arg("--closure-size")
+        .args(paths)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not get the closure sizes: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let path_infos: PathInfos = serde_json::from_slice(&output.stdout)?;
+    Ok(path_infos.into_map())
+}
+
+// Gets the path info of the store paths, and of their closure if recursive is set.
+pub fn get_path_infos(paths: &[String], recursive: bool) -> Result<BTreeMap<String, PathInfo>, anyhow::Error> {
+    let mut command = Command::new("nix");
+    command.arg("path-info").arg("--json");
+    if recursive {
+        command.arg("-r");
+    }
+    let output = command.args(paths).output()?;
+    if !output.status.success() {
+        return Err(anyhow::format_err!(
+            "Could not get the path info of {}: {}",
+            paths.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let path_infos: PathInfos = serde_json::from_slice(&output.stdout)?;
+    Ok(path_infos.into_map())
+}
+
+// The output of nix path-info --json is a list in older versions of Nix,
+// and an object indexed by store path since Nix 2.19.
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum PathInfos {
+    List(Vec<PathInfo>),
+    Map(HashMap<String, PathInfo>),
+}
+
+impl PathInfos {
+    pub fn get_closure_sizes(self) -> Vec<(String, u64)> {
+        self.into_map()
+            .into_iter()
+            .filter_map(|(path, path_info)| Some((path, path_info.closure_size?)))
+            .collect()
+    }
+
+    // Indexes the path infos by store path.
+    pub fn into_map(self) -> BTreeMap<String, PathInfo> {
+        match self {
+            PathInfos::List(l) => l.into_iter().filter_map(|p| Some((p.path.clone()?, p))).collect(),
+            PathInfos::Map(m) => m.into_iter().collect(),
+        }
+    }
 }
 
 #[derive(Debug)]
+#[derive(Clone)]
 #[derive(Default)]
 #[derive(Serialize)]
 #[derive(Deserialize)]
@@ -1099,6 +2956,35 @@ pub struct PackageGraph {
 
     pub root_nodes: BTreeSet<String>,
     pub group_membership: BTreeMap<String, String>,
+
+    /// The resolved inputs of the flake that was evaluated, if any.
+    #[serde(default)]
+    pub flake_inputs: Vec<crate::flake::FlakeInput>,
+
+    /// Nodes that were removed from the graph, with the reason of the exclusion.
+    #[serde(default)]
+    pub excluded_nodes: BTreeMap<String, String>,
+
+    /// The inputSrcs of the packages, indexed by store path. Only populated
+    /// when the input sources are requested.
+    #[serde(default)]
+    pub input_sources: BTreeMap<String, InputSource>,
+
+    /// Every store path of the closure, with its NAR hash when the path is present
+    /// in the local store. Only populated when the store paths are requested.
+    #[serde(default)]
+    pub store_paths: BTreeMap<String, Option<String>>,
+
+    /// The OmniBOR artifact ids of the sources and the patches of the packages, indexed
+    /// by derivation path. Only populated when the OmniBOR ids are requested, and for
+    /// the artifacts that are files present in the local store.
+    #[serde(default)]
+    pub omnibor_ids: BTreeMap<String, Vec<String>>,
+
+    /// Whether the packages were built locally or substituted, indexed by derivation
+    /// path. Only populated when the build provenance is requested.
+    #[serde(default)]
+    pub build_provenance: BTreeMap<String, String>,
 }
 
 impl PackageGraph {
@@ -1133,6 +3019,14 @@ impl PackageGraph {
     }
 
     pub fn transform(&mut self, packages: &Packages) -> Result<(), anyhow::Error> {
+        self.transform_with_resolvers(packages, &crate::identify::Resolvers::default())
+    }
+
+    pub fn transform_with_resolvers(
+        &mut self,
+        packages: &Packages,
+        resolvers: &crate::identify::Resolvers,
+    ) -> Result<(), anyhow::Error> {
         self.populate_source_derivation()?;
         self.populate_source_derivation_from_undeclared_sources()?;
         let mut packages_with_a_source = 0;
@@ -1147,8 +3041,9 @@ impl PackageGraph {
         );
 
         self.populate_url()?;
-        self.populate_version()?;
-        self.populate_name()?;
+        self.populate_version(resolvers)?;
+        self.populate_name(resolvers)?;
+        self.populate_purl_type(resolvers)?;
         self.populate_git_urls()?;
         let mut packages_without_a_url_or_group = 0;
         for node in self.nodes.values() {
@@ -1188,25 +3083,40 @@ impl PackageGraph {
     }
 
     pub fn populate_packages(&mut self, packages: &Packages) -> Result<(), anyhow::Error> {
+        let package_index = PackageIndex::new(packages);
         let package_nodes = self.nodes.values().cloned().collect::<Vec<PackageNode>>();
         for package_node in package_nodes {
-            if let Some(p) = packages.get(&package_node.id) {
-                let package = p.clone();
-                let package_node = self.nodes.get_mut(&package_node.id).unwrap();
-                package_node.package = Some(package);
-                continue;
+            let mut package_match = match packages.get(&package_node.id) {
+                Some(p) => PackageMatch::Found(p),
+                None => package_index.find(&package_node),
+            };
+
+            if let PackageMatch::NotFound(_) = package_match {
+                if let Some(source_derivation_path) = &package_node.source_derivation {
+                    if let Some(p) = packages.get(source_derivation_path) {
+                        package_match = PackageMatch::Found(p);
+                    }
+                }
             }
 
-            let source_derivation_path = match package_node.source_derivation {
-                Some(p) => p,
-                None => continue,
+            let (package, ambiguous_packages, missing_meta_reason) = match package_match {
+                PackageMatch::Found(p) => (Some(p.clone()), vec![], None),
+                PackageMatch::Ambiguous(attribute_paths) => {
+                    log::debug!(
+                        "Found multiple package metadata entries for {}: {}",
+                        &package_node.id,
+                        attribute_paths.join(", ")
+                    );
+                    (None, attribute_paths, Some(AMBIGUOUS_META_REASON))
+                }
+                PackageMatch::NotFound(reason) => (None, vec![], Some(reason)),
             };
-
-            if let Some(p) = packages.get(&source_derivation_path) {
-                let package = p.clone();
-                let package_node = self.nodes.get_mut(&package_node.id).unwrap();
-                package_node.package = Some(package);
-                continue;
+            for nodes in [&mut self.nodes, &mut self.nodes_next] {
+                if let Some(node) = nodes.get_mut(&package_node.id) {
+                    node.package = package.clone();
+                    node.ambiguous_packages = ambiguous_packages.clone();
+                    node.missing_meta_reason = missing_meta_reason.map(|r| r.to_string());
+                }
             }
         }
         Ok(())
@@ -1248,7 +3158,7 @@ impl PackageGraph {
             // derivation, so the package group can span more than 2 levels. I'm not sure how
             // to handle that, or if we need to group all the derivations together. For the moment,
             // I just ignore the derivations except the last 2 levels.
-            if source_derivation.main_derivation.get_url().is_none() {
+            if source_derivation.main_derivation.get_fetched_source().is_none() {
                 log::warn!(
                     "Derivation {} has no URL, so it will not be considered a source derivation.",
                     source_derivation_path
@@ -1299,7 +3209,7 @@ impl PackageGraph {
             // derivation, so the package group can span more than 2 levels. I'm not sure how
             // to handle that, or if we need to group all the derivations together. For the moment,
             // I just ignore the derivations except the last 2 levels.
-            if source_derivation.main_derivation.get_url().is_none() {
+            if source_derivation.main_derivation.get_fetched_source().is_none() {
                 log::warn!(
                     "Derivation {} has no URL, so it will not be considered a source derivation.",
                     source_derivation_path
@@ -1342,10 +3252,10 @@ impl PackageGraph {
         Ok(())
     }
 
-    pub fn populate_version(&mut self) -> Result<(), anyhow::Error> {
+    pub fn populate_version(&mut self, resolvers: &crate::identify::Resolvers) -> Result<(), anyhow::Error> {
         let packages = self.nodes.values().cloned().collect::<Vec<PackageNode>>();
         for package in packages {
-            if let Some(version) = package.main_derivation.get_version() {
+            if let Some(version) = resolvers.resolve_version(&package.main_derivation) {
                 let package_node = self.nodes.get_mut(&package.id).unwrap();
                 package_node.version = Some(version);
                 continue;
@@ -1358,7 +3268,7 @@ impl PackageGraph {
 
             let source_package = self.nodes.get(&source_derivation_path).unwrap();
 
-            if let Some(version) = source_package.main_derivation.get_version() {
+            if let Some(version) = resolvers.resolve_version(&source_package.main_derivation) {
                 let package_node = self.nodes.get_mut(&package.id).unwrap();
                 package_node.version = Some(version);
                 continue;
@@ -1367,6 +3277,24 @@ impl PackageGraph {
         Ok(())
     }
 
+    pub fn populate_purl_type(&mut self, resolvers: &crate::identify::Resolvers) -> Result<(), anyhow::Error> {
+        for package_node in self.nodes.values_mut() {
+            package_node.purl_type = resolvers.resolve_purl_type(&package_node.main_derivation);
+        }
+        Ok(())
+    }
+
+    // Adds the arch and os qualifiers to the purls, so that the packages built for
+    // different systems get different purls.
+    pub fn add_platform_qualifiers(&mut self) {
+        for package_node in self.nodes.values_mut().chain(self.nodes_next.values_mut()) {
+            if let Some((arch, os)) = package_node.main_derivation.get_platform() {
+                package_node.purl_qualifiers.insert("arch".to_string(), arch);
+                package_node.purl_qualifiers.insert("os".to_string(), os);
+            }
+        }
+    }
+
     pub fn populate_git_urls(&mut self) -> Result<(), anyhow::Error> {
         let packages = self.nodes.values().cloned().collect::<Vec<PackageNode>>();
         for package in packages {
@@ -1398,1007 +3326,3572 @@ impl PackageGraph {
         Ok(())
     }
 
-    pub fn populate_name(&mut self) -> Result<(), anyhow::Error> {
+    pub fn populate_name(&mut self, resolvers: &crate::identify::Resolvers) -> Result<(), anyhow::Error> {
         let packages = self.nodes.values().cloned().collect::<Vec<PackageNode>>();
         for package in packages {
-            if let Some(name) = package.main_derivation.get_name() {
+            let source_package = package.source_derivation.as_ref().and_then(|p| self.nodes.get(p));
+            let name = resolvers
+                .resolve_name(&package.main_derivation)
+                .or_else(|| source_package.and_then(|p| resolvers.resolve_name(&p.main_derivation)))
+                .or_else(|| resolvers.resolve_fallback_name(&package.main_derivation));
+            if let Some(name) = name {
                 let package_node = self.nodes.get_mut(&package.id).unwrap();
                 package_node.name = Some(name);
-                continue;
             }
+        }
+        Ok(())
+    }
 
-            let source_derivation_path = match package.source_derivation {
-                Some(p) => p,
-                None => continue,
-            };
-
-            let source_package = self.nodes.get(&source_derivation_path).unwrap();
+    // Sets the concluded license of the packages from user-provided values, indexed
+    // by package name or by purl.
+    pub fn apply_concluded_licenses(&mut self, concluded_licenses: &BTreeMap<String, String>) {
+        if concluded_licenses.is_empty() {
+            return;
+        }
+        for nodes in [&mut self.nodes, &mut self.nodes_next] {
+            for package_node in nodes.values_mut() {
+                let purl = package_node.get_purl().to_string();
+                let concluded_license = match &package_node.name {
+                    Some(name) if concluded_licenses.contains_key(name) => concluded_licenses.get(name),
+                    _ => concluded_licenses.get(&purl),
+                };
+                if let Some(license) = concluded_license {
+                    package_node.concluded_license = Some(license.to_string());
+                }
+            }
+        }
+    }
 
-            if let Some(name) = source_package.main_derivation.get_name() {
-                let package_node = self.nodes.get_mut(&package.id).unwrap();
-                package_node.name = Some(name);
-                continue;
+    pub fn apply_owners(&mut self, ownership_rules: &crate::owners::OwnershipRules) {
+        if ownership_rules.is_empty() {
+            return;
+        }
+        for nodes in [&mut self.nodes, &mut self.nodes_next] {
+            for package_node in nodes.values_mut() {
+                let purl = package_node.get_purl().to_string();
+                package_node.owners = ownership_rules.get_owners(package_node.name.as_deref(), &purl);
             }
         }
+    }
+
+    // Removes the nodes whose outputs are not in the runtime closure of the outputs
+    // of the root nodes, which must be present in the local store. The dependencies
+    // of the nodes are replaced by the references of their outputs.
+    pub fn limit_to_runtime_closure(
+        &mut self,
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<(), anyhow::Error> {
+        let root_out_paths: Vec<String> = self
+            .root_nodes
+            .iter()
+            .filter_map(|p| self.nodes.get(p))
+            .flat_map(|n| n.main_derivation.outputs.values())
+            .map(|o| o.path.to_string())
+            .filter(|p| backend.is_in_store(p))
+            .collect();
+        if root_out_paths.is_empty() {
+            return Err(anyhow::format_err!(
+                "The outputs of the root derivations are not in the local store"
+            ));
+        }
+
+        let references = get_runtime_references(&root_out_paths, backend)?;
+        self.limit_to_references(&references);
         Ok(())
     }
 
-    pub fn populate_nodes(&mut self) -> Result<(), anyhow::Error> {
-        let packages = self.nodes.values().cloned().collect::<Vec<PackageNode>>();
-        for package in packages {
-            let group_id = match package.group_id.clone() {
-                Some(g) => g,
+    // Keeps the nodes with an output in the references, indexed by store path,
+    // and links them using the references of their outputs.
+    pub fn limit_to_references(&mut self, references: &BTreeMap<String, Vec<String>>) {
+        let mut out_path_derivations: HashMap<String, String> = HashMap::default();
+        for (derivation_path, package_node) in &self.nodes {
+            for output in package_node.main_derivation.outputs.values() {
+                out_path_derivations.insert(output.path.to_string(), derivation_path.to_string());
+            }
+        }
+        let mut runtime_nodes: BTreeMap<String, BTreeSet<String>> = BTreeMap::default();
+        for (path, path_references) in references {
+            let derivation_path = match out_path_derivations.get(path) {
+                Some(p) => p,
                 None => continue,
             };
-
-            if group_id != package.id {
-                continue;
+            let children = runtime_nodes.entry(derivation_path.to_string()).or_default();
+            for reference in path_references {
+                if let Some(child_derivation_path) = out_path_derivations.get(reference) {
+                    if child_derivation_path != derivation_path {
+                        children.insert(child_derivation_path.to_string());
+                    }
+                }
+            }
+        }
+        let runtime_groups: HashSet<String> = runtime_nodes
+            .keys()
+            .map(|p| self.group_membership.get(p).unwrap_or(p).to_string())
+            .collect();
+
+        let excluded_nodes: Vec<String> = self
+            .nodes
+            .keys()
+            .filter(|p| !runtime_nodes.contains_key(*p))
+            .cloned()
+            .collect();
+        log::info!(
+            "Excluding {} nodes that are not in the runtime closure",
+            excluded_nodes.len()
+        );
+        for derivation_path in excluded_nodes {
+            self.nodes.remove(&derivation_path);
+            self.excluded_nodes
+                .insert(derivation_path, BUILD_ONLY_EXCLUSION_REASON.to_string());
+        }
+        self.nodes_next
+            .retain(|p, _| runtime_nodes.contains_key(p) || runtime_groups.contains(p));
+        self.root_nodes.retain(|p| runtime_nodes.contains_key(p));
+
+        for nodes in [&mut self.nodes, &mut self.nodes_next] {
+            for (derivation_path, package_node) in nodes.iter_mut() {
+                package_node.children = runtime_nodes.get(derivation_path).cloned().unwrap_or_default();
+                package_node.build_inputs.clear();
+                // The patches are only needed to build the packages.
+                package_node.patches.clear();
             }
-
-            self.nodes_next.insert(package.id.clone(), package.clone());
         }
-        Ok(())
     }
 
-    pub fn get_root_node(&self) -> Option<String> {
-        if self.root_nodes.len() == 1 {
-            self.root_nodes.last().cloned()
-        } else {
-            None
+    // Removes the packages that are not allowed by the license filter. Unlike
+    // remove_nodes, the dependencies of the removed packages are kept, and are
+    // linked to the closest packages that were kept.
+    pub fn filter_by_licenses(&mut self, license_filter: &crate::license::LicenseFilter) {
+        if license_filter.is_empty() {
+            return;
         }
+        let excluded_nodes: BTreeSet<String> = self
+            .nodes_next
+            .iter()
+            .filter(|(_, n)| {
+                let license_expression = crate::license::get_package_license_expression(n);
+                !license_filter.is_allowed(license_expression.as_deref())
+            })
+            .map(|(p, _)| p.to_string())
+            .collect();
+        log::info!("Excluding {} packages filtered by license", excluded_nodes.len());
+        self.remove_nodes_keeping_dependencies(excluded_nodes, LICENSE_EXCLUSION_REASON);
     }
 
-    pub fn get_stats(&self, options: &DumpOptions) -> PackageGraphStats {
-        let mut package_graph_stats = PackageGraphStats::default();
-        package_graph_stats.nodes_count = self.nodes.len();
-        package_graph_stats.root_nodes_count = self.root_nodes.len();
-        for root_node in &self.root_nodes {
-            let package_node = self.nodes.get(root_node).unwrap();
-            package_graph_stats.reachable_nodes_count.insert(
-                root_node.clone(),
-                package_node.get_reachable_nodes_count(&self.nodes, &mut HashSet::default()),
-            );
-            let longest_path =
-                package_node.get_longest_path(&root_node, &self.nodes, &mut HashMap::default(), options);
-            package_graph_stats
-                .longest_path_length
-                .insert(root_node.clone(), longest_path.len());
-            package_graph_stats.longest_path = longest_path;
-            package_graph_stats.purl_scope_count = self.get_purl_scope_stats();
-            package_graph_stats.patches_count = self.get_patches_count();
+    // Removes the packages matching the ignore rules. As with the license filter, the
+    // dependencies of the ignored packages are kept.
+    pub fn apply_ignore_rules(&mut self, ignore_rules: &crate::ignore::IgnoreRules) {
+        if ignore_rules.is_empty() {
+            return;
         }
-        package_graph_stats
+        let excluded_nodes: BTreeSet<String> = self
+            .nodes_next
+            .iter()
+            .filter(|(_, n)| ignore_rules.is_ignored(n.name.as_deref(), &n.get_purl().to_string()))
+            .map(|(p, _)| p.to_string())
+            .collect();
+        log::info!("Excluding {} ignored packages", excluded_nodes.len());
+        self.remove_nodes_keeping_dependencies(excluded_nodes, IGNORE_EXCLUSION_REASON);
     }
 
-    pub fn get_purl_scope_stats(&self) -> BTreeMap<String, usize> {
-        let mut visited_children: HashSet<String> = HashSet::default();
+    // Removes the packages with a name, a purl or a store path matching one of the
+    // patterns. As with the license filter, the dependencies of the excluded packages are kept.
+    pub fn exclude_by_patterns(&mut self, patterns: &[regex::Regex]) {
+        if patterns.is_empty() {
+            return;
+        }
+        let excluded_nodes: BTreeSet<String> = self
+            .nodes_next
+            .iter()
+            .filter(|(_, n)| n.matches_patterns(patterns))
+            .map(|(p, _)| p.to_string())
+            .collect();
+        log::info!(
+            "Excluding {} packages matching the exclude patterns",
+            excluded_nodes.len()
+        );
+        self.remove_nodes_keeping_dependencies(excluded_nodes, PATTERN_EXCLUSION_REASON);
+    }
 
-        let mut response: BTreeMap<String, usize> = BTreeMap::default();
-        let mut node_queue = self.root_nodes.clone();
+    // Keeps only the packages with a name, a purl or a store path matching one of the
+    // patterns, and with one of the purl types. The dependencies between the kept packages
+    // are preserved through the removed packages.
+    pub fn keep_only(&mut self, patterns: &[regex::Regex], purl_types: &[String]) {
+        if patterns.is_empty() && purl_types.is_empty() {
+            return;
+        }
+        let excluded_nodes: BTreeSet<String> = self
+            .nodes_next
+            .iter()
+            .filter(|(_, n)| {
+                let matches_pattern = patterns.is_empty() || n.matches_patterns(patterns);
+                let matches_purl_type = purl_types.is_empty() || purl_types.contains(&n.get_purl().scheme);
+                !(matches_pattern && matches_purl_type)
+            })
+            .map(|(p, _)| p.to_string())
+            .collect();
+        log::info!(
+            "Excluding {} packages not matching the only filters",
+            excluded_nodes.len()
+        );
+        self.remove_nodes_keeping_dependencies(excluded_nodes, ONLY_EXCLUSION_REASON);
+    }
 
-        while !node_queue.is_empty() {
-            let current_node_path = node_queue.pop_first().unwrap();
+    // Removes the packages from the graph, and links their parents to the closest
+    // dependencies that were kept.
+    fn remove_nodes_keeping_dependencies(&mut self, excluded_nodes: BTreeSet<String>, reason: &str) {
+        if excluded_nodes.is_empty() {
+            return;
+        }
 
-            if visited_children.contains(&current_node_path) {
+        let mut linked_nodes: BTreeMap<String, (BTreeSet<String>, BTreeSet<String>)> = BTreeMap::default();
+        for (derivation_path, package_node) in &self.nodes_next {
+            if excluded_nodes.contains(derivation_path) {
                 continue;
             }
+            linked_nodes.insert(
+                derivation_path.to_string(),
+                (
+                    self.get_closest_kept_nodes(&package_node.children, &excluded_nodes),
+                    self.get_closest_kept_nodes(&package_node.build_inputs, &excluded_nodes),
+                ),
+            );
+        }
+        if self.root_nodes.iter().any(|p| excluded_nodes.contains(p)) {
+            self.root_nodes = self.get_closest_kept_nodes(&self.root_nodes, &excluded_nodes);
+        }
+        for (derivation_path, (children, build_inputs)) in linked_nodes {
+            if let Some(package_node) = self.nodes_next.get_mut(&derivation_path) {
+                package_node.children = children;
+                package_node.build_inputs = build_inputs;
+            }
+        }
+        for derivation_path in excluded_nodes {
+            self.nodes_next.remove(&derivation_path);
+            self.excluded_nodes.insert(derivation_path, reason.to_string());
+        }
+    }
 
-            let current_node = self.nodes.get(&current_node_path).unwrap();
-            let purl = current_node.get_purl();
+    // Keeps the root packages and their direct dependencies. Unlike limit_depth, this is
+    // done after the packages are identified, so the sources and patches of the direct
+    // dependencies were still used to get their purls and licenses.
+    pub fn limit_to_direct_dependencies(&mut self, include_build_inputs: bool) {
+        let mut kept_nodes: BTreeSet<String> = self.root_nodes.clone();
+        for root_node_path in &self.root_nodes {
+            let root_node = match self.nodes_next.get(root_node_path) {
+                Some(n) => n,
+                None => continue,
+            };
+            kept_nodes.extend(root_node.children.iter().cloned());
+            if include_build_inputs {
+                kept_nodes.extend(root_node.build_inputs.iter().cloned());
+            }
+        }
 
-            if response.contains_key(&purl.scheme) {
-                let count = response.get_mut(&purl.scheme).unwrap();
-                *count += 1;
-            } else {
-                response.insert(purl.scheme.clone(), 1);
+        let excluded_nodes: Vec<String> = self
+            .nodes_next
+            .keys()
+            .filter(|p| !kept_nodes.contains(*p))
+            .cloned()
+            .collect();
+        log::info!("Excluding {} transitive dependencies", excluded_nodes.len());
+        for derivation_path in excluded_nodes {
+            self.nodes_next.remove(&derivation_path);
+            self.excluded_nodes
+                .insert(derivation_path, TRANSITIVE_EXCLUSION_REASON.to_string());
+        }
+        for (derivation_path, package_node) in self.nodes_next.iter_mut() {
+            if self.root_nodes.contains(derivation_path) {
+                continue;
             }
+            package_node.children.clear();
+            package_node.build_inputs.clear();
+        }
+    }
 
-            for current_node_child in &current_node.children {
-                node_queue.insert(current_node_child.clone());
+    // Replaces the excluded nodes by their closest dependencies that are not excluded.
+    fn get_closest_kept_nodes(
+        &self,
+        derivation_paths: &BTreeSet<String>,
+        excluded_nodes: &BTreeSet<String>,
+    ) -> BTreeSet<String> {
+        let mut response: BTreeSet<String> = BTreeSet::default();
+        let mut visited_nodes: HashSet<String> = HashSet::default();
+        let mut node_queue = derivation_paths.clone();
+        while let Some(current_node_path) = node_queue.pop_first() {
+            if !visited_nodes.insert(current_node_path.to_string()) {
+                continue;
             }
-            for current_node_child in &current_node.build_inputs {
-                node_queue.insert(current_node_child.clone());
+            if !excluded_nodes.contains(&current_node_path) {
+                response.insert(current_node_path);
+                continue;
             }
-            for current_node_child in &current_node.patches {
-                node_queue.insert(current_node_child.clone());
+            if let Some(current_node) = self.nodes_next.get(&current_node_path) {
+                node_queue.extend(
+                    current_node
+                        .children
+                        .iter()
+                        .chain(current_node.build_inputs.iter())
+                        .cloned(),
+                );
             }
-            visited_children.insert(current_node_path.clone());
         }
-
         response
     }
 
-    pub fn get_patches_count(&self) -> usize {
-        let mut response = 0;
-        let mut visited_children: HashSet<String> = HashSet::default();
-
-        let mut node_queue = self.root_nodes.clone();
-
-        while !node_queue.is_empty() {
-            let current_node_path = node_queue.pop_first().unwrap();
-
-            if visited_children.contains(&current_node_path) {
+    // Removes the check hooks, documentation and test fixture derivations from the graph.
+    pub fn prune_test_and_doc_nodes(&mut self) {
+        let runtime_dependencies: BTreeSet<&String> =
+            self.nodes.values().flat_map(|n| n.children.iter()).collect();
+        let mut excluded_nodes: BTreeMap<String, String> = BTreeMap::default();
+        for (derivation_path, package_node) in &self.nodes {
+            if self.root_nodes.contains(derivation_path) {
                 continue;
             }
+            let is_runtime_dependency = runtime_dependencies.contains(derivation_path);
+            if let Some(reason) = package_node.get_test_or_doc_exclusion_reason(is_runtime_dependency) {
+                log::info!("Excluding {} ({})", derivation_path, reason);
+                excluded_nodes.insert(derivation_path.to_string(), reason.to_string());
+            }
+        }
+        log::info!("Excluding {} test and documentation nodes", excluded_nodes.len());
+        self.remove_nodes(excluded_nodes);
+    }
 
-            let current_node = self.nodes.get(&current_node_path).unwrap();
-            response += current_node.patches.len();
+    // Returns the derivations that were missing when building the graph.
+    pub fn get_incomplete_nodes(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter(|(_, n)| n.incomplete)
+            .map(|(p, _)| p.to_string())
+            .collect()
+    }
 
-            // FIXME we should also go through the patches?
-            for current_node_child in &current_node.children {
-                node_queue.insert(current_node_child.clone());
+    // Removes the nodes that are not built for the given system. The derivations
+    // using the builtin fetchers are always kept, as well as the substituted paths
+    // and the missing derivations, for which the system is not known.
+    pub fn limit_to_system(&mut self, system: &str) {
+        let excluded_nodes: BTreeMap<String, String> = self
+            .nodes
+            .iter()
+            .filter(|(_, n)| {
+                !n.incomplete
+                    && !n.substituted
+                    && n.main_derivation.system != system
+                    && n.main_derivation.system != BUILTIN_SYSTEM
+            })
+            .map(|(p, _)| (p.to_string(), SYSTEM_EXCLUSION_REASON.to_string()))
+            .collect();
+        log::info!(
+            "Excluding {} nodes not built for system {}",
+            excluded_nodes.len(),
+            system
+        );
+        self.remove_nodes(excluded_nodes);
+    }
+
+    // Removes the nodes that are more than max_depth edges away from the root nodes.
+    // A depth of 0 only keeps the root nodes.
+    pub fn limit_depth(&mut self, max_depth: usize) {
+        let mut node_depths: HashMap<String, usize> = HashMap::default();
+        let mut node_queue: VecDeque<(String, usize)> =
+            self.root_nodes.iter().map(|p| (p.to_string(), 0)).collect();
+        while let Some((current_node_path, depth)) = node_queue.pop_front() {
+            if node_depths.contains_key(&current_node_path) {
+                continue;
             }
-            for current_node_child in &current_node.build_inputs {
-                node_queue.insert(current_node_child.clone());
+            node_depths.insert(current_node_path.to_string(), depth);
+            if depth >= max_depth {
+                continue;
+            }
+            let current_node = match self.nodes.get(&current_node_path) {
+                Some(n) => n,
+                None => continue,
+            };
+            for child in current_node
+                .children
+                .iter()
+                .chain(current_node.build_inputs.iter())
+                .chain(current_node.patches.iter())
+                .chain(current_node.source_derivation.iter())
+            {
+                if !node_depths.contains_key(child) {
+                    node_queue.push_back((child.to_string(), depth + 1));
+                }
             }
-            visited_children.insert(current_node_path.clone());
         }
 
-        response
+        let excluded_nodes: BTreeMap<String, String> = self
+            .nodes
+            .keys()
+            .filter(|p| !node_depths.contains_key(*p))
+            .map(|p| (p.to_string(), EVAL_DEPTH_EXCLUSION_REASON.to_string()))
+            .collect();
+        log::info!(
+            "Excluding {} nodes deeper than {} levels",
+            excluded_nodes.len(),
+            max_depth
+        );
+        self.remove_nodes(excluded_nodes);
     }
 
-    pub fn pretty_print(&self, depth: usize, display_options: &DisplayOptions) -> String {
-        let mut lines: Vec<PrettyPrintLine> = vec![];
-        let mut response = "".to_string();
+    // Removes the nodes from the graph, along with the edges pointing to them and
+    // the nodes that are not reachable from the root nodes anymore. The removed nodes
+    // are recorded in the excluded nodes of the graph.
+    pub fn remove_nodes(&mut self, excluded_nodes: BTreeMap<String, String>) {
+        if excluded_nodes.is_empty() {
+            return;
+        }
+        for derivation_path in excluded_nodes.keys() {
+            self.nodes.remove(derivation_path);
+            self.nodes_next.remove(derivation_path);
+            self.root_nodes.remove(derivation_path);
+        }
+        self.excluded_nodes.extend(excluded_nodes);
 
-        let mut visited_children: HashSet<String> = HashSet::default();
-        for (derivation_path, package_node) in &self.nodes {
-            if visited_children.contains(derivation_path) {
+        let mut reachable_nodes: HashSet<String> = HashSet::default();
+        let mut node_queue = self.root_nodes.clone();
+        while let Some(current_node_path) = node_queue.pop_first() {
+            if reachable_nodes.contains(&current_node_path) {
                 continue;
             }
-            for child_derivation_path in &package_node.children {
-                let child = self.nodes.get(child_derivation_path).unwrap();
-                add_visited_children(child, &self, &mut visited_children);
+            let current_node = match self.nodes.get(&current_node_path) {
+                Some(n) => n,
+                None => continue,
+            };
+            for child in current_node
+                .children
+                .iter()
+                .chain(current_node.build_inputs.iter())
+                .chain(current_node.patches.iter())
+                .chain(current_node.source_derivation.iter())
+            {
+                if self.nodes.contains_key(child) {
+                    node_queue.insert(child.to_string());
+                }
             }
+            reachable_nodes.insert(current_node_path);
         }
 
-        for (_derivation_path, package_node) in &self.nodes {
-            if !display_options.print_stdenv && is_stdenv(&package_node.main_derivation.get_name().unwrap()) {
-                continue;
+        let unreachable_nodes = self
+            .nodes
+            .keys()
+            .filter(|p| !reachable_nodes.contains(*p))
+            .cloned()
+            .collect::<Vec<String>>();
+        for derivation_path in unreachable_nodes {
+            self.nodes.remove(&derivation_path);
+            self.nodes_next.remove(&derivation_path);
+            self.excluded_nodes
+                .insert(derivation_path, UNREACHABLE_EXCLUSION_REASON.to_string());
+        }
+
+        for nodes in [&mut self.nodes, &mut self.nodes_next] {
+            for package_node in nodes.values_mut() {
+                package_node.children.retain(|p| reachable_nodes.contains(p));
+                package_node.build_inputs.retain(|p| reachable_nodes.contains(p));
+                package_node.patches.retain(|p| reachable_nodes.contains(p));
             }
-            for line in package_node.pretty_print(self, depth, display_options) {
-                lines.push(line);
+        }
+    }
+
+    // Populates the NAR hash, the NAR size and the closure size of the nodes for
+    // which the main output is present in the local store.
+    pub fn populate_output_path_infos(
+        &mut self,
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<(), anyhow::Error> {
+        let mut node_out_paths: HashMap<String, String> = HashMap::default();
+        for (derivation_path, package_node) in &self.nodes_next {
+            let out_path = match package_node.main_derivation.outputs.get("out") {
+                Some(o) => &o.path,
+                None => continue,
+            };
+            if backend.is_in_store(out_path) {
+                node_out_paths.insert(out_path.to_string(), derivation_path.to_string());
             }
         }
+        if node_out_paths.is_empty() {
+            return Ok(());
+        }
 
-        for line in lines {
-            response += &line.to_string();
-            response += "\n";
+        let out_paths: Vec<String> = node_out_paths.keys().cloned().collect();
+        let path_infos = backend.get_output_path_infos(&out_paths)?;
+        for (out_path, path_info) in path_infos {
+            let derivation_path = match node_out_paths.get(&out_path) {
+                Some(p) => p,
+                None => continue,
+            };
+            for nodes in [&mut self.nodes, &mut self.nodes_next] {
+                if let Some(package_node) = nodes.get_mut(derivation_path) {
+                    package_node.closure_size = path_info.closure_size;
+                    package_node.nar_size = path_info.nar_size;
+                    if package_node.main_derivation.nar_hash.is_none() {
+                        package_node.main_derivation.nar_hash = path_info.nar_hash.clone();
+                    }
+                }
+            }
         }
-        response
+        Ok(())
     }
-}
 
-fn add_visited_children(
-    package_node: &PackageNode,
-    package_graph: &PackageGraph,
-    visited_children: &mut HashSet<String>,
-) {
-    for child_derivation_path in &package_node.children {
-        if visited_children.contains(child_derivation_path) {
-            continue;
-        }
-        visited_children.insert(child_derivation_path.to_string());
-        let child_package = match package_graph.nodes.get(child_derivation_path) {
-            Some(p) => p,
-            None => {
-                log::warn!(
-                    "Could not get package in package graph for {}",
-                    &child_derivation_path
-                );
-                continue;
+    // Records whether the main output of the packages was built locally or
+    // substituted from a binary cache.
+    pub fn populate_build_provenance(
+        &mut self,
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<(), anyhow::Error> {
+        let mut node_out_paths: HashMap<String, String> = HashMap::default();
+        for (derivation_path, package_node) in &self.nodes_next {
+            let outputs = &package_node.main_derivation.outputs;
+            let out_path = match outputs.get("out").or(outputs.values().next()) {
+                Some(o) => &o.path,
+                None => continue,
+            };
+            if backend.is_in_store(out_path) {
+                node_out_paths.insert(out_path.to_string(), derivation_path.to_string());
+            } else {
+                self.build_provenance
+                    .insert(derivation_path.to_string(), NOT_IN_STORE_PROVENANCE.to_string());
             }
-        };
+        }
 
-        add_visited_children(&child_package, &package_graph, visited_children);
+        let out_paths: Vec<String> = node_out_paths.keys().cloned().collect();
+        let mut provenances: Vec<(String, &str)> = vec![];
+        for (path, path_info) in backend.get_path_infos(&out_paths, false)? {
+            provenances.push((
+                path,
+                get_build_provenance(path_info.ultimate, &path_info.signatures),
+            ));
+        }
+        for (out_path, provenance) in provenances {
+            if let Some(derivation_path) = node_out_paths.get(&out_path) {
+                self.build_provenance
+                    .insert(derivation_path.to_string(), provenance.to_string());
+            }
+        }
+        Ok(())
     }
-}
 
-// Small struct to make it easier to pretty-print the
-// internal representation for the package graph.
-#[derive(Debug)]
-pub struct PrettyPrintLine {
-    pub indent_level: usize,
-    pub line: String,
-}
-impl PrettyPrintLine {
-    pub fn new<S: AsRef<str>>(line: S, indent_level: usize) -> PrettyPrintLine {
-        PrettyPrintLine {
-            line: line.as_ref().to_string(),
-            indent_level,
+    // Records the output paths and the input sources of every node of the graph,
+    // with their NAR hash when they are present in the local store.
+    pub fn populate_store_paths(
+        &mut self,
+        backend: &dyn crate::backend::NixBackend,
+    ) -> Result<(), anyhow::Error> {
+        for package_node in self.nodes.values() {
+            for output in package_node.main_derivation.outputs.values() {
+                self.store_paths.insert(output.path.to_string(), None);
+            }
+            for input_source_path in &package_node.main_derivation.inputs_sources {
+                self.store_paths.insert(input_source_path.to_string(), None);
+            }
         }
-    }
 
-    pub fn to_string(&self) -> String {
-        "  ".repeat(self.indent_level) + &self.line
+        let local_paths: Vec<String> = self
+            .store_paths
+            .keys()
+            .filter(|p| backend.is_in_store(p))
+            .cloned()
+            .collect();
+        if local_paths.is_empty() {
+            return Ok(());
+        }
+        for (path, path_info) in backend.get_path_infos(&local_paths, false)? {
+            self.store_paths.insert(path, path_info.nar_hash);
+        }
+        Ok(())
     }
-}
-
-pub fn get_package_graph(derivations: &Derivations) -> PackageGraph {
-    let mut response = PackageGraph::default();
 
-    let mut all_child_derivations: HashSet<String> = HashSet::default();
-    for (derivation_path, derivation) in derivations.iter() {
-        let mut current_node = PackageNode {
-            id: derivation_path.clone(),
-            package: None,
-            url: None,
-            git_urls: BTreeSet::default(),
-            version: None,
-            name: None,
-            group_id: None,
-            main_derivation: derivation.clone(),
-            source_derivation: None,
-            children: BTreeSet::default(),
-            patches: BTreeSet::default(),
-            build_inputs: BTreeSet::default(),
-        };
+    // Records the inputSrcs of every package of the graph, so that they can be
+    // listed as files in the SBOM.
+    pub fn populate_input_sources(&mut self) {
+        for package_node in self.nodes_next.values() {
+            for input_source_path in &package_node.main_derivation.inputs_sources {
+                if self.input_sources.contains_key(input_source_path) {
+                    continue;
+                }
+                self.input_sources.insert(
+                    input_source_path.to_string(),
+                    InputSource::from_path(input_source_path),
+                );
+            }
+        }
+    }
 
-        let current_node_patches = derivation.get_patches();
-        let current_node_build_inputs = derivation.get_build_inputs();
+    // Computes the OmniBOR artifact ids of the sources and the patches of every package
+    // of the graph. The unpacked sources are directories, which are not hashed.
+    pub fn populate_omnibor_ids(&mut self) {
+        let artifact_paths: BTreeSet<String> = self
+            .nodes_next
+            .values()
+            .flat_map(|n| n.source_derivation.iter().chain(n.patches.iter()))
+            .cloned()
+            .collect();
+        for artifact_path in artifact_paths {
+            let out_path = match self
+                .nodes
+                .get(&artifact_path)
+                .and_then(|n| n.main_derivation.outputs.get("out"))
+            {
+                Some(o) => o.path.to_string(),
+                None => continue,
+            };
+            match std::fs::metadata(&out_path) {
+                Ok(m) if m.is_file() => match std::fs::read(&out_path) {
+                    Ok(content) => {
+                        self.omnibor_ids
+                            .insert(artifact_path, crate::utils::get_omnibor_ids(&content));
+                    }
+                    Err(e) => log::warn!("Could not read source artifact {}: {}", out_path, e),
+                },
+                Ok(_) => log::debug!("Not hashing source artifact {} since it is not a file", out_path),
+                Err(_) => log::debug!("Source artifact {} is not in the local store", out_path),
+            };
+        }
+    }
 
-        for input_derivation_path in derivation.input_derivations.keys() {
-            let child_derivation = derivations.get(input_derivation_path).unwrap();
-            let mut is_runtime_dep: bool = true;
+    // Applies the URL rewriting rules to every URL of the graph, so that
+    // all the output formats only see the rewritten URLs.
+    pub fn rewrite_urls(&mut self, url_rewriter: &crate::config::UrlRewriter) {
+        if url_rewriter.is_empty() {
+            return;
+        }
+        for nodes in [&mut self.nodes, &mut self.nodes_next] {
+            for package_node in nodes.values_mut() {
+                package_node.rewrite_urls(url_rewriter);
+            }
+        }
+        rewrite_flake_input_urls(&mut self.flake_inputs, url_rewriter);
+    }
 
-            for child_derivation_out_path in &child_derivation.get_output_paths() {
-                if current_node_patches.contains(child_derivation_out_path) {
-                    current_node.patches.insert(input_derivation_path.clone());
-                    all_child_derivations.insert(input_derivation_path.clone());
-                    is_runtime_dep = false;
-                    break;
-                }
+    pub fn populate_nodes(&mut self) -> Result<(), anyhow::Error> {
+        let packages = self.nodes.values().cloned().collect::<Vec<PackageNode>>();
+        for package in packages {
+            let group_id = match package.group_id.clone() {
+                Some(g) => g,
+                None => continue,
+            };
 
-                if current_node_build_inputs.contains(child_derivation_out_path) {
-                    current_node.build_inputs.insert(input_derivation_path.clone());
-                    all_child_derivations.insert(input_derivation_path.clone());
-                    is_runtime_dep = false;
-                    break;
-                }
+            if group_id != package.id {
+                continue;
             }
 
-            if is_runtime_dep {
-                current_node.children.insert(input_derivation_path.clone());
-                all_child_derivations.insert(input_derivation_path.clone());
+            self.nodes_next.insert(package.id.clone(), package.clone());
+        }
+        Ok(())
+    }
+
+    // Makes the derivations roots of the graph, even when they are dependencies of other
+    // nodes, e.g. a container that is also part of a host configuration given in the same run.
+    pub fn add_root_nodes(&mut self, derivation_paths: &BTreeSet<String>) {
+        for derivation_path in derivation_paths {
+            if self.nodes.contains_key(derivation_path) {
+                self.root_nodes.insert(derivation_path.to_string());
             }
         }
+    }
 
-        response.nodes.insert(derivation_path.clone(), current_node);
+    pub fn get_root_node(&self) -> Option<String> {
+        if self.root_nodes.len() == 1 {
+            self.root_nodes.last().cloned()
+        } else {
+            None
+        }
     }
 
-    for (derivation_path, _derivation) in derivations.iter() {
-        if all_child_derivations.contains(derivation_path) {
-            continue;
+    // Returns the names of the directories of the per-root SBOMs, indexed by root node.
+    // The roots sharing a name, e.g. the same package built for two systems, are suffixed
+    // with the hash of their store path so that they are not written to the same directory.
+    pub fn get_root_dir_names(&self) -> BTreeMap<String, String> {
+        let get_name = |root_node: &str| -> String {
+            let name = get_store_path_name(root_node);
+            name.strip_suffix(".drv").unwrap_or(&name).to_string()
+        };
+        let mut name_counts: BTreeMap<String, usize> = BTreeMap::default();
+        for root_node in self.root_nodes.iter() {
+            *name_counts.entry(get_name(root_node)).or_default() += 1;
         }
-        response.root_nodes.insert(derivation_path.to_string());
+        let mut response: BTreeMap<String, String> = BTreeMap::default();
+        for root_node in self.root_nodes.iter() {
+            let name = get_name(root_node);
+            let dir_name = match name_counts.get(&name) {
+                Some(count) if *count > 1 => format!("{}-{}", name, get_store_path_hash(root_node)),
+                _ => name,
+            };
+            response.insert(root_node.to_string(), dir_name);
+        }
+        response
     }
 
-    response
-}
+    // Returns the part of the graph reachable from a root node, with that node as the
+    // only root. The nodes shared between several roots are copied in each subgraph.
+    pub fn get_root_subgraph(&self, root_node: &str) -> PackageGraph {
+        let mut response = PackageGraph {
+            root_nodes: BTreeSet::from([root_node.to_string()]),
+            flake_inputs: self.flake_inputs.clone(),
+            excluded_nodes: self.excluded_nodes.clone(),
+            ..Default::default()
+        };
+        for (nodes, subgraph_nodes) in [
+            (&self.nodes, &mut response.nodes),
+            (&self.nodes_next, &mut response.nodes_next),
+        ] {
+            let mut queue: Vec<&String> = vec![];
+            if let Some((root_node_path, _)) = nodes.get_key_value(root_node) {
+                queue.push(root_node_path);
+            }
+            while let Some(derivation_path) = queue.pop() {
+                if subgraph_nodes.contains_key(derivation_path) {
+                    continue;
+                }
+                let package_node = match nodes.get(derivation_path) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                queue.extend(package_node.children.iter());
+                queue.extend(package_node.source_derivation.iter());
+                queue.extend(package_node.patches.iter());
+                subgraph_nodes.insert(derivation_path.to_string(), package_node.clone());
+            }
+        }
+        // The sources and the patches of the grouped packages are nodes of the full graph.
+        for package_node in response.nodes_next.values() {
+            for derivation_path in package_node
+                .source_derivation
+                .iter()
+                .chain(package_node.patches.iter())
+            {
+                if let Some(source_node) = self.nodes.get(derivation_path) {
+                    response
+                        .nodes
+                        .entry(derivation_path.to_string())
+                        .or_insert_with(|| source_node.clone());
+                }
+            }
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        for (derivation_path, group_id) in &self.group_membership {
+            if response.nodes.contains_key(derivation_path) {
+                response
+                    .group_membership
+                    .insert(derivation_path.to_string(), group_id.to_string());
+            }
+        }
+        let mut paths: BTreeSet<&String> = BTreeSet::default();
+        for package_node in response.nodes.values().chain(response.nodes_next.values()) {
+            paths.extend(package_node.main_derivation.inputs_sources.iter());
+            paths.extend(package_node.main_derivation.outputs.values().map(|o| &o.path));
+        }
+        for (path, input_source) in &self.input_sources {
+            if paths.contains(path) {
+                response
+                    .input_sources
+                    .insert(path.to_string(), input_source.clone());
+            }
+        }
+        for (path, nar_hash) in &self.store_paths {
+            if paths.contains(path) {
+                response.store_paths.insert(path.to_string(), nar_hash.clone());
+            }
+        }
+        for (derivation_path, omnibor_ids) in &self.omnibor_ids {
+            if response.nodes.contains_key(derivation_path) {
+                response
+                    .omnibor_ids
+                    .insert(derivation_path.to_string(), omnibor_ids.clone());
+            }
+        }
+        for (derivation_path, build_provenance) in &self.build_provenance {
+            if response.nodes_next.contains_key(derivation_path) {
+                response
+                    .build_provenance
+                    .insert(derivation_path.to_string(), build_provenance.to_string());
+            }
+        }
+        response
+    }
 
-    #[test]
-    pub fn parse_package_metadata() {
-        let package_metadata: &str = r###"
-          {
-            "name": "0ad-0.0.26",
-            "pname": "0ad",
-            "version": "0.0.26",
-            "system": "x86_64-linux",
-            "outputName": "out",
-            "outputs": {
-              "out": null
-            },
-            "meta": {
-              "available": true,
-              "broken": false,
-              "description": "A free, open-source game of ancient warfare",
-              "homepage": "https://play0ad.com/",
-              "insecure": false,
-              "license": [
-                {
-                  "deprecated": true,
-                  "free": true,
-                  "fullName": "GNU General Public License v2.0",
-                  "redistributable": true,
-                  "shortName": "gpl2",
-                  "spdxId": "GPL-2.0",
-                  "url": "https://spdx.org/licenses/GPL-2.0.html"
-                },
-                {
-                  "deprecated": true,
-                  "free": true,
-                  "fullName": "GNU Lesser General Public License v2.1",
-                  "redistributable": true,
-                  "shortName": "lgpl21",
-                  "spdxId": "LGPL-2.1",
-                  "url": "https://spdx.org/licenses/LGPL-2.1.html"
-                },
-                {
-                  "deprecated": false,
-                  "free": true,
-                  "fullName": "MIT License",
-                  "redistributable": true,
-                  "shortName": "mit",
-                  "spdxId": "MIT",
-                  "url": "https://spdx.org/licenses/MIT.html"
-                },
-                {
-                  "deprecated": false,
-                  "free": true,
-                  "fullName": "Creative Commons Attribution Share Alike 3.0",
-                  "redistributable": true,
-                  "shortName": "cc-by-sa-30",
-                  "spdxId": "CC-BY-SA-3.0",
-                  "url": "https://spdx.org/licenses/CC-BY-SA-3.0.html"
-                },
-                {
-                  "deprecated": false,
-                  "free": true,
-                  "fullName": "zlib License",
-                  "redistributable": true,
-                  "shortName": "zlib",
-                  "spdxId": "Zlib",
-                  "url": "https://spdx.org/licenses/Zlib.html"
+    pub fn get_stats(&self, options: &StatsOptions) -> PackageGraphStats {
+        let mut package_graph_stats = PackageGraphStats {
+            schema_version: STATS_SCHEMA_VERSION.to_string(),
+            ..Default::default()
+        };
+        package_graph_stats.nodes_count = self.nodes.len();
+        package_graph_stats.root_nodes_count = self.root_nodes.len();
+        for root_node in self.root_nodes.iter().filter(|_| options.per_root) {
+            let package_node = self.nodes.get(root_node).unwrap();
+            package_graph_stats.reachable_nodes_count.insert(
+                root_node.clone(),
+                package_node.get_reachable_nodes_count(&self.nodes, &mut HashSet::default()),
+            );
+            let longest_path = package_node.get_longest_path(
+                root_node,
+                &self.nodes,
+                &mut HashMap::default(),
+                options.runtime_only,
+            );
+            package_graph_stats
+                .longest_path_length
+                .insert(root_node.clone(), longest_path.len());
+            package_graph_stats.longest_path = longest_path;
+        }
+        if options.purl_types {
+            package_graph_stats.purl_scope_count = self.get_purl_scope_stats();
+        }
+        if options.licenses {
+            package_graph_stats.license_count = Some(self.get_license_stats());
+        }
+        package_graph_stats.patches_count = self.get_patches_count();
+        package_graph_stats.excluded_nodes_count = self.get_excluded_nodes_count();
+        package_graph_stats.package_meta_count = self.nodes.values().filter(|n| n.package.is_some()).count();
+        if options.with_meta {
+            package_graph_stats.missing_meta = Some(self.get_missing_meta());
+        }
+        if !self.build_provenance.is_empty() {
+            let mut build_provenance_count: BTreeMap<String, usize> = BTreeMap::default();
+            for provenance in self.build_provenance.values() {
+                *build_provenance_count.entry(provenance.to_string()).or_insert(0) += 1;
+            }
+            package_graph_stats.build_provenance_count = Some(build_provenance_count);
+        }
+        for package_node in self.nodes_next.values() {
+            let package = match &package_node.package {
+                Some(p) => p,
+                None => continue,
+            };
+            for signal in package.meta.get_lifecycle_signals() {
+                *package_graph_stats
+                    .lifecycle_signals_count
+                    .entry(signal.to_string())
+                    .or_insert(0) += 1;
+            }
+        }
+        // The compilers are build inputs, so the whole graph is searched.
+        for package_node in self.nodes.values() {
+            if let Some(compiler) = crate::classify::get_compiler(package_node) {
+                let versions = package_graph_stats
+                    .toolchains
+                    .entry(compiler.to_string())
+                    .or_default();
+                if let Some(version) = package_node.get_version() {
+                    versions.insert(version);
+                }
+            }
+        }
+        package_graph_stats.duplicated_packages = self.get_duplicated_packages(options.runtime_only);
+        package_graph_stats
+    }
+
+    // Returns the packages present at several versions, for example when some dependencies
+    // pin an older openssl. The dependency chains are the shortest paths from a root node.
+    pub fn get_duplicated_packages(
+        &self,
+        runtime_only: bool,
+    ) -> BTreeMap<String, BTreeMap<String, Vec<String>>> {
+        let mut versions: BTreeMap<String, BTreeMap<String, Vec<&String>>> = BTreeMap::default();
+        for (derivation_path, package_node) in &self.nodes_next {
+            if package_node.is_inline_script() {
+                continue;
+            }
+            if let (Some(name), Some(version)) = (&package_node.name, package_node.get_version()) {
+                versions
+                    .entry(name.to_string())
+                    .or_default()
+                    .entry(version)
+                    .or_default()
+                    .push(derivation_path);
+            }
+        }
+        versions.retain(|_, v| v.len() > 1);
+        if versions.is_empty() {
+            return BTreeMap::default();
+        }
+
+        let parents = crate::search::get_parents(self, runtime_only);
+        let mut response: BTreeMap<String, BTreeMap<String, Vec<String>>> = BTreeMap::default();
+        for (name, derivation_paths_by_version) in versions {
+            let mut dependency_chains: BTreeMap<String, Vec<String>> = BTreeMap::default();
+            for (version, derivation_paths) in derivation_paths_by_version {
+                let mut chains: Vec<String> = vec![];
+                for derivation_path in derivation_paths {
+                    let mut dependency_path =
+                        crate::search::get_dependency_path(self, &parents, derivation_path);
+                    dependency_path.push(name.to_string());
+                    chains.push(dependency_path.join(" -> "));
                 }
+                chains.sort();
+                chains.dedup();
+                dependency_chains.insert(version, chains);
+            }
+            response.insert(name, dependency_chains);
+        }
+        response
+    }
+
+    // Returns the derivations of the package groups without package metadata, by reason.
+    pub fn get_missing_meta(&self) -> BTreeMap<String, Vec<String>> {
+        let mut response: BTreeMap<String, Vec<String>> = BTreeMap::default();
+        for (derivation_path, package_node) in &self.nodes_next {
+            if let Some(reason) = &package_node.missing_meta_reason {
+                response
+                    .entry(reason.to_string())
+                    .or_default()
+                    .push(derivation_path.to_string());
+            }
+        }
+        response
+    }
+
+    pub fn get_excluded_nodes_count(&self) -> BTreeMap<String, usize> {
+        let mut response: BTreeMap<String, usize> = BTreeMap::default();
+        for reason in self.excluded_nodes.values() {
+            *response.entry(reason.to_string()).or_insert(0) += 1;
+        }
+        response
+    }
+
+    // Counts the packages of the SBOM by license. A package with a license expression
+    // is counted once for each license of the expression.
+    pub fn get_license_stats(&self) -> BTreeMap<String, usize> {
+        let mut response: BTreeMap<String, usize> = BTreeMap::default();
+        for package_node in self.nodes_next.values() {
+            let license_ids = crate::license::get_package_license_expression(package_node)
+                .map(|e| crate::license::get_license_ids(&e))
+                .unwrap_or_default();
+            if license_ids.is_empty() {
+                *response.entry("NOASSERTION".to_string()).or_insert(0) += 1;
+            }
+            for license_id in license_ids {
+                *response.entry(license_id).or_insert(0) += 1;
+            }
+        }
+        response
+    }
+
+    pub fn get_purl_scope_stats(&self) -> BTreeMap<String, usize> {
+        let mut visited_children: HashSet<String> = HashSet::default();
+
+        let mut response: BTreeMap<String, usize> = BTreeMap::default();
+        let mut node_queue = self.root_nodes.clone();
+
+        while !node_queue.is_empty() {
+            let current_node_path = node_queue.pop_first().unwrap();
+
+            if visited_children.contains(&current_node_path) {
+                continue;
+            }
+
+            let current_node = self.nodes.get(&current_node_path).unwrap();
+            let purl = current_node.get_purl();
+
+            if response.contains_key(&purl.scheme) {
+                let count = response.get_mut(&purl.scheme).unwrap();
+                *count += 1;
+            } else {
+                response.insert(purl.scheme.clone(), 1);
+            }
+
+            for current_node_child in &current_node.children {
+                node_queue.insert(current_node_child.clone());
+            }
+            for current_node_child in &current_node.build_inputs {
+                node_queue.insert(current_node_child.clone());
+            }
+            for current_node_child in &current_node.patches {
+                node_queue.insert(current_node_child.clone());
+            }
+            visited_children.insert(current_node_path.clone());
+        }
+
+        response
+    }
+
+    pub fn get_patches_count(&self) -> usize {
+        let mut response = 0;
+        let mut visited_children: HashSet<String> = HashSet::default();
+
+        let mut node_queue = self.root_nodes.clone();
+
+        while !node_queue.is_empty() {
+            let current_node_path = node_queue.pop_first().unwrap();
+
+            if visited_children.contains(&current_node_path) {
+                continue;
+            }
+
+            let current_node = self.nodes.get(&current_node_path).unwrap();
+            response += current_node.patches.len();
+
+            // FIXME we should also go through the patches?
+            for current_node_child in &current_node.children {
+                node_queue.insert(current_node_child.clone());
+            }
+            for current_node_child in &current_node.build_inputs {
+                node_queue.insert(current_node_child.clone());
+            }
+            visited_children.insert(current_node_path.clone());
+        }
+
+        response
+    }
+
+    pub fn pretty_print(&self, depth: usize, display_options: &DisplayOptions) -> String {
+        let mut lines: Vec<PrettyPrintLine> = vec![];
+        let mut response = "".to_string();
+
+        let mut visited_children: HashSet<String> = HashSet::default();
+        for (derivation_path, package_node) in &self.nodes {
+            if visited_children.contains(derivation_path) {
+                continue;
+            }
+            for child_derivation_path in &package_node.children {
+                let child = self.nodes.get(child_derivation_path).unwrap();
+                add_visited_children(child, &self, &mut visited_children);
+            }
+        }
+
+        for (_derivation_path, package_node) in &self.nodes {
+            if !display_options.print_stdenv && is_stdenv(&package_node.main_derivation.get_name().unwrap()) {
+                continue;
+            }
+            for line in package_node.pretty_print(self, depth, display_options) {
+                lines.push(line);
+            }
+        }
+
+        for line in lines {
+            response += &line.to_string();
+            response += "\n";
+        }
+        response
+    }
+}
+
+fn rewrite_flake_input_urls(
+    flake_inputs: &mut [crate::flake::FlakeInput],
+    url_rewriter: &crate::config::UrlRewriter,
+) {
+    for flake_input in flake_inputs.iter_mut() {
+        // The URL of the github, gitlab and sourcehut inputs is built from the owner and
+        // the repo, so it has to be built before applying the rules.
+        if let Some(locked) = flake_input.locked.as_mut() {
+            locked.url = locked.get_url().map(|u| url_rewriter.rewrite(&u));
+        }
+        rewrite_flake_input_urls(&mut flake_input.inputs, url_rewriter);
+    }
+}
+
+fn add_visited_children(
+    package_node: &PackageNode,
+    package_graph: &PackageGraph,
+    visited_children: &mut HashSet<String>,
+) {
+    for child_derivation_path in &package_node.children {
+        if visited_children.contains(child_derivation_path) {
+            continue;
+        }
+        visited_children.insert(child_derivation_path.to_string());
+        let child_package = match package_graph.nodes.get(child_derivation_path) {
+            Some(p) => p,
+            None => {
+                log::warn!(
+                    "Could not get package in package graph for {}",
+                    &child_derivation_path
+                );
+                continue;
+            }
+        };
+
+        add_visited_children(&child_package, &package_graph, visited_children);
+    }
+}
+
+// Small struct to make it easier to pretty-print the
+// internal representation for the package graph.
+#[derive(Debug)]
+pub struct PrettyPrintLine {
+    pub indent_level: usize,
+    pub line: String,
+}
+impl PrettyPrintLine {
+    pub fn new<S: AsRef<str>>(line: S, indent_level: usize) -> PrettyPrintLine {
+        PrettyPrintLine {
+            line: line.as_ref().to_string(),
+            indent_level,
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        "  ".repeat(self.indent_level) + &self.line
+    }
+}
+
+// Returns the derivations that are not an input of any other derivation of the set.
+pub fn get_root_derivation_paths(derivations: &Derivations) -> BTreeSet<String> {
+    let input_derivation_paths: HashSet<&String> = derivations
+        .values()
+        .flat_map(|d| d.input_derivations.keys())
+        .collect();
+    derivations
+        .keys()
+        .filter(|p| !input_derivation_paths.contains(p))
+        .cloned()
+        .collect()
+}
+
+pub fn get_package_graph(derivations: &Derivations) -> PackageGraph {
+    let mut response = PackageGraph::default();
+
+    let mut all_child_derivations: HashSet<String> = HashSet::default();
+    let mut missing_derivations: BTreeSet<String> = BTreeSet::default();
+    for (derivation_path, derivation) in derivations.iter() {
+        let mut current_node = PackageNode {
+            id: derivation_path.clone(),
+            package: None,
+            url: None,
+            git_urls: BTreeSet::default(),
+            version: None,
+            name: None,
+            group_id: None,
+            main_derivation: derivation.clone(),
+            source_derivation: None,
+            children: BTreeSet::default(),
+            concluded_license: None,
+            owners: vec![],
+            closure_size: None,
+            nar_size: None,
+            ambiguous_packages: vec![],
+            missing_meta_reason: None,
+            incomplete: false,
+            substituted: derivation.nar_hash.is_some(),
+            purl_type: None,
+            purl_qualifiers: BTreeMap::default(),
+            patches: BTreeSet::default(),
+            build_inputs: BTreeSet::default(),
+        };
+
+        let current_node_patches = derivation.get_patches();
+        let current_node_build_inputs = derivation.get_build_inputs();
+
+        for input_derivation_path in derivation.input_derivations.keys() {
+            let child_derivation = match derivations.get(input_derivation_path) {
+                Some(d) => d,
+                None => {
+                    log::warn!(
+                        "Derivation {} is missing, adding a placeholder to the graph",
+                        input_derivation_path
+                    );
+                    missing_derivations.insert(input_derivation_path.clone());
+                    current_node.children.insert(input_derivation_path.clone());
+                    all_child_derivations.insert(input_derivation_path.clone());
+                    continue;
+                }
+            };
+            let mut is_runtime_dep: bool = true;
+
+            for child_derivation_out_path in &child_derivation.get_output_paths() {
+                if current_node_patches.contains(child_derivation_out_path) {
+                    current_node.patches.insert(input_derivation_path.clone());
+                    all_child_derivations.insert(input_derivation_path.clone());
+                    is_runtime_dep = false;
+                    break;
+                }
+
+                if current_node_build_inputs.contains(child_derivation_out_path) {
+                    current_node.build_inputs.insert(input_derivation_path.clone());
+                    all_child_derivations.insert(input_derivation_path.clone());
+                    is_runtime_dep = false;
+                    break;
+                }
+            }
+
+            if is_runtime_dep {
+                current_node.children.insert(input_derivation_path.clone());
+                all_child_derivations.insert(input_derivation_path.clone());
+            }
+        }
+
+        response.nodes.insert(derivation_path.clone(), current_node);
+    }
+
+    for derivation_path in missing_derivations {
+        let placeholder_node = PackageNode {
+            id: derivation_path.clone(),
+            package: None,
+            url: None,
+            git_urls: BTreeSet::default(),
+            version: None,
+            name: None,
+            group_id: None,
+            main_derivation: Derivation::get_placeholder(&derivation_path),
+            source_derivation: None,
+            children: BTreeSet::default(),
+            concluded_license: None,
+            owners: vec![],
+            closure_size: None,
+            nar_size: None,
+            ambiguous_packages: vec![],
+            missing_meta_reason: None,
+            incomplete: true,
+            substituted: false,
+            purl_type: None,
+            purl_qualifiers: BTreeMap::default(),
+            patches: BTreeSet::default(),
+            build_inputs: BTreeSet::default(),
+        };
+        response.nodes.insert(derivation_path, placeholder_node);
+    }
+
+    for (derivation_path, _derivation) in derivations.iter() {
+        if all_child_derivations.contains(derivation_path) {
+            continue;
+        }
+        response.root_nodes.insert(derivation_path.to_string());
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parse_package_metadata() {
+        let package_metadata: &str = r###"
+          {
+            "name": "0ad-0.0.26",
+            "pname": "0ad",
+            "version": "0.0.26",
+            "system": "x86_64-linux",
+            "outputName": "out",
+            "outputs": {
+              "out": null
+            },
+            "meta": {
+              "available": true,
+              "broken": false,
+              "description": "A free, open-source game of ancient warfare",
+              "homepage": "https://play0ad.com/",
+              "insecure": false,
+              "license": [
+                {
+                  "deprecated": true,
+                  "free": true,
+                  "fullName": "GNU General Public License v2.0",
+                  "redistributable": true,
+                  "shortName": "gpl2",
+                  "spdxId": "GPL-2.0",
+                  "url": "https://spdx.org/licenses/GPL-2.0.html"
+                },
+                {
+                  "deprecated": true,
+                  "free": true,
+                  "fullName": "GNU Lesser General Public License v2.1",
+                  "redistributable": true,
+                  "shortName": "lgpl21",
+                  "spdxId": "LGPL-2.1",
+                  "url": "https://spdx.org/licenses/LGPL-2.1.html"
+                },
+                {
+                  "deprecated": false,
+                  "free": true,
+                  "fullName": "MIT License",
+                  "redistributable": true,
+                  "shortName": "mit",
+                  "spdxId": "MIT",
+                  "url": "https://spdx.org/licenses/MIT.html"
+                },
+                {
+                  "deprecated": false,
+                  "free": true,
+                  "fullName": "Creative Commons Attribution Share Alike 3.0",
+                  "redistributable": true,
+                  "shortName": "cc-by-sa-30",
+                  "spdxId": "CC-BY-SA-3.0",
+                  "url": "https://spdx.org/licenses/CC-BY-SA-3.0.html"
+                },
+                {
+                  "deprecated": false,
+                  "free": true,
+                  "fullName": "zlib License",
+                  "redistributable": true,
+                  "shortName": "zlib",
+                  "spdxId": "Zlib",
+                  "url": "https://spdx.org/licenses/Zlib.html"
+                }
+              ],
+              "maintainers": [
+                {
+                  "email": "nixpkgs@cvpetegem.be",
+                  "github": "chvp",
+                  "githubId": 42220376,
+                  "matrix": "@charlotte:vanpetegem.me",
+                  "name": "Charlotte Van Petegem"
+                },
+                {
+                  "email": "dev.primeos@gmail.com",
+                  "github": "primeos",
+                  "githubId": 7537109,
+                  "keys": [
+                    {
+                      "fingerprint": "86A7 4A55 07D0 58D1 322E  37FD 1308 26A6 C2A3 89FD",
+                      "longkeyid": "ed25519/0x130826A6C2A389FD"
+                    },
+                    {
+                      "fingerprint": "AF85 991C C950 49A2 4205  1933 BCA9 943D D1DF 4C04",
+                      "longkeyid": "rsa3072/0xBCA9943DD1DF4C04"
+                    }
+                  ],
+                  "matrix": "@primeos:matrix.org",
+                  "name": "Michael Weiss"
+                }
+              ],
+              "name": "0ad-0.0.26",
+              "outputsToInstall": [
+                "out"
+              ],
+              "platforms": [
+                "aarch64-linux",
+                "armv5tel-linux",
+                "armv6l-linux",
+                "armv7a-linux",
+                "armv7l-linux",
+                "m68k-linux",
+                "microblaze-linux",
+                "microblazeel-linux",
+                "mipsel-linux",
+                "mips64el-linux",
+                "powerpc64-linux",
+                "powerpc64le-linux",
+                "riscv32-linux",
+                "riscv64-linux",
+                "s390-linux",
+                "s390x-linux",
+                "x86_64-linux"
+              ],
+              "unfree": false,
+              "unsupported": false
+            }
+          }
+        "###;
+        let package: Package = serde_json::from_str(package_metadata).unwrap();
+        assert_eq!(package.name, "0ad-0.0.26");
+        assert_eq!(
+            package.meta.get_spdx_license_expression(),
+            Some("GPL-2.0 AND LGPL-2.1 AND MIT AND CC-BY-SA-3.0 AND Zlib".to_string())
+        );
+    }
+
+    #[test]
+    pub fn parse_package_metadata_embedded_maintainers_list() {
+        // This parsing issue was raised in https://github.com/louib/nix2sbom/issues/10
+        let package_metadata: &str = r###"
+          {
+            "meta": {
+              "available": true,
+              "broken": false,
+              "description": "A parser generator for building parsers from grammars",
+              "homepage": "https://javacc.github.io/javacc",
+              "insecure": false,
+              "license": {
+                "deprecated": false,
+                "free": true,
+                "fullName": "BSD 2-clause \"Simplified\" License",
+                "redistributable": true,
+                "shortName": "bsd2",
+                "spdxId": "BSD-2-Clause",
+                "url": "https://spdx.org/licenses/BSD-2-Clause.html"
+              },
+              "maintainers": [
+                [
+                  {
+                    "email": "limeytexan@gmail.com",
+                    "github": "limeytexan",
+                    "githubId": 36448130,
+                    "name": "Michael Brantley"
+                  }
+                ]
+              ],
+              "name": "javacc-7.0.10",
+              "outputsToInstall": [
+                "out"
+              ],
+              "unfree": false,
+              "unsupported": false
+            },
+            "name": "javacc-7.0.10",
+            "outputName": "out",
+            "outputs": {
+              "out": null
+            },
+            "pname": "javacc",
+            "system": "x86_64-linux",
+            "version": "7.0.10"
+          }
+        "###;
+        let package: Package = serde_json::from_str(package_metadata).unwrap();
+        assert_eq!(package.name, "javacc-7.0.10");
+        assert_eq!(package.meta.get_maintainers().len(), 1);
+    }
+
+    #[test]
+    pub fn parse_package_metadata_maintainers_mixed() {
+        let package_metadata: &str = r###"
+          {
+            "meta": {
+              "available": true,
+              "broken": false,
+              "description": "A software reverse engineering (SRE) suite of tools developed by NSA's Research Directorate in support of the Cybersecurity mission",
+              "homepage": "https://ghidra-sre.org/",
+              "insecure": false,
+              "license": {
+                "deprecated": false,
+                "free": true,
+                "fullName": "Apache License 2.0",
+                "redistributable": true,
+                "shortName": "asl20",
+                "spdxId": "Apache-2.0",
+                "url": "https://spdx.org/licenses/Apache-2.0.html"
+              },
+              "maintainers": [
+                "roblabla",
+                [
+                    {
+                        "email": "dev.primeos@gmail.com",
+                        "github": "primeos",
+                        "githubId": 7537109,
+                        "keys": [
+                            {
+                                "fingerprint": "86A7 4A55 07D0 58D1 322E  37FD 1308 26A6 C2A3 89FD",
+                                "longkeyid": "ed25519/0x130826A6C2A389FD"
+                            },
+                            {
+                                "fingerprint": "AF85 991C C950 49A2 4205  1933 BCA9 943D D1DF 4C04",
+                                "longkeyid": "rsa3072/0xBCA9943DD1DF4C04"
+                            }
+                        ],
+                        "matrix": "@primeos:matrix.org",
+                        "name": "Michael Weiss"
+                    },
+                    {
+                        "email": "nix@hilhorst.be",
+                        "github": "Synthetica9",
+                        "githubId": 7075751,
+                        "name": "Patrick Hilhorst"
+                    },
+                    {
+                        "email": "maximilian@mbosch.me",
+                        "github": "ma27",
+                        "githubId": 6025220,
+                        "matrix": "@ma27:nicht-so.sexy",
+                        "name": "Maximilian Bosch"
+                    }
+                ],
+                {
+                    "email": "evils.devils@protonmail.com",
+                    "github": "evils",
+                    "githubId": 30512529,
+                    "matrix": "@evils:nixos.dev",
+                    "name": "Evils"
+                }
+              ],
+              "name": "ghidra-10.1.2",
+              "outputsToInstall": [
+                "out"
+              ],
+              "platforms": [
+                "x86_64-linux",
+                "x86_64-darwin"
+              ],
+              "position": "/nix/store/3nyf6ydmhggdskci66mv0vlyfwwinx0l-nixos/nixos/pkgs/tools/security/ghidra/build.nix:171",
+              "unfree": false,
+              "unsupported": false
+            },
+            "name": "ghidra-10.1.2",
+            "outputName": "out",
+            "outputs": {
+              "out": null
+            },
+            "pname": "ghidra",
+            "system": "x86_64-linux",
+            "version": "10.1.2"
+          }
+        "###;
+        let package: Package = serde_json::from_str(package_metadata).unwrap();
+        assert_eq!(package.name, "ghidra-10.1.2");
+    }
+
+    #[test]
+    pub fn parse_package_metadata_malformed_maintainers() {
+        let package_metadata: &str = r###"
+          {
+            "meta": {
+              "available": false,
+              "broken": false,
+              "description": "Software for rapid LiDAR processing",
+              "homepage": "http://lastools.org/",
+              "insecure": false,
+              "license": {
+                "deprecated": false,
+                "free": false,
+                "fullName": "Unfree",
+                "redistributable": false,
+                "shortName": "unfree"
+              },
+              "maintainers": [
+                {
+                  "github": "StephenWithPH",
+                  "githubId": 2990492,
+                  "name": "StephenWithPH"
+                }
+              ],
+              "name": "LAStools-2.0.2",
+              "outputsToInstall": [
+                "out"
+              ],
+              "platforms": [
+                "i686-cygwin",
+                "x86_64-cygwin",
+                "x86_64-darwin",
+                "i686-darwin",
+                "aarch64-darwin",
+                "armv7a-darwin",
+                "i686-freebsd13",
+                "x86_64-freebsd13",
+                "x86_64-solaris",
+                "aarch64-linux",
+                "armv5tel-linux",
+                "armv6l-linux",
+                "armv7a-linux",
+                "armv7l-linux",
+                "i686-linux",
+                "loongarch64-linux",
+                "m68k-linux",
+                "microblaze-linux",
+                "microblazeel-linux",
+                "mipsel-linux",
+                "mips64el-linux",
+                "powerpc64-linux",
+                "powerpc64le-linux",
+                "riscv32-linux",
+                "riscv64-linux",
+                "s390-linux",
+                "s390x-linux",
+                "x86_64-linux",
+                "aarch64-netbsd",
+                "armv6l-netbsd",
+                "armv7a-netbsd",
+                "armv7l-netbsd",
+                "i686-netbsd",
+                "m68k-netbsd",
+                "mipsel-netbsd",
+                "powerpc-netbsd",
+                "riscv32-netbsd",
+                "riscv64-netbsd",
+                "x86_64-netbsd",
+                "i686-openbsd",
+                "x86_64-openbsd",
+                "x86_64-redox"
+              ],
+              "unfree": true,
+              "unsupported": false
+            },
+            "name": "LAStools-2.0.2",
+            "outputName": "out",
+            "outputs": {
+              "out": null
+            },
+            "pname": "LAStools",
+            "system": "x86_64-linux",
+            "version": "2.0.2"
+          }
+        "###;
+        let package: Package = serde_json::from_str(package_metadata).unwrap();
+        assert_eq!(package.name, "LAStools-2.0.2");
+        assert_eq!(
+            package.meta.get_spdx_license_expression(),
+            Some("LicenseRef-unfree".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_get_spdx_license_expression() {
+        let meta: PackageMeta =
+            serde_json::from_str(r#"{ "license": ["mit", "unfree", "GPL-2.0+"] }"#).unwrap();
+        assert_eq!(
+            meta.get_spdx_license_expression(),
+            Some("MIT AND LicenseRef-unfree AND GPL-2.0+".to_string())
+        );
+    }
+
+    #[test]
+    pub fn get_version_from_rev() {
+        let derivation: &str = r###"
+          {
+            "args": [
+              "-e",
+              "/nix/store/v7wqh83pzn39kjx6pdfixwyqlbmsqid3-builder.sh"
+            ],
+            "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+            "env": {
+              "GIT_SSL_CAINFO": "/nix/store/9hx76jndjw5881pb83ghvlq6k4aqagz4-nss-cacert-3.92/etc/ssl/certs/ca-bundle.crt",
+              "__structuredAttrs": "",
+              "buildInputs": "",
+              "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+              "cmakeFlags": "",
+              "configureFlags": "",
+              "deepClone": "",
+              "depsBuildBuild": "",
+              "depsBuildBuildPropagated": "",
+              "depsBuildTarget": "",
+              "depsBuildTargetPropagated": "",
+              "depsHostHost": "",
+              "depsHostHostPropagated": "",
+              "depsTargetTarget": "",
+              "depsTargetTargetPropagated": "",
+              "doCheck": "",
+              "doInstallCheck": "",
+              "fetchLFS": "",
+              "fetchSubmodules": "1",
+              "fetcher": "/nix/store/fqkqnkkwzhqn21fh9ba4nz75nhd89irm-nix-prefetch-git",
+              "impureEnvVars": "http_proxy https_proxy ftp_proxy all_proxy no_proxy GIT_PROXY_COMMAND NIX_GIT_SSL_CAINFO SOCKS_SERVER",
+              "leaveDotGit": "",
+              "mesonFlags": "",
+              "name": "source",
+              "nativeBuildInputs": "/nix/store/zy5r5ssh2zk6n1k34gv09fd9865lcniq-git-minimal-2.40.1",
+              "nonConeMode": "",
+              "out": "/nix/store/gj39c9gmjz3z5f6lgkcsl0lc07fwhq0c-source",
+              "outputHash": "sha256-I3PGgh0XqRkCFz7lUZ3Q4eU0+0GwaQcVb6t4Pru1kKo=",
+              "outputHashMode": "recursive",
+              "outputs": "out",
+              "patches": "",
+              "postFetch": "",
+              "preferLocalBuild": "1",
+              "propagatedBuildInputs": "",
+              "propagatedNativeBuildInputs": "",
+              "rev": "v0.8.2",
+              "sparseCheckout": "",
+              "stdenv": "/nix/store/9v8sc2q2dflxjcz1hsw84b10bvg0wand-stdenv-linux",
+              "strictDeps": "",
+              "system": "x86_64-linux",
+              "url": "https://github.com/libjxl/libjxl.git"
+            },
+            "inputDrvs": {
+              "/nix/store/331fppp0q0n5xy5mrhkg3abp3sbpb869-stdenv-linux.drv": [
+                "out"
+              ],
+              "/nix/store/33xwn0p89b0iwqxqdnp36hyy138flhkg-nss-cacert-3.92.drv": [
+                "out"
+              ],
+              "/nix/store/hla091y2jgs76hd8ps5ky6d81qzkdfz5-bash-5.2-p15.drv": [
+                "out"
+              ],
+              "/nix/store/pz619bwk8qgpb0zd3g11fm0hclk3pfz3-git-minimal-2.40.1.drv": [
+                "out"
+              ]
+            },
+            "inputSrcs": [
+              "/nix/store/fqkqnkkwzhqn21fh9ba4nz75nhd89irm-nix-prefetch-git",
+              "/nix/store/v7wqh83pzn39kjx6pdfixwyqlbmsqid3-builder.sh"
+            ],
+            "outputs": {
+              "out": {
+                "hash": "2373c6821d17a91902173ee5519dd0e1e534fb41b06907156fab783ebbb590aa",
+                "hashAlgo": "r:sha256",
+                "path": "/nix/store/gj39c9gmjz3z5f6lgkcsl0lc07fwhq0c-source"
+              }
+            },
+            "system": "x86_64-linux"
+          }
+        "###;
+        let derivation: Derivation = serde_json::from_str(derivation).unwrap();
+        assert_eq!(derivation.get_name(), Some("libjxl".to_string()));
+        assert_eq!(derivation.get_version(), Some("0.8.2".to_string()));
+        // The SRI hash of the env and the base16 hash of the outputs are the same hash.
+        let expected_hash =
+            Some("2373c6821d17a91902173ee5519dd0e1e534fb41b06907156fab783ebbb590aa".to_string());
+        let output_hash = crate::fetcher::get_output_hash(&derivation.env).unwrap();
+        assert_eq!(output_hash.get_sha256_hex(), expected_hash);
+        let output_hash = crate::fetcher::get_output_hash_from_outputs(&derivation.outputs).unwrap();
+        assert_eq!(output_hash.mode, "recursive");
+        assert_eq!(output_hash.get_sha256_hex(), expected_hash);
+    }
+
+    #[test]
+    pub fn test_remove_version_from_name() {
+        let derivation: &str = r###"
+          {
+            "args": [
+              "-e",
+              "/nix/store/6xg259477c90a229xwmb53pdfkn6ig3g-default-builder.sh"
+            ],
+            "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+            "env": {
+              "LDFLAGS": "",
+              "__structuredAttrs": "",
+              "bin": "/nix/store/j41ms763gpyya3hylqmaq1p108bhvkcm-zstd-1.5.5-bin",
+              "buildInputs": "/nix/store/2i83qvxdxps1s91335icgkd2mp6v6b91-bash-5.2-p15-dev",
+              "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+              "checkPhase": "runHook preCheck\n# Patch shebangs for playTests\npatchShebangs ../programs/zstdgrep\nctest -R playTests # The only relatively fast test.\nrunHook postCheck\n",
+              "cmakeDir": "../build/cmake",
+              "cmakeFlags": "-DZSTD_BUILD_CONTRIB:BOOL=ON -DZSTD_BUILD_SHARED:BOOL=ON -DZSTD_BUILD_STATIC:BOOL=OFF -DZSTD_BUILD_TESTS:BOOL=ON -DZSTD_LEGACY_SUPPORT:BOOL=OFF -DZSTD_PROGRAMS_LINK_SHARED:BOOL=ON",
+              "configureFlags": "",
+              "depsBuildBuild": "",
+              "depsBuildBuildPropagated": "",
+              "depsBuildTarget": "",
+              "depsBuildTargetPropagated": "",
+              "depsHostHost": "",
+              "depsHostHostPropagated": "",
+              "depsTargetTarget": "",
+              "depsTargetTargetPropagated": "",
+              "dev": "/nix/store/4pifi04nz9l2i0l692ny148q94klml0r-zstd-1.5.5-dev",
+              "doCheck": "1",
+              "doInstallCheck": "",
+              "dontUseCmakeBuildDir": "1",
+              "man": "/nix/store/qxv3dnwvi2xw1kx8bhf8lcyssbdvna8d-zstd-1.5.5-man",
+              "mesonFlags": "",
+              "name": "zstd-1.5.5",
+              "nativeBuildInputs": "/nix/store/hdwhs75n9ydc4pdqv0hamjzjv1fkw1zz-cmake-boot-3.25.3 /nix/store/xv00ljxfrgdi9m53w96mj7pqgb0m0c3l-file-5.44-dev",
+              "out": "/nix/store/81d38brw9cnw2qk2kynrf5dr6hhkcq66-zstd-1.5.5",
+              "outputs": "bin dev man out",
+              "patches": "/nix/store/n91acyjrlchm0snw0w16i4683pf788ax-playtests-darwin.patch",
+              "pname": "zstd",
+              "postPatch": "substituteInPlace build/cmake/CMakeLists.txt \\\n  --replace 'message(SEND_ERROR \"You need to build static library to build tests\")' \"\"\nsubstituteInPlace build/cmake/tests/CMakeLists.txt \\\n  --replace 'libzstd_static' 'libzstd_shared'\nsed -i \\\n  \"1aexport LD_LIBRARY_PATH=$PWD/build_/lib\" \\\n  tests/playTests.sh\n",
+              "preConfigure": "mkdir -p build_ && cd $_\n",
+              "preInstall": "mkdir -p $bin/bin\nsubstituteInPlace ../programs/zstdgrep \\\n  --replace \":-grep\" \":-/nix/store/b4in4hmq54h6l34a0v6ha40z97c0lzw2-gnugrep-3.7/bin/grep\" \\\n  --replace \":-zstdcat\" \":-$bin/bin/zstdcat\"\n\nsubstituteInPlace ../programs/zstdless \\\n  --replace \"zstdcat\" \"$bin/bin/zstdcat\"\ncp contrib/pzstd/pzstd $bin/bin/pzstd\n",
+              "propagatedBuildInputs": "",
+              "propagatedNativeBuildInputs": "",
+              "src": "/nix/store/2w0cnsrfgapi5jf9z9yciir4hgz7nyj8-source",
+              "stdenv": "/nix/store/gv2cl6qvvslz5h15vqd89f1rpvrdg5yc-stdenv-linux",
+              "strictDeps": "",
+              "system": "x86_64-linux",
+              "version": "1.5.5"
+            },
+            "inputDrvs": {
+              "/nix/store/975cwk57d5xy6cyakapsifyg19n3g516-file-5.44.drv": [
+                "dev"
+              ],
+              "/nix/store/f69xwgpf415s7fvg64qhsfmqpmb7xnjg-source.drv": [
+                "out"
+              ],
+              "/nix/store/hla091y2jgs76hd8ps5ky6d81qzkdfz5-bash-5.2-p15.drv": [
+                "dev",
+                "out"
+              ],
+              "/nix/store/im7dywhi0ycfnkdplpmh9xqzynf6v2mg-cmake-boot-3.25.3.drv": [
+                "out"
+              ],
+              "/nix/store/m4cyqwyzda46912dirznjzx5cml6d018-gnugrep-3.7.drv": [
+                "out"
+              ],
+              "/nix/store/z9vnfwzs0226f7qid0j0iglfbpvb61hx-stdenv-linux.drv": [
+                "out"
+              ]
+            },
+            "inputSrcs": [
+              "/nix/store/6xg259477c90a229xwmb53pdfkn6ig3g-default-builder.sh",
+              "/nix/store/n91acyjrlchm0snw0w16i4683pf788ax-playtests-darwin.patch"
+            ],
+            "outputs": {
+              "bin": {
+                "path": "/nix/store/j41ms763gpyya3hylqmaq1p108bhvkcm-zstd-1.5.5-bin"
+              },
+              "dev": {
+                "path": "/nix/store/4pifi04nz9l2i0l692ny148q94klml0r-zstd-1.5.5-dev"
+              },
+              "man": {
+                "path": "/nix/store/qxv3dnwvi2xw1kx8bhf8lcyssbdvna8d-zstd-1.5.5-man"
+              },
+              "out": {
+                "path": "/nix/store/81d38brw9cnw2qk2kynrf5dr6hhkcq66-zstd-1.5.5"
+              }
+            },
+            "system": "x86_64-linux"
+          }
+        "###;
+        let derivation: Derivation = serde_json::from_str(derivation).unwrap();
+        assert_eq!(derivation.get_name(), Some("zstd".to_string()));
+        assert_eq!(derivation.get_version(), Some("1.5.5".to_string()));
+    }
+
+    #[test]
+    pub fn test_split_version_and_name() {
+        let derivation: &str = r###"
+          {
+            "args": [
+              "-e",
+              "/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"
+            ],
+            "builder": "/nix/store/izpf49b74i15pcr9708s3xdwyqs4jxwl-bash-5.2p32/bin/bash",
+            "env": {
+              "__structuredAttrs": "",
+              "buildCommand": "",
+              "buildInputs": "",
+              "builder": "/nix/store/izpf49b74i15pcr9708s3xdwyqs4jxwl-bash-5.2p32/bin/bash",
+              "cmakeFlags": "",
+              "configureFlags": "",
+              "depsBuildBuild": "",
+              "depsBuildBuildPropagated": "",
+              "depsBuildTarget": "",
+              "depsBuildTargetPropagated": "",
+              "depsHostHost": "",
+              "depsHostHostPropagated": "",
+              "depsTargetTarget": "",
+              "depsTargetTargetPropagated": "",
+              "doCheck": "",
+              "doInstallCheck": "",
+              "enableParallelBuilding": "1",
+              "enableParallelChecking": "1",
+              "enableParallelInstalling": "1",
+              "mesonFlags": "",
+              "name": "anstyle-query-1.1.1",
+              "nativeBuildInputs": "",
+              "out": "/nix/store/vbry7m4bvv035bvwm1jspdv9ccj1h20m-anstyle-query-1.1.1",
+              "outputs": "out",
+              "passAsFile": "buildCommand",
+              "patches": "",
+              "propagatedBuildInputs": "",
+              "propagatedNativeBuildInputs": "",
+              "stdenv": "/nix/store/9ip31vbkgg0lp4rx042svlllv3945nxq-stdenv-linux",
+              "strictDeps": "",
+              "system": "x86_64-linux"
+            },
+            "inputDrvs": {
+              "/nix/store/j2fnz39ss5iha58y76hrls6kf9nksxyj-crate-anstyle-query-1.1.1.tar.gz.drv": {
+                "dynamicOutputs": {},
+                "outputs": [
+                  "out"
+                ]
+              },
+              "/nix/store/s7hvxiy7934f539xy4df5mm6bsigvns0-bash-5.2p32.drv": {
+                "dynamicOutputs": {},
+                "outputs": [
+                  "out"
+                ]
+              },
+              "/nix/store/w7ab6iz9by4fn92y8j2qj0nknfxq9snl-stdenv-linux.drv": {
+                "dynamicOutputs": {},
+                "outputs": [
+                  "out"
+                ]
+              }
+            },
+            "inputSrcs": [
+              "/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"
+            ],
+            "name": "anstyle-query-1.1.1",
+            "outputs": {
+              "out": {
+                "path": "/nix/store/vbry7m4bvv035bvwm1jspdv9ccj1h20m-anstyle-query-1.1.1"
+              }
+            },
+            "system": "x86_64-linux"
+          }
+        "###;
+        let derivation: Derivation = serde_json::from_str(derivation).unwrap();
+        assert_eq!(derivation.get_name(), Some("anstyle-query".to_string()));
+        assert_eq!(derivation.get_version(), Some("1.1.1".to_string()));
+    }
+
+    #[test]
+    pub fn test_get_name_from_pname() {
+        let derivation: &str = r###"
+          {
+            "args": [
+              "-e",
+              "/nix/store/6xg259477c90a229xwmb53pdfkn6ig3g-default-builder.sh"
+            ],
+            "builder": "/nix/store/wllx077cz9z34zgrhwj2fc8r5r1hn6mx-bash-5.2-p15/bin/bash",
+            "env": {
+              "LANG": "C.UTF-8",
+              "__structuredAttrs": "",
+              "buildInputs": "/nix/store/3806m4syrck3andi5bw1jygclzrkbqnw-cairo-1.16.0-dev",
+              "builder": "/nix/store/wllx077cz9z34zgrhwj2fc8r5r1hn6mx-bash-5.2-p15/bin/bash",
+              "cmakeFlags": "",
+              "configureFlags": "",
+              "depsBuildBuild": "",
+              "depsBuildBuildPropagated": "",
+              "depsBuildTarget": "",
+              "depsBuildTargetPropagated": "",
+              "depsHostHost": "",
+              "depsHostHostPropagated": "",
+              "depsTargetTarget": "",
+              "depsTargetTargetPropagated": "",
+              "disallowedReferences": "",
+              "doCheck": "",
+              "doInstallCheck": "1",
+              "mesonFlags": "-Dpython=/nix/store/bhnfk4sy0ki5kp7hrbcq7pyqppbg7cwv-python3-3.10.13/bin/python3.10",
+              "name": "python3.10-pycairo-1.23.0",
+              "nativeBuildInputs": "/nix/store/bhnfk4sy0ki5kp7hrbcq7pyqppbg7cwv-python3-3.10.13 /nix/store/pg4wl9d9c6wb9i50wrgzxp5iqb0s5vbf-wrap-python-hook /nix/store/2znsgzazszhd9jns39fc688c15sdqakw-ensure-newer-sources-hook /nix/store/6ljpqq1imwfxadh586lj33ck7s84cgzv-python-remove-tests-dir-hook /nix/store/zq4g4h4dqvlk72nr9plcrqacxl37z3s1-python-catch-conflicts-hook /nix/store/3srpdh3b7an72xjnsdcl0h1ybpqwfq40-python-remove-bin-bytecode-hook /nix/store/vhns06ximyi3r11qy4zv3f63vrlg7kss-python-imports-check-hook.sh /nix/store/gzzh23js0w8mjr578j6sy7zlw6qlnzqz-python-namespaces-hook.sh /nix/store/aqjdg2d3g7nd4p0lwgr13v66vap3w9kd-meson-1.1.0 /nix/store/g0m0q186glhbxzv2mbh6y201r0gw989x-ninja-1.11.1 /nix/store/7g8wjkxx774bbnx0v06xc58qap2pvj6l-pkg-config-wrapper-0.29.2 /nix/store/459n6kmy4hsdaq8lr8s7ap7bshzymv2w-pytest-check-hook",
+              "out": "/nix/store/cp5adghz8pyv01a15w1a8xxzmhqrv7nj-python3.10-pycairo-1.23.0",
+              "outputs": "out",
+              "patches": "",
+              "pname": "pycairo",
+              "postFixup": "wrapPythonPrograms\n",
+              "propagatedBuildInputs": "/nix/store/bhnfk4sy0ki5kp7hrbcq7pyqppbg7cwv-python3-3.10.13",
+              "propagatedNativeBuildInputs": "",
+              "src": "/nix/store/ai0y1sqcklhjzjg04s7js5xc92g9j542-source",
+              "stdenv": "/nix/store/zpcxdbzssnhig0czrfvc1bd33lzrdy2i-stdenv-linux",
+              "strictDeps": "1",
+              "system": "i686-linux",
+              "version": "1.23.0"
+            },
+            "inputDrvs": {
+              "/nix/store/098ldl2py7yz31vwry8nhcjwrvs8gp56-source.drv": [
+                "out"
               ],
-              "maintainers": [
-                {
-                  "email": "nixpkgs@cvpetegem.be",
-                  "github": "chvp",
-                  "githubId": 42220376,
-                  "matrix": "@charlotte:vanpetegem.me",
-                  "name": "Charlotte Van Petegem"
-                },
-                {
-                  "email": "dev.primeos@gmail.com",
-                  "github": "primeos",
-                  "githubId": 7537109,
-                  "keys": [
-                    {
-                      "fingerprint": "86A7 4A55 07D0 58D1 322E  37FD 1308 26A6 C2A3 89FD",
-                      "longkeyid": "ed25519/0x130826A6C2A389FD"
-                    },
-                    {
-                      "fingerprint": "AF85 991C C950 49A2 4205  1933 BCA9 943D D1DF 4C04",
-                      "longkeyid": "rsa3072/0xBCA9943DD1DF4C04"
-                    }
-                  ],
-                  "matrix": "@primeos:matrix.org",
-                  "name": "Michael Weiss"
-                }
+              "/nix/store/3mrp1f80d1838n6wrmcn2w8dbzd1b53d-python-remove-tests-dir-hook.drv": [
+                "out"
               ],
-              "name": "0ad-0.0.26",
-              "outputsToInstall": [
+              "/nix/store/7aqhla8b73157glwx8klvnvdq014ykri-python-namespaces-hook.sh.drv": [
                 "out"
               ],
-              "platforms": [
-                "aarch64-linux",
-                "armv5tel-linux",
-                "armv6l-linux",
-                "armv7a-linux",
-                "armv7l-linux",
-                "m68k-linux",
-                "microblaze-linux",
-                "microblazeel-linux",
-                "mipsel-linux",
-                "mips64el-linux",
-                "powerpc64-linux",
-                "powerpc64le-linux",
-                "riscv32-linux",
-                "riscv64-linux",
-                "s390-linux",
-                "s390x-linux",
-                "x86_64-linux"
+              "/nix/store/9m6yhn4w3myqhk6w83xfkl81w7d71avl-stdenv-linux.drv": [
+                "out"
               ],
-              "unfree": false,
-              "unsupported": false
+              "/nix/store/ampp49cj5qvql9wlxarjpfybpqvn12nm-pytest-check-hook.drv": [
+                "out"
+              ],
+              "/nix/store/drqc6nfs5jg0cq6a65smcml6zysvbrnp-cairo-1.16.0.drv": [
+                "dev"
+              ],
+              "/nix/store/h4nfr8plqsc7628xgngqvc3ddr1kbj65-ensure-newer-sources-hook.drv": [
+                "out"
+              ],
+              "/nix/store/lj7902rl4qzxg8aa60y49m802759nh1p-meson-1.1.0.drv": [
+                "out"
+              ],
+              "/nix/store/mp10xqzg2ww4v0i7v81ccz5k0x9im77q-bash-5.2-p15.drv": [
+                "out"
+              ],
+              "/nix/store/njsalvap31p8vxyl22pzrdjxn34fb1kv-python3-3.10.13.drv": [
+                "out"
+              ],
+              "/nix/store/q3lg2gv800gzhx123jgyc2y85zv7i15y-python-imports-check-hook.sh.drv": [
+                "out"
+              ],
+              "/nix/store/rmi4ax1bzwdn0kgy2pr0yxrkcakyaw2d-pkg-config-wrapper-0.29.2.drv": [
+                "out"
+              ],
+              "/nix/store/s6da7mav0ac0fmqc4f5vrbh3qlfhgyx7-wrap-python-hook.drv": [
+                "out"
+              ],
+              "/nix/store/v8hm8h7cz5jj191fx071m18l04mp2vbx-ninja-1.11.1.drv": [
+                "out"
+              ],
+              "/nix/store/x28mrjkg28i9srjyhw1249f3g42hzyj4-python-catch-conflicts-hook.drv": [
+                "out"
+              ],
+              "/nix/store/y7qkcyqqlgy24hc42vfzildzhx5nx9pi-python-remove-bin-bytecode-hook.drv": [
+                "out"
+              ]
+            },
+            "inputSrcs": [
+              "/nix/store/6xg259477c90a229xwmb53pdfkn6ig3g-default-builder.sh"
+            ],
+            "outputs": {
+              "out": {
+                "path": "/nix/store/cp5adghz8pyv01a15w1a8xxzmhqrv7nj-python3.10-pycairo-1.23.0"
+              }
+            },
+            "system": "i686-linux"
+
+          }
+        "###;
+        let derivation: Derivation = serde_json::from_str(derivation).unwrap();
+        assert_eq!(derivation.get_name(), Some("pycairo".to_string()));
+        assert_eq!(derivation.get_version(), Some("1.23.0".to_string()));
+    }
+
+    #[test]
+    pub fn test_test_and_doc_exclusion_reasons() {
+        let derivation: &str = r###"
+          {
+            "args": [],
+            "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+            "env": {
+              "name": "pytest-check-hook"
+            },
+            "inputDrvs": {},
+            "inputSrcs": [],
+            "outputs": {
+              "out": {
+                "path": "/nix/store/459n6kmy4hsdaq8lr8s7ap7bshzymv2w-pytest-check-hook"
+              }
+            },
+            "system": "x86_64-linux"
+          }
+        "###;
+        let mut derivation: Derivation = serde_json::from_str(derivation).unwrap();
+        assert!(derivation.is_check_hook());
+        assert!(!derivation.has_documentation_name());
+
+        derivation
+            .env
+            .insert("name".to_string(), "zstd-1.5.5-man".to_string());
+        derivation.env.insert("version".to_string(), "1.5.5".to_string());
+        assert!(!derivation.is_check_hook());
+        assert!(derivation.has_documentation_name());
+        assert!(!derivation.has_documentation_outputs_only());
+
+        derivation
+            .env
+            .insert("name".to_string(), "glibc-locales".to_string());
+        derivation.outputs = HashMap::from([(
+            "man".to_string(),
+            Output {
+                path: "/nix/store/qxv3dnwvi2xw1kx8bhf8lcyssbdvna8d-glibc-locales-man".to_string(),
+                hash: None,
+                hash_algo: None,
+            },
+        )]);
+        assert!(derivation.has_documentation_outputs_only());
+        assert!(!derivation.has_documentation_name());
+
+        // The version is removed from the name before looking at the suffix.
+        derivation
+            .env
+            .insert("name".to_string(), "openssl-tests-3.0.13".to_string());
+        derivation.env.insert("version".to_string(), "3.0.13".to_string());
+        derivation.outputs = HashMap::default();
+        assert!(derivation.has_test_name());
+        assert!(!derivation.is_nixos_test());
+
+        derivation
+            .env
+            .insert("name".to_string(), "vm-test-run-zstd".to_string());
+        derivation.env.remove("version");
+        derivation
+            .env
+            .insert("requiredSystemFeatures".to_string(), "kvm nixos-test".to_string());
+        assert!(derivation.is_nixos_test());
+    }
+
+    #[test]
+    pub fn test_prune_test_and_doc_nodes() {
+        let derivations: &str = r###"
+          {
+            "/nix/store/a-app-1.0.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {
+                "name": "app-1.0",
+                "version": "1.0",
+                "nativeBuildInputs": "/nix/store/c-openssl-tests-3.0.13 /nix/store/e-app-manual-1.0 /nix/store/g-pytest-check-hook"
+              },
+              "inputDrvs": {
+                "/nix/store/b-libfoo-test-2.0.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/c-openssl-tests-3.0.13.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/d-vm-test-run-app.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/e-app-manual-1.0.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/f-glibc-locales-2.39.drv": { "dynamicOutputs": {}, "outputs": ["man"] },
+                "/nix/store/g-pytest-check-hook.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app-1.0" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/b-libfoo-test-2.0.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "libfoo-test-2.0", "version": "2.0" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-libfoo-test-2.0" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/c-openssl-tests-3.0.13.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "openssl-tests-3.0.13", "version": "3.0.13" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/c-openssl-tests-3.0.13" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/d-vm-test-run-app.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "vm-test-run-app", "requiredSystemFeatures": "kvm nixos-test" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/d-vm-test-run-app" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/e-app-manual-1.0.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "app-manual-1.0", "version": "1.0" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/e-app-manual-1.0" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/f-glibc-locales-2.39.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "glibc-locales-2.39", "version": "2.39" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "man": { "path": "/nix/store/f-glibc-locales-2.39-man" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/g-pytest-check-hook.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "pytest-check-hook" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/g-pytest-check-hook" } },
+              "system": "x86_64-linux"
+            }
+          }
+        "###;
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        // The library is tested through the passthru.tests of its package.
+        package_graph
+            .nodes
+            .get_mut("/nix/store/b-libfoo-test-2.0.drv")
+            .unwrap()
+            .package = Some(
+            serde_json::from_str(
+                r#"{ "name": "libfoo-test-2.0", "pname": "libfoo-test", "version": "2.0", "attributePath": "libfoo-test", "meta": {} }"#,
+            )
+            .unwrap(),
+        );
+        assert!(!package_graph.nodes["/nix/store/b-libfoo-test-2.0.drv"].is_passthru_test());
+        let mut passthru_test_node = package_graph.nodes["/nix/store/b-libfoo-test-2.0.drv"].clone();
+        passthru_test_node.package.as_mut().unwrap().attribute_path =
+            Some("libfoo-test.tests.version".to_string());
+        assert!(passthru_test_node.is_passthru_test());
+        assert_eq!(
+            passthru_test_node.get_test_or_doc_exclusion_reason(true),
+            Some(TEST_FIXTURE_EXCLUSION_REASON)
+        );
+
+        package_graph.prune_test_and_doc_nodes();
+        // The library is a runtime input of the application, so its name is not used.
+        assert_eq!(
+            package_graph.nodes.keys().collect::<Vec<&String>>(),
+            vec!["/nix/store/a-app-1.0.drv", "/nix/store/b-libfoo-test-2.0.drv"]
+        );
+        assert_eq!(
+            package_graph.excluded_nodes,
+            BTreeMap::from([
+                (
+                    "/nix/store/c-openssl-tests-3.0.13.drv".to_string(),
+                    TEST_FIXTURE_EXCLUSION_REASON.to_string()
+                ),
+                (
+                    "/nix/store/d-vm-test-run-app.drv".to_string(),
+                    TEST_FIXTURE_EXCLUSION_REASON.to_string()
+                ),
+                (
+                    "/nix/store/e-app-manual-1.0.drv".to_string(),
+                    DOCUMENTATION_EXCLUSION_REASON.to_string()
+                ),
+                (
+                    "/nix/store/f-glibc-locales-2.39.drv".to_string(),
+                    DOCUMENTATION_EXCLUSION_REASON.to_string()
+                ),
+                (
+                    "/nix/store/g-pytest-check-hook.drv".to_string(),
+                    CHECK_HOOK_EXCLUSION_REASON.to_string()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    pub fn test_parse_nix_search_output() {
+        let search_output: &str = r###"
+          {
+            "legacyPackages.x86_64-linux.hello": {
+              "description": "Program that produces a familiar, friendly greeting",
+              "pname": "hello",
+              "version": "2.12.1"
+            },
+            "legacyPackages.x86_64-linux.zstd": {
+              "description": "",
+              "pname": "zstd",
+              "version": "1.5.5"
+            }
+          }
+        "###;
+        let packages = parse_nix_search_output(search_output.as_bytes()).unwrap();
+        assert_eq!(packages.len(), 2);
+        let hello = packages.get("legacyPackages.x86_64-linux.hello").unwrap();
+        assert_eq!(hello.name, "hello-2.12.1");
+        assert_eq!(hello.system, "x86_64-linux");
+        assert_eq!(hello.attribute_path, Some("hello".to_string()));
+        assert!(hello.meta.description.is_some());
+        let zstd = packages.get("legacyPackages.x86_64-linux.zstd").unwrap();
+        assert!(zstd.meta.description.is_none());
+    }
+
+    #[test]
+    pub fn test_meta_fields() {
+        let metadata_path =
+            std::env::temp_dir().join(format!("nix2sbom-test-meta-fields-{}.json", std::process::id()));
+        std::fs::write(
+            &metadata_path,
+            r###"
+            {
+              "hello": {
+                "name": "hello-2.12.1",
+                "pname": "hello",
+                "version": "2.12.1",
+                "meta": {
+                  "description": "Program that produces a familiar, friendly greeting",
+                  "license": "GPL-3.0-or-later",
+                  "maintainers": [{ "email": "jane@example.com", "name": "Jane Doe" }],
+                  "unfree": false
+                }
+              }
+            }
+            "###,
+        )
+        .unwrap();
+        let metadata_options = MetadataOptions {
+            metadata_paths: vec![metadata_path.display().to_string()],
+            meta_fields: Some(vec!["license".to_string()]),
+            ..Default::default()
+        };
+        let (packages, _metadata_source, _invalid_packages) = get_packages_with_source(
+            &metadata_options,
+            &Derivations::default(),
+            &crate::backend::MockBackend::default(),
+        )
+        .unwrap();
+        std::fs::remove_file(&metadata_path).unwrap();
+        let hello = &packages["hello"];
+        assert!(hello.meta.license.is_some());
+        assert!(hello.meta.description.is_none());
+        assert!(hello.meta.maintainers.is_none());
+        assert_eq!(hello.meta.unfree, Some(false));
+
+        // Only the selected fields and the status fields are evaluated.
+        let function = crate::flake::get_package_metadata_function(Some(&["license".to_string()]));
+        assert!(function.contains("license = null;"));
+        assert!(function.contains("unfree = null;"));
+        assert!(!function.contains("maintainers = null;"));
+        assert!(crate::flake::get_package_metadata_function(None).contains("maintainers = null;"));
+
+        assert!(validate_meta_fields(&["license".to_string(), "homepage".to_string()]).is_ok());
+        assert!(validate_meta_fields(&["email".to_string()]).is_err());
+    }
+
+    #[test]
+    pub fn test_add_package_metas() {
+        let search_output: &str = r###"
+          {
+            "legacyPackages.x86_64-linux.hello": { "description": "", "pname": "hello", "version": "2.12.1" },
+            "legacyPackages.x86_64-linux.zstd": { "description": "", "pname": "zstd", "version": "1.5.5" }
+          }
+        "###;
+        let mut packages = parse_nix_search_output(search_output.as_bytes()).unwrap();
+        let derivations: Derivations = serde_json::from_str(
+            r###"
+            {
+              "/nix/store/a-hello-2.12.1.drv": {
+                "args": [],
+                "builder": "/bin/sh",
+                "env": { "name": "hello-2.12.1" },
+                "inputDrvs": {},
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/a-hello-2.12.1" } },
+                "system": "x86_64-linux"
+              }
+            }
+            "###,
+        )
+        .unwrap();
+        let backend = crate::backend::MockBackend {
+            package_metas: BTreeMap::from([
+                (
+                    "nixpkgs#hello".to_string(),
+                    serde_json::from_str(r#"{ "license": "GPL-3.0-or-later", "homepage": "https://www.gnu.org/software/hello/manual/" }"#)
+                        .unwrap(),
+                ),
+                (
+                    "nixpkgs#zstd".to_string(),
+                    serde_json::from_str(r#"{ "license": "BSD-3-Clause" }"#).unwrap(),
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        add_package_metas(&mut packages, &derivations, &backend);
+        let hello = &packages["legacyPackages.x86_64-linux.hello"];
+        assert_eq!(
+            hello.meta.get_spdx_license_expression(),
+            Some("GPL-3.0-or-later".to_string())
+        );
+        assert_eq!(hello.meta.get_homepages().len(), 1);
+        // The packages that are not in the graph are not evaluated.
+        assert!(packages["legacyPackages.x86_64-linux.zstd"]
+            .meta
+            .license
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_redact_maintainers() {
+        let packages: &str = r###"
+          {
+            "hello": {
+              "name": "hello-2.12.1",
+              "pname": "hello",
+              "version": "2.12.1",
+              "meta": {
+                "maintainers": [
+                  { "email": "Jane@example.com", "name": "Jane Doe", "github": "janedoe" },
+                  "john@example.com"
+                ]
+              }
+            }
+          }
+        "###;
+        let packages: Packages = serde_json::from_str(packages).unwrap();
+        let get_maintainers = |key: &str| {
+            let mut packages = packages.clone();
+            redact_maintainers(&mut packages, key);
+            packages["hello"].meta.maintainers.clone().unwrap()
+        };
+
+        let maintainers = get_maintainers("secret");
+        let maintainer = match &maintainers[0] {
+            PackageManagerItem::Details(m) => m,
+            m => panic!("Unexpected maintainer {:?}", m),
+        };
+        assert_eq!(
+            maintainer.email,
+            Some(get_redacted_id("jane@example.com", "secret"))
+        );
+        assert!(maintainer.email.as_ref().unwrap().starts_with("redacted-"));
+        assert_eq!(maintainer.name, "Jane Doe");
+        assert_eq!(maintainer.github_username, Some("janedoe".to_string()));
+        assert_eq!(
+            maintainers[1],
+            PackageManagerItem::String(get_redacted_id("john@example.com", "secret"))
+        );
+
+        // The identifiers are stable for a key, and cannot be computed without it.
+        assert_eq!(get_maintainers("secret"), maintainers);
+        assert_ne!(get_maintainers("other"), maintainers);
+        assert_ne!(
+            get_redacted_id("jane@example.com", "secret"),
+            format!("redacted-{}", crate::utils::get_stable_hash("jane@example.com"))
+        );
+    }
+
+    #[test]
+    pub fn test_metadata_backend() {
+        assert_eq!(
+            MetadataBackend::from_string("nix-eval"),
+            Some(MetadataBackend::NixEval("nixpkgs".to_string()))
+        );
+        assert_eq!(
+            MetadataBackend::from_string("nix-eval:github:NixOS/nixpkgs/nixos-24.05"),
+            Some(MetadataBackend::NixEval(
+                "github:NixOS/nixpkgs/nixos-24.05".to_string()
+            ))
+        );
+        assert_eq!(
+            MetadataBackend::from_string("nix-env"),
+            Some(MetadataBackend::NixEnv)
+        );
+        assert_eq!(MetadataBackend::from_string("nix-env:foo"), None);
+
+        assert!(get_metadata_cache_path("24.05.20240601.abcdef0")
+            .is_some_and(|p| p.ends_with("nix2sbom/metadata-24.05.20240601.abcdef0.json")));
+        assert_eq!(get_metadata_cache_path("24.11pre-git"), None);
+        assert_eq!(get_metadata_cache_path("../../etc"), None);
+
+        let derivations: Derivations = serde_json::from_str(
+            r###"
+          {
+            "/nix/store/a-hello-2.12.1.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "pname": "hello", "version": "2.12.1", "name": "hello-2.12.1" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-hello-2.12.1" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/b-hello-2.12.1.tar.gz.drv": {
+              "args": [],
+              "builder": "builtin:fetchurl",
+              "env": { "name": "hello-2.12.1.tar.gz", "url": "mirror://gnu/hello/hello-2.12.1.tar.gz" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-hello-2.12.1.tar.gz" } },
+              "system": "builtin"
+            }
+          }
+        "###,
+        )
+        .unwrap();
+        assert_eq!(
+            get_metadata_attribute_names(&derivations)
+                .into_iter()
+                .collect::<Vec<String>>(),
+            vec!["hello".to_string()]
+        );
+        assert!(
+            get_nix_eval_metadata_expression("nixpkgs", &["hello".to_string()], None)
+                .contains(r#"[ "hello" ]"#)
+        );
+    }
+
+    #[test]
+    pub fn test_get_invalid_packages() {
+        let channel_packages: &str = r###"
+          {
+            "version": 2,
+            "packages": {
+              "hello": {
+                "name": "hello-2.12.1",
+                "pname": "hello",
+                "version": "2.12.1",
+                "system": "x86_64-linux",
+                "outputName": "out",
+                "meta": { "maintainers": [{ "name": "Jane Doe", "github": "jdoe" }] }
+              },
+              "broken": {
+                "name": "broken-1.0",
+                "pname": "broken",
+                "version": "1.0",
+                "system": "x86_64-linux",
+                "outputName": "out",
+                "meta": { "maintainers": 42 }
+              }
             }
           }
         "###;
-        let package: Package = serde_json::from_str(package_metadata).unwrap();
-        assert_eq!(package.name, "0ad-0.0.26");
+        let invalid_packages = get_invalid_packages(channel_packages.as_bytes()).unwrap();
+        assert_eq!(invalid_packages.keys().collect::<Vec<_>>(), vec!["broken"]);
+        assert!(parse_packages(channel_packages.as_bytes()).is_err());
+        let (packages, invalid_packages) =
+            parse_packages_with_mode(channel_packages.as_bytes(), false).unwrap();
+        assert_eq!(packages.keys().collect::<Vec<_>>(), vec!["hello"]);
+        assert_eq!(invalid_packages.len(), 1);
+        let packages = parse_packages(channel_packages.replace("42", "[]").as_bytes()).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages["hello"].pname, "hello");
+        assert!(get_invalid_packages(b"[]").is_err());
     }
 
     #[test]
-    pub fn parse_package_metadata_embedded_maintainers_list() {
-        // This parsing issue was raised in https://github.com/louib/nix2sbom/issues/10
-        let package_metadata: &str = r###"
+    pub fn test_read_merged_packages() {
+        let directory = std::env::temp_dir().join(format!("nix2sbom-test-metadata-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let get_metadata = |version: &str| {
+            format!(
+                r#"{{ "hello": {{ "name": "hello-{0}", "pname": "hello", "version": "{0}", "system": "x86_64-linux", "outputName": "out", "meta": {{}} }} }}"#,
+                version
+            )
+        };
+        let stable_path = directory.join("stable.json").display().to_string();
+        let unstable_path = directory.join("unstable.json").display().to_string();
+        std::fs::write(&stable_path, get_metadata("2.12.1")).unwrap();
+        std::fs::write(&unstable_path, get_metadata("2.12.2")).unwrap();
+
+        let (packages, metadata_source, _invalid_packages) =
+            read_merged_packages(&[stable_path.clone(), unstable_path.clone()], true).unwrap();
+        assert_eq!(packages["hello"].version, "2.12.2");
+        assert_eq!(
+            metadata_source,
+            MetadataSource::Merged(vec![
+                MetadataSource::File(stable_path.clone()),
+                MetadataSource::File(unstable_path.clone()),
+            ])
+        );
+        let (packages, _metadata_source, _invalid_packages) =
+            read_merged_packages(&[unstable_path, stable_path], true).unwrap();
+        assert_eq!(packages["hello"].version, "2.12.1");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_get_derivations_from_json_file() {
+        let directory = std::env::temp_dir().join(format!("nix2sbom-test-derivations-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let derivations_path = directory.join("derivations.json").display().to_string();
+        std::fs::write(
+            &derivations_path,
+            r###"
+            {
+              "/nix/store/a-hello-2.12.1.drv": {
+                "args": [],
+                "builder": "/bin/sh",
+                "env": { "name": "hello-2.12.1" },
+                "inputDrvs": {},
+                "inputSrcs": [],
+                "outputs": { "out": { "path": "/nix/store/a-hello-2.12.1" } },
+                "system": "x86_64-linux"
+              }
+            }
+            "###,
+        )
+        .unwrap();
+        let derivations = Derivation::get_derivations_from_json_file(&derivations_path).unwrap();
+        assert_eq!(derivations.len(), 1);
+        assert_eq!(
+            derivations["/nix/store/a-hello-2.12.1.drv"].get_name(),
+            Some("hello".to_string())
+        );
+
+        std::fs::write(&derivations_path, "{}").unwrap();
+        assert!(Derivation::get_derivations_from_json_file(&derivations_path).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_get_store_path_name() {
+        assert_eq!(
+            get_store_path_name("/nix/store/9krlzvny65gdc8s7kpb6lkx8cd02c25b-default-builder.sh"),
+            "default-builder.sh"
+        );
+        assert_eq!(get_store_path_name("builder.sh"), "builder.sh");
+        assert_eq!(
+            get_store_path_hash("/nix/store/9krlzvny65gdc8s7kpb6lkx8cd02c25b-default-builder.sh"),
+            "9krlzvny65gdc8s7kpb6lkx8cd02c25b"
+        );
+
+        let dump_options = DumpOptions {
+            build_url_template: Some("https://hydra.example.com/build/{drvhash}?name={drvname}".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            dump_options.get_build_url("/nix/store/9krlzvny65gdc8s7kpb6lkx8cd02c25b-hello-2.12.1.drv"),
+            Some(
+                "https://hydra.example.com/build/9krlzvny65gdc8s7kpb6lkx8cd02c25b?name=hello-2.12.1.drv"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_add_platform_qualifiers() {
+        let derivations: &str = r###"
           {
-            "meta": {
-              "available": true,
-              "broken": false,
-              "description": "A parser generator for building parsers from grammars",
-              "homepage": "https://javacc.github.io/javacc",
-              "insecure": false,
-              "license": {
-                "deprecated": false,
-                "free": true,
-                "fullName": "BSD 2-clause \"Simplified\" License",
-                "redistributable": true,
-                "shortName": "bsd2",
-                "spdxId": "BSD-2-Clause",
-                "url": "https://spdx.org/licenses/BSD-2-Clause.html"
+            "/nix/store/a-zlib-1.3.1.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "zlib-1.3.1", "pname": "zlib", "version": "1.3.1" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-zlib-1.3.1" } },
+              "system": "aarch64-darwin"
+            }
+          }
+        "###;
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let zlib = &derivations["/nix/store/a-zlib-1.3.1.drv"];
+        assert_eq!(
+            zlib.get_platform(),
+            Some(("aarch64".to_string(), "darwin".to_string()))
+        );
+        assert_eq!(
+            Derivation::get_placeholder("/nix/store/b-src.drv").get_platform(),
+            None
+        );
+
+        let mut package_graph = get_package_graph(&derivations);
+        let purl = package_graph.nodes["/nix/store/a-zlib-1.3.1.drv"]
+            .get_purl()
+            .to_string();
+        assert!(!purl.contains('?'));
+        package_graph.add_platform_qualifiers();
+        let purl = package_graph.nodes["/nix/store/a-zlib-1.3.1.drv"]
+            .get_purl()
+            .to_string();
+        assert!(purl.ends_with("@1.3.1?arch=aarch64&os=darwin"));
+    }
+
+    #[test]
+    pub fn test_missing_derivation() {
+        let derivations: &str = r###"
+          {
+            "/nix/store/a-app.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/5c4zl3hmbjc5vh9nrxcx5pq6zm4hzkhh-zlib-1.3.1.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
               },
-              "maintainers": [
-                [
-                  {
-                    "email": "limeytexan@gmail.com",
-                    "github": "limeytexan",
-                    "githubId": 36448130,
-                    "name": "Michael Brantley"
-                  }
-                ]
-              ],
-              "name": "javacc-7.0.10",
-              "outputsToInstall": [
-                "out"
-              ],
-              "unfree": false,
-              "unsupported": false
-            },
-            "name": "javacc-7.0.10",
-            "outputName": "out",
-            "outputs": {
-              "out": null
-            },
-            "pname": "javacc",
-            "system": "x86_64-linux",
-            "version": "7.0.10"
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app" } },
+              "system": "x86_64-linux"
+            }
           }
         "###;
-        let package: Package = serde_json::from_str(package_metadata).unwrap();
-        assert_eq!(package.name, "javacc-7.0.10");
-        assert_eq!(package.meta.get_maintainers().len(), 1);
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        assert_eq!(
+            package_graph.get_incomplete_nodes(),
+            vec!["/nix/store/5c4zl3hmbjc5vh9nrxcx5pq6zm4hzkhh-zlib-1.3.1.drv".to_string()]
+        );
+        assert_eq!(package_graph.root_nodes.len(), 1);
+
+        package_graph.limit_to_system("x86_64-linux");
+        let placeholder_node =
+            &package_graph.nodes["/nix/store/5c4zl3hmbjc5vh9nrxcx5pq6zm4hzkhh-zlib-1.3.1.drv"];
+        assert_eq!(
+            placeholder_node.main_derivation.get_name(),
+            Some("zlib".to_string())
+        );
+        assert!(placeholder_node.get_warnings()[0].contains("missing from the store"));
     }
 
     #[test]
-    pub fn parse_package_metadata_maintainers_mixed() {
-        let package_metadata: &str = r###"
+    pub fn test_add_root_nodes() {
+        let derivations: &str = r###"
           {
-            "meta": {
-              "available": true,
-              "broken": false,
-              "description": "A software reverse engineering (SRE) suite of tools developed by NSA's Research Directorate in support of the Cybersecurity mission",
-              "homepage": "https://ghidra-sre.org/",
-              "insecure": false,
-              "license": {
-                "deprecated": false,
-                "free": true,
-                "fullName": "Apache License 2.0",
-                "redistributable": true,
-                "shortName": "asl20",
-                "spdxId": "Apache-2.0",
-                "url": "https://spdx.org/licenses/Apache-2.0.html"
+            "/nix/store/a-host.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/b-container.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
               },
-              "maintainers": [
-                "roblabla",
-                [
-                    {
-                        "email": "dev.primeos@gmail.com",
-                        "github": "primeos",
-                        "githubId": 7537109,
-                        "keys": [
-                            {
-                                "fingerprint": "86A7 4A55 07D0 58D1 322E  37FD 1308 26A6 C2A3 89FD",
-                                "longkeyid": "ed25519/0x130826A6C2A389FD"
-                            },
-                            {
-                                "fingerprint": "AF85 991C C950 49A2 4205  1933 BCA9 943D D1DF 4C04",
-                                "longkeyid": "rsa3072/0xBCA9943DD1DF4C04"
-                            }
-                        ],
-                        "matrix": "@primeos:matrix.org",
-                        "name": "Michael Weiss"
-                    },
-                    {
-                        "email": "nix@hilhorst.be",
-                        "github": "Synthetica9",
-                        "githubId": 7075751,
-                        "name": "Patrick Hilhorst"
-                    },
-                    {
-                        "email": "maximilian@mbosch.me",
-                        "github": "ma27",
-                        "githubId": 6025220,
-                        "matrix": "@ma27:nicht-so.sexy",
-                        "name": "Maximilian Bosch"
-                    }
-                ],
-                {
-                    "email": "evils.devils@protonmail.com",
-                    "github": "evils",
-                    "githubId": 30512529,
-                    "matrix": "@evils:nixos.dev",
-                    "name": "Evils"
-                }
-              ],
-              "name": "ghidra-10.1.2",
-              "outputsToInstall": [
-                "out"
-              ],
-              "platforms": [
-                "x86_64-linux",
-                "x86_64-darwin"
-              ],
-              "position": "/nix/store/3nyf6ydmhggdskci66mv0vlyfwwinx0l-nixos/nixos/pkgs/tools/security/ghidra/build.nix:171",
-              "unfree": false,
-              "unsupported": false
-            },
-            "name": "ghidra-10.1.2",
-            "outputName": "out",
-            "outputs": {
-              "out": null
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-host" } },
+              "system": "x86_64-linux"
             },
-            "pname": "ghidra",
-            "system": "x86_64-linux",
-            "version": "10.1.2"
+            "/nix/store/b-container.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-container" } },
+              "system": "x86_64-linux"
+            }
           }
         "###;
-        let package: Package = serde_json::from_str(package_metadata).unwrap();
-        assert_eq!(package.name, "ghidra-10.1.2");
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        assert_eq!(
+            package_graph.root_nodes,
+            BTreeSet::from(["/nix/store/a-host.drv".to_string()])
+        );
+
+        let mut container_derivations = derivations.clone();
+        container_derivations.remove("/nix/store/a-host.drv");
+        let container_roots = get_root_derivation_paths(&container_derivations);
+        assert_eq!(
+            container_roots,
+            BTreeSet::from(["/nix/store/b-container.drv".to_string()])
+        );
+        package_graph.add_root_nodes(&container_roots);
+        package_graph.add_root_nodes(&BTreeSet::from(["/nix/store/c-unknown.drv".to_string()]));
+        assert_eq!(package_graph.root_nodes.len(), 2);
     }
 
     #[test]
-    pub fn parse_package_metadata_malformed_maintainers() {
-        let package_metadata: &str = r###"
+    pub fn test_get_stats() {
+        let derivations: &str = r###"
           {
-            "meta": {
-              "available": false,
-              "broken": false,
-              "description": "Software for rapid LiDAR processing",
-              "homepage": "http://lastools.org/",
-              "insecure": false,
-              "license": {
-                "deprecated": false,
-                "free": false,
-                "fullName": "Unfree",
-                "redistributable": false,
-                "shortName": "unfree"
+            "/nix/store/a-hello-2.12.1.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "hello-2.12.1" },
+              "inputDrvs": {
+                "/nix/store/b-zlib-1.3.1.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
               },
-              "maintainers": [
-                {
-                  "github": "StephenWithPH",
-                  "githubId": 2990492,
-                  "name": "StephenWithPH"
-                }
-              ],
-              "name": "LAStools-2.0.2",
-              "outputsToInstall": [
-                "out"
-              ],
-              "platforms": [
-                "i686-cygwin",
-                "x86_64-cygwin",
-                "x86_64-darwin",
-                "i686-darwin",
-                "aarch64-darwin",
-                "armv7a-darwin",
-                "i686-freebsd13",
-                "x86_64-freebsd13",
-                "x86_64-solaris",
-                "aarch64-linux",
-                "armv5tel-linux",
-                "armv6l-linux",
-                "armv7a-linux",
-                "armv7l-linux",
-                "i686-linux",
-                "loongarch64-linux",
-                "m68k-linux",
-                "microblaze-linux",
-                "microblazeel-linux",
-                "mipsel-linux",
-                "mips64el-linux",
-                "powerpc64-linux",
-                "powerpc64le-linux",
-                "riscv32-linux",
-                "riscv64-linux",
-                "s390-linux",
-                "s390x-linux",
-                "x86_64-linux",
-                "aarch64-netbsd",
-                "armv6l-netbsd",
-                "armv7a-netbsd",
-                "armv7l-netbsd",
-                "i686-netbsd",
-                "m68k-netbsd",
-                "mipsel-netbsd",
-                "powerpc-netbsd",
-                "riscv32-netbsd",
-                "riscv64-netbsd",
-                "x86_64-netbsd",
-                "i686-openbsd",
-                "x86_64-openbsd",
-                "x86_64-redox"
-              ],
-              "unfree": true,
-              "unsupported": false
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-hello-2.12.1" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/b-zlib-1.3.1.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "zlib-1.3.1" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-zlib-1.3.1" } },
+              "system": "x86_64-linux"
+            }
+          }
+        "###;
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        package_graph.nodes_next = package_graph.nodes.clone();
+
+        let stats = package_graph.get_stats(&StatsOptions::default());
+        assert_eq!(stats.schema_version, STATS_SCHEMA_VERSION);
+        assert_eq!(stats.reachable_nodes_count["/nix/store/a-hello-2.12.1.drv"], 2);
+        assert_eq!(stats.longest_path.len(), 2);
+        assert_eq!(stats.purl_scope_count["generic"], 2);
+        assert_eq!(stats.license_count, None);
+
+        let stats = package_graph.get_stats(&StatsOptions {
+            per_root: false,
+            purl_types: false,
+            licenses: true,
+            ..Default::default()
+        });
+        assert!(stats.reachable_nodes_count.is_empty());
+        assert!(stats.purl_scope_count.is_empty());
+        assert_eq!(
+            stats.license_count,
+            Some(BTreeMap::from([("NOASSERTION".to_string(), 2)]))
+        );
+    }
+
+    #[test]
+    pub fn test_get_substituted_derivations() {
+        let derivations: &str = r###"
+          {
+            "/nix/store/a-system.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-system" } },
+              "system": "x86_64-linux"
+            }
+          }
+        "###;
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let path_infos: &str = r###"
+          {
+            "/nix/store/a-system": {
+              "deriver": "/nix/store/a-system.drv",
+              "narHash": "sha256-AAAA",
+              "references": ["/nix/store/b-zlib-1.3.1"]
             },
-            "name": "LAStools-2.0.2",
-            "outputName": "out",
-            "outputs": {
-              "out": null
+            "/nix/store/b-zlib-1.3.1": {
+              "deriver": "/nix/store/c-zlib-1.3.1.drv",
+              "narHash": "sha256-BBBB",
+              "references": ["/nix/store/b-zlib-1.3.1"]
             },
-            "pname": "LAStools",
-            "system": "x86_64-linux",
-            "version": "2.0.2"
+            "/nix/store/d-zlib-1.3.1-dev": {
+              "deriver": "/nix/store/c-zlib-1.3.1.drv",
+              "narHash": "sha256-DDDD",
+              "references": ["/nix/store/b-zlib-1.3.1"]
+            }
           }
         "###;
-        let package: Package = serde_json::from_str(package_metadata).unwrap();
-        assert_eq!(package.name, "LAStools-2.0.2");
+        let path_infos: PathInfos = serde_json::from_str(path_infos).unwrap();
+        let substituted_derivations =
+            Derivation::get_substituted_derivations(&derivations, &path_infos.into_map());
+        assert_eq!(substituted_derivations.len(), 1);
+
+        let zlib = &substituted_derivations["/nix/store/c-zlib-1.3.1.drv"];
+        assert_eq!(zlib.get_name(), Some("zlib".to_string()));
+        assert_eq!(zlib.outputs["out"].path, "/nix/store/b-zlib-1.3.1");
+        assert_eq!(zlib.outputs["dev"].path, "/nix/store/d-zlib-1.3.1-dev");
+        assert_eq!(zlib.nar_hash, Some("sha256-BBBB".to_string()));
+        assert!(zlib.input_derivations.is_empty());
+
+        // The substituted paths are not missing from the store, so they are not incomplete.
+        let mut all_derivations = derivations.clone();
+        all_derivations.extend(substituted_derivations);
+        let mut package_graph = get_package_graph(&all_derivations);
+        let zlib_node = &package_graph.nodes["/nix/store/c-zlib-1.3.1.drv"];
+        assert!(zlib_node.substituted);
+        assert!(!zlib_node.incomplete);
+        assert!(!package_graph.nodes["/nix/store/a-system.drv"].substituted);
+        assert!(package_graph.get_incomplete_nodes().is_empty());
+        package_graph.limit_to_system("x86_64-linux");
+        assert!(package_graph.nodes.contains_key("/nix/store/c-zlib-1.3.1.drv"));
     }
 
     #[test]
-    pub fn get_version_from_rev() {
-        let derivation: &str = r###"
+    pub fn test_limit_depth() {
+        let derivations: &str = r###"
           {
-            "args": [
-              "-e",
-              "/nix/store/v7wqh83pzn39kjx6pdfixwyqlbmsqid3-builder.sh"
-            ],
-            "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
-            "env": {
-              "GIT_SSL_CAINFO": "/nix/store/9hx76jndjw5881pb83ghvlq6k4aqagz4-nss-cacert-3.92/etc/ssl/certs/ca-bundle.crt",
-              "__structuredAttrs": "",
-              "buildInputs": "",
-              "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
-              "cmakeFlags": "",
-              "configureFlags": "",
-              "deepClone": "",
-              "depsBuildBuild": "",
-              "depsBuildBuildPropagated": "",
-              "depsBuildTarget": "",
-              "depsBuildTargetPropagated": "",
-              "depsHostHost": "",
-              "depsHostHostPropagated": "",
-              "depsTargetTarget": "",
-              "depsTargetTargetPropagated": "",
-              "doCheck": "",
-              "doInstallCheck": "",
-              "fetchLFS": "",
-              "fetchSubmodules": "1",
-              "fetcher": "/nix/store/fqkqnkkwzhqn21fh9ba4nz75nhd89irm-nix-prefetch-git",
-              "impureEnvVars": "http_proxy https_proxy ftp_proxy all_proxy no_proxy GIT_PROXY_COMMAND NIX_GIT_SSL_CAINFO SOCKS_SERVER",
-              "leaveDotGit": "",
-              "mesonFlags": "",
-              "name": "source",
-              "nativeBuildInputs": "/nix/store/zy5r5ssh2zk6n1k34gv09fd9865lcniq-git-minimal-2.40.1",
-              "nonConeMode": "",
-              "out": "/nix/store/gj39c9gmjz3z5f6lgkcsl0lc07fwhq0c-source",
-              "outputHash": "sha256-I3PGgh0XqRkCFz7lUZ3Q4eU0+0GwaQcVb6t4Pru1kKo=",
-              "outputHashMode": "recursive",
-              "outputs": "out",
-              "patches": "",
-              "postFetch": "",
-              "preferLocalBuild": "1",
-              "propagatedBuildInputs": "",
-              "propagatedNativeBuildInputs": "",
-              "rev": "v0.8.2",
-              "sparseCheckout": "",
-              "stdenv": "/nix/store/9v8sc2q2dflxjcz1hsw84b10bvg0wand-stdenv-linux",
-              "strictDeps": "",
-              "system": "x86_64-linux",
-              "url": "https://github.com/libjxl/libjxl.git"
+            "/nix/store/a-app.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/b-lib.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app" } },
+              "system": "x86_64-linux"
             },
-            "inputDrvs": {
-              "/nix/store/331fppp0q0n5xy5mrhkg3abp3sbpb869-stdenv-linux.drv": [
-                "out"
-              ],
-              "/nix/store/33xwn0p89b0iwqxqdnp36hyy138flhkg-nss-cacert-3.92.drv": [
-                "out"
-              ],
-              "/nix/store/hla091y2jgs76hd8ps5ky6d81qzkdfz5-bash-5.2-p15.drv": [
-                "out"
-              ],
-              "/nix/store/pz619bwk8qgpb0zd3g11fm0hclk3pfz3-git-minimal-2.40.1.drv": [
-                "out"
-              ]
+            "/nix/store/b-lib.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/c-libc.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-lib" } },
+              "system": "x86_64-linux"
             },
-            "inputSrcs": [
-              "/nix/store/fqkqnkkwzhqn21fh9ba4nz75nhd89irm-nix-prefetch-git",
-              "/nix/store/v7wqh83pzn39kjx6pdfixwyqlbmsqid3-builder.sh"
-            ],
-            "outputs": {
-              "out": {
-                "hash": "2373c6821d17a91902173ee5519dd0e1e534fb41b06907156fab783ebbb590aa",
-                "hashAlgo": "r:sha256",
-                "path": "/nix/store/gj39c9gmjz3z5f6lgkcsl0lc07fwhq0c-source"
-              }
+            "/nix/store/c-libc.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/c-libc" } },
+              "system": "x86_64-linux"
+            }
+          }
+        "###;
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        assert_eq!(package_graph.nodes.len(), 3);
+
+        package_graph.limit_depth(1);
+        assert_eq!(package_graph.nodes.len(), 2);
+        assert!(package_graph.nodes.contains_key("/nix/store/b-lib.drv"));
+        assert_eq!(
+            package_graph.excluded_nodes.get("/nix/store/c-libc.drv"),
+            Some(&EVAL_DEPTH_EXCLUSION_REASON.to_string())
+        );
+        assert!(package_graph
+            .nodes
+            .get("/nix/store/b-lib.drv")
+            .unwrap()
+            .children
+            .is_empty());
+    }
+
+    #[test]
+    pub fn test_get_warnings() {
+        let derivations: &str = r###"
+          {
+            "/nix/store/a-hello-2.12.1.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "hello-2.12.1", "pname": "hello", "version": "2.12.1" },
+              "inputDrvs": {
+                "/nix/store/b-zlib-1.3.1.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/c-missing.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-hello-2.12.1" } },
+              "system": "x86_64-linux"
             },
-            "system": "x86_64-linux"
+            "/nix/store/b-zlib-1.3.1.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "zlib-1.3.1" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-zlib-1.3.1" } },
+              "system": "x86_64-linux"
+            }
           }
         "###;
-        let derivation: Derivation = serde_json::from_str(derivation).unwrap();
-        assert_eq!(derivation.get_name(), Some("libjxl".to_string()));
-        assert_eq!(derivation.get_version(), Some("0.8.2".to_string()));
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+
+        let hello_node = package_graph
+            .nodes
+            .get_mut("/nix/store/a-hello-2.12.1.drv")
+            .unwrap();
+        hello_node.version = Some("2.12.1".to_string());
+        assert_eq!(
+            hello_node.get_warnings(),
+            vec![
+                "The package type could not be detected, so the purl uses the generic type.",
+                "No package metadata was found, so the description, licenses and maintainers are missing.",
+            ]
+        );
+        hello_node.ambiguous_packages = vec!["hello".to_string(), "hello-wayland".to_string()];
+        assert_eq!(
+            hello_node.get_warnings()[1],
+            "Several package metadata entries matched the package (hello, hello-wayland), so none of them was used."
+        );
+
+        let zlib_warnings = package_graph.nodes["/nix/store/b-zlib-1.3.1.drv"].get_warnings();
+        assert!(zlib_warnings.contains(&"The package name was inferred from the derivation name.".to_string()));
+        assert!(zlib_warnings.contains(&"No version could be found for the package.".to_string()));
+
+        let missing_warnings = package_graph.nodes["/nix/store/c-missing.drv"].get_warnings();
+        assert_eq!(
+            missing_warnings[0],
+            "The derivation was missing from the store, so the package is incomplete."
+        );
+        assert!(!zlib_warnings.contains(&missing_warnings[0]));
     }
 
     #[test]
-    pub fn test_remove_version_from_name() {
-        let derivation: &str = r###"
+    pub fn test_limit_to_system() {
+        let derivations: &str = r###"
           {
-            "args": [
-              "-e",
-              "/nix/store/6xg259477c90a229xwmb53pdfkn6ig3g-default-builder.sh"
-            ],
-            "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
-            "env": {
-              "LDFLAGS": "",
-              "__structuredAttrs": "",
-              "bin": "/nix/store/j41ms763gpyya3hylqmaq1p108bhvkcm-zstd-1.5.5-bin",
-              "buildInputs": "/nix/store/2i83qvxdxps1s91335icgkd2mp6v6b91-bash-5.2-p15-dev",
-              "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
-              "checkPhase": "runHook preCheck\n# Patch shebangs for playTests\npatchShebangs ../programs/zstdgrep\nctest -R playTests # The only relatively fast test.\nrunHook postCheck\n",
-              "cmakeDir": "../build/cmake",
-              "cmakeFlags": "-DZSTD_BUILD_CONTRIB:BOOL=ON -DZSTD_BUILD_SHARED:BOOL=ON -DZSTD_BUILD_STATIC:BOOL=OFF -DZSTD_BUILD_TESTS:BOOL=ON -DZSTD_LEGACY_SUPPORT:BOOL=OFF -DZSTD_PROGRAMS_LINK_SHARED:BOOL=ON",
-              "configureFlags": "",
-              "depsBuildBuild": "",
-              "depsBuildBuildPropagated": "",
-              "depsBuildTarget": "",
-              "depsBuildTargetPropagated": "",
-              "depsHostHost": "",
-              "depsHostHostPropagated": "",
-              "depsTargetTarget": "",
-              "depsTargetTargetPropagated": "",
-              "dev": "/nix/store/4pifi04nz9l2i0l692ny148q94klml0r-zstd-1.5.5-dev",
-              "doCheck": "1",
-              "doInstallCheck": "",
-              "dontUseCmakeBuildDir": "1",
-              "man": "/nix/store/qxv3dnwvi2xw1kx8bhf8lcyssbdvna8d-zstd-1.5.5-man",
-              "mesonFlags": "",
-              "name": "zstd-1.5.5",
-              "nativeBuildInputs": "/nix/store/hdwhs75n9ydc4pdqv0hamjzjv1fkw1zz-cmake-boot-3.25.3 /nix/store/xv00ljxfrgdi9m53w96mj7pqgb0m0c3l-file-5.44-dev",
-              "out": "/nix/store/81d38brw9cnw2qk2kynrf5dr6hhkcq66-zstd-1.5.5",
-              "outputs": "bin dev man out",
-              "patches": "/nix/store/n91acyjrlchm0snw0w16i4683pf788ax-playtests-darwin.patch",
-              "pname": "zstd",
-              "postPatch": "substituteInPlace build/cmake/CMakeLists.txt \\\n  --replace 'message(SEND_ERROR \"You need to build static library to build tests\")' \"\"\nsubstituteInPlace build/cmake/tests/CMakeLists.txt \\\n  --replace 'libzstd_static' 'libzstd_shared'\nsed -i \\\n  \"1aexport LD_LIBRARY_PATH=$PWD/build_/lib\" \\\n  tests/playTests.sh\n",
-              "preConfigure": "mkdir -p build_ && cd $_\n",
-              "preInstall": "mkdir -p $bin/bin\nsubstituteInPlace ../programs/zstdgrep \\\n  --replace \":-grep\" \":-/nix/store/b4in4hmq54h6l34a0v6ha40z97c0lzw2-gnugrep-3.7/bin/grep\" \\\n  --replace \":-zstdcat\" \":-$bin/bin/zstdcat\"\n\nsubstituteInPlace ../programs/zstdless \\\n  --replace \"zstdcat\" \"$bin/bin/zstdcat\"\ncp contrib/pzstd/pzstd $bin/bin/pzstd\n",
-              "propagatedBuildInputs": "",
-              "propagatedNativeBuildInputs": "",
-              "src": "/nix/store/2w0cnsrfgapi5jf9z9yciir4hgz7nyj8-source",
-              "stdenv": "/nix/store/gv2cl6qvvslz5h15vqd89f1rpvrdg5yc-stdenv-linux",
-              "strictDeps": "",
-              "system": "x86_64-linux",
-              "version": "1.5.5"
+            "/nix/store/a-app.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/b-lib.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/c-source.tar.gz.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/d-missing.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app" } },
+              "system": "x86_64-linux"
             },
-            "inputDrvs": {
-              "/nix/store/975cwk57d5xy6cyakapsifyg19n3g516-file-5.44.drv": [
-                "dev"
-              ],
-              "/nix/store/f69xwgpf415s7fvg64qhsfmqpmb7xnjg-source.drv": [
-                "out"
-              ],
-              "/nix/store/hla091y2jgs76hd8ps5ky6d81qzkdfz5-bash-5.2-p15.drv": [
-                "dev",
-                "out"
-              ],
-              "/nix/store/im7dywhi0ycfnkdplpmh9xqzynf6v2mg-cmake-boot-3.25.3.drv": [
-                "out"
-              ],
-              "/nix/store/m4cyqwyzda46912dirznjzx5cml6d018-gnugrep-3.7.drv": [
-                "out"
-              ],
-              "/nix/store/z9vnfwzs0226f7qid0j0iglfbpvb61hx-stdenv-linux.drv": [
-                "out"
-              ]
+            "/nix/store/b-lib.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-lib" } },
+              "system": "aarch64-linux"
             },
-            "inputSrcs": [
-              "/nix/store/6xg259477c90a229xwmb53pdfkn6ig3g-default-builder.sh",
-              "/nix/store/n91acyjrlchm0snw0w16i4683pf788ax-playtests-darwin.patch"
-            ],
-            "outputs": {
-              "bin": {
-                "path": "/nix/store/j41ms763gpyya3hylqmaq1p108bhvkcm-zstd-1.5.5-bin"
+            "/nix/store/c-source.tar.gz.drv": {
+              "args": [],
+              "builder": "builtin:fetchurl",
+              "env": { "url": "https://example.com/source.tar.gz" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/c-source.tar.gz", "hash": "abcd", "hashAlgo": "sha256" } },
+              "system": "builtin"
+            }
+          }
+        "###;
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        assert_eq!(package_graph.nodes.len(), 4);
+
+        package_graph.limit_to_system("x86_64-linux");
+        assert_eq!(package_graph.nodes.len(), 3);
+        assert!(!package_graph.nodes.contains_key("/nix/store/b-lib.drv"));
+        assert_eq!(
+            package_graph.excluded_nodes.get("/nix/store/b-lib.drv"),
+            Some(&SYSTEM_EXCLUSION_REASON.to_string())
+        );
+        assert!(package_graph.nodes.contains_key("/nix/store/c-source.tar.gz.drv"));
+        assert!(package_graph.nodes.contains_key("/nix/store/d-missing.drv"));
+        assert!(!package_graph.nodes["/nix/store/a-app.drv"]
+            .children
+            .contains("/nix/store/b-lib.drv"));
+    }
+
+    #[test]
+    pub fn test_limit_to_references() {
+        let derivations: &str = r###"
+          {
+            "/nix/store/a-app.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/b-lib.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/d-compiler.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
               },
-              "dev": {
-                "path": "/nix/store/4pifi04nz9l2i0l692ny148q94klml0r-zstd-1.5.5-dev"
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/b-lib.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/c-libc.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
               },
-              "man": {
-                "path": "/nix/store/qxv3dnwvi2xw1kx8bhf8lcyssbdvna8d-zstd-1.5.5-man"
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-lib" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/c-libc.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/c-libc" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/d-compiler.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/d-compiler" } },
+              "system": "x86_64-linux"
+            }
+          }
+        "###;
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        assert_eq!(package_graph.nodes.len(), 4);
+
+        // The app references the libc directly, for example through its RPATH.
+        package_graph.limit_to_references(&BTreeMap::from([
+            (
+                "/nix/store/a-app".to_string(),
+                vec!["/nix/store/a-app".to_string(), "/nix/store/c-libc".to_string()],
+            ),
+            ("/nix/store/c-libc".to_string(), vec![]),
+        ]));
+        assert_eq!(package_graph.nodes.len(), 2);
+        assert_eq!(
+            package_graph.excluded_nodes.get("/nix/store/d-compiler.drv"),
+            Some(&BUILD_ONLY_EXCLUSION_REASON.to_string())
+        );
+        assert_eq!(
+            package_graph.excluded_nodes.get("/nix/store/b-lib.drv"),
+            Some(&BUILD_ONLY_EXCLUSION_REASON.to_string())
+        );
+        let app_node = package_graph.nodes.get("/nix/store/a-app.drv").unwrap();
+        assert_eq!(
+            app_node.children.iter().collect::<Vec<_>>(),
+            vec!["/nix/store/c-libc.drv"]
+        );
+        assert!(app_node.build_inputs.is_empty());
+    }
+
+    #[test]
+    pub fn test_filter_by_licenses() {
+        let derivations: &str = r###"
+          {
+            "/nix/store/a-app.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/b-lib.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/b-lib.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/c-libc.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-lib" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/c-libc.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/c-libc" } },
+              "system": "x86_64-linux"
+            }
+          }
+        "###;
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        package_graph.nodes_next = package_graph.nodes.clone();
+        for (derivation_path, license) in [
+            ("/nix/store/a-app.drv", "GPL-3.0-or-later"),
+            ("/nix/store/b-lib.drv", "MIT"),
+            ("/nix/store/c-libc.drv", "LGPL-2.1-or-later"),
+        ] {
+            package_graph
+                .nodes_next
+                .get_mut(derivation_path)
+                .unwrap()
+                .concluded_license = Some(license.to_string());
+        }
+
+        package_graph.filter_by_licenses(&crate::license::LicenseFilter {
+            only_licenses: vec!["GPL-*".to_string(), "LGPL-*".to_string()],
+            exclude_licenses: vec![],
+        });
+        assert_eq!(package_graph.nodes_next.len(), 2);
+        assert_eq!(
+            package_graph.excluded_nodes.get("/nix/store/b-lib.drv"),
+            Some(&LICENSE_EXCLUSION_REASON.to_string())
+        );
+        let app_node = package_graph.nodes_next.get("/nix/store/a-app.drv").unwrap();
+        assert_eq!(
+            app_node.children.iter().collect::<Vec<_>>(),
+            vec!["/nix/store/c-libc.drv"]
+        );
+
+        let mut selected_package_graph = package_graph.clone();
+        selected_package_graph.keep_only(&[regex::Regex::new("libc").unwrap()], &[]);
+        assert_eq!(
+            selected_package_graph.nodes_next.keys().collect::<Vec<_>>(),
+            vec!["/nix/store/c-libc.drv"]
+        );
+        assert_eq!(
+            selected_package_graph.root_nodes.iter().collect::<Vec<_>>(),
+            vec!["/nix/store/c-libc.drv"]
+        );
+        selected_package_graph.keep_only(&[], &["cargo".to_string()]);
+        assert!(selected_package_graph.nodes_next.is_empty());
+
+        package_graph.exclude_by_patterns(&[regex::Regex::new("^/nix/store/c-libc$").unwrap()]);
+        assert_eq!(package_graph.nodes_next.len(), 1);
+        assert_eq!(
+            package_graph.excluded_nodes.get("/nix/store/c-libc.drv"),
+            Some(&PATTERN_EXCLUSION_REASON.to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_get_os_pretty_name() {
+        assert_eq!(
+            get_os_pretty_name(
+                r###"
+ANSI_COLOR="1;34"
+NAME=NixOS
+PRETTY_NAME="NixOS 24.05 (Uakari)"
+VERSION="24.05 (Uakari)"
+"###
+            ),
+            Some("NixOS 24.05 (Uakari)".to_string())
+        );
+        assert_eq!(
+            get_os_pretty_name("NAME='Alpine Linux'\nVERSION=3.20\n"),
+            Some("Alpine Linux 3.20".to_string())
+        );
+        assert_eq!(get_os_pretty_name(""), None);
+    }
+
+    #[test]
+    pub fn test_get_lifecycle_signals() {
+        let meta: PackageMeta = serde_json::from_str(
+            r###"
+            {
+              "broken": true,
+              "insecure": true,
+              "knownVulnerabilities": ["Python 2.7 is EOL since 2020-01-01"],
+              "license": [
+                { "shortName": "gpl2", "spdxId": "GPL-2.0", "deprecated": true },
+                { "shortName": "mit", "spdxId": "MIT", "deprecated": false }
+              ]
+            }
+            "###,
+        )
+        .unwrap();
+        assert_eq!(
+            meta.get_lifecycle_signals(),
+            vec![
+                END_OF_LIFE_SIGNAL,
+                INSECURE_SIGNAL,
+                BROKEN_SIGNAL,
+                DEPRECATED_LICENSE_SIGNAL
+            ]
+        );
+
+        let meta: PackageMeta = serde_json::from_str(
+            r###"
+            { "knownVulnerabilities": ["CVE-2024-1234 affects the geolocation module"] }
+            "###,
+        )
+        .unwrap();
+        assert_eq!(meta.get_lifecycle_signals(), vec![INSECURE_SIGNAL]);
+        assert!(PackageMeta::default().get_lifecycle_signals().is_empty());
+    }
+
+    #[test]
+    pub fn test_rewrite_flake_input_urls() {
+        let config: crate::config::Config = serde_yaml::from_str(
+            r###"
+            url_rewrites:
+              - pattern: "^https://github.com/acme/"
+                replacement: "https://git.example.com/acme/"
+            "###,
+        )
+        .unwrap();
+        let mut flake_inputs: Vec<crate::flake::FlakeInput> = serde_json::from_str(
+            r###"
+            [
+              {
+                "name": "tools",
+                "path": ["tools"],
+                "locked": { "type": "github", "owner": "acme", "repo": "tools", "rev": "abcd" },
+                "inputs": [
+                  {
+                    "name": "nixpkgs",
+                    "path": ["tools", "nixpkgs"],
+                    "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "efgh" },
+                    "inputs": []
+                  }
+                ]
               },
-              "out": {
-                "path": "/nix/store/81d38brw9cnw2qk2kynrf5dr6hhkcq66-zstd-1.5.5"
+              {
+                "name": "lib",
+                "path": ["lib"],
+                "locked": { "type": "git", "url": "https://github.com/acme/lib.git", "rev": "ijkl" },
+                "inputs": []
               }
-            },
-            "system": "x86_64-linux"
-          }
-        "###;
-        let derivation: Derivation = serde_json::from_str(derivation).unwrap();
-        assert_eq!(derivation.get_name(), Some("zstd".to_string()));
-        assert_eq!(derivation.get_version(), Some("1.5.5".to_string()));
+            ]
+            "###,
+        )
+        .unwrap();
+        rewrite_flake_input_urls(&mut flake_inputs, &config.get_url_rewriter().unwrap());
+        assert_eq!(
+            flake_inputs[0].locked.as_ref().unwrap().get_url(),
+            Some("https://git.example.com/acme/tools".to_string())
+        );
+        assert_eq!(
+            flake_inputs[0].inputs[0].locked.as_ref().unwrap().get_url(),
+            Some("https://github.com/NixOS/nixpkgs".to_string())
+        );
+        assert_eq!(
+            flake_inputs[1].locked.as_ref().unwrap().get_url(),
+            Some("https://git.example.com/acme/lib.git".to_string())
+        );
     }
 
     #[test]
-    pub fn test_split_version_and_name() {
-        let derivation: &str = r###"
+    pub fn test_get_root_dir_names() {
+        let package_graph = PackageGraph {
+            root_nodes: BTreeSet::from([
+                "/nix/store/a-hello-2.12.1.drv".to_string(),
+                "/nix/store/b-hello-2.12.1.drv".to_string(),
+                "/nix/store/c-zlib-1.3.1.drv".to_string(),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            package_graph.get_root_dir_names(),
+            BTreeMap::from([
+                (
+                    "/nix/store/a-hello-2.12.1.drv".to_string(),
+                    "hello-2.12.1-a".to_string()
+                ),
+                (
+                    "/nix/store/b-hello-2.12.1.drv".to_string(),
+                    "hello-2.12.1-b".to_string()
+                ),
+                (
+                    "/nix/store/c-zlib-1.3.1.drv".to_string(),
+                    "zlib-1.3.1".to_string()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    pub fn test_get_root_subgraph() {
+        let derivations: &str = r###"
           {
-            "args": [
-              "-e",
-              "/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"
-            ],
-            "builder": "/nix/store/izpf49b74i15pcr9708s3xdwyqs4jxwl-bash-5.2p32/bin/bash",
-            "env": {
-              "__structuredAttrs": "",
-              "buildCommand": "",
-              "buildInputs": "",
-              "builder": "/nix/store/izpf49b74i15pcr9708s3xdwyqs4jxwl-bash-5.2p32/bin/bash",
-              "cmakeFlags": "",
-              "configureFlags": "",
-              "depsBuildBuild": "",
-              "depsBuildBuildPropagated": "",
-              "depsBuildTarget": "",
-              "depsBuildTargetPropagated": "",
-              "depsHostHost": "",
-              "depsHostHostPropagated": "",
-              "depsTargetTarget": "",
-              "depsTargetTargetPropagated": "",
-              "doCheck": "",
-              "doInstallCheck": "",
-              "enableParallelBuilding": "1",
-              "enableParallelChecking": "1",
-              "enableParallelInstalling": "1",
-              "mesonFlags": "",
-              "name": "anstyle-query-1.1.1",
-              "nativeBuildInputs": "",
-              "out": "/nix/store/vbry7m4bvv035bvwm1jspdv9ccj1h20m-anstyle-query-1.1.1",
-              "outputs": "out",
-              "passAsFile": "buildCommand",
-              "patches": "",
-              "propagatedBuildInputs": "",
-              "propagatedNativeBuildInputs": "",
-              "stdenv": "/nix/store/9ip31vbkgg0lp4rx042svlllv3945nxq-stdenv-linux",
-              "strictDeps": "",
+            "/nix/store/a-app.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/c-lib.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app" } },
               "system": "x86_64-linux"
             },
-            "inputDrvs": {
-              "/nix/store/j2fnz39ss5iha58y76hrls6kf9nksxyj-crate-anstyle-query-1.1.1.tar.gz.drv": {
-                "dynamicOutputs": {},
-                "outputs": [
-                  "out"
-                ]
-              },
-              "/nix/store/s7hvxiy7934f539xy4df5mm6bsigvns0-bash-5.2p32.drv": {
-                "dynamicOutputs": {},
-                "outputs": [
-                  "out"
-                ]
+            "/nix/store/b-tool.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {
+                "/nix/store/c-lib.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/d-libc.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
               },
-              "/nix/store/w7ab6iz9by4fn92y8j2qj0nknfxq9snl-stdenv-linux.drv": {
-                "dynamicOutputs": {},
-                "outputs": [
-                  "out"
-                ]
-              }
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-tool" } },
+              "system": "x86_64-linux"
             },
-            "inputSrcs": [
-              "/nix/store/v6x3cs394jgqfbi0a42pam708flxaphh-default-builder.sh"
-            ],
-            "name": "anstyle-query-1.1.1",
-            "outputs": {
-              "out": {
-                "path": "/nix/store/vbry7m4bvv035bvwm1jspdv9ccj1h20m-anstyle-query-1.1.1"
-              }
+            "/nix/store/c-lib.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/c-lib" } },
+              "system": "x86_64-linux"
             },
-            "system": "x86_64-linux"
+            "/nix/store/d-libc.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": {},
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/d-libc" } },
+              "system": "x86_64-linux"
+            }
           }
         "###;
-        let derivation: Derivation = serde_json::from_str(derivation).unwrap();
-        assert_eq!(derivation.get_name(), Some("anstyle-query".to_string()));
-        assert_eq!(derivation.get_version(), Some("1.1.1".to_string()));
+        let derivations: Derivations = serde_json::from_str(derivations).unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        package_graph.nodes_next = package_graph.nodes.clone();
+        package_graph
+            .store_paths
+            .insert("/nix/store/d-libc".to_string(), None);
+
+        let subgraph = package_graph.get_root_subgraph("/nix/store/a-app.drv");
+        assert_eq!(
+            subgraph.root_nodes.iter().collect::<Vec<_>>(),
+            vec!["/nix/store/a-app.drv"]
+        );
+        assert_eq!(
+            subgraph.nodes_next.keys().collect::<Vec<_>>(),
+            vec!["/nix/store/a-app.drv", "/nix/store/c-lib.drv"]
+        );
+        assert!(subgraph.store_paths.is_empty());
+
+        let subgraph = package_graph.get_root_subgraph("/nix/store/b-tool.drv");
+        assert_eq!(subgraph.nodes.len(), 3);
+        assert_eq!(subgraph.nodes_next.len(), 3);
+        assert!(subgraph.store_paths.contains_key("/nix/store/d-libc"));
     }
 
     #[test]
-    pub fn test_get_name_from_pname() {
-        let derivation: &str = r###"
+    pub fn test_get_build_provenance() {
+        let path_infos: PathInfos = serde_json::from_str(
+            r###"{
+              "/nix/store/a-hello-2.12.1": {"ultimate": false, "signatures": ["cache.nixos.org-1:abc"]},
+              "/nix/store/b-app": {"ultimate": true, "signatures": []},
+              "/nix/store/c-copied": {"narHash": "sha256-abc"}
+            }"###,
+        )
+        .unwrap();
+        let provenances = path_infos
+            .into_map()
+            .into_iter()
+            .map(|(p, i)| (p, get_build_provenance(i.ultimate, &i.signatures)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            provenances,
+            vec![
+                ("/nix/store/a-hello-2.12.1".to_string(), SUBSTITUTED_PROVENANCE),
+                ("/nix/store/b-app".to_string(), BUILT_PROVENANCE),
+                ("/nix/store/c-copied".to_string(), UNKNOWN_PROVENANCE),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_get_closure_sizes() {
+        let path_infos: PathInfos =
+            serde_json::from_str(r###"[{"path": "/nix/store/a-hello-2.12.1", "closureSize": 33000000}]"###)
+                .unwrap();
+        assert_eq!(
+            path_infos.get_closure_sizes(),
+            vec![("/nix/store/a-hello-2.12.1".to_string(), 33000000)]
+        );
+
+        let path_infos: PathInfos = serde_json::from_str(
+            r###"{"/nix/store/a-hello-2.12.1": {"closureSize": 33000000, "narSize": 200000}}"###,
+        )
+        .unwrap();
+        assert_eq!(
+            path_infos.get_closure_sizes(),
+            vec![("/nix/store/a-hello-2.12.1".to_string(), 33000000)]
+        );
+    }
+
+    #[test]
+    pub fn test_package_index() {
+        let packages: Packages = serde_json::from_str(
+            r###"
           {
-            "args": [
-              "-e",
-              "/nix/store/6xg259477c90a229xwmb53pdfkn6ig3g-default-builder.sh"
-            ],
-            "builder": "/nix/store/wllx077cz9z34zgrhwj2fc8r5r1hn6mx-bash-5.2-p15/bin/bash",
-            "env": {
-              "LANG": "C.UTF-8",
-              "__structuredAttrs": "",
-              "buildInputs": "/nix/store/3806m4syrck3andi5bw1jygclzrkbqnw-cairo-1.16.0-dev",
-              "builder": "/nix/store/wllx077cz9z34zgrhwj2fc8r5r1hn6mx-bash-5.2-p15/bin/bash",
-              "cmakeFlags": "",
-              "configureFlags": "",
-              "depsBuildBuild": "",
-              "depsBuildBuildPropagated": "",
-              "depsBuildTarget": "",
-              "depsBuildTargetPropagated": "",
-              "depsHostHost": "",
-              "depsHostHostPropagated": "",
-              "depsTargetTarget": "",
-              "depsTargetTargetPropagated": "",
-              "disallowedReferences": "",
-              "doCheck": "",
-              "doInstallCheck": "1",
-              "mesonFlags": "-Dpython=/nix/store/bhnfk4sy0ki5kp7hrbcq7pyqppbg7cwv-python3-3.10.13/bin/python3.10",
-              "name": "python3.10-pycairo-1.23.0",
-              "nativeBuildInputs": "/nix/store/bhnfk4sy0ki5kp7hrbcq7pyqppbg7cwv-python3-3.10.13 /nix/store/pg4wl9d9c6wb9i50wrgzxp5iqb0s5vbf-wrap-python-hook /nix/store/2znsgzazszhd9jns39fc688c15sdqakw-ensure-newer-sources-hook /nix/store/6ljpqq1imwfxadh586lj33ck7s84cgzv-python-remove-tests-dir-hook /nix/store/zq4g4h4dqvlk72nr9plcrqacxl37z3s1-python-catch-conflicts-hook /nix/store/3srpdh3b7an72xjnsdcl0h1ybpqwfq40-python-remove-bin-bytecode-hook /nix/store/vhns06ximyi3r11qy4zv3f63vrlg7kss-python-imports-check-hook.sh /nix/store/gzzh23js0w8mjr578j6sy7zlw6qlnzqz-python-namespaces-hook.sh /nix/store/aqjdg2d3g7nd4p0lwgr13v66vap3w9kd-meson-1.1.0 /nix/store/g0m0q186glhbxzv2mbh6y201r0gw989x-ninja-1.11.1 /nix/store/7g8wjkxx774bbnx0v06xc58qap2pvj6l-pkg-config-wrapper-0.29.2 /nix/store/459n6kmy4hsdaq8lr8s7ap7bshzymv2w-pytest-check-hook",
-              "out": "/nix/store/cp5adghz8pyv01a15w1a8xxzmhqrv7nj-python3.10-pycairo-1.23.0",
-              "outputs": "out",
-              "patches": "",
-              "pname": "pycairo",
-              "postFixup": "wrapPythonPrograms\n",
-              "propagatedBuildInputs": "/nix/store/bhnfk4sy0ki5kp7hrbcq7pyqppbg7cwv-python3-3.10.13",
-              "propagatedNativeBuildInputs": "",
-              "src": "/nix/store/ai0y1sqcklhjzjg04s7js5xc92g9j542-source",
-              "stdenv": "/nix/store/zpcxdbzssnhig0czrfvc1bd33lzrdy2i-stdenv-linux",
-              "strictDeps": "1",
-              "system": "i686-linux",
-              "version": "1.23.0"
+            "nixpkgs.openssl_3": {
+              "name": "openssl-3.0.13",
+              "pname": "openssl",
+              "version": "3.0.13",
+              "system": "x86_64-linux",
+              "outputName": "bin",
+              "meta": { "license": { "spdxId": "Apache-2.0" } }
             },
-            "inputDrvs": {
-              "/nix/store/098ldl2py7yz31vwry8nhcjwrvs8gp56-source.drv": [
-                "out"
-              ],
-              "/nix/store/3mrp1f80d1838n6wrmcn2w8dbzd1b53d-python-remove-tests-dir-hook.drv": [
-                "out"
-              ],
-              "/nix/store/7aqhla8b73157glwx8klvnvdq014ykri-python-namespaces-hook.sh.drv": [
-                "out"
-              ],
-              "/nix/store/9m6yhn4w3myqhk6w83xfkl81w7d71avl-stdenv-linux.drv": [
-                "out"
-              ],
-              "/nix/store/ampp49cj5qvql9wlxarjpfybpqvn12nm-pytest-check-hook.drv": [
-                "out"
-              ],
-              "/nix/store/drqc6nfs5jg0cq6a65smcml6zysvbrnp-cairo-1.16.0.drv": [
-                "dev"
-              ],
-              "/nix/store/h4nfr8plqsc7628xgngqvc3ddr1kbj65-ensure-newer-sources-hook.drv": [
-                "out"
-              ],
-              "/nix/store/lj7902rl4qzxg8aa60y49m802759nh1p-meson-1.1.0.drv": [
-                "out"
-              ],
-              "/nix/store/mp10xqzg2ww4v0i7v81ccz5k0x9im77q-bash-5.2-p15.drv": [
-                "out"
-              ],
-              "/nix/store/njsalvap31p8vxyl22pzrdjxn34fb1kv-python3-3.10.13.drv": [
-                "out"
-              ],
-              "/nix/store/q3lg2gv800gzhx123jgyc2y85zv7i15y-python-imports-check-hook.sh.drv": [
-                "out"
-              ],
-              "/nix/store/rmi4ax1bzwdn0kgy2pr0yxrkcakyaw2d-pkg-config-wrapper-0.29.2.drv": [
-                "out"
-              ],
-              "/nix/store/s6da7mav0ac0fmqc4f5vrbh3qlfhgyx7-wrap-python-hook.drv": [
-                "out"
-              ],
-              "/nix/store/v8hm8h7cz5jj191fx071m18l04mp2vbx-ninja-1.11.1.drv": [
-                "out"
-              ],
-              "/nix/store/x28mrjkg28i9srjyhw1249f3g42hzyj4-python-catch-conflicts-hook.drv": [
-                "out"
-              ],
-              "/nix/store/y7qkcyqqlgy24hc42vfzildzhx5nx9pi-python-remove-bin-bytecode-hook.drv": [
-                "out"
-              ]
+            "nixpkgs.openssl_1_1": {
+              "name": "openssl-1.1.1w",
+              "pname": "openssl",
+              "version": "1.1.1w",
+              "system": "x86_64-linux",
+              "outputName": "bin",
+              "outputs": { "bin": "/nix/store/b-openssl-1.1.1w-bin", "out": null },
+              "meta": { "license": { "spdxId": "OpenSSL" } }
+            }
+          }
+        "###,
+        )
+        .unwrap();
+        let packages: Packages = packages
+            .into_iter()
+            .map(|(attribute_path, mut package)| {
+                package.attribute_path = Some(attribute_path.to_string());
+                (attribute_path, package)
+            })
+            .collect();
+        let package_index = PackageIndex::new(&packages);
+
+        let derivation: Derivation = serde_json::from_str(
+            r###"
+          {
+            "args": [],
+            "builder": "/bin/sh",
+            "env": { "pname": "openssl", "version": "3.0.13", "name": "openssl-3.0.13" },
+            "inputDrvs": {},
+            "inputSrcs": [],
+            "outputs": { "out": { "path": "/nix/store/a-openssl-3.0.13" } },
+            "system": "x86_64-linux"
+          }
+        "###,
+        )
+        .unwrap();
+        let mut package_node = PackageNode {
+            id: "/nix/store/a-openssl-3.0.13.drv".to_string(),
+            url: None,
+            version: None,
+            name: Some("openssl".to_string()),
+            git_urls: BTreeSet::default(),
+            main_derivation: derivation,
+            source_derivation: None,
+            group_id: None,
+            package: None,
+            patches: BTreeSet::default(),
+            build_inputs: BTreeSet::default(),
+            children: BTreeSet::default(),
+            concluded_license: None,
+            owners: vec![],
+            closure_size: None,
+            nar_size: None,
+            ambiguous_packages: vec![],
+            missing_meta_reason: None,
+            incomplete: false,
+            substituted: false,
+            purl_type: None,
+            purl_qualifiers: BTreeMap::default(),
+        };
+        match package_index.find(&package_node) {
+            PackageMatch::Found(p) => assert_eq!(p.version, "3.0.13"),
+            m => panic!("Unexpected package match {:?}", m),
+        };
+
+        // The derivation name is not always the package name, e.g. for wrapped packages.
+        package_node
+            .main_derivation
+            .env
+            .insert("name".to_string(), "openssl-wrapped-1.1.1w".to_string());
+        package_node
+            .main_derivation
+            .env
+            .insert("version".to_string(), "1.1.1w".to_string());
+        match package_index.find(&package_node) {
+            PackageMatch::Found(p) => assert_eq!(p.version, "1.1.1w"),
+            m => panic!("Unexpected package match {:?}", m),
+        };
+
+        // The output paths are used before the names, which can be changed.
+        package_node
+            .main_derivation
+            .env
+            .insert("name".to_string(), "renamed-ssl-3.0.13".to_string());
+        package_node
+            .main_derivation
+            .env
+            .insert("version".to_string(), "3.0.13".to_string());
+        package_node.main_derivation.outputs.insert(
+            "bin".to_string(),
+            serde_json::from_str(r#"{ "path": "/nix/store/b-openssl-1.1.1w-bin" }"#).unwrap(),
+        );
+        match package_index.find(&package_node) {
+            PackageMatch::Found(p) => assert_eq!(p.version, "1.1.1w"),
+            m => panic!("Unexpected package match {:?}", m),
+        };
+
+        // Without a name, version or output path to tell them apart, none of the
+        // packages can be used.
+        package_node.main_derivation.env.remove("name");
+        package_node.main_derivation.env.remove("version");
+        package_node.main_derivation.outputs.clear();
+        assert_eq!(
+            package_index.find(&package_node),
+            PackageMatch::Ambiguous(vec![
+                "nixpkgs.openssl_1_1".to_string(),
+                "nixpkgs.openssl_3".to_string()
+            ])
+        );
+
+        package_node
+            .main_derivation
+            .env
+            .insert("pname".to_string(), "libressl".to_string());
+        package_node.name = Some("libressl".to_string());
+        assert_eq!(
+            package_index.find(&package_node),
+            PackageMatch::NotFound(NO_METADATA_ENTRY_META_REASON)
+        );
+    }
+
+    #[test]
+    pub fn test_get_duplicated_packages() {
+        let derivations: Derivations = serde_json::from_str(
+            r###"
+          {
+            "/nix/store/a-app-1.0.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "app-1.0", "pname": "app", "version": "1.0" },
+              "inputDrvs": {
+                "/nix/store/b-openssl-3.0.13.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/c-libfoo-2.1.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app-1.0" } },
+              "system": "x86_64-linux"
             },
-            "inputSrcs": [
-              "/nix/store/6xg259477c90a229xwmb53pdfkn6ig3g-default-builder.sh"
-            ],
-            "outputs": {
-              "out": {
-                "path": "/nix/store/cp5adghz8pyv01a15w1a8xxzmhqrv7nj-python3.10-pycairo-1.23.0"
-              }
+            "/nix/store/b-openssl-3.0.13.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "openssl-3.0.13", "pname": "openssl", "version": "3.0.13" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-openssl-3.0.13" } },
+              "system": "x86_64-linux"
             },
-            "system": "i686-linux"
-
+            "/nix/store/c-libfoo-2.1.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "libfoo-2.1", "pname": "libfoo", "version": "2.1" },
+              "inputDrvs": {
+                "/nix/store/d-openssl-1.1.1w.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/c-libfoo-2.1" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/d-openssl-1.1.1w.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "openssl-1.1.1w", "pname": "openssl", "version": "1.1.1w" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/d-openssl-1.1.1w" } },
+              "system": "x86_64-linux"
+            }
           }
-        "###;
-        let derivation: Derivation = serde_json::from_str(derivation).unwrap();
-        assert_eq!(derivation.get_name(), Some("pycairo".to_string()));
-        assert_eq!(derivation.get_version(), Some("1.23.0".to_string()));
+        "###,
+        )
+        .unwrap();
+        let mut package_graph = get_package_graph(&derivations);
+        let resolvers = crate::identify::Resolvers::default();
+        package_graph.populate_name(&resolvers).unwrap();
+        package_graph.populate_version(&resolvers).unwrap();
+        package_graph.nodes_next = package_graph.nodes.clone();
+
+        let duplicated_packages = package_graph.get_duplicated_packages(false);
+        assert_eq!(duplicated_packages.len(), 1);
+        let openssl = duplicated_packages.get("openssl").unwrap();
+        assert_eq!(
+            openssl.get("3.0.13").unwrap(),
+            &vec!["app -> openssl".to_string()]
+        );
+        assert_eq!(
+            openssl.get("1.1.1w").unwrap(),
+            &vec!["app -> libfoo -> openssl".to_string()]
+        );
     }
 }
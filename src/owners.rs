@@ -0,0 +1,96 @@
+// Ownership of the components, using rules in the style of the CODEOWNERS files:
+//
+//   # Comments start with a hash.
+//   pkg:cargo/*       rust-team
+//   openssl           security-team platform-team
+//
+// Each rule is a pattern followed by one or more owners. The patterns are matched
+// against the name and the purl of the components, and `*` matches any sequence of
+// characters. As with CODEOWNERS, the last matching rule takes precedence.
+use regex::Regex;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct OwnershipRules {
+    rules: Vec<(Regex, Vec<String>)>,
+}
+
+impl OwnershipRules {
+    pub fn from_file(path: &str) -> Result<OwnershipRules, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::format_err!("Could not read the ownership rules {}: {}", path, e))?;
+        OwnershipRules::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<OwnershipRules, anyhow::Error> {
+        let mut rules: Vec<(Regex, Vec<String>)> = vec![];
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let pattern = tokens.next().unwrap_or_default();
+            let owners: Vec<String> = tokens.map(|t| t.to_string()).collect();
+            if owners.is_empty() {
+                return Err(anyhow::format_err!(
+                    "No owner for the pattern {} on line {} of the ownership rules",
+                    pattern,
+                    line_number + 1
+                ));
+            }
+            let regex = format!("^{}$", regex::escape(pattern).replace("\\*", ".*"));
+            rules.push((Regex::new(&regex)?, owners));
+        }
+        Ok(OwnershipRules { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn get_owners(&self, name: Option<&str>, purl: &str) -> Vec<String> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern.is_match(purl) || name.is_some_and(|n| pattern.is_match(n)))
+            .map(|(_, owners)| owners.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_owners() {
+        let ownership_rules = OwnershipRules::parse(
+            r###"
+            # Default owner
+            *                  platform-team
+            pkg:cargo/*        rust-team
+            openssl            security-team platform-team
+            "###,
+        )
+        .unwrap();
+        assert_eq!(
+            ownership_rules.get_owners(Some("openssl"), "pkg:generic/openssl@3.0.13"),
+            vec!["security-team", "platform-team"]
+        );
+        assert_eq!(
+            ownership_rules.get_owners(Some("serde"), "pkg:cargo/serde@1.0.197"),
+            vec!["rust-team"]
+        );
+        assert_eq!(
+            ownership_rules.get_owners(None, "pkg:generic/zlib@1.3.1"),
+            vec!["platform-team"]
+        );
+
+        assert!(OwnershipRules::parse("openssl").is_err());
+        assert!(OwnershipRules::default()
+            .get_owners(Some("openssl"), "")
+            .is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+// Packages installed imperatively with `nix profile install`. The packages of a profile
+// are listed in the manifest.json file of the profile, see
+// https://nix.dev/manual/nix/latest/command-ref/new-cli/nix3-profile
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+pub const DEFAULT_PROFILE_NAME: &str = "default";
+
+const PROFILE_MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(PartialEq)]
+pub struct ProfileManifest {
+    pub version: u64,
+    pub elements: ProfileElements,
+}
+
+/// The elements are a list up to version 2 of the manifest, and are indexed
+/// by name since version 3.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(PartialEq)]
+#[serde(untagged)]
+pub enum ProfileElements {
+    List(Vec<ProfileElement>),
+    Map(BTreeMap<String, ProfileElement>),
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(PartialEq)]
+pub struct ProfileElement {
+    /// The inactive elements are kept in the manifest but are not linked in the profile.
+    #[serde(default = "default_active")]
+    pub active: bool,
+
+    #[serde(rename = "storePaths")]
+    #[serde(default)]
+    pub store_paths: Vec<String>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl ProfileManifest {
+    pub fn parse(content: &str) -> Result<ProfileManifest, anyhow::Error> {
+        serde_json::from_str(content)
+            .map_err(|e| anyhow::format_err!("Could not parse the profile manifest: {}", e))
+    }
+
+    // Returns the store paths of the active elements of the profile.
+    pub fn get_store_paths(&self) -> Vec<String> {
+        let elements: Vec<&ProfileElement> = match &self.elements {
+            ProfileElements::List(l) => l.iter().collect(),
+            ProfileElements::Map(m) => m.values().collect(),
+        };
+        let mut response: Vec<String> = vec![];
+        for element in elements.iter().filter(|e| e.active) {
+            for store_path in &element.store_paths {
+                if !response.contains(store_path) {
+                    response.push(store_path.to_string());
+                }
+            }
+        }
+        response
+    }
+}
+
+// Returns the path of the profile. The default profile is ~/.nix-profile, or the
+// profile in the XDG state directory when use-xdg-base-directories is enabled.
+pub fn get_profile_path(profile: &str) -> Result<String, anyhow::Error> {
+    if profile != DEFAULT_PROFILE_NAME {
+        return Ok(profile.to_string());
+    }
+    let home = std::env::var("HOME").map_err(|_| anyhow::format_err!("HOME is not set"))?;
+    let legacy_profile_path = std::path::Path::new(&home).join(".nix-profile");
+    if legacy_profile_path.exists() {
+        return Ok(legacy_profile_path.display().to_string());
+    }
+    let state_home = match std::env::var("XDG_STATE_HOME") {
+        Ok(s) if !s.is_empty() => s,
+        _ => std::path::Path::new(&home)
+            .join(".local/state")
+            .display()
+            .to_string(),
+    };
+    Ok(std::path::Path::new(&state_home)
+        .join("nix/profile")
+        .display()
+        .to_string())
+}
+
+pub fn read_profile_manifest(profile_path: &str) -> Result<ProfileManifest, anyhow::Error> {
+    let manifest_path = std::path::Path::new(profile_path).join(PROFILE_MANIFEST_FILE_NAME);
+    let content = std::fs::read_to_string(&manifest_path).map_err(|e| {
+        anyhow::format_err!(
+            "Could not read {}. Is {} a nix profile? The profiles managed with nix-env are not supported: {}",
+            manifest_path.display(),
+            profile_path,
+            e
+        )
+    })?;
+    ProfileManifest::parse(&content)
+}
+
+// Same as Derivation::get_derivations_for_current_system, for the packages of a profile.
+pub fn get_derivations_for_profile(
+    profile_path: &str,
+    backend: &dyn crate::backend::NixBackend,
+) -> Result<crate::nix::Derivations, anyhow::Error> {
+    let store_paths = read_profile_manifest(profile_path)?.get_store_paths();
+    log::info!(
+        "Found {} store paths in profile {}",
+        store_paths.len(),
+        profile_path
+    );
+
+    let mut derivations = crate::nix::Derivations::default();
+    for store_path in &store_paths {
+        match backend.get_derivations(store_path) {
+            Ok(d) => derivations.extend(d),
+            Err(e) => log::warn!("{}", e),
+        };
+    }
+    if store_paths.is_empty() {
+        return Ok(derivations);
+    }
+    let path_infos = backend.get_path_infos(&store_paths, true)?;
+    let substituted_derivations =
+        crate::nix::Derivation::get_substituted_derivations(&derivations, &path_infos);
+    log::info!(
+        "Found {} store paths without a local derivation",
+        substituted_derivations.len()
+    );
+    derivations.extend(substituted_derivations);
+    Ok(derivations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_store_paths() {
+        let profile_manifest = ProfileManifest::parse(
+            r###"
+            {
+              "version": 3,
+              "elements": {
+                "hello": {
+                  "active": true,
+                  "attrPath": "legacyPackages.x86_64-linux.hello",
+                  "originalUrl": "flake:nixpkgs",
+                  "priority": 5,
+                  "storePaths": ["/nix/store/a-hello-2.12.1"],
+                  "url": "github:NixOS/nixpkgs/a1b2c3"
+                },
+                "ripgrep": {
+                  "active": false,
+                  "priority": 5,
+                  "storePaths": ["/nix/store/b-ripgrep-14.1.0"]
+                }
+              }
+            }
+            "###,
+        )
+        .unwrap();
+        assert_eq!(
+            profile_manifest.get_store_paths(),
+            vec!["/nix/store/a-hello-2.12.1"]
+        );
+
+        let profile_manifest = ProfileManifest::parse(
+            r###"
+            {
+              "version": 2,
+              "elements": [
+                { "active": true, "priority": 5, "storePaths": ["/nix/store/a-hello-2.12.1"] },
+                { "storePaths": ["/nix/store/c-jq-1.7.1-bin", "/nix/store/d-jq-1.7.1-man"] }
+              ]
+            }
+            "###,
+        )
+        .unwrap();
+        assert_eq!(profile_manifest.get_store_paths().len(), 3);
+    }
+}
@@ -0,0 +1,266 @@
+// Nix-specific data that has no dedicated field in the SBOM formats is emitted
+// as name/value properties. All the output formats must use the names defined
+// here so that consumers only have to learn a single namespace.
+//
+// | Name                     | Value                                                  |
+// |--------------------------|--------------------------------------------------------|
+// | nix:drvPath              | Store path of the derivation                           |
+// | nix:storePath            | Store path of an output. Repeated for every output     |
+// | nix:outputs              | Comma-separated names of the derivation outputs        |
+// | nix:system               | System the derivation is built for                     |
+// | nix:arch                 | Architecture of the system, e.g. x86_64 or aarch64     |
+// | nix:os                   | Operating system of the system, e.g. linux or darwin   |
+// | nix:attributePath        | Attribute path of the package in the package set       |
+// | nix:closureSize          | Size in bytes of the closure of the `out` output       |
+// | nix:narSize              | Size in bytes of the NAR of the `out` output           |
+// | nix:incomplete           | `true` if the derivation was missing from the store    |
+// | nix:substituted          | `true` if only the path info of the output was known   |
+// | nix:sourceProvenance     | Comma-separated `meta.sourceProvenance` short names    |
+// | nix:license:concluded    | License concluded for the package after review         |
+// | nix:license:text         | License text written with --license-texts. Repeated    |
+// | nix:lifecycle            | Comma-separated end-of-life, insecure, broken, etc.    |
+// | nix:lifecycle:note       | A `meta.knownVulnerabilities` note, e.g. an EOL notice |
+// | nix:narHash              | NAR hash of a locked flake input or of a store path    |
+// | nix:flake:type           | Type of a locked flake input (github, git, path, etc.) |
+// | nix:flake:lastModified   | Last modification timestamp of a locked flake input    |
+// | nix:excluded:<reason>    | Number of derivations excluded for that reason         |
+// | nix:closure:storePath    | Store path of the closure and its NAR hash, if known   |
+// | nix:apiVersion           | Version of the nix2sbom output contract                |
+// | nix:category             | Category of the packages grouped under a component     |
+// | nix:kernel:component     | `kernel`, `kernel-module` or `firmware`                |
+// | nix:toolchain            | `true` if the package is a compiler toolchain          |
+// | nix:toolchain:compiler   | Compiler of the toolchain: gcc, clang, rustc or go     |
+// | nix:metadata:error       | Why the SBOM was generated without package metadata    |
+// | nix:nixpkgs:revision     | Revision of the nixpkgs input of the flake             |
+// | nix:nixpkgs:version      | Version of the nixpkgs of the channels                 |
+// | nix:version              | Output of `nix --version` during the evaluation        |
+// | nix:generation:command   | Command line used to generate the SBOM                 |
+// | nix:host:name            | Hostname of the machine, with --record-host            |
+// | nix:host:os              | Operating system of the machine, with --record-host    |
+// | nix:host:currentSystem   | Store path of the NixOS system of the machine          |
+// | nix:env:<name>           | Value of a derivation env variable, with --capture-env |
+// | org:owner                | Team owning the component. Repeated for every owner    |
+
+pub const NAMESPACE: &str = "nix";
+
+pub const DRV_PATH: &str = "nix:drvPath";
+pub const STORE_PATH: &str = "nix:storePath";
+pub const OUTPUTS: &str = "nix:outputs";
+pub const SYSTEM: &str = "nix:system";
+pub const ARCH: &str = "nix:arch";
+pub const OS: &str = "nix:os";
+pub const ATTRIBUTE_PATH: &str = "nix:attributePath";
+pub const CLOSURE_SIZE: &str = "nix:closureSize";
+pub const NAR_SIZE: &str = "nix:narSize";
+pub const INCOMPLETE: &str = "nix:incomplete";
+pub const SUBSTITUTED: &str = "nix:substituted";
+pub const SOURCE_PROVENANCE: &str = "nix:sourceProvenance";
+pub const CONCLUDED_LICENSE: &str = "nix:license:concluded";
+pub const LICENSE_TEXT: &str = "nix:license:text";
+pub const LIFECYCLE: &str = "nix:lifecycle";
+pub const LIFECYCLE_NOTE: &str = "nix:lifecycle:note";
+pub const NAR_HASH: &str = "nix:narHash";
+pub const FLAKE_TYPE: &str = "nix:flake:type";
+pub const FLAKE_LAST_MODIFIED: &str = "nix:flake:lastModified";
+pub const CLOSURE_STORE_PATH: &str = "nix:closure:storePath";
+pub const API_VERSION: &str = "nix:apiVersion";
+pub const CATEGORY: &str = "nix:category";
+pub const KERNEL_COMPONENT: &str = "nix:kernel:component";
+pub const TOOLCHAIN: &str = "nix:toolchain";
+pub const TOOLCHAIN_COMPILER: &str = "nix:toolchain:compiler";
+pub const METADATA_ERROR: &str = "nix:metadata:error";
+pub const NIXPKGS_REVISION: &str = "nix:nixpkgs:revision";
+pub const NIXPKGS_VERSION: &str = "nix:nixpkgs:version";
+pub const NIX_VERSION: &str = "nix:version";
+pub const GENERATION_COMMAND: &str = "nix:generation:command";
+pub const HOST_NAME: &str = "nix:host:name";
+pub const HOST_OS: &str = "nix:host:os";
+pub const HOST_CURRENT_SYSTEM: &str = "nix:host:currentSystem";
+// The owners are not Nix-specific, and are defined by the organization.
+pub const OWNER: &str = "org:owner";
+
+pub type Properties = Vec<(String, String)>;
+
+pub fn get_excluded_property_name(reason: &str) -> String {
+    format!("{}:excluded:{}", NAMESPACE, reason)
+}
+
+pub fn get_env_property_name(variable_name: &str) -> String {
+    format!("{}:env:{}", NAMESPACE, variable_name)
+}
+
+// Only the variables explicitly requested are captured, since the derivation env
+// can contain anything, including credentials passed to fixed-output derivations.
+pub fn get_captured_env_properties(
+    package_node: &crate::nix::PackageNode,
+    captured_env: &[String],
+) -> Properties {
+    let mut response: Properties = vec![];
+    for variable_name in captured_env {
+        if let Some(value) = package_node.main_derivation.env.get(variable_name) {
+            response.push((get_env_property_name(variable_name), value.to_string()));
+        }
+    }
+    response
+}
+
+// References the license texts written with --license-texts for every license of the
+// package expression, since the license fields can only reference a single license text.
+pub fn get_license_text_properties(
+    package_node: &crate::nix::PackageNode,
+    license_texts: &std::collections::BTreeMap<String, String>,
+) -> Properties {
+    crate::license::get_package_license_texts(package_node, license_texts)
+        .into_values()
+        .map(|path| (LICENSE_TEXT.to_string(), path.to_string()))
+        .collect()
+}
+
+// Describes the evaluation that produced the SBOM, in the SBOM metadata.
+pub fn get_evaluation_properties(evaluation: &crate::nix::EvaluationInfo) -> Properties {
+    let mut response: Properties = vec![];
+    for (name, value) in [
+        (NIXPKGS_REVISION, &evaluation.nixpkgs_revision),
+        (NIXPKGS_VERSION, &evaluation.nixpkgs_version),
+        (NIX_VERSION, &evaluation.nix_version),
+        (GENERATION_COMMAND, &evaluation.command),
+    ] {
+        if let Some(value) = value {
+            response.push((name.to_string(), value.to_string()));
+        }
+    }
+    if let Some(host) = &evaluation.host {
+        for (name, value) in [
+            (HOST_NAME, &host.hostname),
+            (HOST_OS, &host.os),
+            (HOST_CURRENT_SYSTEM, &host.current_system),
+        ] {
+            if let Some(value) = value {
+                response.push((name.to_string(), value.to_string()));
+            }
+        }
+    }
+    response
+}
+
+// Returns the store paths of the closure, as listed in the SBOM metadata.
+pub fn get_store_path_properties(
+    store_paths: &std::collections::BTreeMap<String, Option<String>>,
+) -> Properties {
+    store_paths
+        .iter()
+        .map(|(path, nar_hash)| {
+            let value = match nar_hash {
+                Some(h) => format!("{} {}", path, h),
+                None => path.to_string(),
+            };
+            (CLOSURE_STORE_PATH.to_string(), value)
+        })
+        .collect()
+}
+
+pub fn get_package_node_properties(package_node: &crate::nix::PackageNode) -> Properties {
+    let mut response: Properties = vec![];
+    let derivation = &package_node.main_derivation;
+
+    response.push((DRV_PATH.to_string(), package_node.id.to_string()));
+
+    let mut output_names = derivation.outputs.keys().cloned().collect::<Vec<String>>();
+    output_names.sort();
+    for output_name in &output_names {
+        response.push((
+            STORE_PATH.to_string(),
+            derivation.outputs[output_name].path.to_string(),
+        ));
+    }
+    if !output_names.is_empty() {
+        response.push((OUTPUTS.to_string(), output_names.join(",")));
+    }
+
+    if !derivation.system.is_empty() {
+        response.push((SYSTEM.to_string(), derivation.system.to_string()));
+    }
+    if let Some((arch, os)) = derivation.get_platform() {
+        response.push((ARCH.to_string(), arch));
+        response.push((OS.to_string(), os));
+    }
+
+    if let Some(package) = &package_node.package {
+        if let Some(attribute_path) = &package.attribute_path {
+            response.push((ATTRIBUTE_PATH.to_string(), attribute_path.to_string()));
+        }
+        if let Some(source_provenance) = &package.meta.source_provenance {
+            let short_names = source_provenance
+                .iter()
+                .map(|p| p.short_name.to_string())
+                .collect::<Vec<String>>();
+            if !short_names.is_empty() {
+                response.push((SOURCE_PROVENANCE.to_string(), short_names.join(",")));
+            }
+        }
+        let lifecycle_signals = package.meta.get_lifecycle_signals();
+        if !lifecycle_signals.is_empty() {
+            response.push((LIFECYCLE.to_string(), lifecycle_signals.join(",")));
+        }
+        for note in package.meta.known_vulnerabilities.iter().flatten() {
+            response.push((LIFECYCLE_NOTE.to_string(), note.to_string()));
+        }
+    }
+
+    if let Some(closure_size) = package_node.closure_size {
+        response.push((CLOSURE_SIZE.to_string(), closure_size.to_string()));
+    }
+    if let Some(nar_size) = package_node.nar_size {
+        response.push((NAR_SIZE.to_string(), nar_size.to_string()));
+    }
+
+    if let Some(nar_hash) = &derivation.nar_hash {
+        response.push((NAR_HASH.to_string(), nar_hash.to_string()));
+    }
+
+    if package_node.incomplete {
+        response.push((INCOMPLETE.to_string(), "true".to_string()));
+    }
+    if package_node.substituted {
+        response.push((SUBSTITUTED.to_string(), "true".to_string()));
+    }
+
+    if let Some(concluded_license) = &package_node.concluded_license {
+        response.push((CONCLUDED_LICENSE.to_string(), concluded_license.to_string()));
+    }
+
+    for owner in &package_node.owners {
+        response.push((OWNER.to_string(), owner.to_string()));
+    }
+
+    if let Some(kernel_component) = crate::classify::get_kernel_component(package_node) {
+        response.push((
+            KERNEL_COMPONENT.to_string(),
+            kernel_component.get_name().to_string(),
+        ));
+    }
+
+    // The version of the compiler is the version of the component.
+    if let Some(compiler) = crate::classify::get_compiler(package_node) {
+        response.push((TOOLCHAIN.to_string(), "true".to_string()));
+        response.push((TOOLCHAIN_COMPILER.to_string(), compiler.to_string()));
+    }
+
+    response
+}
+
+pub fn get_flake_input_properties(flake_input: &crate::flake::FlakeInput) -> Properties {
+    let mut response: Properties = vec![];
+    let locked = match &flake_input.locked {
+        Some(l) => l,
+        None => return response,
+    };
+    response.push((FLAKE_TYPE.to_string(), locked.type_.to_string()));
+    if let Some(nar_hash) = &locked.nar_hash {
+        response.push((NAR_HASH.to_string(), nar_hash.to_string()));
+    }
+    if let Some(last_modified) = &locked.last_modified {
+        response.push((FLAKE_LAST_MODIFIED.to_string(), last_modified.to_string()));
+    }
+    response
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+/// Summary of a SBOM generation, returned with the SBOM so that the callers
+/// can check its quality, for example to fail a CI job when the coverage is too low.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(PartialEq)]
+pub struct GenerationReport {
+    /// Number of nodes in the package graph.
+    pub nodes_count: usize,
+
+    /// Number of packages in the SBOM.
+    pub components_count: usize,
+
+    /// Duration of the phases of the generation, in seconds, indexed by phase.
+    pub durations: BTreeMap<String, f64>,
+
+    /// Number of packages with a warning, indexed by warning.
+    pub warnings: BTreeMap<String, usize>,
+
+    /// Derivations excluded from the SBOM, with the reason of the exclusion.
+    pub skipped: BTreeMap<String, String>,
+
+    /// Derivations that were missing from the store.
+    pub incomplete: Vec<String>,
+
+    /// Package metadata entries that could not be parsed and were skipped, with
+    /// the error, indexed by attribute path.
+    pub invalid_packages: BTreeMap<String, String>,
+
+    /// Licenses of the kernel, the kernel modules and the firmware, indexed by package.
+    /// The license is unknown when the package has no metadata.
+    pub kernel_components: BTreeMap<String, String>,
+
+    pub coverage: Coverage,
+}
+
+/// Ratio, between 0 and 1, of the packages of the SBOM for which the information is known.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(PartialEq)]
+pub struct Coverage {
+    pub version: f64,
+    pub license: f64,
+    pub package_meta: f64,
+    /// Packages with a purl type other than generic.
+    pub purl_type: f64,
+}
+
+impl GenerationReport {
+    pub fn new(package_graph: &crate::nix::PackageGraph) -> GenerationReport {
+        let mut report = GenerationReport {
+            nodes_count: package_graph.nodes.len(),
+            components_count: package_graph.nodes_next.len(),
+            skipped: package_graph.excluded_nodes.clone(),
+            incomplete: package_graph.get_incomplete_nodes(),
+            ..Default::default()
+        };
+
+        let mut version_count = 0;
+        let mut license_count = 0;
+        let mut package_meta_count = 0;
+        let mut purl_type_count = 0;
+        for package_node in package_graph.nodes_next.values() {
+            for warning in package_node.get_warnings() {
+                *report.warnings.entry(warning).or_insert(0) += 1;
+            }
+            if package_node.get_version().is_some() {
+                version_count += 1;
+            }
+            if package_node.concluded_license.is_some()
+                || package_node
+                    .package
+                    .as_ref()
+                    .is_some_and(|p| !p.meta.get_licenses().is_empty())
+            {
+                license_count += 1;
+            }
+            if package_node.package.is_some() {
+                package_meta_count += 1;
+            }
+            if package_node.get_purl().scheme != "generic" {
+                purl_type_count += 1;
+            }
+            if let Some(kernel_component) = crate::classify::get_kernel_component(package_node) {
+                let license = package_node
+                    .concluded_license
+                    .clone()
+                    .or(package_node
+                        .package
+                        .as_ref()
+                        .and_then(|p| p.meta.get_spdx_license_expression()))
+                    .unwrap_or("unknown".to_string());
+                let mut name = package_node.name.clone().unwrap_or_default();
+                if let Some(version) = package_node.get_version() {
+                    name = format!("{} {}", name, version);
+                }
+                report
+                    .kernel_components
+                    .insert(format!("{} ({})", name, kernel_component.get_name()), license);
+            }
+        }
+        report.coverage = Coverage {
+            version: get_ratio(version_count, report.components_count),
+            license: get_ratio(license_count, report.components_count),
+            package_meta: get_ratio(package_meta_count, report.components_count),
+            purl_type: get_ratio(purl_type_count, report.components_count),
+        };
+        report
+    }
+
+    pub fn add_duration(&mut self, phase: &str, started_at: Instant) {
+        self.durations
+            .insert(phase.to_string(), started_at.elapsed().as_secs_f64());
+    }
+
+    // Formats the report for humans, one section per line.
+    pub fn to_summary(&self) -> String {
+        self.to_localized_summary(&crate::i18n::Language::default())
+    }
+
+    // The reasons of the exclusions, the warnings and the phases are identifiers,
+    // and are not translated.
+    pub fn to_localized_summary(&self, language: &crate::i18n::Language) -> String {
+        use crate::i18n::Message;
+        let text = |message: Message| language.get_text(&message);
+        let mut lines: Vec<String> = vec![];
+        lines.push(format!(
+            "{}: {} ({} {}, {} {}, {} {})",
+            text(Message::Components),
+            self.components_count,
+            self.nodes_count,
+            text(Message::Nodes),
+            self.skipped.len(),
+            text(Message::Skipped),
+            self.incomplete.len(),
+            text(Message::Incomplete)
+        ));
+        lines.push(format!(
+            "{}: {} {}, {} {}, {} {}, {} {}",
+            text(Message::Coverage),
+            text(Message::Version),
+            language.format_percentage(self.coverage.version),
+            text(Message::License),
+            language.format_percentage(self.coverage.license),
+            text(Message::PackageMetadata),
+            language.format_percentage(self.coverage.package_meta),
+            text(Message::PurlType),
+            language.format_percentage(self.coverage.purl_type)
+        ));
+        let mut skipped_count: BTreeMap<&str, usize> = BTreeMap::default();
+        for reason in self.skipped.values() {
+            *skipped_count.entry(reason).or_insert(0) += 1;
+        }
+        for (reason, count) in skipped_count {
+            lines.push(format!(
+                "{}: {} ({})",
+                text(Message::SkippedReason),
+                reason,
+                count
+            ));
+        }
+        if !self.invalid_packages.is_empty() {
+            lines.push(format!(
+                "{}: {} {}",
+                text(Message::InvalidPackageMetadata),
+                self.invalid_packages.len(),
+                text(Message::EntriesSkipped)
+            ));
+        }
+        for (kernel_component, license) in &self.kernel_components {
+            lines.push(format!(
+                "{}: {}: {}",
+                text(Message::KernelComponent),
+                kernel_component,
+                license
+            ));
+        }
+        for (warning, count) in &self.warnings {
+            lines.push(format!("{}: {} ({})", text(Message::Warning), warning, count));
+        }
+        let durations = self
+            .durations
+            .iter()
+            .map(|(phase, duration)| format!("{} {}s", phase, language.format_number(*duration, 2)))
+            .collect::<Vec<String>>();
+        if !durations.is_empty() {
+            lines.push(format!("{}: {}", text(Message::Durations), durations.join(", ")));
+        }
+        lines.join("\n")
+    }
+}
+
+fn get_ratio(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    count as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_generation_report() {
+        let derivations: crate::nix::Derivations = serde_json::from_str(
+            r###"
+          {
+            "/nix/store/a-app-1.0.0.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "pname": "app", "version": "1.0.0", "src": "/nix/store/b-app-1.0.0.crate" },
+              "inputDrvs": {
+                "/nix/store/b-app-1.0.0.crate.drv": { "dynamicOutputs": {}, "outputs": ["out"] },
+                "/nix/store/5c4zl3hmbjc5vh9nrxcx5pq6zm4hzkhh-zlib-1.3.1.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app-1.0.0" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/b-app-1.0.0.crate.drv": {
+              "args": [],
+              "builder": "builtin:fetchurl",
+              "env": { "name": "app-1.0.0.crate", "urls": "https://crates.io/api/v1/crates/app/1.0.0/download" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-app-1.0.0.crate" } },
+              "system": "builtin"
+            }
+          }
+        "###,
+        )
+        .unwrap();
+        let mut package_graph = crate::nix::get_package_graph(&derivations);
+        package_graph.transform(&crate::nix::Packages::default()).unwrap();
+
+        let mut report = GenerationReport::new(&package_graph);
+        report.add_duration("dump", Instant::now());
+        assert_eq!(report.nodes_count, 3);
+        assert_eq!(report.components_count, 1);
+        assert_eq!(
+            report.incomplete,
+            vec!["/nix/store/5c4zl3hmbjc5vh9nrxcx5pq6zm4hzkhh-zlib-1.3.1.drv".to_string()]
+        );
+        assert_eq!(report.coverage.version, 1.0);
+        assert_eq!(report.coverage.package_meta, 0.0);
+        assert_eq!(report.coverage.purl_type, 0.0);
+        assert_eq!(
+            report.warnings.get(
+                "No package metadata was found, so the description, licenses and maintainers are missing."
+            ),
+            Some(&1)
+        );
+        assert!(report.to_summary().contains("version 100.0%"));
+        assert!(report.to_summary().starts_with("Components: 1 (3 nodes"));
+        assert!(report
+            .to_localized_summary(&crate::i18n::Language::French)
+            .starts_with("Composants: 1 (3 nœuds"));
+        assert!(report
+            .to_localized_summary(&crate::i18n::Language::German)
+            .contains("Version 100,0%"));
+    }
+}
@@ -0,0 +1,185 @@
+// Search of the components of a package graph, as a quicker alternative to
+// generating a full SBOM and searching it.
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use regex::Regex;
+use serde::Serialize;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct SearchResult {
+    /// Path of the derivation of the component.
+    pub id: String,
+
+    pub name: String,
+
+    pub version: Option<String>,
+
+    pub purl: String,
+
+    pub license: Option<String>,
+
+    /// Names of the components leading to this component, starting from a root
+    /// component. Empty for the root components.
+    pub dependency_path: Vec<String>,
+}
+
+// Returns the components with a name or a purl matching the pattern.
+pub fn search(
+    package_graph: &crate::nix::PackageGraph,
+    pattern: &Regex,
+    runtime_only: bool,
+) -> Vec<SearchResult> {
+    let parents = get_parents(package_graph, runtime_only);
+    let mut response: Vec<SearchResult> = vec![];
+    for (derivation_path, package_node) in &package_graph.nodes_next {
+        let name = get_component_name(derivation_path, package_node);
+        let purl = package_node.get_purl().to_string();
+        if !pattern.is_match(&name) && !pattern.is_match(&purl) {
+            continue;
+        }
+        response.push(SearchResult {
+            id: derivation_path.to_string(),
+            name,
+            version: package_node.get_version(),
+            purl,
+            license: crate::license::get_package_license_expression(package_node),
+            dependency_path: get_dependency_path(package_graph, &parents, derivation_path),
+        });
+    }
+    response
+}
+
+// Indexes the components by dependency, using the first parent found in a breadth-first
+// walk from the root components, so that the dependency paths are the shortest ones.
+pub fn get_parents(package_graph: &crate::nix::PackageGraph, runtime_only: bool) -> BTreeMap<String, String> {
+    let mut parents: BTreeMap<String, String> = BTreeMap::default();
+    let mut visited: BTreeSet<String> = package_graph.root_nodes.clone();
+    let mut queue: VecDeque<String> = package_graph.root_nodes.iter().cloned().collect();
+    while let Some(current_path) = queue.pop_front() {
+        let current_node = match package_graph.nodes_next.get(&current_path) {
+            Some(n) => n,
+            None => continue,
+        };
+        let mut children: Vec<&String> = current_node.children.iter().collect();
+        if !runtime_only {
+            children.extend(current_node.build_inputs.iter());
+        }
+        for child_path in children {
+            if visited.insert(child_path.to_string()) {
+                parents.insert(child_path.to_string(), current_path.to_string());
+                queue.push_back(child_path.to_string());
+            }
+        }
+    }
+    parents
+}
+
+pub fn get_dependency_path(
+    package_graph: &crate::nix::PackageGraph,
+    parents: &BTreeMap<String, String>,
+    derivation_path: &str,
+) -> Vec<String> {
+    let mut response: Vec<String> = vec![];
+    let mut current_path = derivation_path;
+    while let Some(parent_path) = parents.get(current_path) {
+        let name = match package_graph.nodes_next.get(parent_path) {
+            Some(n) => get_component_name(parent_path, n),
+            None => crate::nix::get_store_path_name(parent_path),
+        };
+        response.insert(0, name);
+        current_path = parent_path;
+    }
+    response
+}
+
+fn get_component_name(derivation_path: &str, package_node: &crate::nix::PackageNode) -> String {
+    match &package_node.name {
+        Some(n) => n.to_string(),
+        None => crate::nix::get_store_path_name(derivation_path),
+    }
+}
+
+// One component per paragraph, with its purl and the path leading to it.
+pub fn to_text(search_results: &[SearchResult]) -> String {
+    let mut lines: Vec<String> = vec![];
+    for search_result in search_results {
+        let mut title = search_result.name.to_string();
+        if let Some(version) = &search_result.version {
+            title.push_str(&format!(" {}", version));
+        }
+        if let Some(license) = &search_result.license {
+            title.push_str(&format!(" ({})", license));
+        }
+        lines.push(title);
+        lines.push(format!("  {}", search_result.purl));
+        if !search_result.dependency_path.is_empty() {
+            let mut dependency_path = search_result.dependency_path.clone();
+            dependency_path.push(search_result.name.to_string());
+            lines.push(format!("  {}", dependency_path.join(" -> ")));
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_search() {
+        let derivations: crate::nix::Derivations = serde_json::from_str(
+            r###"
+          {
+            "/nix/store/a-app-1.0.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "app-1.0" },
+              "inputDrvs": {
+                "/nix/store/b-libfoo-2.1.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/a-app-1.0" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/b-libfoo-2.1.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "libfoo-2.1" },
+              "inputDrvs": {
+                "/nix/store/c-libbar-0.3.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+              },
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/b-libfoo-2.1" } },
+              "system": "x86_64-linux"
+            },
+            "/nix/store/c-libbar-0.3.drv": {
+              "args": [],
+              "builder": "/bin/sh",
+              "env": { "name": "libbar-0.3" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": { "out": { "path": "/nix/store/c-libbar-0.3" } },
+              "system": "x86_64-linux"
+            }
+          }
+        "###,
+        )
+        .unwrap();
+        let mut package_graph = crate::nix::get_package_graph(&derivations);
+        package_graph.nodes_next = package_graph.nodes.clone();
+
+        let search_results = search(&package_graph, &Regex::new("^lib").unwrap(), false);
+        assert_eq!(search_results.len(), 2);
+        let libbar = search_results
+            .iter()
+            .find(|r| r.id == "/nix/store/c-libbar-0.3.drv")
+            .unwrap();
+        assert_eq!(libbar.dependency_path.len(), 2);
+        assert!(to_text(&search_results).contains(" -> "));
+
+        assert!(search(&package_graph, &Regex::new("^nothing$").unwrap(), false).is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+// SBOM of nix2sbom itself, built from the Cargo.lock file embedded in the binary at
+// compile time, in the spirit of cargo auditable. The lock file lists every crate
+// that could be compiled in, including the dev-dependencies and the dependencies of
+// the other platforms, so the SBOM is an over-approximation of the binary.
+use std::collections::BTreeMap;
+
+use serde_cyclonedx::cyclonedx::v_1_5::{
+    Component, ComponentBuilder, CycloneDxBuilder, Dependency, DependencyBuilder, HashAlg, HashBuilder,
+    Metadata,
+};
+
+const CARGO_LOCK: &str = include_str!("../Cargo.lock");
+const CRATES_IO_SOURCE: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+
+    /// The dependencies, as `name` or `name version` when several versions of
+    /// the dependency are locked.
+    pub dependencies: Vec<String>,
+}
+
+impl LockedPackage {
+    pub fn get_purl(&self) -> String {
+        format!("pkg:cargo/{}@{}", self.name, self.version)
+    }
+
+    // Only the crates of a registry are published, the others are part of the workspace
+    // or fetched from git.
+    pub fn is_from_crates_io(&self) -> bool {
+        self.source.as_deref() == Some(CRATES_IO_SOURCE)
+    }
+}
+
+// Parses the [[package]] entries of a Cargo.lock file. Only the fields used for the
+// SBOM are read.
+pub fn parse_cargo_lock(content: &str) -> Vec<LockedPackage> {
+    let mut response: Vec<LockedPackage> = vec![];
+    let mut current_package: Option<LockedPackage> = None;
+    let mut in_dependencies = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') && !in_dependencies {
+            if let Some(package) = current_package.take() {
+                response.push(package);
+            }
+            if line == "[[package]]" {
+                current_package = Some(LockedPackage::default());
+            }
+            continue;
+        }
+        let package = match current_package.as_mut() {
+            Some(p) => p,
+            None => continue,
+        };
+        if in_dependencies {
+            if line == "]" {
+                in_dependencies = false;
+            } else {
+                let dependency = line.trim_end_matches(',').trim_matches('"');
+                // The source is only given when the name and the version are ambiguous.
+                let dependency = dependency.split(" (").next().unwrap_or(dependency);
+                package.dependencies.push(dependency.to_string());
+            }
+            continue;
+        }
+        let (key, value) = match line.split_once(" = ") {
+            Some((k, v)) => (k, v.trim_matches('"').to_string()),
+            None => continue,
+        };
+        match key {
+            "name" => package.name = value,
+            "version" => package.version = value,
+            "source" => package.source = Some(value),
+            "checksum" => package.checksum = Some(value),
+            "dependencies" => in_dependencies = value != "[]",
+            _ => {}
+        };
+    }
+    if let Some(package) = current_package {
+        response.push(package);
+    }
+    response
+}
+
+pub fn dump(
+    format: &crate::format::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, anyhow::Error> {
+    let locked_packages = parse_cargo_lock(CARGO_LOCK);
+    let root_package = locked_packages
+        .iter()
+        .find(|p| p.name == crate::consts::PROJECT_NAME && p.source.is_none())
+        .ok_or(anyhow::format_err!(
+            "{} is not in the lock file",
+            crate::consts::PROJECT_NAME
+        ))?;
+
+    // The dependencies are given by name, and by version when several versions are locked.
+    let mut package_refs: BTreeMap<String, String> = BTreeMap::default();
+    let mut locked_versions: BTreeMap<&str, usize> = BTreeMap::default();
+    for package in &locked_packages {
+        *locked_versions.entry(&package.name).or_default() += 1;
+    }
+    for package in &locked_packages {
+        let purl = get_package_purl(package);
+        package_refs.insert(format!("{} {}", package.name, package.version), purl.clone());
+        if locked_versions.get(package.name.as_str()) == Some(&1) {
+            package_refs.insert(package.name.to_string(), purl);
+        }
+    }
+
+    let mut metadata = Metadata::default();
+    metadata.timestamp = match &options.metadata.timestamp {
+        Some(timestamp) => Some(timestamp.to_string()),
+        None => Some(chrono::Utc::now().to_rfc3339()),
+    };
+    metadata.component = Some(dump_locked_package(root_package, "application"));
+
+    let mut components: Vec<Component> = vec![];
+    let mut dependencies: Vec<Dependency> = vec![];
+    for package in &locked_packages {
+        if package != root_package {
+            components.push(dump_locked_package(package, "library"));
+        }
+        let depends_on: Vec<serde_json::Value> = package
+            .dependencies
+            .iter()
+            .filter_map(|d| package_refs.get(d))
+            .map(|r| r.as_str().into())
+            .collect();
+        dependencies.push(
+            DependencyBuilder::default()
+                .ref_(get_package_purl(package))
+                .depends_on(depends_on)
+                .build()
+                .unwrap(),
+        );
+    }
+
+    let cyclonedx = CycloneDxBuilder::default()
+        .bom_format(crate::format::CYCLONE_DX_NAME)
+        .spec_version(crate::format::cyclone_dx::CURRENT_SPEC_VERSION)
+        .version(1)
+        .metadata(metadata)
+        .components(components)
+        .dependencies(dependencies)
+        .build()
+        .unwrap();
+    let spec_version = options
+        .spec_version
+        .clone()
+        .unwrap_or(crate::format::cyclone_dx::DEFAULT_SPEC_VERSION.to_string());
+    let cyclonedx = crate::format::cyclone_dx::convert_to_spec_version(&cyclonedx, &spec_version)?;
+    crate::format::cyclone_dx::serialize(&cyclonedx, format, options)
+}
+
+// The purl is also used as the bom-ref of the component.
+fn get_package_purl(package: &LockedPackage) -> String {
+    if package.is_from_crates_io() || package.source.is_none() {
+        return package.get_purl();
+    }
+    let source = package.source.as_deref().unwrap_or_default();
+    format!(
+        "{}?repository_url={}",
+        package.get_purl(),
+        source.split_once('+').map(|(_, url)| url).unwrap_or(source)
+    )
+}
+
+fn dump_locked_package(package: &LockedPackage, component_type: &str) -> Component {
+    let purl = get_package_purl(package);
+    let mut component_builder = ComponentBuilder::default();
+    component_builder
+        .bom_ref(purl.clone())
+        .type_(component_type)
+        .name(package.name.to_string())
+        .version(package.version.to_string())
+        .purl(purl);
+    if let Some(checksum) = &package.checksum {
+        component_builder.hashes(vec![HashBuilder::default()
+            .alg(HashAlg::Sha256)
+            .content(checksum.to_string())
+            .build()
+            .unwrap()]);
+    }
+    component_builder.build().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_cargo_lock() {
+        let locked_packages = parse_cargo_lock(
+            r###"
+# This file is automatically @generated by Cargo.
+# It is not intended for manual editing.
+version = 3
+
+[[package]]
+name = "aho-corasick"
+version = "1.1.3"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "8e60d3430d3a69478ad0993f19238d2df97c507009a52b3c10addcd7f6bcb916"
+dependencies = [
+ "memchr",
+]
+
+[[package]]
+name = "nix2sbom"
+version = "0.0.0-placeholder-version"
+dependencies = [
+ "aho-corasick",
+ "syn 2.0.72",
+ "serde-cyclonedx (git+https://github.com/louib/serde-cyclonedx#abcdef)",
+]
+            "###,
+        );
+        assert_eq!(locked_packages.len(), 2);
+        assert_eq!(locked_packages[0].name, "aho-corasick");
+        assert_eq!(locked_packages[0].dependencies, vec!["memchr"]);
+        assert!(locked_packages[0].is_from_crates_io());
+        assert_eq!(locked_packages[0].get_purl(), "pkg:cargo/aho-corasick@1.1.3");
+        assert_eq!(
+            locked_packages[1].dependencies,
+            vec!["aho-corasick", "syn 2.0.72", "serde-cyclonedx"]
+        );
+        assert_eq!(locked_packages[1].source, None);
+
+        assert!(parse_cargo_lock(CARGO_LOCK)
+            .iter()
+            .any(|p| p.name == crate::consts::PROJECT_NAME));
+    }
+}
@@ -266,6 +266,280 @@ pub fn get_project_name_from_archive_url(archive_url: &str) -> Option<String> {
     return None;
 }
 
+// FNV-1a hash, used when we need identifiers that are stable across runs
+// and platforms. This is not a cryptographic hash.
+pub fn get_stable_hash(value: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in value.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+const SHA256_ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98,
+    0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8,
+    0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819,
+    0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+    0xc67178f2,
+];
+
+// Returns the hex-encoded SHA-256 digest of the content.
+pub fn get_sha256_digest(content: &[u8]) -> String {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    let mut message = content.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((content.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            words[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = words[i - 15].rotate_right(7) ^ words[i - 15].rotate_right(18) ^ (words[i - 15] >> 3);
+            let s1 = words[i - 2].rotate_right(17) ^ words[i - 2].rotate_right(19) ^ (words[i - 2] >> 10);
+            words[i] = words[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(words[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_ROUND_CONSTANTS[i])
+                .wrapping_add(words[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (value, new_value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(new_value);
+        }
+    }
+
+    state.iter().map(|v| format!("{:08x}", v)).collect::<String>()
+}
+
+// Returns the hex-encoded HMAC-SHA256 of the content, see RFC 2104.
+pub fn get_hmac_sha256_digest(key: &[u8], content: &[u8]) -> String {
+    let mut block_key = match key.len() > 64 {
+        true => decode_base16(&get_sha256_digest(key)).unwrap_or_default(),
+        false => key.to_vec(),
+    };
+    block_key.resize(64, 0);
+
+    let mut inner: Vec<u8> = block_key.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(content);
+    let mut outer: Vec<u8> = block_key.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend(decode_base16(&get_sha256_digest(&inner)).unwrap_or_default());
+    get_sha256_digest(&outer)
+}
+
+// Returns the hex-encoded SHA-1 digest of the content. SHA-1 is only used where
+// a specification requires it, like the gitoids.
+pub fn get_sha1_digest(content: &[u8]) -> String {
+    sha1_smol::Sha1::from(content).digest().to_string()
+}
+
+// Returns the OmniBOR artifact ids of a file, which are the gitoids of its content
+// hashed as a git blob, with SHA-1 and SHA-256.
+// See https://omnibor.io/docs/artifact-ids/
+pub fn get_omnibor_ids(content: &[u8]) -> Vec<String> {
+    let mut blob = format!("blob {}\0", content.len()).into_bytes();
+    blob.extend_from_slice(content);
+    vec![
+        format!("gitoid:blob:sha1:{}", get_sha1_digest(&blob)),
+        format!("gitoid:blob:sha256:{}", get_sha256_digest(&blob)),
+    ]
+}
+
+const NIX_BASE32_ALPHABET: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode_base16(content: &[u8]) -> String {
+    content.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+}
+
+pub fn decode_base16(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Decodes the standard base64 encoding used by the SRI hashes, e.g. sha256-I3PGgh0X...=
+pub fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim_end_matches('=');
+    let mut response: Vec<u8> = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in value.bytes() {
+        let digit = BASE64_ALPHABET.iter().position(|a| *a == c)? as u32;
+        buffer = (buffer << 6) | digit;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            response.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(response)
+}
+
+// Encodes in the standard base64 encoding, with the padding, as in the SRI hashes.
+pub fn encode_base64(content: &[u8]) -> String {
+    let mut response = String::default();
+    for chunk in content.chunks(3) {
+        let buffer = chunk.iter().fold(0u32, |b, c| (b << 8) | *c as u32) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            if i > chunk.len() {
+                response.push('=');
+            } else {
+                response.push(BASE64_ALPHABET[((buffer >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            }
+        }
+    }
+    response
+}
+
+// Decodes the base32 encoding of Nix, which uses its own alphabet and
+// starts with the last byte of the hash.
+pub fn decode_nix_base32(value: &str) -> Option<Vec<u8>> {
+    let size = value.len() * 5 / 8;
+    let mut response: Vec<u8> = vec![0; size];
+    for (n, c) in value.bytes().rev().enumerate() {
+        let digit = NIX_BASE32_ALPHABET.iter().position(|a| *a == c)? as u16;
+        let i = n * 5 / 8;
+        let j = n * 5 % 8;
+        response[i] |= (digit << j) as u8;
+        let carry = (digit << j) >> 8;
+        if i + 1 < size {
+            response[i + 1] |= carry as u8;
+        } else if carry != 0 {
+            return None;
+        }
+    }
+    Some(response)
+}
+
+// Returns the cache directory of nix2sbom, following the XDG base directory specification.
+pub fn get_cache_dir() -> Option<std::path::PathBuf> {
+    let cache_home = match std::env::var("XDG_CACHE_HOME") {
+        Ok(c) if !c.is_empty() => std::path::PathBuf::from(c),
+        _ => std::path::PathBuf::from(std::env::var("HOME").ok()?).join(".cache"),
+    };
+    Some(cache_home.join(crate::consts::PROJECT_NAME))
+}
+
+// Writes the content to a temporary file next to the destination, and then renames it,
+// so that readers never see a partially written file.
+pub fn write_file_atomically(path: &str, content: &str) -> Result<(), anyhow::Error> {
+    write_atomically(path, |temp_path| Ok(std::fs::write(temp_path, content)?))
+}
+
+// Same as write_file_atomically, but the content is compressed while it is written.
+pub fn write_compressed_file_atomically(
+    path: &str,
+    content: &str,
+    compression: &crate::format::Compression,
+) -> Result<(), anyhow::Error> {
+    write_atomically(path, |temp_path| {
+        let file = std::fs::File::create(temp_path)?;
+        compression.compress_to(content.as_bytes(), file.into())
+    })
+}
+
+// Returns the time set with SOURCE_DATE_EPOCH, in seconds since the Unix epoch.
+// See https://reproducible-builds.org/specs/source-date-epoch/
+pub fn get_source_date_epoch() -> Result<Option<chrono::DateTime<chrono::Utc>>, anyhow::Error> {
+    let source_date_epoch = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(v) if !v.trim().is_empty() => v,
+        _ => return Ok(None),
+    };
+    let seconds: i64 = source_date_epoch
+        .trim()
+        .parse()
+        .map_err(|e| anyhow::format_err!("Invalid SOURCE_DATE_EPOCH {}: {}", source_date_epoch, e))?;
+    match chrono::DateTime::from_timestamp(seconds, 0) {
+        Some(t) => Ok(Some(t)),
+        None => Err(anyhow::format_err!(
+            "SOURCE_DATE_EPOCH {} is out of range",
+            source_date_epoch
+        )),
+    }
+}
+
+// Joins the arguments of a command, quoting the arguments that the shell would
+// otherwise interpret, so that the command can be copied and run again.
+pub fn get_command_line(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let is_safe = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@+%#".contains(c));
+            if is_safe {
+                arg.to_string()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn write_atomically<F>(path: &str, write: F) -> Result<(), anyhow::Error>
+where
+    F: FnOnce(&std::path::Path) -> Result<(), anyhow::Error>,
+{
+    let destination = std::path::Path::new(path);
+    let file_name = match destination.file_name() {
+        Some(n) => n.to_string_lossy().to_string(),
+        None => return Err(anyhow::format_err!("Invalid output path {}", path)),
+    };
+    let temp_path = destination.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()));
+    if let Err(e) = write(&temp_path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(anyhow::format_err!(
+            "Could not write {}: {}",
+            temp_path.display(),
+            e
+        ));
+    }
+    if let Err(e) = std::fs::rename(&temp_path, destination) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(anyhow::format_err!("Could not write {}: {}", path, e));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -426,4 +700,124 @@ mod tests {
         assert!(project_name.is_some());
         assert_eq!(project_name.unwrap(), "clap_lex");
     }
+
+    #[test]
+    pub fn test_get_stable_hash() {
+        assert_eq!(crate::utils::get_stable_hash(""), "cbf29ce484222325");
+        assert_eq!(crate::utils::get_stable_hash("a"), "af63dc4c8601ec8c");
+    }
+
+    #[test]
+    pub fn test_write_file_atomically() {
+        let directory = std::env::temp_dir().join(format!("nix2sbom-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("sbom.json").display().to_string();
+        crate::utils::write_file_atomically(&path, "first").unwrap();
+        crate::utils::write_file_atomically(&path, "second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_write_compressed_file_atomically() {
+        let directory = std::env::temp_dir().join(format!("nix2sbom-test-gzip-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("sbom.json.gz").display().to_string();
+        crate::utils::write_compressed_file_atomically(&path, "{}", &crate::format::Compression::Gzip).unwrap();
+        let output = std::process::Command::new("gzip")
+            .arg("--decompress")
+            .arg("--stdout")
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "{}");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_decode_hashes() {
+        let empty_sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(
+            crate::utils::decode_nix_base32("0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")
+                .map(|d| crate::utils::encode_base16(&d)),
+            Some(empty_sha256.to_string())
+        );
+        assert_eq!(
+            crate::utils::decode_base64("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=")
+                .map(|d| crate::utils::encode_base16(&d)),
+            Some(empty_sha256.to_string())
+        );
+        assert_eq!(
+            crate::utils::decode_base16(empty_sha256).map(|d| crate::utils::encode_base16(&d)),
+            Some(empty_sha256.to_string())
+        );
+        assert_eq!(
+            crate::utils::decode_base16(empty_sha256).map(|d| crate::utils::encode_base64(&d)),
+            Some("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string())
+        );
+        assert_eq!(crate::utils::encode_base64(b"ab"), "YWI=");
+        assert_eq!(crate::utils::encode_base64(b"a"), "YQ==");
+        assert_eq!(crate::utils::decode_nix_base32("e"), None);
+    }
+
+    #[test]
+    pub fn test_get_command_line() {
+        let args: Vec<String> = ["nix2sbom", ".#hello", "--format", "cdx,spdx", "--lang", "it's"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(
+            crate::utils::get_command_line(&args),
+            "nix2sbom .#hello --format cdx,spdx --lang 'it'\\''s'"
+        );
+        assert_eq!(crate::utils::get_command_line(&["a b".to_string()]), "'a b'");
+    }
+
+    #[test]
+    pub fn test_get_sha256_digest() {
+        assert_eq!(
+            crate::utils::get_sha256_digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            crate::utils::get_sha256_digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            crate::utils::get_sha256_digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    pub fn test_get_hmac_sha256_digest() {
+        // Test cases 2 and 6 of RFC 4231.
+        assert_eq!(
+            crate::utils::get_hmac_sha256_digest(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            crate::utils::get_hmac_sha256_digest(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    pub fn test_get_omnibor_ids() {
+        assert_eq!(
+            crate::utils::get_omnibor_ids(b""),
+            vec![
+                "gitoid:blob:sha1:e69de29bb2d1d6434b8b29ae775ad8c2e48c5391",
+                "gitoid:blob:sha256:473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813",
+            ]
+        );
+        assert_eq!(
+            crate::utils::get_omnibor_ids(b"hello world\n")[0],
+            "gitoid:blob:sha1:3b18e512dba79e4c8300dd08aeb37f8e728b8dad"
+        );
+    }
 }
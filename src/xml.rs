@@ -0,0 +1,338 @@
+// Minimal XML document model, used to serialize the SBOM formats that have an
+// XML representation. Only elements, attributes and text are supported, which
+// is all the SBOM formats need. Mixed content (text and elements in the same
+// element) is not supported.
+
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+const INDENTATION: &str = "  ";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct XmlElement {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<XmlElement>,
+    pub text: Option<String>,
+}
+
+impl XmlElement {
+    pub fn new(name: &str) -> XmlElement {
+        XmlElement {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<&String> {
+        self.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn get_child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn get_children(&self, name: &str) -> Vec<&XmlElement> {
+        self.children.iter().filter(|c| c.name == name).collect()
+    }
+
+    // Serializes the element as a XML document.
+    pub fn to_document(&self, pretty: bool) -> String {
+        let mut response = XML_DECLARATION.to_string();
+        if pretty {
+            response += "\n";
+        }
+        self.write(&mut response, 0, pretty);
+        response
+    }
+
+    fn write(&self, response: &mut String, depth: usize, pretty: bool) {
+        if pretty {
+            *response += &INDENTATION.repeat(depth);
+        }
+        *response += "<";
+        *response += &self.name;
+        for (name, value) in &self.attributes {
+            *response += &format!(" {}=\"{}\"", name, escape(value));
+        }
+        if self.children.is_empty() && self.text.is_none() {
+            *response += "/>";
+        } else if self.children.is_empty() {
+            *response += ">";
+            *response += &escape(self.text.as_deref().unwrap_or_default());
+            *response += &format!("</{}>", self.name);
+        } else {
+            *response += ">";
+            for child in &self.children {
+                if pretty {
+                    *response += "\n";
+                }
+                child.write(response, depth + 1, pretty);
+            }
+            if pretty {
+                *response += "\n";
+                *response += &INDENTATION.repeat(depth);
+            }
+            *response += &format!("</{}>", self.name);
+        }
+    }
+
+    // Parses a XML document and returns its root element.
+    pub fn parse(document: &str) -> Result<XmlElement, anyhow::Error> {
+        let mut parser = XmlParser {
+            input: document,
+            position: 0,
+        };
+        parser.skip_prolog()?;
+        let root = parser.parse_element()?;
+        parser.skip_misc()?;
+        if parser.position != parser.input.len() {
+            return Err(parser.error("Unexpected content after the root element"));
+        }
+        Ok(root)
+    }
+}
+
+struct XmlParser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> XmlParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow::format_err!("Invalid XML at position {}: {}", self.position, message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_until(&mut self, delimiter: &str) -> Result<(), anyhow::Error> {
+        match self.rest().find(delimiter) {
+            Some(index) => {
+                self.position += index + delimiter.len();
+                Ok(())
+            }
+            None => Err(self.error(&format!("Expected {}", delimiter))),
+        }
+    }
+
+    // Skips the comments, processing instructions and whitespace.
+    fn skip_misc(&mut self) -> Result<(), anyhow::Error> {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<?") {
+                self.skip_until("?>")?;
+            } else if self.rest().starts_with("<!--") {
+                self.skip_until("-->")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_prolog(&mut self) -> Result<(), anyhow::Error> {
+        self.skip_misc()?;
+        if self.rest().starts_with("<!DOCTYPE") {
+            return Err(self.error("Document type declarations are not supported"));
+        }
+        Ok(())
+    }
+
+    fn parse_name(&mut self) -> Result<String, anyhow::Error> {
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("Expected a name"));
+        }
+        self.position += length;
+        Ok(rest[..length].to_string())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), anyhow::Error> {
+        if !self.rest().starts_with(expected) {
+            return Err(self.error(&format!("Expected {}", expected)));
+        }
+        self.position += expected.len();
+        Ok(())
+    }
+
+    fn parse_element(&mut self) -> Result<XmlElement, anyhow::Error> {
+        self.expect("<")?;
+        let mut element = XmlElement::new(&self.parse_name()?);
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.position += 2;
+                return Ok(element);
+            }
+            if self.rest().starts_with('>') {
+                self.position += 1;
+                break;
+            }
+            let attribute_name = self.parse_name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(q) if q == '"' || q == '\'' => q,
+                _ => return Err(self.error("Expected a quoted attribute value")),
+            };
+            self.position += 1;
+            let length = match self.rest().find(quote) {
+                Some(l) => l,
+                None => return Err(self.error("Unterminated attribute value")),
+            };
+            let attribute_value = unescape(&self.rest()[..length])?;
+            self.position += length + 1;
+            element.attributes.push((attribute_name, attribute_value));
+        }
+
+        let mut text = String::default();
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.position += 2;
+                let closing_name = self.parse_name()?;
+                if closing_name != element.name {
+                    return Err(self.error(&format!(
+                        "Expected closing tag for {}, found {}",
+                        element.name, closing_name
+                    )));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                break;
+            }
+            if rest.starts_with("<!--") {
+                self.skip_until("-->")?;
+                continue;
+            }
+            if rest.starts_with("<![CDATA[") {
+                self.position += "<![CDATA[".len();
+                let length = match self.rest().find("]]>") {
+                    Some(l) => l,
+                    None => return Err(self.error("Unterminated CDATA section")),
+                };
+                text += &self.rest()[..length];
+                self.position += length + 3;
+                continue;
+            }
+            if rest.starts_with('<') {
+                element.children.push(self.parse_element()?);
+                continue;
+            }
+            let length = match rest.find('<') {
+                Some(l) => l,
+                None => return Err(self.error(&format!("Unterminated element {}", element.name))),
+            };
+            text += &unescape(&rest[..length])?;
+            self.position += length;
+        }
+
+        // Whitespace between child elements is not significant.
+        if element.children.is_empty() {
+            element.text = Some(text);
+        }
+        Ok(element)
+    }
+}
+
+pub fn escape(value: &str) -> String {
+    let mut response = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '<' => response += "&lt;",
+            '>' => response += "&gt;",
+            '&' => response += "&amp;",
+            '"' => response += "&quot;",
+            '\'' => response += "&apos;",
+            _ => response.push(c),
+        }
+    }
+    response
+}
+
+pub fn unescape(value: &str) -> Result<String, anyhow::Error> {
+    let mut response = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(index) = rest.find('&') {
+        response += &rest[..index];
+        rest = &rest[index..];
+        let end = match rest.find(';') {
+            Some(e) => e,
+            None => return Err(anyhow::format_err!("Unterminated entity in {}", value)),
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(decimal) = entity.strip_prefix('#') {
+                    decimal.parse::<u32>().ok()
+                } else {
+                    None
+                };
+                match code.and_then(char::from_u32) {
+                    Some(c) => c,
+                    None => return Err(anyhow::format_err!("Unknown entity &{};", entity)),
+                }
+            }
+        };
+        response.push(c);
+        rest = &rest[end + 1..];
+    }
+    response += rest;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_xml() {
+        let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- generated -->
+<bom xmlns="http://cyclonedx.org/schema/bom/1.5" version='1'>
+  <component type="library">
+    <name>a &amp; b &#x263A;</name>
+    <description><![CDATA[<raw>]]></description>
+    <empty/>
+  </component>
+</bom>
+"#;
+        let root = XmlElement::parse(document).unwrap();
+        assert_eq!(root.name, "bom");
+        assert_eq!(root.get_attribute("version"), Some(&"1".to_string()));
+        let component = root.get_child("component").unwrap();
+        assert_eq!(component.get_attribute("type"), Some(&"library".to_string()));
+        assert_eq!(
+            component.get_child("name").unwrap().text,
+            Some("a & b ☺".to_string())
+        );
+        assert_eq!(
+            component.get_child("description").unwrap().text,
+            Some("<raw>".to_string())
+        );
+        assert_eq!(component.get_child("empty").unwrap().text, None);
+
+        for pretty in [true, false] {
+            assert_eq!(XmlElement::parse(&root.to_document(pretty)).unwrap(), root);
+        }
+        assert!(XmlElement::parse("<bom><component></bom>").is_err());
+    }
+}
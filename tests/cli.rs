@@ -0,0 +1,17 @@
+// Runs the nix2sbom binary with argument combinations that do not need Nix to reach
+// the code under test.
+use std::process::Command;
+
+#[test]
+fn test_search_with_all_outputs() {
+    // The flake cannot be evaluated without Nix, but the references of the search
+    // command must be used to find the flake instead of the empty positional references.
+    let output = Command::new(env!("CARGO_BIN_EXE_nix2sbom"))
+        .args(["--all-outputs", "search", ".#hello", "foo"])
+        .env("PATH", "")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert_ne!(output.status.code(), Some(101));
+}
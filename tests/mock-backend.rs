@@ -0,0 +1,161 @@
+// Runs the metadata pipeline against the mock backend, without Nix on the PATH, to check
+// that every interaction with Nix goes through the NixBackend trait.
+use std::collections::BTreeMap;
+
+use nix2sbom::backend::NixBackend;
+
+const DERIVATIONS: &str = r###"
+{
+  "/nix/store/a-hello-2.12.1.drv": {
+    "args": [],
+    "builder": "/bin/sh",
+    "env": { "name": "hello-2.12.1", "pname": "hello", "version": "2.12.1" },
+    "inputDrvs": {
+      "/nix/store/b-glibc-2.39.drv": { "dynamicOutputs": {}, "outputs": ["out"] }
+    },
+    "inputSrcs": [],
+    "outputs": { "out": { "path": "/nix/store/a-hello-2.12.1" } },
+    "system": "x86_64-linux"
+  },
+  "/nix/store/b-glibc-2.39.drv": {
+    "args": [],
+    "builder": "/bin/sh",
+    "env": { "name": "glibc-2.39", "pname": "glibc", "version": "2.39" },
+    "inputDrvs": {},
+    "inputSrcs": [],
+    "outputs": { "out": { "path": "/nix/store/b-glibc-2.39" } },
+    "system": "x86_64-linux"
+  }
+}
+"###;
+
+const PACKAGES: &str = r###"
+{
+  "hello": {
+    "name": "hello-2.12.1",
+    "pname": "hello",
+    "version": "2.12.1",
+    "meta": { "description": "Program that produces a familiar, friendly greeting", "license": "GPL-3.0-or-later" }
+  },
+  "glibc": {
+    "name": "glibc-2.39",
+    "pname": "glibc",
+    "version": "2.39",
+    "meta": { "description": "The GNU C Library", "license": "LGPL-2.1-or-later" }
+  },
+  "zstd": {
+    "name": "zstd-1.5.6",
+    "pname": "zstd",
+    "version": "1.5.6",
+    "meta": { "description": "Zstandard real-time compression algorithm" }
+  }
+}
+"###;
+
+const SEARCH_RESULTS: &str = r###"
+{
+  "legacyPackages.x86_64-linux.hello": {
+    "pname": "hello",
+    "version": "2.12.1",
+    "description": "Program that produces a familiar, friendly greeting"
+  }
+}
+"###;
+
+const FLAKE_METADATA: &str = r###"
+{
+  "revision": "0123456789abcdef0123456789abcdef01234567",
+  "locks": {
+    "nodes": {
+      "nixpkgs": {
+        "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "abcdef0" }
+      },
+      "root": { "inputs": { "nixpkgs": "nixpkgs" } }
+    },
+    "root": "root",
+    "version": 7
+  }
+}
+"###;
+
+fn get_mock_backend() -> nix2sbom::backend::MockBackend {
+    nix2sbom::backend::MockBackend {
+        derivations: serde_json::from_str(DERIVATIONS).unwrap(),
+        installables: BTreeMap::from([(
+            "github:example/project#hello".to_string(),
+            vec!["/nix/store/a-hello-2.12.1.drv".to_string()],
+        )]),
+        nix_env_metadata: Some(PACKAGES.as_bytes().to_vec()),
+        package_metas: BTreeMap::from([(
+            "nixpkgs#hello".to_string(),
+            serde_json::from_str(r#"{ "license": "GPL-3.0-or-later" }"#).unwrap(),
+        )]),
+        nix_search_metadata: Some(SEARCH_RESULTS.as_bytes().to_vec()),
+        nix_eval_metadata: BTreeMap::from([("nixpkgs".to_string(), PACKAGES.as_bytes().to_vec())]),
+        flake_metadatas: BTreeMap::from([(
+            "github:example/project".to_string(),
+            serde_json::from_str(FLAKE_METADATA).unwrap(),
+        )]),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_get_packages_with_source_without_nix() {
+    // This is the only test of this binary, so the PATH can be changed safely.
+    std::env::set_var("PATH", "");
+    let backend = get_mock_backend();
+
+    let derivations = nix2sbom::nix::Derivation::get_derivations_from_refs(
+        &["github:example/project#hello".to_string()],
+        1,
+        &backend,
+    )
+    .unwrap();
+    assert_eq!(derivations.len(), 2);
+
+    // The whole package set, with nix-env.
+    let metadata_options = nix2sbom::nix::MetadataOptions::default();
+    let (packages, metadata_source, _invalid_packages) =
+        nix2sbom::nix::get_packages_with_source(&metadata_options, &derivations, &backend).unwrap();
+    assert_eq!(metadata_source, nix2sbom::nix::MetadataSource::NixEnv);
+    let mut attribute_paths = packages.keys().collect::<Vec<&String>>();
+    attribute_paths.sort();
+    assert_eq!(attribute_paths, vec!["glibc", "hello", "zstd"]);
+
+    // Only the packages of the derivations, with nix eval.
+    let metadata_options = nix2sbom::nix::MetadataOptions {
+        backend: nix2sbom::nix::MetadataBackend::NixEval("nixpkgs".to_string()),
+        ..Default::default()
+    };
+    let (packages, metadata_source, _invalid_packages) =
+        nix2sbom::nix::get_packages_with_source(&metadata_options, &derivations, &backend).unwrap();
+    assert_eq!(
+        metadata_source,
+        nix2sbom::nix::MetadataSource::NixEval("nixpkgs".to_string())
+    );
+    let mut attribute_paths = packages.keys().collect::<Vec<&String>>();
+    attribute_paths.sort();
+    assert_eq!(attribute_paths, vec!["glibc", "hello"]);
+
+    // nix search, when nix-env fails.
+    let backend = nix2sbom::backend::MockBackend {
+        nix_env_metadata: None,
+        ..get_mock_backend()
+    };
+    let metadata_options = nix2sbom::nix::MetadataOptions::default();
+    let (packages, metadata_source, _invalid_packages) =
+        nix2sbom::nix::get_packages_with_source(&metadata_options, &derivations, &backend).unwrap();
+    assert_eq!(metadata_source, nix2sbom::nix::MetadataSource::NixSearch);
+    let hello = &packages["legacyPackages.x86_64-linux.hello"];
+    assert!(hello.meta.license.is_some());
+
+    let mut package_graph = nix2sbom::nix::get_package_graph(&derivations);
+    package_graph.transform(&packages).unwrap();
+    let flake_metadata = backend.get_flake_metadata("github:example/project").unwrap();
+    package_graph.flake_inputs = flake_metadata.locks.unwrap().get_input_tree();
+    assert_eq!(
+        nix2sbom::flake::get_nixpkgs_revision(&package_graph.flake_inputs),
+        Some("abcdef0".to_string())
+    );
+}
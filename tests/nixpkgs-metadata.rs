@@ -0,0 +1,63 @@
+// Regression suite for the package metadata published with the nixpkgs channels.
+// It needs network access, curl and brotli, so it is ignored by default. Run it with
+//   cargo test --test nixpkgs-metadata -- --ignored
+// The channel can be selected with NIXPKGS_CHANNEL, e.g. NIXPKGS_CHANNEL=nixos-24.05
+use std::path::PathBuf;
+use std::process::Command;
+
+const DEFAULT_CHANNEL: &str = "nixpkgs-unstable";
+
+// Downloads the packages.json.br file of the channel, unless it was already downloaded
+// by a previous run, and returns the decompressed content.
+fn get_channel_packages(channel: &str) -> Vec<u8> {
+    let cache_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("nixpkgs-metadata");
+    std::fs::create_dir_all(&cache_dir).unwrap();
+    let archive_path = cache_dir.join(format!("{}-packages.json.br", channel));
+
+    if !archive_path.exists() {
+        let url = format!("https://channels.nixos.org/{}/packages.json.br", channel);
+        let status = Command::new("curl")
+            .arg("--fail")
+            .arg("--silent")
+            .arg("--show-error")
+            .arg("--location")
+            .arg("--output")
+            .arg(&archive_path)
+            .arg(&url)
+            .status()
+            .unwrap();
+        assert!(status.success(), "Could not download {}", url);
+    }
+
+    let output = Command::new("brotli")
+        .arg("--decompress")
+        .arg("--stdout")
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "Could not decompress {}: {}",
+        archive_path.display(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+#[test]
+#[ignore = "requires network access"]
+fn test_nixpkgs_channel_metadata() {
+    let channel = std::env::var("NIXPKGS_CHANNEL").unwrap_or(DEFAULT_CHANNEL.to_string());
+    let content = get_channel_packages(&channel);
+
+    let invalid_packages = nix2sbom::nix::get_invalid_packages(&content).unwrap();
+    for (attribute_path, error) in &invalid_packages {
+        eprintln!("{}: {}", attribute_path, error);
+    }
+    assert!(
+        invalid_packages.is_empty(),
+        "{} packages of {} could not be deserialized",
+        invalid_packages.len(),
+        channel
+    );
+}
@@ -33,4 +33,17 @@ fn for_each_file(#[files("tests/fixtures/*")] path: PathBuf) {
     expected_package_graph.transform(&packages).unwrap();
 
     assert_eq!(expected_package_graph.nodes_next, package_graph);
+
+    // The native format must be read back to the same packages it was generated from.
+    let native_packages = nix2sbom::format::native::get_native_packages(&expected_package_graph).unwrap();
+    let native_dump = nix2sbom::format::native::dump(
+        &expected_package_graph,
+        &nix2sbom::format::SerializationFormat::JSON,
+        &nix2sbom::nix::DumpOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        nix2sbom::format::native::load(&native_dump).unwrap(),
+        native_packages
+    );
 }